@@ -8,15 +8,18 @@ use {
     crate::{
         code_directory::CodeDirectoryBlob,
         code_requirement::{CodeRequirementExpression, RequirementType},
-        code_resources::{CodeResourcesBuilder, CodeResourcesRule},
+        code_resources::{CodeResourcesBuilder, CodeResourcesRule, RulesEvaluation},
         embedded_signature::{Blob, BlobData, DigestType},
         error::AppleCodesignError,
         macho::MachFile,
         macho_signing::{write_macho_file, MachOSigner},
+        provisioning_profile::{EMBEDDED_MOBILE_PROVISION, EMBEDDED_PROVISION_PROFILE},
+        signing_plan::{BundleFileAction, BundleFilePlan, BundlePlan, CodeSignaturePlan},
         signing_settings::{SettingsScope, SigningSettings},
     },
     apple_bundles::{BundlePackageType, DirectoryBundle, DirectoryBundleFile},
-    log::{info, warn},
+    log::{debug, info, warn},
+    rayon::prelude::*,
     simple_file_manifest::create_symlink,
     std::{
         borrow::Cow,
@@ -33,6 +36,7 @@ pub fn copy_bundle(bundle: &DirectoryBundle, dest_dir: &Path) -> Result<(), Appl
     let handler = SingleBundleHandler {
         dest_dir: dest_dir.to_path_buf(),
         settings: &settings,
+        signed_machos: BTreeMap::new(),
     };
 
     for file in bundle
@@ -89,52 +93,107 @@ impl BundleSigner {
     ) -> Result<DirectoryBundle, AppleCodesignError> {
         let dest_dir = dest_dir.as_ref();
 
-        // We need to sign the leaf-most bundles first since a parent bundle may need
-        // to record information about the child in its signature.
-        let mut bundles = self
-            .bundles
-            .iter()
-            .filter_map(|(rel, bundle)| rel.as_ref().map(|rel| (rel, bundle)))
-            .collect::<Vec<_>>();
-
-        // This won't preserve alphabetical order. But since the input was stable, output
-        // should be deterministic.
-        bundles.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()));
+        let levels = self.nested_bundle_levels();
 
         warn!(
             "signing {} nested bundles in the following order:",
-            bundles.len()
+            self.bundles.len() - 1
         );
-        for bundle in &bundles {
-            warn!("{}", bundle.0);
+        for bundles in levels.values() {
+            for (rel, _) in bundles {
+                warn!("{}", rel);
+            }
         }
 
-        for (rel, nested) in bundles {
-            let nested_dest_dir = dest_dir.join(rel);
-            info!(
-                "entering nested bundle {}",
-                nested.bundle.root_dir().display(),
-            );
+        for bundles in levels.into_values() {
+            bundles.into_par_iter().try_for_each(|(rel, nested)| {
+                let nested_dest_dir = dest_dir.join(rel);
+                info!(
+                    "entering nested bundle {}",
+                    nested.bundle.root_dir().display(),
+                );
+
+                // If we excluded this bundle from signing, just copy all the files.
+                if settings
+                    .path_exclusion_patterns()
+                    .iter()
+                    .any(|pattern| pattern.matches(rel))
+                {
+                    warn!("bundle is in exclusion list; it will be copied instead of signed");
+                    copy_bundle(&nested.bundle, &nested_dest_dir)?;
+                } else {
+                    let nested_settings = settings.as_nested_bundle_settings(rel);
+
+                    if !settings.force()
+                        && nested.is_signed_with(&nested_dest_dir, &nested_settings)?
+                    {
+                        info!(
+                            "{} is already signed with the same settings and resources; reusing existing signature",
+                            rel
+                        );
+                        if nested.bundle.root_dir() != nested_dest_dir {
+                            copy_bundle(&nested.bundle, &nested_dest_dir)?;
+                        }
+                    } else {
+                        nested.write_signed_bundle(nested_dest_dir, &nested_settings)?;
+                    }
+                }
+
+                info!(
+                    "leaving nested bundle {}",
+                    nested.bundle.root_dir().display()
+                );
+
+                Ok::<(), AppleCodesignError>(())
+            })?;
+        }
+
+        let main = self
+            .bundles
+            .get(&None)
+            .expect("main bundle should have a key");
+
+        let bundle = main.write_signed_bundle(dest_dir, settings)?;
+
+        if let Some(signing_time) = settings.signing_time() {
+            warn!("setting modification time of all files to {}", signing_time);
+            set_tree_mtime(
+                dest_dir,
+                filetime::FileTime::from_unix_time(signing_time.timestamp(), 0),
+            )?;
+        }
+
+        Ok(bundle)
+    }
 
-            // If we excluded this bundle from signing, just copy all the files.
+    /// Describe how [Self::write_signed_bundle()] would sign the bundle.
+    ///
+    /// Bundles are described in the order they would be signed. Nothing is written.
+    pub fn plan(&self, settings: &SigningSettings) -> Result<Vec<BundlePlan>, AppleCodesignError> {
+        let mut plans = vec![];
+
+        for (rel, nested) in self.nested_bundle_levels().into_values().flatten() {
             if settings
                 .path_exclusion_patterns()
                 .iter()
                 .any(|pattern| pattern.matches(rel))
             {
-                warn!("bundle is in exclusion list; it will be copied instead of signed");
-                copy_bundle(&nested.bundle, &nested_dest_dir)?;
+                plans.push(BundlePlan {
+                    path: rel.to_string(),
+                    identifier: nested
+                        .bundle
+                        .identifier()
+                        .map_err(AppleCodesignError::DirectoryBundle)?,
+                    excluded: true,
+                    reused: false,
+                    resources_digests: vec![],
+                    files: vec![],
+                });
             } else {
-                nested.write_signed_bundle(
-                    nested_dest_dir,
-                    &settings.as_nested_bundle_settings(rel),
-                )?;
+                let mut plan = nested.plan(rel, &settings.as_nested_bundle_settings(rel))?;
+                plan.reused &= !Self::nested_bundle_resigned(&plans, Some(rel));
+                plans.push(plan);
             }
-
-            info!(
-                "leaving nested bundle {}",
-                nested.bundle.root_dir().display()
-            );
         }
 
         let main = self
@@ -142,8 +201,93 @@ impl BundleSigner {
             .get(&None)
             .expect("main bundle should have a key");
 
-        main.write_signed_bundle(dest_dir, settings)
+        let mut plan = main.plan("", settings)?;
+        plan.reused &= !Self::nested_bundle_resigned(&plans, None);
+        plans.push(plan);
+
+        Ok(plans)
     }
+
+    /// Whether any planned bundle nested in the bundle at `rel` would be signed.
+    ///
+    /// `None` denotes the main bundle. The parent bundle seals the new signature.
+    /// So it can't be reused.
+    fn nested_bundle_resigned(plans: &[BundlePlan], rel: Option<&str>) -> bool {
+        let prefix = rel.map(|rel| format!("{rel}/"));
+
+        plans.iter().any(|plan| {
+            prefix
+                .as_ref()
+                .map_or(true, |prefix| plan.path.starts_with(prefix))
+                && !plan.excluded
+                && !plan.reused
+        })
+    }
+
+    /// Group nested bundles by their height in the nesting tree.
+    fn nested_bundle_levels(&self) -> BTreeMap<usize, Vec<(&str, &SingleBundleSigner)>> {
+        // We need to sign the leaf-most bundles first since a parent bundle may need
+        // to record information about the child in its signature. So we group bundles
+        // by their height in the nesting tree (leaf bundles have height 0) and sign each
+        // group after all groups below it have finished.
+        //
+        // Bundles within a group don't contain each other. So they can be signed
+        // concurrently.
+        let mut heights = BTreeMap::<&str, usize>::new();
+
+        let mut rels = self
+            .bundles
+            .keys()
+            .filter_map(|rel| rel.as_deref())
+            .collect::<Vec<_>>();
+        rels.sort_by(|a, b| b.len().cmp(&a.len()));
+
+        for rel in rels {
+            let prefix = format!("{rel}/");
+
+            let height = heights
+                .iter()
+                .filter(|(child, _)| child.starts_with(&prefix))
+                .map(|(_, height)| height + 1)
+                .max()
+                .unwrap_or(0);
+
+            heights.insert(rel, height);
+        }
+
+        let mut levels = BTreeMap::<usize, Vec<(&str, &SingleBundleSigner)>>::new();
+        for (rel, height) in heights {
+            levels.entry(height).or_default().push((
+                rel,
+                self.bundles
+                    .get(&Some(rel.to_string()))
+                    .expect("bundle should be present"),
+            ));
+        }
+
+        levels
+    }
+}
+
+/// Set the modification time of a directory and everything within it.
+fn set_tree_mtime(dir: &Path, mtime: filetime::FileTime) -> Result<(), AppleCodesignError> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            set_tree_mtime(&path, mtime)?;
+        } else if file_type.is_symlink() {
+            filetime::set_symlink_file_times(&path, mtime, mtime)?;
+        } else {
+            filetime::set_file_mtime(&path, mtime)?;
+        }
+    }
+
+    filetime::set_file_mtime(dir, mtime)?;
+
+    Ok(())
 }
 
 /// Metadata about a signed Mach-O file or bundle.
@@ -153,6 +297,7 @@ impl BundleSigner {
 ///
 /// This contains enough metadata to construct references to the file/bundle
 /// in [crate::code_resources::CodeResources] files.
+#[derive(Clone)]
 pub struct SignedMachOInfo {
     /// Raw data constituting the code directory blob.
     ///
@@ -281,6 +426,8 @@ pub trait BundleFileHandler {
 struct SingleBundleHandler<'a, 'key> {
     settings: &'a SigningSettings<'key>,
     dest_dir: PathBuf,
+    /// Mach-O files that have already been signed and installed, keyed by relative path.
+    signed_machos: BTreeMap<PathBuf, SignedMachOInfo>,
 }
 
 impl<'a, 'key> BundleFileHandler for SingleBundleHandler<'a, 'key> {
@@ -331,43 +478,34 @@ impl<'a, 'key> BundleFileHandler for SingleBundleHandler<'a, 'key> {
         &self,
         file: &DirectoryBundleFile,
     ) -> Result<SignedMachOInfo, AppleCodesignError> {
+        if let Some(info) = self.signed_machos.get(file.relative_path()) {
+            return Ok(info.clone());
+        }
+
         info!("signing Mach-O file {}", file.relative_path().display());
 
         let macho_data = std::fs::read(file.absolute_path())?;
         let signer = MachOSigner::new(&macho_data)?;
 
-        let mut settings = self
-            .settings
-            .as_bundle_macho_settings(file.relative_path().to_string_lossy().as_ref());
+        let settings = bundle_macho_settings(self.settings, file, &macho_data)?;
 
-        settings.import_settings_from_macho(&macho_data)?;
-
-        // If there isn't a defined binary identifier, derive one from the file name so one is set
-        // and we avoid a signing error due to missing identifier.
-        // TODO do we need to check the nested Mach-O settings?
-        if settings.binary_identifier(SettingsScope::Main).is_none() {
-            let identifier = file
-                .relative_path()
-                .file_name()
-                .expect("failure to extract filename (this should never happen)")
-                .to_string_lossy();
-
-            let identifier = identifier
-                .strip_suffix(".dylib")
-                .unwrap_or_else(|| identifier.as_ref());
+        let dest_path = self.dest_dir.join(file.relative_path());
 
+        if !settings.force() && signer.is_signed_with(&settings)? {
             info!(
-                "Mach-O is missing binary identifier; setting to {} based on file name",
-                identifier
+                "{} is already signed with the same settings; reusing existing signature",
+                file.relative_path().display()
             );
-            settings.set_binary_identifier(SettingsScope::Main, identifier);
+            if file.absolute_path() != dest_path {
+                write_macho_file(file.absolute_path(), &dest_path, &macho_data)?;
+            }
+
+            return SignedMachOInfo::parse_data(&macho_data);
         }
 
         let mut new_data = Vec::<u8>::with_capacity(macho_data.len() + 2_usize.pow(17));
         signer.write_signed_binary(&settings, &mut new_data)?;
 
-        let dest_path = self.dest_dir.join(file.relative_path());
-
         info!("writing Mach-O to {}", dest_path.display());
         write_macho_file(file.absolute_path(), &dest_path, &new_data)?;
 
@@ -375,6 +513,68 @@ impl<'a, 'key> BundleFileHandler for SingleBundleHandler<'a, 'key> {
     }
 }
 
+/// Checks whether nested Mach-O files are already signed as they would be.
+///
+/// Nothing is installed. Sealing a Mach-O file that would be re-signed fails.
+struct ExistingSignatureHandler<'a, 'key> {
+    settings: &'a SigningSettings<'key>,
+}
+
+impl<'a, 'key> BundleFileHandler for ExistingSignatureHandler<'a, 'key> {
+    fn install_file(&self, _file: &DirectoryBundleFile) -> Result<(), AppleCodesignError> {
+        Ok(())
+    }
+
+    fn sign_and_install_macho(
+        &self,
+        file: &DirectoryBundleFile,
+    ) -> Result<SignedMachOInfo, AppleCodesignError> {
+        let macho_data = std::fs::read(file.absolute_path())?;
+        let settings = bundle_macho_settings(self.settings, file, &macho_data)?;
+
+        if MachOSigner::new(&macho_data)?.is_signed_with(&settings)? {
+            SignedMachOInfo::parse_data(&macho_data)
+        } else {
+            Err(AppleCodesignError::BinaryNoCodeSignature)
+        }
+    }
+}
+
+/// Resolve the settings to use for signing a non-main Mach-O file in a bundle.
+fn bundle_macho_settings<'key>(
+    settings: &SigningSettings<'key>,
+    file: &DirectoryBundleFile,
+    macho_data: &[u8],
+) -> Result<SigningSettings<'key>, AppleCodesignError> {
+    let mut settings =
+        settings.as_bundle_macho_settings(file.relative_path().to_string_lossy().as_ref());
+
+    settings.import_settings_from_macho(macho_data)?;
+
+    // If there isn't a defined binary identifier, derive one from the file name so one is set
+    // and we avoid a signing error due to missing identifier.
+    // TODO do we need to check the nested Mach-O settings?
+    if settings.binary_identifier(SettingsScope::Main).is_none() {
+        let identifier = file
+            .relative_path()
+            .file_name()
+            .expect("failure to extract filename (this should never happen)")
+            .to_string_lossy();
+
+        let identifier = identifier
+            .strip_suffix(".dylib")
+            .unwrap_or_else(|| identifier.as_ref());
+
+        info!(
+            "Mach-O is missing binary identifier; setting to {} based on file name",
+            identifier
+        );
+        settings.set_binary_identifier(SettingsScope::Main, identifier);
+    }
+
+    Ok(settings)
+}
+
 /// A primitive for signing a single Apple bundle.
 ///
 /// Unlike [BundleSigner], this type only signs a single bundle and is ignorant
@@ -392,6 +592,373 @@ impl SingleBundleSigner {
         Self { bundle }
     }
 
+    /// Find the main executable of the bundle.
+    fn main_executable(&self) -> Result<Option<DirectoryBundleFile<'_>>, AppleCodesignError> {
+        Ok(self
+            .bundle
+            .files(false)
+            .map_err(AppleCodesignError::DirectoryBundle)?
+            .into_iter()
+            .find(|f| matches!(f.is_main_executable(), Ok(true))))
+    }
+
+    /// Resolve the digests to record in the bundle's sealed resources.
+    ///
+    /// State in the main executable can influence signing settings of the bundle.
+    fn resources_digests(
+        &self,
+        settings: &SigningSettings,
+        main_exe: Option<&DirectoryBundleFile>,
+    ) -> Result<Vec<DigestType>, AppleCodesignError> {
+        let mut resources_digests = settings.all_digests(SettingsScope::Main);
+
+        if let Some(exe) = main_exe {
+            let macho_data = std::fs::read(exe.absolute_path())?;
+            let mach = MachFile::parse(&macho_data)?;
+
+            for macho in mach.iter_macho() {
+                if let Some(targeting) = macho.find_targeting()? {
+                    let sha256_version = targeting.platform.sha256_digest_support()?;
+
+                    if !sha256_version.matches(&targeting.minimum_os_version)
+                        && resources_digests != vec![DigestType::Sha1, DigestType::Sha256]
+                    {
+                        info!("main executable targets OS requiring SHA-1 signatures; activating SHA-1 + SHA-256 signing");
+                        resources_digests = vec![DigestType::Sha1, DigestType::Sha256];
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(resources_digests)
+    }
+
+    /// Verify the provisioning profile to embed, if any, is compatible with what is signed.
+    ///
+    /// Returns the path relative to the bundle root the profile is written to.
+    fn check_provisioning_profile(
+        &self,
+        settings: &SigningSettings,
+        main_exe: Option<&DirectoryBundleFile>,
+    ) -> Result<Option<PathBuf>, AppleCodesignError> {
+        let profile = if let Some(profile) = settings.provisioning_profile(SettingsScope::Main) {
+            profile
+        } else {
+            return Ok(None);
+        };
+
+        let (_, cert) = settings
+            .signing_key()
+            .ok_or(AppleCodesignError::ProvisioningProfileNoSigningCertificate)?;
+
+        let mut exe_settings = settings.clone();
+        if let Some(exe) = main_exe {
+            exe_settings.import_settings_from_macho(&std::fs::read(exe.absolute_path())?)?;
+        }
+
+        warn!("verifying provisioning profile allows signing certificate and entitlements");
+        profile.check_signing(cert, exe_settings.entitlements_plist(SettingsScope::Main))?;
+
+        Ok(Some(if self.bundle.shallow() {
+            PathBuf::from(EMBEDDED_MOBILE_PROVISION)
+        } else {
+            PathBuf::from("Contents").join(EMBEDDED_PROVISION_PROFILE)
+        }))
+    }
+
+    /// Construct the builder used to seal the bundle's resources.
+    fn resources_builder(
+        &self,
+        digests: Vec<DigestType>,
+    ) -> Result<CodeResourcesBuilder, AppleCodesignError> {
+        // The set of rules to use is determined by whether the bundle *can* have a
+        // `Resources/`, not whether it necessarily does. The exact rules for this are not
+        // known. Essentially we want to test for the result of CFBundleCopyResourcesDirectoryURL().
+        // We assume that we can use the resources rules when there is a `Resources` directory
+        // (this seems obvious!) or when the bundle isn't shallow, as a non-shallow bundle should
+        // be an app bundle and app bundles can always have resources (we think).
+        let mut resources_builder =
+            if self.bundle.resolve_path("Resources").is_dir() || !self.bundle.shallow() {
+                CodeResourcesBuilder::default_resources_rules()?
+            } else {
+                CodeResourcesBuilder::default_no_resources_rules()?
+            };
+
+        // Ensure emitted digests match what we're configured to emit.
+        resources_builder.set_digests(digests.into_iter());
+
+        // Exclude code signature files we'll write.
+        resources_builder.add_exclusion_rule(CodeResourcesRule::new("^_CodeSignature/")?.exclude());
+        // Ignore notarization ticket.
+        resources_builder.add_exclusion_rule(CodeResourcesRule::new("^CodeResources$")?.exclude());
+
+        Ok(resources_builder)
+    }
+
+    /// Resolve the settings to use for signing the main executable.
+    ///
+    /// Sealed resources and the Info.plist still need to be registered with them.
+    fn main_executable_settings<'key>(
+        &self,
+        settings: &SigningSettings<'key>,
+        macho_data: &[u8],
+    ) -> Result<SigningSettings<'key>, AppleCodesignError> {
+        let mut settings = settings.clone();
+
+        // The identifier for the main executable is defined in the bundle's Info.plist.
+        if let Some(ident) = self
+            .bundle
+            .identifier()
+            .map_err(AppleCodesignError::DirectoryBundle)?
+        {
+            info!("setting main executable binary identifier to {} (derived from CFBundleIdentifier in Info.plist)", ident);
+            settings.set_binary_identifier(SettingsScope::Main, ident);
+        } else {
+            info!("unable to determine binary identifier from bundle's Info.plist (CFBundleIdentifier not set?)");
+        }
+
+        settings.import_settings_from_macho(macho_data)?;
+
+        Ok(settings)
+    }
+
+    /// Whether the bundle is already signed as [Self::write_signed_bundle()] would sign it.
+    ///
+    /// This is true if every nested Mach-O file is already signed with the given
+    /// settings, the existing `CodeResources` is identical to the one that would be
+    /// written, and the main executable is signed with the given settings and seals
+    /// that `CodeResources` and the `Info.plist`. Nested bundles are sealed from
+    /// `dest_dir`, where they have already been written.
+    ///
+    /// When true, the bundle can be copied as-is instead of being signed.
+    pub fn is_signed_with(
+        &self,
+        dest_dir: &Path,
+        settings: &SigningSettings,
+    ) -> Result<bool, AppleCodesignError> {
+        // Versioned frameworks and bundles with profiles to embed aren't worth the trouble.
+        if (self.bundle.package_type() == BundlePackageType::Framework
+            && self.bundle.root_dir().join("Versions").is_dir())
+            || settings.provisioning_profile(SettingsScope::Main).is_some()
+        {
+            return Ok(false);
+        }
+
+        let main_exe = if let Some(exe) = self.main_executable()? {
+            exe
+        } else {
+            return Ok(false);
+        };
+
+        let contents_dir = |root: &Path| {
+            if self.bundle.shallow() {
+                root.to_path_buf()
+            } else {
+                root.join("Contents")
+            }
+        };
+
+        let existing_resources = match std::fs::read(
+            contents_dir(self.bundle.root_dir())
+                .join("_CodeSignature")
+                .join("CodeResources"),
+        ) {
+            Ok(data) => data,
+            Err(_) => return Ok(false),
+        };
+
+        let resources_digests = self.resources_digests(settings, Some(&main_exe))?;
+        let mut resources_builder = self.resources_builder(resources_digests)?;
+        let handler = ExistingSignatureHandler { settings };
+
+        let mut info_plist_data = None;
+
+        for file in self
+            .bundle
+            .files(true)
+            .map_err(AppleCodesignError::DirectoryBundle)?
+        {
+            if file
+                .is_main_executable()
+                .map_err(AppleCodesignError::DirectoryBundle)?
+            {
+                continue;
+            }
+
+            if file.is_info_plist() {
+                info_plist_data = Some(std::fs::read(file.absolute_path())?);
+            }
+
+            if let Err(e) = resources_builder.process_file(&file, &handler) {
+                debug!("{} is not reusable: {}", file.relative_path().display(), e);
+                return Ok(false);
+            }
+        }
+
+        if !self.bundle.shallow() {
+            let nested_root = if contents_dir(dest_dir).is_dir() {
+                contents_dir(dest_dir)
+            } else {
+                contents_dir(self.bundle.root_dir())
+            };
+
+            let nested_root = DirectoryBundle::new_from_path(&nested_root)
+                .map_err(AppleCodesignError::DirectoryBundle)?;
+
+            for (rel_path, nested_bundle) in nested_root
+                .nested_bundles(false)
+                .map_err(AppleCodesignError::DirectoryBundle)?
+            {
+                resources_builder.process_nested_bundle(&rel_path, &nested_bundle)?;
+            }
+        }
+
+        let mut resources_data = Vec::<u8>::new();
+        resources_builder.write_code_resources(&mut resources_data)?;
+
+        if resources_data != existing_resources {
+            debug!("existing CodeResources differs from what signing would produce");
+            return Ok(false);
+        }
+
+        let macho_data = std::fs::read(main_exe.absolute_path())?;
+        let mut settings = self.main_executable_settings(settings, &macho_data)?;
+        settings.set_code_resources_data(SettingsScope::Main, resources_data);
+        if let Some(info_plist_data) = info_plist_data {
+            settings.set_info_plist_data(SettingsScope::Main, info_plist_data);
+        }
+
+        MachOSigner::new(&macho_data)?.is_signed_with(&settings)
+    }
+
+    /// Describe how [Self::write_signed_bundle()] would sign the bundle.
+    ///
+    /// `path` is the path of the bundle relative to the path being signed.
+    pub fn plan(
+        &self,
+        path: &str,
+        settings: &SigningSettings,
+    ) -> Result<BundlePlan, AppleCodesignError> {
+        let mut plan = BundlePlan {
+            path: path.to_string(),
+            identifier: self
+                .bundle
+                .identifier()
+                .map_err(AppleCodesignError::DirectoryBundle)?,
+            excluded: false,
+            reused: !settings.force() && self.is_signed_with(self.bundle.root_dir(), settings)?,
+            resources_digests: vec![],
+            files: vec![],
+        };
+
+        // Only the versions of versioned frameworks are signed.
+        if self.bundle.package_type() == BundlePackageType::Framework
+            && self.bundle.root_dir().join("Versions").is_dir()
+        {
+            for file in self
+                .bundle
+                .files(false)
+                .map_err(AppleCodesignError::DirectoryBundle)?
+            {
+                plan.files.push(BundleFilePlan {
+                    path: file.relative_path().to_string_lossy().to_string(),
+                    action: BundleFileAction::Copied,
+                    signatures: vec![],
+                });
+            }
+
+            return Ok(plan);
+        }
+
+        if plan.identifier.is_none() {
+            return Err(AppleCodesignError::BundleNoIdentifier(
+                self.bundle.info_plist_path(),
+            ));
+        }
+
+        let main_exe = self.main_executable()?;
+        let resources_digests = self.resources_digests(settings, main_exe.as_ref())?;
+        plan.resources_digests = resources_digests
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+
+        let provisioning_profile_path =
+            self.check_provisioning_profile(settings, main_exe.as_ref())?;
+        let resources_builder = self.resources_builder(resources_digests)?;
+
+        for file in self
+            .bundle
+            .files(true)
+            .map_err(AppleCodesignError::DirectoryBundle)?
+        {
+            let (action, signatures) =
+                if provisioning_profile_path.as_deref() == Some(file.relative_path()) {
+                    (BundleFileAction::ProvisioningProfile, vec![])
+                } else if file
+                    .is_main_executable()
+                    .map_err(AppleCodesignError::DirectoryBundle)?
+                {
+                    let macho_data = std::fs::read(file.absolute_path())?;
+                    let settings = self.main_executable_settings(settings, &macho_data)?;
+
+                    (
+                        BundleFileAction::MainExecutable,
+                        CodeSignaturePlan::from_macho(&settings, &macho_data)?,
+                    )
+                } else {
+                    match resources_builder.evaluate_file(&file)? {
+                        RulesEvaluation::SealNested(..) => {
+                            let macho_data = std::fs::read(file.absolute_path())?;
+                            let settings = bundle_macho_settings(settings, &file, &macho_data)?;
+
+                            let action = if !settings.force()
+                                && MachOSigner::new(&macho_data)?.is_signed_with(&settings)?
+                            {
+                                BundleFileAction::ReusedCode
+                            } else {
+                                BundleFileAction::NestedCode
+                            };
+
+                            (
+                                action,
+                                CodeSignaturePlan::from_macho(&settings, &macho_data)?,
+                            )
+                        }
+                        RulesEvaluation::SealRegularFile(..) | RulesEvaluation::SealSymlink(..) => {
+                            (BundleFileAction::Resource, vec![])
+                        }
+                        RulesEvaluation::Omit => (BundleFileAction::Omitted, vec![]),
+                        RulesEvaluation::Exclude | RulesEvaluation::NoRule => {
+                            (BundleFileAction::Excluded, vec![])
+                        }
+                    }
+                };
+
+            plan.files.push(BundleFilePlan {
+                path: file.relative_path().to_string_lossy().to_string(),
+                action,
+                signatures,
+            });
+        }
+
+        // The provisioning profile is usually a new file.
+        if let Some(path) = provisioning_profile_path {
+            let path = path.to_string_lossy().to_string();
+
+            if !plan.files.iter().any(|file| file.path == path) {
+                plan.files.push(BundleFilePlan {
+                    path,
+                    action: BundleFileAction::ProvisioningProfile,
+                    signatures: vec![],
+                });
+            }
+        }
+
+        Ok(plan)
+    }
+
     /// Write a signed bundle to the given directory.
     pub fn write_signed_bundle(
         &self,
@@ -430,6 +997,7 @@ impl SingleBundleSigner {
                 let handler = SingleBundleHandler {
                     dest_dir: dest_dir.to_path_buf(),
                     settings,
+                    signed_machos: BTreeMap::new(),
                 };
 
                 for file in self
@@ -460,65 +1028,55 @@ impl SingleBundleSigner {
             .map_err(AppleCodesignError::DirectoryBundle)?
             .ok_or_else(|| AppleCodesignError::BundleNoIdentifier(self.bundle.info_plist_path()))?;
 
-        let mut resources_digests = settings.all_digests(SettingsScope::Main);
+        let main_exe = self.main_executable()?;
+        let resources_digests = self.resources_digests(settings, main_exe.as_ref())?;
 
-        // State in the main executable can influence signing settings of the bundle. So examine
-        // it first.
-
-        let main_exe = self
-            .bundle
-            .files(false)
-            .map_err(AppleCodesignError::DirectoryBundle)?
-            .into_iter()
-            .find(|f| matches!(f.is_main_executable(), Ok(true)));
-
-        if let Some(exe) = &main_exe {
-            let macho_data = std::fs::read(exe.absolute_path())?;
-            let mach = MachFile::parse(&macho_data)?;
-
-            for macho in mach.iter_macho() {
-                if let Some(targeting) = macho.find_targeting()? {
-                    let sha256_version = targeting.platform.sha256_digest_support()?;
-
-                    if !sha256_version.matches(&targeting.minimum_os_version)
-                        && resources_digests != vec![DigestType::Sha1, DigestType::Sha256]
-                    {
-                        info!("main executable targets OS requiring SHA-1 signatures; activating SHA-1 + SHA-256 signing");
-                        resources_digests = vec![DigestType::Sha1, DigestType::Sha256];
-                        break;
-                    }
-                }
-            }
-        }
+        // A provisioning profile must be compatible with what we're signing. Verify
+        // that before writing anything.
+        let provisioning_profile = settings.provisioning_profile(SettingsScope::Main);
+        let provisioning_profile_path =
+            self.check_provisioning_profile(settings, main_exe.as_ref())?;
 
         warn!("collecting code resources files");
 
-        // The set of rules to use is determined by whether the bundle *can* have a
-        // `Resources/`, not whether it necessarily does. The exact rules for this are not
-        // known. Essentially we want to test for the result of CFBundleCopyResourcesDirectoryURL().
-        // We assume that we can use the resources rules when there is a `Resources` directory
-        // (this seems obvious!) or when the bundle isn't shallow, as a non-shallow bundle should
-        // be an app bundle and app bundles can always have resources (we think).
-        let mut resources_builder =
-            if self.bundle.resolve_path("Resources").is_dir() || !self.bundle.shallow() {
-                CodeResourcesBuilder::default_resources_rules()?
-            } else {
-                CodeResourcesBuilder::default_no_resources_rules()?
-            };
-
-        // Ensure emitted digests match what we're configured to emit.
-        resources_builder.set_digests(resources_digests.into_iter());
-
-        // Exclude code signature files we'll write.
-        resources_builder.add_exclusion_rule(CodeResourcesRule::new("^_CodeSignature/")?.exclude());
-        // Ignore notarization ticket.
-        resources_builder.add_exclusion_rule(CodeResourcesRule::new("^CodeResources$")?.exclude());
+        let mut resources_builder = self.resources_builder(resources_digests)?;
 
-        let handler = SingleBundleHandler {
+        let mut handler = SingleBundleHandler {
             dest_dir: dest_dir_root.clone(),
             settings,
+            signed_machos: BTreeMap::new(),
         };
 
+        let files = self
+            .bundle
+            .files(true)
+            .map_err(AppleCodesignError::DirectoryBundle)?;
+
+        // Signing Mach-O files is the expensive part of sealing resources. Sign all
+        // the ones that will be sealed concurrently up front. The results are keyed by
+        // path, so the sealed resources are the same regardless of signing order.
+        let mut machos = vec![];
+        for file in &files {
+            if provisioning_profile_path.as_deref() != Some(file.relative_path())
+                && !file
+                    .is_main_executable()
+                    .map_err(AppleCodesignError::DirectoryBundle)?
+                && resources_builder.is_nested_code(file)?
+            {
+                machos.push(file);
+            }
+        }
+
+        handler.signed_machos = machos
+            .into_par_iter()
+            .map(|file| {
+                Ok((
+                    file.relative_path().to_path_buf(),
+                    handler.sign_and_install_macho(file)?,
+                ))
+            })
+            .collect::<Result<BTreeMap<_, _>, AppleCodesignError>>()?;
+
         let mut info_plist_data = None;
 
         // Iterate files in this bundle and register as code resources.
@@ -531,13 +1089,15 @@ impl SingleBundleSigner {
         // bundles. For example, you could have a framework bundle with an embedded app bundle in
         // `Resources/MyApp.app`! In this case, the framework's CodeResources encapsulates the
         // content of `Resources/My.app` per the processing rules.
-        for file in self
-            .bundle
-            .files(true)
-            .map_err(AppleCodesignError::DirectoryBundle)?
-        {
+        for file in files {
             // The main executable is special and handled below.
-            if file
+            if provisioning_profile_path.as_deref() == Some(file.relative_path()) {
+                info!(
+                    "{} will be replaced by provisioning profile",
+                    file.relative_path().display()
+                );
+                continue;
+            } else if file
                 .is_main_executable()
                 .map_err(AppleCodesignError::DirectoryBundle)?
             {
@@ -556,6 +1116,26 @@ impl SingleBundleSigner {
             }
         }
 
+        // Embed the provisioning profile and seal it like any other resource.
+        if let (Some(profile), Some(rel_path)) = (provisioning_profile, &provisioning_profile_path)
+        {
+            let dest_path = dest_dir_root.join(rel_path);
+            warn!("writing provisioning profile to {}", dest_path.display());
+            std::fs::write(&dest_path, profile.as_der())?;
+
+            let dest_bundle = DirectoryBundle::new_from_path(&dest_dir_root)
+                .map_err(AppleCodesignError::DirectoryBundle)?;
+
+            if let Some(file) = dest_bundle
+                .files(false)
+                .map_err(AppleCodesignError::DirectoryBundle)?
+                .into_iter()
+                .find(|file| file.relative_path() == rel_path)
+            {
+                resources_builder.process_file(&file, &handler)?;
+            }
+        }
+
         // Seal code directory digests of any nested bundles.
         //
         // Apple's tooling seems to only do this for some bundle type combinations. I'm
@@ -597,21 +1177,7 @@ impl SingleBundleSigner {
             let macho_data = std::fs::read(exe.absolute_path())?;
             let signer = MachOSigner::new(&macho_data)?;
 
-            let mut settings = settings.clone();
-
-            // The identifier for the main executable is defined in the bundle's Info.plist.
-            if let Some(ident) = self
-                .bundle
-                .identifier()
-                .map_err(AppleCodesignError::DirectoryBundle)?
-            {
-                info!("setting main executable binary identifier to {} (derived from CFBundleIdentifier in Info.plist)", ident);
-                settings.set_binary_identifier(SettingsScope::Main, ident);
-            } else {
-                info!("unable to determine binary identifier from bundle's Info.plist (CFBundleIdentifier not set?)");
-            }
-
-            settings.import_settings_from_macho(&macho_data)?;
+            let mut settings = self.main_executable_settings(settings, &macho_data)?;
 
             settings.set_code_resources_data(SettingsScope::Main, resources_data);
 
@@ -619,12 +1185,20 @@ impl SingleBundleSigner {
                 settings.set_info_plist_data(SettingsScope::Main, info_plist_data);
             }
 
-            let mut new_data = Vec::<u8>::with_capacity(macho_data.len() + 2_usize.pow(17));
-            signer.write_signed_binary(&settings, &mut new_data)?;
-
             let dest_path = dest_dir_root.join(exe.relative_path());
-            info!("writing signed main executable to {}", dest_path.display());
-            write_macho_file(exe.absolute_path(), &dest_path, &new_data)?;
+
+            if !settings.force() && signer.is_signed_with(&settings)? {
+                info!("main executable is already signed with the same settings and resources; reusing existing signature");
+                if exe.absolute_path() != dest_path {
+                    write_macho_file(exe.absolute_path(), &dest_path, &macho_data)?;
+                }
+            } else {
+                let mut new_data = Vec::<u8>::with_capacity(macho_data.len() + 2_usize.pow(17));
+                signer.write_signed_binary(&settings, &mut new_data)?;
+
+                info!("writing signed main executable to {}", dest_path.display());
+                write_macho_file(exe.absolute_path(), &dest_path, &new_data)?;
+            }
         } else {
             warn!("bundle has no main executable to sign specially");
         }
@@ -632,3 +1206,170 @@ impl SingleBundleSigner {
         DirectoryBundle::new_from_path(&dest_dir_root).map_err(AppleCodesignError::DirectoryBundle)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            testutil::{read_tree, rsa_signing_identity, write_app_bundle},
+            verify::{verify_bundle_with_settings, VerificationSettings},
+        },
+        chrono::TimeZone,
+    };
+
+    #[test]
+    fn parallel_signing_is_deterministic() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("source.app");
+        write_app_bundle(&source)?;
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+        settings.set_signing_time(Some(chrono::Utc.timestamp_opt(1_600_000_000, 0).unwrap()));
+
+        let signer = BundleSigner::new_from_path(&source)?;
+
+        let mut trees = vec![];
+        for jobs in [1, 4] {
+            let dest = temp_dir.path().join(format!("signed-{jobs}.app"));
+
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(jobs)
+                .build()?
+                .install(|| signer.write_signed_bundle(&dest, &settings))?;
+
+            trees.push(read_tree(&dest)?);
+        }
+
+        for path in [
+            "Contents/_CodeSignature/CodeResources",
+            "Contents/MacOS/app",
+            "Contents/MacOS/helper",
+            "Contents/PlugIns/One.bundle/Contents/_CodeSignature/CodeResources",
+            "Contents/PlugIns/One.bundle/Contents/MacOS/One",
+            "Contents/PlugIns/Two.bundle/Contents/MacOS/Two",
+        ] {
+            assert!(trees[0].contains_key(path), "{path} should be signed");
+        }
+        assert_eq!(trees[0], trees[1]);
+
+        // Every Mach-O was signed rather than copied.
+        for path in ["Contents/MacOS/app", "Contents/MacOS/helper"] {
+            let mach = MachFile::parse(&trees[0][path])?;
+            assert!(mach.nth_macho(0)?.code_signature()?.is_some());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn reuse_existing_signatures() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("test.app");
+        write_app_bundle(&path)?;
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert.clone());
+
+        BundleSigner::new_from_path(&path)?.write_signed_bundle(&path, &settings)?;
+
+        // Signatures are padded. Changing the padding doesn't invalidate them but
+        // lets us detect whether they were rewritten.
+        let marked = [
+            "Contents/MacOS/helper",
+            "Contents/PlugIns/One.bundle/Contents/MacOS/One",
+            "Contents/PlugIns/Two.bundle/Contents/MacOS/Two",
+        ];
+        let mark = |value: u8| -> std::io::Result<()> {
+            for rel in marked {
+                let mut data = std::fs::read(path.join(rel))?;
+                *data.last_mut().unwrap() = value;
+                std::fs::write(path.join(rel), data)?;
+            }
+
+            Ok(())
+        };
+        let marks = || -> std::io::Result<Vec<u8>> {
+            marked
+                .iter()
+                .map(|rel| Ok(*std::fs::read(path.join(rel))?.last().unwrap()))
+                .collect()
+        };
+
+        assert_eq!(marks()?, vec![0, 0, 0]);
+        mark(0xff)?;
+
+        // Changing resources of the main bundle and a nested bundle only re-signs those.
+        std::fs::write(path.join("Contents/Resources/a.txt"), b"changed")?;
+        std::fs::create_dir_all(path.join("Contents/PlugIns/Two.bundle/Contents/Resources"))?;
+        std::fs::write(
+            path.join("Contents/PlugIns/Two.bundle/Contents/Resources/b.txt"),
+            b"b",
+        )?;
+
+        let one_resources =
+            path.join("Contents/PlugIns/One.bundle/Contents/_CodeSignature/CodeResources");
+        let one_mtime = std::fs::metadata(&one_resources)?.modified()?;
+
+        BundleSigner::new_from_path(&path)?.write_signed_bundle(&path, &settings)?;
+        assert_eq!(marks()?, vec![0xff, 0xff, 0]);
+        // The unchanged nested bundle is reused as a whole.
+        assert_eq!(std::fs::metadata(&one_resources)?.modified()?, one_mtime);
+
+        let mut verify_settings = VerificationSettings::default();
+        verify_settings.add_trust_anchor(cert);
+        let problems = verify_bundle_with_settings(&path, &verify_settings);
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        // Nothing is reused when forced.
+        settings.set_force(true);
+        BundleSigner::new_from_path(&path)?.write_signed_bundle(&path, &settings)?;
+        assert_eq!(marks()?, vec![0, 0, 0]);
+        assert!(verify_bundle_with_settings(&path, &verify_settings).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_signing() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let signing_time = chrono::Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+        settings.set_signing_time(Some(signing_time));
+        // Nothing listens here. Signing would fail if a token were requested.
+        settings.set_time_stamp_url("http://127.0.0.1:1/")?;
+
+        let mut trees = vec![];
+
+        // Inputs differ only in modification times.
+        for (i, mtime) in [1_000_000_000, 1_200_000_000].into_iter().enumerate() {
+            let source = temp_dir.path().join(format!("source-{i}.app"));
+            let dest = temp_dir.path().join(format!("signed-{i}.app"));
+
+            write_app_bundle(&source)?;
+            set_tree_mtime(&source, filetime::FileTime::from_unix_time(mtime, 0))?;
+
+            BundleSigner::new_from_path(&source)?.write_signed_bundle(&dest, &settings)?;
+
+            for rel in read_tree(&dest)?.keys() {
+                let metadata = std::fs::symlink_metadata(dest.join(rel))?;
+                assert_eq!(
+                    filetime::FileTime::from_last_modification_time(&metadata).unix_seconds(),
+                    signing_time.timestamp()
+                );
+            }
+
+            trees.push(read_tree(&dest)?);
+        }
+
+        assert_eq!(trees[0], trees[1]);
+
+        Ok(())
+    }
+}
@@ -11,12 +11,14 @@ use {
         ConstOid, Oid,
     },
     bytes::Bytes,
+    cryptographic_message_syntax::{SignedData, SignerInfo},
     std::{
+        borrow::Cow,
         fmt::{Display, Formatter},
         str::FromStr,
     },
     x509_certificate::{
-        certificate::KeyUsage, rfc4519::OID_COUNTRY_NAME, CapturedX509Certificate,
+        asn1time::Time, certificate::KeyUsage, rfc4519::OID_COUNTRY_NAME, CapturedX509Certificate,
         InMemorySigningKeyPair, KeyAlgorithm, X509CertificateBuilder,
     },
 };
@@ -462,6 +464,11 @@ impl CodeSigningCertificateExtension {
             Self::TestFlight => OID_EXTENSION_TEST_FLIGHT,
         }
     }
+
+    /// Obtain the OID in the form stored in code requirement expressions.
+    pub fn as_oid_cow(&self) -> Oid<Cow<'static, [u8]>> {
+        Oid(self.as_oid().0.into())
+    }
 }
 
 impl Display for CodeSigningCertificateExtension {
@@ -635,6 +642,11 @@ impl CertificateAuthorityExtension {
             }
         }
     }
+
+    /// Obtain the OID in the form stored in code requirement expressions.
+    pub fn as_oid_cow(&self) -> Oid<Cow<'static, [u8]>> {
+        Oid(self.as_oid().0.into())
+    }
 }
 
 impl Display for CertificateAuthorityExtension {
@@ -1309,6 +1321,29 @@ impl AppleCertificateBuilder for X509CertificateBuilder {
     }
 }
 
+/// Convert an ASN.1 time value to a [chrono::DateTime].
+pub(crate) fn asn1_time_to_datetime(time: &Time) -> chrono::DateTime<chrono::Utc> {
+    match time {
+        Time::UtcTime(t) => **t,
+        Time::GeneralTime(t) => t.clone().into(),
+    }
+}
+
+/// Find the certificate in a CMS [SignedData] that a [SignerInfo] was signed with.
+///
+/// The certificate is located by the issuer and serial number recorded in the
+/// signer info.
+pub(crate) fn cms_signer_certificate<'a>(
+    signed_data: &'a SignedData,
+    signer: &SignerInfo,
+) -> Option<&'a CapturedX509Certificate> {
+    let (issuer, serial) = signer.certificate_issuer_and_serial()?;
+
+    signed_data
+        .certificates()
+        .find(|cert| cert.issuer_name() == issuer && cert.serial_number_asn1() == serial)
+}
+
 /// Create a new self-signed X.509 certificate suitable for signing code.
 ///
 /// The created certificate contains all the extensions needed to convey
@@ -9,12 +9,20 @@ use {
         },
         code_directory::{CodeDirectoryBlob, CodeSignatureFlags},
         code_requirement::CodeRequirements,
-        cryptography::{parse_pfx_data, InMemoryPrivateKey, PrivateKey},
+        cryptography::{
+            decrypt_legacy_pem, encrypt_pkcs8_der, is_legacy_encrypted_pem, parse_pfx_data,
+            InMemoryPrivateKey, PrivateKey,
+        },
         embedded_signature::{Blob, CodeSigningSlot, DigestType, RequirementSetBlob},
         error::AppleCodesignError,
+        external_signer::ExternalSigner,
         macho::MachFile,
-        reader::SignatureReader,
+        macho_signing::write_macho_file,
+        macho_universal::UniversalBinaryBuilder,
+        policy::ExecutionPolicy,
+        reader::{PathType, SignatureReader},
         remote_signing::{
+            relay_server::RelayServer,
             session_negotiation::{
                 create_session_joiner, PublicKeyInitiator, SessionInitiatePeer, SessionJoinState,
                 SharedSecretInitiator,
@@ -22,7 +30,9 @@ use {
             RemoteSignError, UnjoinedSigningClient,
         },
         signing::UnifiedSigner,
-        signing_settings::{SettingsScope, SigningSettings},
+        signing_config::{CertificateConfig, SigningConfig, SigningConfigFormat},
+        signing_settings::SigningSettings,
+        time_stamp_authority::TimeStampAuthority,
     },
     base64::{engine::general_purpose::STANDARD as STANDARD_ENGINE, Engine},
     clap::{ArgAction, Args, Parser},
@@ -30,8 +40,13 @@ use {
     difference::{Changeset, Difference},
     log::{error, warn, LevelFilter},
     spki::EncodePublicKey,
-    std::{io::Write, path::PathBuf, str::FromStr},
+    std::{
+        io::Write,
+        path::{Path, PathBuf},
+        str::FromStr,
+    },
     x509_certificate::{CapturedX509Certificate, EcdsaCurve, KeyAlgorithm, X509CertificateBuilder},
+    zeroize::Zeroizing,
 };
 
 #[cfg(feature = "notarize")]
@@ -43,6 +58,12 @@ use {
     yubikey::{PinPolicy, TouchPolicy},
 };
 
+#[cfg(feature = "pkcs11")]
+use {
+    crate::pkcs11::{ObjectSelector, Pkcs11Module},
+    std::sync::Arc,
+};
+
 #[cfg(target_os = "macos")]
 use crate::macos::{
     keychain_find_code_signing_certificates, macos_keychain_find_certificate_chain, KeychainDomain,
@@ -130,6 +151,10 @@ certificate pair to a pair of files. The destination files will have
 `.crt` and `.key` appended to the value provided.
 
 When the certificate is written to a file, it isn't printed to stdout.
+
+`--encrypt-key` encrypts the private key with a password entered when
+prompted. `--key-password-file` encrypts it with the password in the given
+file. Encrypted keys are written as `ENCRYPTED PRIVATE KEY` PEM blocks.
 ";
 
 const PARSE_CODE_SIGNING_REQUIREMENT_ABOUT: &str = "\
@@ -161,6 +186,9 @@ This command can sign the following entities:
 * A bundle (specified by its directory path)
 * A DMG disk image (specified by its path)
 * A XAR archive (commonly a .pkg installer file)
+* A ZIP archive containing bundles (including iOS .ipa files)
+* Any other file, such as a script (the signature is stored in extended
+  attributes)
 
 If the input is Mach-O binary, it can be a single or multiple/fat/universal
 Mach-O binary. If a fat binary is given, each Mach-O within that binary will
@@ -170,6 +198,13 @@ If the input is a bundle, the bundle will be recursively signed. If the
 bundle contains nested bundles or Mach-O binaries, those will be signed
 automatically.
 
+If the input is a ZIP archive, it is extracted to a temporary directory and
+the bundles within are signed. Bundles in the `Payload` directory are signed
+if it exists (as in .ipa files). Otherwise bundles at the root of the archive
+are signed. A new archive is written preserving the order, permissions, and
+symlinks of the original entries. Scoped settings with paths are relative to
+each signed bundle.
+
 # Settings Scope
 
 The following signing settings are global and apply to all signed entities:
@@ -184,10 +219,12 @@ entities:
 
 * --binary-identifier
 * --code-requirements-path
+* --designated-requirement
 * --code-resources-path
 * --code-signature-flags
 * --entitlements-xml-path
 * --info-plist-path
+* --provisioning-profile
 
 Scoped settings take the form <value> or <scope>:<value>. If the 2nd form
 is used, the string before the first colon is parsed as a \"scoping string\".
@@ -230,6 +267,48 @@ Signing will traverse into nested entities:
 * A bundle will traverse non-main Mach-O binaries and sign them, adding their
   metadata to the signed resources file.
 
+# Configuration Files
+
+--config takes the path to a TOML (`.toml` extension) or YAML file defining
+signing settings. Settings given as arguments take precedence over those in
+the file. e.g.
+
+    digest: sha256
+    team-name: MK22MZP987
+    exclude: [\"Contents/Resources/*.plugin\"]
+    certificate:
+      p12-file: developer-id.p12
+      p12-password-file: password.txt
+    scopes:
+      \"@main\":
+        code-signature-flags: [runtime]
+        entitlements-xml-path: entitlements.plist
+      Contents/MacOS/helper:
+        binary-identifier: com.example.helper
+
+Top-level keys are `digest`, `team-name`, `timestamp-url`, `exclude`,
+`certificate`, and `scopes`. `certificate` accepts the certificate arguments
+below except plain text passwords and remote signing arguments. `scopes` maps
+scoping strings to scoped settings, which are named like their arguments.
+Settings that can be specified multiple times take lists. Relative paths are
+resolved against the directory of the file.
+
+--dump-config prints the effective configuration after merging the file and
+arguments, then exits without signing. Its output can be used as a
+configuration file.
+
+# Dry Run
+
+--dry-run prints a JSON description of what signing would do, then exits
+without writing anything. For bundles, every file of every bundle is listed
+with how it would be handled: signed as the main executable or as nested
+code, sealed as a resource, omitted, or excluded. Bundles are listed in the
+order they would be signed. Each signature lists the identifier, team ID,
+code signature flags, digests, designated requirement, and entitlements that
+would apply after scoped settings are merged and settings from existing
+signatures are imported. ZIP archives are extracted to a temporary directory
+to examine the bundles within.
+
 # Bundle Signing Overrides Settings
 
 When signing bundles, some settings specified on the command line will be
@@ -243,6 +322,33 @@ settings ignored include (but may not be limited to):
 * --info-plist-path. The `Info.plist` from the bundle will be used instead.
 * --digest and --extra-digest
 
+# Provisioning Profiles
+
+--provisioning-profile takes a path to a provisioning profile (e.g. a
+.mobileprovision file) to embed in a signed bundle. The profile is written
+to `embedded.mobileprovision` (iOS bundles) or
+`Contents/embedded.provisionprofile` (macOS bundles) and sealed with the
+bundle's other resources.
+
+Before signing, the profile is checked to ensure it hasn't expired, that it
+allows the signing certificate, and that it grants the entitlements of the
+bundle's main executable. Signing aborts if any of these checks fail.
+
+A profile scoped to `main` only applies to the bundle being signed. To embed
+a profile in a nested bundle (e.g. an app extension), scope it to the path
+of that bundle.
+
+# Detached Signatures
+
+--detached-signature-path writes a detached signature for a Mach-O binary to
+the given path instead of modifying the binary. This is similar to
+`codesign --detached`. The signature file contains a signature for each
+architecture in the binary. The binary is left untouched, so this can be used
+with read-only or vendor-supplied binaries. An output path cannot be specified
+when writing a detached signature.
+
+Detached signatures can be verified via `rcodesign verify --detached-signature`.
+
 # Designated Code Requirements
 
 When using Apple issued code signing certificates, we will attempt to apply
@@ -251,14 +357,24 @@ matches the behavior of what `codesign` would do. We do not yet support all
 signing certificates and signing targets for this, however. So you may
 need to provide your own requirements.
 
-Designated code requirements can be specified via --code-requirements-path.
+Designated code requirements can be specified via --designated-requirement
+or --code-requirements-path.
+
+--designated-requirement takes an expression in the human-friendly Code
+Signing Requirement Language. e.g.
+`identifier \"com.example.foo\" and anchor apple generic`. The expression is
+compiled to its binary form during signing. Like other scoped settings, the
+expression can be prefixed with a scope. If the expression contains a colon
+(e.g. in a certificate common name), a scope MUST be specified so the
+expression isn't interpreted as a scope. e.g.
+`main:certificate leaf[subject.CN] = \"...\"`.
+
+--code-requirements-path takes a path to a file containing a binary/compiled
+code requirements expression. A binary/compiled file can be produced via
+Apple's `csreq` tool. e.g. `csreq -r '=<expression>' -b /output/path`.
 
-This file MUST contain a binary/compiled code requirements expression. We do
-not (yet) support parsing the human-friendly code requirements DSL. A
-binary/compiled file can be produced via Apple's `csreq` tool. e.g.
-`csreq -r '=<expression>' -b /output/path`. If code requirements data is
-specified, it will be parsed and displayed as part of signing to ensure it
-is well-formed.
+If code requirements are specified, they will be parsed and displayed as part
+of signing to ensure they are well-formed.
 
 # Code Signing Key Pair
 
@@ -276,6 +392,9 @@ ways:
   when prompted.
 * The --pem-source argument defines paths to files containing PEM encoded
   certificate/key data. (e.g. files with \"===== BEGIN CERTIFICATE =====\").
+  Encrypted private keys (`ENCRYPTED PRIVATE KEY` and legacy OpenSSL
+  `Proc-Type: 4,ENCRYPTED` keys) are decrypted using the password given via
+  --pem-password or --pem-password-file, or entered when prompted.
 * The --source-source argument defines paths to files containiner DER
   encoded certificate/key data.
 * The --keychain-domain and --keychain-fingerprint arguments can be used to
@@ -283,6 +402,15 @@ ways:
   ignored on non-macOS platforms.
 * The --smartcard-slot argument defines the name of a slot in a connected
   smartcard device to read from. `9c` is common.
+* The --pkcs11-module argument defines the path to a PKCS#11 module for
+  signing with keys held in a hardware security module or other token.
+  The key and its certificate are found by --pkcs11-key-label or
+  --pkcs11-key-id. --pkcs11-token-label selects the token. Requires the
+  `pkcs11` crate feature.
+* The --external-signer argument defines the path to a program that signing
+  is delegated to. The program speaks a JSON protocol over stdin and stdout
+  and provides the signing certificate. --external-signer-arg passes an
+  argument to the program and can be specified multiple times.
 * Arguments beginning with --remote activate *remote signing mode* and can
   be used to delegate cryptographic signing operations to a separate machine.
   It is strongly advised to read the user documentation on remote signing
@@ -360,6 +488,68 @@ To exclude all nested bundles from being signed and only sign the main bundle
 (the default behavior of ``codesign`` without ``--deep``), use `--exclude '**'`.
 ";
 
+const VERIFY_ABOUT: &str = "\
+Verifies code signature data.
+
+This command parses the code signature data in a Mach-O binary and verifies
+its integrity. For example, digests in the code directory are compared against
+the binary content and the cryptographic signature is verified.
+
+If the path is a bundle directory, the bundle's main executable is verified and
+the resources sealed in `_CodeSignature/CodeResources` are verified against the
+files in the bundle. Added, removed, and modified files are reported. Nested
+code and nested bundles are verified as well.
+
+DMGs and flat packages (`.pkg` installers) are also supported. For DMGs, the
+digests in the code directory are compared against the image content and a
+stapled notarization ticket must be well-formed. For flat packages, the table
+of contents checksum, its RSA and CMS signatures, and the checksums of all
+files in the archive are verified. ZIP archives (including .ipa files) are
+extracted and the bundles within are verified. Other files are verified using
+the signature stored in their extended attributes.
+
+Additional code requirements that the signature must satisfy can be specified.
+This is similar to `codesign --verify -R <requirement>`.
+
+--requirement defines a requirement using the Code Signing Requirement Language.
+e.g. `identifier \"com.example.foo\" and anchor apple generic`.
+
+--policy defines a requirement via a well-known execution policy. Supported
+values are:
+
+developer-id-signed
+   Code is signed by a Developer ID certificate issued by Apple.
+developer-id-notarized-executable
+   Like developer-id-signed but for executables that are also notarized.
+developer-id-notarized-installer
+   Like developer-id-signed but for installers that are also notarized.
+
+Both arguments can be specified multiple times and all requirements must be
+satisfied.
+
+Requirements are evaluated against the identifier, code directory hashes,
+certificate chain, entitlements, and embedded Info.plist of the binary. The
+certificate chain is resolved using certificates in the signature plus built-in
+Apple certificates. Requirements that depend on state only available on
+Apple machines, such as `anchor trusted`, cannot be satisfied.
+
+The certificate chain of the signing certificate is validated. The chain must
+lead to a known Apple root certificate authority or to a certificate specified
+via --trust-anchor. --trust-anchor accepts a path to a PEM or DER encoded
+certificate and can be specified multiple times. This allows verifying
+signatures made with self-signed certificates or private certificate
+authorities. Certificates in the chain must be valid at the time of the
+signature's time-stamp token, if present, else at the signing time or the
+current time.
+
+--detached-signature verifies a Mach-O binary against a detached signature
+file, such as one written by `rcodesign sign --detached-signature-path` or
+`codesign --detached`, instead of its embedded signature.
+
+Verification performed by this command may differ from Apple's. Do not assume
+that a signature which passes verification here will be accepted by Apple.
+";
+
 const APPLE_TIMESTAMP_URL: &str = "http://timestamp.apple.com/ts01";
 
 const SUPPORTED_HASHES: [&str; 6] = [
@@ -373,12 +563,15 @@ const SUPPORTED_HASHES: [&str; 6] = [
 
 const KEYCHAIN_DOMAINS: [&str; 4] = ["user", "system", "common", "dynamic"];
 
-fn parse_scoped_value(s: &str) -> Result<(SettingsScope, &str), AppleCodesignError> {
+/// Split a `[<scope>:]<value>` argument into its scope string and value.
+///
+/// Values without a scope apply to `@main`.
+fn split_scoped_value(s: &str) -> Result<(&str, &str), AppleCodesignError> {
     let parts = s.splitn(2, ':').collect::<Vec<_>>();
 
     match parts.len() {
-        1 => Ok((SettingsScope::Main, s)),
-        2 => Ok((SettingsScope::try_from(parts[0])?, parts[1])),
+        1 => Ok(("@main", s)),
+        2 => Ok((parts[0], parts[1])),
         _ => Err(AppleCodesignError::CliBadArgument),
     }
 }
@@ -393,6 +586,34 @@ struct CertificateSource {
     #[arg(long)]
     smartcard_pin_env: Option<String>,
 
+    /// Path to a PKCS#11 module (shared library) providing the signing key
+    #[arg(long)]
+    pkcs11_module: Option<String>,
+
+    /// Label of the PKCS#11 token holding the signing key (defaults to the first token)
+    #[arg(long)]
+    pkcs11_token_label: Option<String>,
+
+    /// Label of the PKCS#11 private key and certificate to sign with
+    #[arg(long, group = "pkcs11-key")]
+    pkcs11_key_label: Option<String>,
+
+    /// Hex encoded ID of the PKCS#11 private key and certificate to sign with
+    #[arg(long, group = "pkcs11-key")]
+    pkcs11_key_id: Option<String>,
+
+    /// Environment variable holding the PKCS#11 token PIN
+    #[arg(long)]
+    pkcs11_pin_env: Option<String>,
+
+    /// Path to an external signer program to delegate signing to
+    #[arg(long)]
+    external_signer: Option<String>,
+
+    /// Argument to pass to the external signer program
+    #[arg(long, allow_hyphen_values = true, requires = "external_signer")]
+    external_signer_arg: Vec<String>,
+
     /// (macOS only) Keychain domain to operate on
     #[arg(long, group = "keychain", value_parser = KEYCHAIN_DOMAINS)]
     keychain_domain: Vec<String>,
@@ -405,6 +626,14 @@ struct CertificateSource {
     #[arg(long)]
     pem_source: Vec<String>,
 
+    /// The password to use to decrypt encrypted private keys in --pem-source files
+    #[arg(long, group = "pem-password")]
+    pem_password: Option<String>,
+
+    /// Path to file containing password for decrypting --pem-source private keys
+    #[arg(long, group = "pem-password")]
+    pem_password_file: Option<String>,
+
     /// Path to file containing DER encoded certificate data
     #[arg(long)]
     der_source: Vec<String>,
@@ -448,6 +677,72 @@ struct CertificateSource {
 }
 
 impl CertificateSource {
+    /// Express the certificate arguments as a [CertificateConfig].
+    ///
+    /// Plain text passwords and remote signing arguments aren't represented.
+    fn to_config(&self) -> CertificateConfig {
+        CertificateConfig {
+            pem_source: self.pem_source.iter().map(PathBuf::from).collect(),
+            pem_password_file: self.pem_password_file.as_ref().map(PathBuf::from),
+            der_source: self.der_source.iter().map(PathBuf::from).collect(),
+            p12_file: self.p12_path.as_ref().map(PathBuf::from),
+            p12_password_file: self.p12_password_file.as_ref().map(PathBuf::from),
+            smartcard_slot: self.smartcard_slot.clone(),
+            smartcard_pin_env: self.smartcard_pin_env.clone(),
+            keychain_domain: self.keychain_domain.clone(),
+            keychain_fingerprint: self.keychain_fingerprint.clone(),
+            pkcs11_module: self.pkcs11_module.as_ref().map(PathBuf::from),
+            pkcs11_token_label: self.pkcs11_token_label.clone(),
+            pkcs11_key_label: self.pkcs11_key_label.clone(),
+            pkcs11_key_id: self.pkcs11_key_id.clone(),
+            pkcs11_pin_env: self.pkcs11_pin_env.clone(),
+            external_signer: self.external_signer.as_ref().map(PathBuf::from),
+            external_signer_args: self.external_signer_arg.clone(),
+        }
+    }
+
+    /// Replace the arguments represented by [CertificateConfig] with its values.
+    fn apply_config(&mut self, config: &CertificateConfig) -> Result<(), AppleCodesignError> {
+        fn path_string(path: &Path) -> String {
+            path.to_string_lossy().to_string()
+        }
+
+        for domain in &config.keychain_domain {
+            if !KEYCHAIN_DOMAINS.contains(&domain.as_str()) {
+                return Err(AppleCodesignError::SigningConfigInvalid(
+                    "certificate.keychain-domain".into(),
+                    format!("unknown keychain domain {domain}"),
+                ));
+            }
+        }
+
+        if config.pkcs11_key_label.is_some() && config.pkcs11_key_id.is_some() {
+            return Err(AppleCodesignError::SigningConfigInvalid(
+                "certificate.pkcs11-key-id".into(),
+                "cannot be used with pkcs11-key-label".into(),
+            ));
+        }
+
+        self.pem_source = config.pem_source.iter().map(|x| path_string(x)).collect();
+        self.pem_password_file = config.pem_password_file.as_deref().map(path_string);
+        self.der_source = config.der_source.iter().map(|x| path_string(x)).collect();
+        self.p12_path = config.p12_file.as_deref().map(path_string);
+        self.p12_password_file = config.p12_password_file.as_deref().map(path_string);
+        self.smartcard_slot = config.smartcard_slot.clone();
+        self.smartcard_pin_env = config.smartcard_pin_env.clone();
+        self.keychain_domain = config.keychain_domain.clone();
+        self.keychain_fingerprint = config.keychain_fingerprint.clone();
+        self.pkcs11_module = config.pkcs11_module.as_deref().map(path_string);
+        self.pkcs11_token_label = config.pkcs11_token_label.clone();
+        self.pkcs11_key_label = config.pkcs11_key_label.clone();
+        self.pkcs11_key_id = config.pkcs11_key_id.clone();
+        self.pkcs11_pin_env = config.pkcs11_pin_env.clone();
+        self.external_signer = config.external_signer.as_deref().map(path_string);
+        self.external_signer_arg = config.external_signer_args.clone();
+
+        Ok(())
+    }
+
     fn resolve_certificates(
         &self,
         scan_smartcard: bool,
@@ -478,24 +773,35 @@ impl CertificateSource {
             certs.push(cert);
         }
 
+        // Only resolved once an encrypted key is encountered.
+        let mut pem_password = None;
+
         for pem_source in &self.pem_source {
             warn!("reading PEM data from {}", pem_source);
             let pem_data = std::fs::read(pem_source)?;
 
             for pem in pem::parse_many(pem_data).map_err(AppleCodesignError::CertificatePem)? {
+                let contents = if is_legacy_encrypted_pem(&pem) {
+                    decrypt_legacy_pem(&pem, self.resolve_pem_password(&mut pem_password)?)?
+                } else {
+                    Zeroizing::new(pem.contents().to_vec())
+                };
+
                 match pem.tag() {
                     "CERTIFICATE" => {
-                        certs.push(CapturedX509Certificate::from_der(pem.contents())?);
+                        certs.push(CapturedX509Certificate::from_der(contents.as_slice())?);
                     }
                     "PRIVATE KEY" => {
-                        keys.push(Box::new(InMemoryPrivateKey::from_pkcs8_der(
-                            pem.contents(),
+                        keys.push(Box::new(InMemoryPrivateKey::from_pkcs8_der(&contents)?));
+                    }
+                    "ENCRYPTED PRIVATE KEY" => {
+                        keys.push(Box::new(InMemoryPrivateKey::from_encrypted_pkcs8_der(
+                            &contents,
+                            self.resolve_pem_password(&mut pem_password)?,
                         )?));
                     }
                     "RSA PRIVATE KEY" => {
-                        keys.push(Box::new(InMemoryPrivateKey::from_pkcs1_der(
-                            pem.contents(),
-                        )?));
+                        keys.push(Box::new(InMemoryPrivateKey::from_pkcs1_der(&contents)?));
                     }
                     tag => warn!("(unhandled PEM tag {}; ignoring)", tag),
                 }
@@ -522,6 +828,17 @@ impl CertificateSource {
             }
         }
 
+        self.find_certificates_in_pkcs11_module(&mut keys, &mut certs)?;
+
+        if let Some(program) = &self.external_signer {
+            warn!("spawning external signer {}", program);
+            let signer = ExternalSigner::spawn(program, &self.external_signer_arg)?;
+
+            certs.push(signer.certificate().clone());
+            certs.extend(signer.certificate_chain().iter().cloned());
+            keys.push(Box::new(signer));
+        }
+
         let remote_signing_url = if self.remote_signer {
             Some(self.remote_signing_url.clone())
         } else {
@@ -556,6 +873,32 @@ impl CertificateSource {
         Ok((keys, certs))
     }
 
+    fn resolve_pem_password<'a>(
+        &self,
+        password: &'a mut Option<Zeroizing<String>>,
+    ) -> Result<&'a str, AppleCodesignError> {
+        if password.is_none() {
+            *password = Some(Zeroizing::new(if let Some(value) = &self.pem_password {
+                value.to_string()
+            } else if let Some(path) = &self.pem_password_file {
+                std::fs::read_to_string(path)?
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string()
+            } else {
+                dialoguer::Password::new()
+                    .with_prompt("Please enter password for encrypted PEM private key")
+                    .interact()?
+            }));
+        }
+
+        Ok(password
+            .as_ref()
+            .expect("password should be resolved")
+            .as_str())
+    }
+
     #[cfg(target_os = "macos")]
     fn find_certificates_in_keychain(
         &self,
@@ -620,6 +963,63 @@ impl CertificateSource {
         Ok(())
     }
 
+    #[cfg(feature = "pkcs11")]
+    fn find_certificates_in_pkcs11_module(
+        &self,
+        private_keys: &mut Vec<Box<dyn PrivateKey>>,
+        public_certificates: &mut Vec<CapturedX509Certificate>,
+    ) -> Result<(), AppleCodesignError> {
+        let module = if let Some(module) = &self.pkcs11_module {
+            module
+        } else {
+            return Ok(());
+        };
+
+        let selector = if let Some(label) = &self.pkcs11_key_label {
+            ObjectSelector::Label(label.clone())
+        } else if let Some(id) = &self.pkcs11_key_id {
+            ObjectSelector::Id(hex::decode(id).map_err(|e| {
+                AppleCodesignError::CliGeneralError(format!("invalid --pkcs11-key-id: {e}"))
+            })?)
+        } else {
+            return Err(AppleCodesignError::CliGeneralError(
+                "--pkcs11-module requires --pkcs11-key-label or --pkcs11-key-id".into(),
+            ));
+        };
+
+        let pin = match self.pkcs11_pin_env.as_ref().map(std::env::var) {
+            Some(Ok(pin)) => pin,
+            _ => dialoguer::Password::new()
+                .with_prompt("Please enter PKCS#11 token PIN")
+                .interact()?,
+        };
+
+        warn!("loading PKCS#11 module {}", module);
+        let module = Arc::new(Pkcs11Module::load(module)?);
+        let session =
+            module.open_session(self.pkcs11_token_label.as_deref(), Some(pin.as_bytes()))?;
+        let signer = session.certificate_signer(&selector)?;
+
+        warn!("using PKCS#11 key with {}", selector);
+        public_certificates.push(signer.certificate().clone());
+        private_keys.push(Box::new(signer));
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "pkcs11"))]
+    fn find_certificates_in_pkcs11_module(
+        &self,
+        _private_keys: &mut [Box<dyn PrivateKey>],
+        _public_certificates: &mut [CapturedX509Certificate],
+    ) -> Result<(), AppleCodesignError> {
+        if self.pkcs11_module.is_some() {
+            error!("PKCS#11 support not available; ignoring --pkcs11-module");
+        }
+
+        Ok(())
+    }
+
     fn get_remote_signing_initiator(
         &self,
     ) -> Result<Box<dyn SessionInitiatePeer>, RemoteSignError> {
@@ -1726,6 +2126,14 @@ struct GenerateSelfSignedCertificate {
     /// Base name of files to write PEM encoded certificate to
     #[arg(long)]
     pem_filename: Option<String>,
+
+    /// Encrypt the private key with a password entered when prompted
+    #[arg(long, group = "key-password")]
+    encrypt_key: bool,
+
+    /// Encrypt the private key with the password in the given file
+    #[arg(long, group = "key-password")]
+    key_password_file: Option<String>,
 }
 
 fn command_generate_self_signed_certificate(
@@ -1751,7 +2159,35 @@ fn command_generate_self_signed_certificate(
     )?;
 
     let cert_pem = cert.encode_pem();
-    let key_pem = pem::encode(&pem::Pem::new("PRIVATE KEY", raw.as_ref().to_vec()));
+    let key_password = if let Some(path) = &args.key_password_file {
+        Some(Zeroizing::new(
+            std::fs::read_to_string(path)?
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ))
+    } else if args.encrypt_key {
+        Some(Zeroizing::new(
+            dialoguer::Password::new()
+                .with_prompt("Please enter password for private key")
+                .with_confirmation("Please confirm password", "passwords do not match")
+                .interact()?,
+        ))
+    } else {
+        None
+    };
+
+    let key_pem = if let Some(password) = key_password {
+        let encrypted = encrypt_pkcs8_der(raw.as_ref(), password.as_bytes())?;
+
+        pem::encode(&pem::Pem::new(
+            "ENCRYPTED PRIVATE KEY",
+            encrypted.as_bytes().to_vec(),
+        ))
+    } else {
+        pem::encode(&pem::Pem::new("PRIVATE KEY", raw.as_ref().to_vec()))
+    };
 
     let mut wrote_file = false;
 
@@ -1881,6 +2317,205 @@ fn command_keychain_print_certificates(
     ))
 }
 
+const MACHO_UNIVERSAL_CREATE_ABOUT: &str = "\
+Create a universal Mach-O binary from thin Mach-O binaries.
+
+This is similar to `lipo -create`. Inputs can be thin or universal Mach-O
+binaries. Every architecture in the inputs is written to the output. An
+architecture can only occur once.
+
+Binaries within the universal binary are aligned according to their CPU type:
+ARM binaries are aligned to 16 KiB and other binaries to 4 KiB, matching
+`lipo`.
+
+Signatures of the input binaries are preserved unless `--resign` is given,
+in which case every architecture of the output is signed using the given
+signing certificate (or ad-hoc if no certificate is given).
+";
+
+/// Controls the signatures of Mach-O binaries written by universal binary commands.
+#[derive(Args)]
+struct UniversalSigning {
+    /// Sign the written binary instead of preserving existing signatures
+    #[arg(long)]
+    resign: bool,
+
+    /// URL of timestamp server to use when signing
+    #[arg(long, default_value = APPLE_TIMESTAMP_URL)]
+    timestamp_url: String,
+
+    #[command(flatten)]
+    certificate: CertificateSource,
+}
+
+impl UniversalSigning {
+    /// Write Mach-O data to a path and sign it if requested.
+    ///
+    /// `source_path` is the path whose file permissions are reflected.
+    fn write_macho(
+        &self,
+        source_path: &Path,
+        output_path: &Path,
+        data: &[u8],
+    ) -> Result<(), AppleCodesignError> {
+        warn!("writing Mach-O to {}", output_path.display());
+        write_macho_file(source_path, output_path, data)?;
+
+        if self.resign {
+            let mut settings = SigningSettings::default();
+
+            let (private_keys, public_certificates) =
+                self.certificate.resolve_certificates(true)?;
+            let private = register_signing_certificates(
+                &mut settings,
+                &private_keys,
+                public_certificates,
+                &self.timestamp_url,
+            )?;
+
+            UnifiedSigner::new(settings).sign_macho(output_path, output_path)?;
+
+            if let Some(private) = private {
+                private.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser)]
+struct MachoUniversalCreate {
+    /// Mach-O binaries to combine
+    #[arg(required = true)]
+    input: Vec<PathBuf>,
+
+    /// Path to universal Mach-O binary to write
+    #[arg(short = 'o', long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    signing: UniversalSigning,
+}
+
+fn command_macho_universal_create(args: &MachoUniversalCreate) -> Result<(), AppleCodesignError> {
+    let mut builder = UniversalBinaryBuilder::default();
+
+    for path in &args.input {
+        let count = builder.add_binary(std::fs::read(path)?)?;
+        warn!("added {} binaries from {}", count, path.display());
+    }
+
+    let mut data = vec![];
+    builder.write(&mut data)?;
+
+    args.signing
+        .write_macho(&args.input[0], &args.output, &data)
+}
+
+#[derive(Parser)]
+struct MachoUniversalExtract {
+    /// Name of architecture to extract (e.g. `x86_64` or `arm64`)
+    #[arg(long)]
+    arch: String,
+
+    /// Path to universal Mach-O binary to read
+    input: PathBuf,
+
+    /// Path to thin Mach-O binary to write
+    #[arg(short = 'o', long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    signing: UniversalSigning,
+}
+
+fn command_macho_universal_extract(args: &MachoUniversalExtract) -> Result<(), AppleCodesignError> {
+    let data = std::fs::read(&args.input)?;
+    let mach = MachFile::parse(&data)?;
+
+    let thin = mach.extract_architecture(&args.arch)?;
+
+    args.signing.write_macho(&args.input, &args.output, &thin)
+}
+
+#[derive(Parser)]
+struct MachoUniversalList {
+    /// Path to Mach-O binary to examine
+    path: PathBuf,
+}
+
+fn command_macho_universal_list(args: &MachoUniversalList) -> Result<(), AppleCodesignError> {
+    let data = std::fs::read(&args.path)?;
+    let mach = MachFile::parse(&data)?;
+
+    for macho in mach.iter_macho() {
+        println!(
+            "{} ({})",
+            macho.architecture_name(),
+            if macho.code_signature_load_command().is_some() {
+                "signed"
+            } else {
+                "unsigned"
+            }
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Parser)]
+struct MachoUniversalRemove {
+    /// Name of architecture to remove (e.g. `x86_64` or `arm64`)
+    #[arg(long)]
+    arch: String,
+
+    /// Path to universal Mach-O binary to read
+    input: PathBuf,
+
+    /// Path to universal Mach-O binary to write
+    #[arg(short = 'o', long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    signing: UniversalSigning,
+}
+
+fn command_macho_universal_remove(args: &MachoUniversalRemove) -> Result<(), AppleCodesignError> {
+    let data = std::fs::read(&args.input)?;
+    let mach = MachFile::parse(&data)?;
+
+    let data = mach.remove_architecture(&args.arch)?;
+
+    args.signing.write_macho(&args.input, &args.output, &data)
+}
+
+#[derive(Parser)]
+struct MachoUniversalReplace {
+    /// Path to universal Mach-O binary to read
+    input: PathBuf,
+
+    /// Path to thin Mach-O binary replacing the binary of the same architecture
+    replacement: PathBuf,
+
+    /// Path to universal Mach-O binary to write
+    #[arg(short = 'o', long)]
+    output: PathBuf,
+
+    #[command(flatten)]
+    signing: UniversalSigning,
+}
+
+fn command_macho_universal_replace(args: &MachoUniversalReplace) -> Result<(), AppleCodesignError> {
+    let data = std::fs::read(&args.input)?;
+    let mach = MachFile::parse(&data)?;
+
+    let replacement = std::fs::read(&args.replacement)?;
+    let data = mach.replace_architecture(&replacement)?;
+
+    args.signing.write_macho(&args.input, &args.output, &data)
+}
+
 #[cfg(feature = "notarize")]
 const NOTARIZE_ABOUT: &str = "\
 Submit a notarization request to Apple.
@@ -1954,6 +2589,56 @@ fn command_notary_log(args: &NotaryLog) -> Result<(), AppleCodesignError> {
     Ok(())
 }
 
+const NOTARY_PREFLIGHT_ABOUT: &str = "\
+Check an asset for common notarization rejections without contacting Apple.
+
+Apple's notary service rejects submissions for a number of well-known reasons.
+This command walks a bundle, DMG, flat package (`.pkg` installer), ZIP archive,
+or Mach-O binary and reports issues the notary service is known to reject:
+
+* Mach-O binaries that aren't signed or are ad-hoc signed.
+* Signatures not made with a Developer ID certificate issued by Apple.
+  (Flat packages must be signed with a Developer ID Installer certificate.)
+* Signatures without a secure time-stamp token.
+* Executables without the hardened runtime enabled.
+* The `com.apple.security.get-task-allow` entitlement.
+* macOS binaries built against an SDK older than 10.9.
+
+Every Mach-O binary is checked, including each architecture of fat binaries,
+binaries nested in bundles, and binaries in flat package payloads. The content
+of DMGs can't be read, so only the DMG's own signature is checked.
+
+Issues are printed as JSON resembling the notarization log retrievable via
+`rcodesign notary-log`. The command fails if any errors are found.
+
+No checks are performed against Apple's servers. Passing these checks doesn't
+guarantee that Apple will notarize the asset.
+";
+
+#[derive(Parser)]
+struct NotaryPreflight {
+    /// Path of bundle, DMG, flat package, ZIP archive, or Mach-O binary to check
+    path: PathBuf,
+}
+
+fn command_notary_preflight(args: &NotaryPreflight) -> Result<(), AppleCodesignError> {
+    let report = crate::notary_preflight::notary_preflight_path(&args.path)?;
+
+    for issue in &report.issues {
+        warn!("{}", issue);
+    }
+
+    for line in serde_json::to_string_pretty(&report)?.lines() {
+        println!("{line}");
+    }
+
+    if report.has_errors() {
+        Err(AppleCodesignError::NotaryPreflightIssues)
+    } else {
+        Ok(())
+    }
+}
+
 #[cfg(feature = "notarize")]
 #[derive(Parser)]
 struct NotarySubmit {
@@ -2169,6 +2854,70 @@ fn command_remote_sign(args: &RemoteSign) -> Result<(), AppleCodesignError> {
     Ok(())
 }
 
+const REMOTE_SIGNING_RELAY_ABOUT: &str = "\
+Run a relay server for remote signing sessions.
+
+Remote signing peers (`rcodesign sign --remote-signer` and `rcodesign
+remote-sign`) communicate through a websocket relay server. By default,
+a public relay server is used. This command runs a relay server so you
+can operate your own.
+
+Point peers at the relay with `--remote-signing-url ws://<host>:<port>/`.
+
+Messages between peers are end-to-end encrypted. So the relay never sees
+signing material. The relay does not speak TLS: put it behind a
+TLS-terminating proxy to expose it via `wss://` URLs.
+
+Sessions are closed when a peer says goodbye or when the session
+time-to-live expires. The time-to-live requested by clients is capped by
+`--max-session-ttl`.
+
+Peers that lose their connection to the relay reconnect and resume their
+session. Messages sent to them while disconnected are delivered once they
+resume. A session is closed if a disconnected peer does not resume it
+within `--resume-grace-period`.
+
+The server runs until it is terminated.
+";
+
+#[derive(Parser)]
+struct RemoteSigningRelay {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: String,
+
+    /// Maximum lifetime of signing sessions, in seconds
+    #[arg(long, default_value_t = crate::remote_signing::relay_server::DEFAULT_MAX_SESSION_TTL.as_secs())]
+    max_session_ttl: u64,
+
+    /// Seconds a disconnected session member has to resume the session before it is closed
+    #[arg(long, default_value_t = crate::remote_signing::relay_server::DEFAULT_RESUME_GRACE_PERIOD.as_secs())]
+    resume_grace_period: u64,
+
+    /// Message of the day to send to connecting clients
+    #[arg(long)]
+    motd: Option<String>,
+}
+
+fn command_remote_signing_relay(args: &RemoteSigningRelay) -> Result<(), AppleCodesignError> {
+    let mut server = RelayServer::default();
+    server.set_max_session_ttl(std::time::Duration::from_secs(args.max_session_ttl));
+    server.set_resume_grace_period(std::time::Duration::from_secs(args.resume_grace_period));
+    if let Some(motd) = &args.motd {
+        server.set_motd(motd);
+    }
+
+    let listener = std::net::TcpListener::bind(&args.listen)?;
+    warn!(
+        "remote signing relay listening on ws://{}/",
+        listener.local_addr()?
+    );
+
+    server.serve(&listener)?;
+
+    Ok(())
+}
+
 #[derive(Parser)]
 struct Sign {
     /// Identifier string for binary. The value normally used by CFBundleIdentifier
@@ -2179,6 +2928,10 @@ struct Sign {
     #[arg(long)]
     code_requirements_path: Vec<String>,
 
+    /// Code Signing Requirement Language expression to be used as designated requirements
+    #[arg(long)]
+    designated_requirement: Vec<String>,
+
     /// Path to an XML plist file containing code resources
     #[arg(long)]
     code_resources: Vec<String>,
@@ -2207,18 +2960,54 @@ struct Sign {
     #[arg(long)]
     info_plist_path: Vec<String>,
 
+    /// Path to a provisioning profile to embed in signed bundles
+    #[arg(long)]
+    provisioning_profile: Vec<String>,
+
     /// Team name/identifier to include in code signature
     #[arg(long)]
     team_name: Option<String>,
 
-    /// URL of timestamp server to use to obtain a token of the CMS signature
-    #[arg(long, default_value = APPLE_TIMESTAMP_URL)]
-    timestamp_url: String,
+    /// URL of timestamp server to use to obtain a token of the CMS signature [default: http://timestamp.apple.com/ts01]
+    #[arg(long)]
+    timestamp_url: Option<String>,
 
     /// Glob expression of paths to exclude from signing
     #[arg(long)]
     exclude: Vec<String>,
 
+    /// Sign deterministically by pinning the signing time and omitting time-stamp tokens
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Signing time to use with --deterministic, in seconds since the UNIX epoch [default: $SOURCE_DATE_EPOCH]
+    #[arg(long, requires = "deterministic")]
+    signing_time: Option<i64>,
+
+    /// Sign nested code in bundles even if it is already signed with the same settings
+    #[arg(long)]
+    force: bool,
+
+    /// Maximum number of nested bundles and Mach-O files to sign concurrently [default: number of CPUs]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    jobs: Option<u32>,
+
+    /// Path to a TOML or YAML file defining signing settings
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Print the effective signing configuration and exit without signing
+    #[arg(long)]
+    dump_config: bool,
+
+    /// Print a JSON description of what signing would do and exit without signing
+    #[arg(long, conflicts_with_all = ["detached_signature_path", "output_path"])]
+    dry_run: bool,
+
+    /// Write a detached signature for a Mach-O binary to this path instead of modifying it
+    #[arg(long, conflicts_with = "output_path")]
+    detached_signature_path: Option<PathBuf>,
+
     /// Path to Mach-O binary to sign
     input_path: PathBuf,
 
@@ -2229,21 +3018,21 @@ struct Sign {
     certificate: CertificateSource,
 }
 
-fn command_sign(args: &Sign) -> Result<(), AppleCodesignError> {
-    let mut settings = SigningSettings::default();
-
-    let (private_keys, mut public_certificates) = args.certificate.resolve_certificates(true)?;
-
+/// Register the signing key and certificates resolved from a [CertificateSource].
+///
+/// Returns the private key that will be used for signing, if any.
+fn register_signing_certificates<'key>(
+    settings: &mut SigningSettings<'key>,
+    private_keys: &'key [Box<dyn PrivateKey>],
+    mut public_certificates: Vec<CapturedX509Certificate>,
+    timestamp_url: &str,
+) -> Result<Option<&'key dyn PrivateKey>, AppleCodesignError> {
     if private_keys.len() > 1 {
         error!("at most 1 PRIVATE KEY can be present; aborting");
         return Err(AppleCodesignError::CliBadArgument);
     }
 
-    let private = if private_keys.is_empty() {
-        None
-    } else {
-        Some(&private_keys[0])
-    };
+    let private = private_keys.first().map(|key| key.as_ref());
 
     if let Some(signing_key) = &private {
         if public_certificates.is_empty() {
@@ -2265,9 +3054,9 @@ fn command_sign(args: &Sign) -> Result<(), AppleCodesignError> {
             }
         }
 
-        if args.timestamp_url != "none" {
-            warn!("using time-stamp protocol server {}", args.timestamp_url);
-            settings.set_time_stamp_url(&args.timestamp_url)?;
+        if timestamp_url != "none" {
+            warn!("using time-stamp protocol server {}", timestamp_url);
+            settings.set_time_stamp_url(timestamp_url)?;
         }
     }
 
@@ -2283,96 +3072,182 @@ fn command_sign(args: &Sign) -> Result<(), AppleCodesignError> {
         settings.chain_certificate(cert);
     }
 
-    if let Some(team_name) = &args.team_name {
-        settings.set_team_id(team_name);
-    }
+    Ok(private)
+}
 
-    if let Some(value) = &args.digest {
-        let digest_type = DigestType::try_from(value.as_str())?;
-        settings.set_digest_type(digest_type);
-    }
+impl Sign {
+    /// Express the signing settings given via arguments as a [SigningConfig].
+    fn signing_config(&self) -> Result<SigningConfig, AppleCodesignError> {
+        let mut config = SigningConfig {
+            digest: self.digest.clone(),
+            team_name: self.team_name.clone(),
+            timestamp_url: self.timestamp_url.clone(),
+            exclude: self.exclude.clone(),
+            certificate: self.certificate.to_config(),
+            ..Default::default()
+        };
 
-    for value in &args.extra_digest {
-        let (scope, digest_type) = parse_scoped_value(value)?;
-        let digest_type = DigestType::try_from(digest_type)?;
-        settings.add_extra_digest(scope, digest_type);
-    }
+        for value in &self.binary_identifier {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.binary_identifier = Some(value.to_string());
+        }
 
-    for pattern in &args.exclude {
-        settings.add_path_exclusion(pattern)?;
-    }
+        for value in &self.code_requirements_path {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.code_requirements_path = Some(value.into());
+        }
 
-    for value in &args.binary_identifier {
-        let (scope, identifier) = parse_scoped_value(value)?;
-        settings.set_binary_identifier(scope, identifier);
-    }
+        for value in &self.designated_requirement {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.designated_requirement = Some(value.to_string());
+        }
 
-    for value in &args.code_requirements_path {
-        let (scope, path) = parse_scoped_value(value)?;
+        for value in &self.code_resources {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.code_resources_path = Some(value.into());
+        }
 
-        let code_requirements_data = std::fs::read(path)?;
-        let reqs = CodeRequirements::parse_blob(&code_requirements_data)?.0;
-        for expr in reqs.iter() {
-            warn!(
-                "setting designated code requirements for {}: {}",
-                scope, expr
-            );
-            settings.set_designated_requirement_expression(scope.clone(), expr)?;
+        for value in &self.code_signature_flags {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.code_signature_flags = Some(vec![value.to_string()]);
         }
-    }
 
-    for value in &args.code_resources {
-        let (scope, path) = parse_scoped_value(value)?;
+        for value in &self.entitlements_xml_path {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.entitlements_xml_path = Some(value.into());
+        }
 
-        warn!(
-            "setting code resources data for {} from path {}",
-            scope, path
-        );
-        let code_resources_data = std::fs::read(path)?;
-        settings.set_code_resources_data(scope, code_resources_data);
-    }
+        for value in &self.runtime_version {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.runtime_version = Some(value.to_string());
+        }
 
-    for value in &args.code_signature_flags {
-        let (scope, value) = parse_scoped_value(value)?;
+        for value in &self.info_plist_path {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.info_plist_path = Some(value.into());
+        }
 
-        let flags = CodeSignatureFlags::from_str(value)?;
-        settings.set_code_signature_flags(scope, flags);
-    }
+        for value in &self.provisioning_profile {
+            let (scope, value) = split_scoped_value(value)?;
+            config.scope_mut(scope)?.provisioning_profile = Some(value.into());
+        }
 
-    for value in &args.entitlements_xml_path {
-        let (scope, path) = parse_scoped_value(value)?;
+        for value in &self.extra_digest {
+            let (scope, value) = split_scoped_value(value)?;
+            config
+                .scope_mut(scope)?
+                .extra_digests
+                .push(value.to_string());
+        }
 
-        warn!("setting entitlments XML for {} from path {}", scope, path);
-        let entitlements_data = std::fs::read_to_string(path)?;
-        settings.set_entitlements_xml(scope, entitlements_data)?;
+        Ok(config)
     }
+}
 
-    for value in &args.runtime_version {
-        let (scope, value) = parse_scoped_value(value)?;
+fn command_sign(args: &Sign) -> Result<(), AppleCodesignError> {
+    // Arguments take precedence over the configuration file.
+    let mut config = if let Some(path) = &args.config {
+        warn!("loading signing configuration from {}", path.display());
+        SigningConfig::from_path(path)?
+    } else {
+        SigningConfig::default()
+    };
+    config.merge(args.signing_config()?)?;
 
-        let version = semver::Version::parse(value)?;
-        settings.set_runtime_version(scope, version);
-    }
+    let mut settings = SigningSettings::default();
+
+    if args.dump_config {
+        config.apply_to_settings(&mut settings)?;
 
-    for value in &args.info_plist_path {
-        let (scope, value) = parse_scoped_value(value)?;
+        let format = args
+            .config
+            .as_deref()
+            .map(SigningConfigFormat::from_path)
+            .unwrap_or(SigningConfigFormat::Yaml);
+        print!("{}", config.to_string(format)?);
 
-        let content = std::fs::read(value)?;
-        settings.set_info_plist_data(scope, content);
+        return Ok(());
     }
 
-    let signer = UnifiedSigner::new(settings);
+    let mut certificate = args.certificate.clone();
+    certificate.apply_config(&config.certificate)?;
+    let (private_keys, public_certificates) = certificate.resolve_certificates(true)?;
+
+    let private = register_signing_certificates(
+        &mut settings,
+        &private_keys,
+        public_certificates,
+        config
+            .timestamp_url
+            .as_deref()
+            .unwrap_or(APPLE_TIMESTAMP_URL),
+    )?;
+
+    config.apply_to_settings(&mut settings)?;
+    settings.set_force(args.force);
+
+    if args.deterministic {
+        let seconds = if let Some(seconds) = args.signing_time {
+            seconds
+        } else if let Ok(value) = std::env::var("SOURCE_DATE_EPOCH") {
+            value.parse::<i64>().map_err(|_| {
+                error!("SOURCE_DATE_EPOCH is not an integer: {}", value);
+                AppleCodesignError::CliBadArgument
+            })?
+        } else {
+            error!("--deterministic requires --signing-time or the SOURCE_DATE_EPOCH environment variable");
+            return Err(AppleCodesignError::CliBadArgument);
+        };
+
+        let signing_time = chrono::TimeZone::timestamp_opt(&chrono::Utc, seconds, 0)
+            .single()
+            .ok_or_else(|| {
+                error!("invalid signing time: {}", seconds);
+                AppleCodesignError::CliBadArgument
+            })?;
 
-    if let Some(output_path) = &args.output_path {
         warn!(
-            "signing {} to {}",
-            args.input_path.display(),
-            output_path.display()
+            "signing deterministically with signing time {}",
+            signing_time
         );
-        signer.sign_path(&args.input_path, output_path)?;
+        settings.set_signing_time(Some(signing_time));
+    }
+
+    let signer = UnifiedSigner::new(settings);
+
+    if args.dry_run {
+        let plan = signer.plan_path(&args.input_path)?;
+
+        for line in serde_json::to_string_pretty(&plan)?.lines() {
+            println!("{line}");
+        }
     } else {
-        warn!("signing {} in place", args.input_path.display());
-        signer.sign_path_in_place(&args.input_path)?;
+        // Use a dedicated pool so --jobs doesn't reconfigure process-global state.
+        let mut pool = rayon::ThreadPoolBuilder::new();
+        if let Some(jobs) = args.jobs {
+            pool = pool.num_threads(jobs as usize);
+        }
+
+        pool.build()?.install(|| {
+            if let Some(signature_path) = &args.detached_signature_path {
+                if !matches!(PathType::from_path(&args.input_path)?, PathType::MachO) {
+                    error!("detached signatures can only be created for Mach-O binaries");
+                    return Err(AppleCodesignError::CliBadArgument);
+                }
+
+                signer.sign_macho_detached(&args.input_path, signature_path)
+            } else if let Some(output_path) = &args.output_path {
+                warn!(
+                    "signing {} to {}",
+                    args.input_path.display(),
+                    output_path.display()
+                );
+                signer.sign_path(&args.input_path, output_path)
+            } else {
+                warn!("signing {} in place", args.input_path.display());
+                signer.sign_path_in_place(&args.input_path)
+            }
+        })?;
     }
 
     if let Some(private) = &private {
@@ -2556,16 +3431,177 @@ fn command_staple(args: &Staple) -> Result<(), AppleCodesignError> {
     Ok(())
 }
 
+const UNSIGN_ABOUT: &str = "\
+Remove code signatures from a filesystem path.
+
+This command is the inverse of `rcodesign sign`. It accepts the same kinds
+of paths and removes signatures as follows:
+
+* Mach-O binaries have their `LC_CODE_SIGNATURE` load command removed and
+  the `__LINKEDIT` segment truncated to drop the signature data. Every
+  architecture of a universal/fat binary is unsigned.
+* Bundles have `_CodeSignature` directories and stapled notarization tickets
+  (`CodeResources` files) deleted. Signatures are removed from all Mach-O
+  binaries in the bundle, including binaries in nested bundles.
+* DMGs have their embedded signature removed.
+* Flat packages (`.pkg` installers) have their signatures removed from the
+  XAR table of contents.
+* ZIP archives (including `.ipa` files) have signatures removed from the
+  bundles they contain.
+* Other files have their signature extended attributes removed.
+
+If an output path is given, the input is copied there and the copy is
+unsigned. Otherwise the input path is modified in place.
+";
+
+const TIMESTAMP_SERVER_ABOUT: &str = "\
+Run an RFC 3161 time-stamp authority (TSA) server.
+
+Signing records signatures with a time-stamp server. By default this is
+Apple's server, which requires network access. This command runs a
+server issuing time-stamp tokens signed with the specified key and
+certificate so machines without access to Apple's server can still
+produce time-stamped signatures. Point signing at this server via e.g.
+`rcodesign sign --timestamp-url http://127.0.0.1:3180/`.
+
+The first certificate is the certificate of the TSA. Additional
+certificates are included in time-stamp tokens as its issuing chain.
+
+Apple does not trust time-stamp tokens from other authorities. Verify
+signatures time-stamped by this server with `rcodesign verify
+--trust-anchor` pointing at the TSA certificate or its issuer.
+
+The server runs until it is terminated.
+";
+
+#[derive(Parser)]
+struct TimestampServer {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:3180")]
+    listen: String,
+
+    /// Policy OID to record in time-stamp tokens
+    #[arg(long, default_value = crate::time_stamp_authority::DEFAULT_TIME_STAMP_POLICY)]
+    policy: String,
+
+    #[command(flatten)]
+    certificate: CertificateSource,
+}
+
+fn command_timestamp_server(args: &TimestampServer) -> Result<(), AppleCodesignError> {
+    let (private_keys, mut public_certificates) = args.certificate.resolve_certificates(true)?;
+
+    if private_keys.len() != 1 || public_certificates.is_empty() {
+        error!("a single PRIVATE KEY and its CERTIFICATE are required");
+        return Err(AppleCodesignError::CliBadArgument);
+    }
+
+    let policy = bcder::Oid::from_str(&args.policy).map_err(|_| {
+        error!("invalid policy OID: {}", args.policy);
+        AppleCodesignError::CliBadArgument
+    })?;
+
+    let private = private_keys[0].as_ref();
+    let cert = public_certificates.remove(0);
+
+    if let Some(cn) = cert.subject_common_name() {
+        warn!("issuing time-stamp tokens with certificate {}", cn);
+    }
+
+    let mut tsa = TimeStampAuthority::new(private.as_key_info_signer(), cert);
+    tsa.set_policy(policy);
+    for cert in public_certificates {
+        tsa.chain_certificate(cert);
+    }
+
+    let listener = std::net::TcpListener::bind(&args.listen)?;
+    warn!(
+        "time-stamp server listening on http://{}/",
+        listener.local_addr()?
+    );
+
+    tsa.serve(&listener)?;
+
+    private.finish()?;
+
+    Ok(())
+}
+
+#[derive(Parser)]
+struct Unsign {
+    /// Path to entity to remove signatures from
+    input_path: PathBuf,
+
+    /// Path to write unsigned output to
+    output_path: Option<PathBuf>,
+}
+
+fn command_unsign(args: &Unsign) -> Result<(), AppleCodesignError> {
+    let output_path = args.output_path.as_ref().unwrap_or(&args.input_path);
+
+    crate::unsign::unsign_path(&args.input_path, output_path)?;
+
+    Ok(())
+}
+
 #[derive(Parser)]
 struct Verify {
-    /// Path of Mach-O binary to examine
+    /// Code Signing Requirement Language expression the signature must satisfy
+    #[arg(long)]
+    requirement: Vec<String>,
+
+    /// Well-known execution policy the signature must satisfy
+    #[arg(long, value_parser = ExecutionPolicy::str_names())]
+    policy: Vec<String>,
+
+    /// Path to a PEM or DER encoded certificate to trust as a root
+    ///
+    /// Trust anchors also apply to the authority issuing time-stamp tokens.
+    #[arg(long)]
+    trust_anchor: Vec<PathBuf>,
+
+    /// Path to a detached signature to verify the Mach-O binary against
+    #[arg(long)]
+    detached_signature: Option<PathBuf>,
+
+    /// Path of Mach-O binary, bundle, DMG, flat package, or ZIP archive to examine
     path: PathBuf,
 }
 
 fn command_verify(args: &Verify) -> Result<(), AppleCodesignError> {
-    let data = std::fs::read(&args.path)?;
+    let mut settings = crate::verify::VerificationSettings::default();
+
+    for expression in &args.requirement {
+        for expr in CodeRequirements::parse_str(expression)?.iter() {
+            settings.add_requirement(expr.clone());
+        }
+    }
+
+    for policy in &args.policy {
+        let policy = ExecutionPolicy::try_from(policy.as_str())?;
+        settings.add_requirement((*policy).clone());
+    }
+
+    for path in &args.trust_anchor {
+        let data = std::fs::read(path)?;
+
+        match CapturedX509Certificate::from_pem_multiple(&data) {
+            Ok(certs) if !certs.is_empty() => {
+                for cert in certs {
+                    settings.add_trust_anchor(cert);
+                }
+            }
+            _ => {
+                settings.add_trust_anchor(CapturedX509Certificate::from_der(data)?);
+            }
+        }
+    }
 
-    let problems = crate::verify::verify_macho_data(data);
+    let problems = if let Some(signature_path) = &args.detached_signature {
+        crate::verify::verify_macho_detached_with_settings(&args.path, signature_path, &settings)
+    } else {
+        crate::verify::verify_path_with_settings(&args.path, &settings)
+    };
 
     for problem in &problems {
         println!("{problem}");
@@ -2643,10 +3679,30 @@ enum Subcommands {
     /// Print information about certificates in the macOS keychain
     KeychainPrintCertificates(KeychainPrintCertificates),
 
+    /// Create a universal Mach-O binary from thin Mach-O binaries
+    #[command(long_about = MACHO_UNIVERSAL_CREATE_ABOUT)]
+    MachoUniversalCreate(MachoUniversalCreate),
+
+    /// Extract a thin Mach-O binary for a single architecture from a universal binary
+    MachoUniversalExtract(MachoUniversalExtract),
+
+    /// List the architectures of a Mach-O binary
+    MachoUniversalList(MachoUniversalList),
+
+    /// Remove an architecture from a universal Mach-O binary
+    MachoUniversalRemove(MachoUniversalRemove),
+
+    /// Replace an architecture in a universal Mach-O binary
+    MachoUniversalReplace(MachoUniversalReplace),
+
     #[cfg(feature = "notarize")]
     /// Fetch the notarization log for a previous submission
     NotaryLog(NotaryLog),
 
+    /// Check an asset for common notarization issues without contacting Apple
+    #[command(long_about = NOTARY_PREFLIGHT_ABOUT)]
+    NotaryPreflight(NotaryPreflight),
+
     /// Upload an asset to Apple for notarization and possibly staple it
     #[cfg(feature = "notarize")]
     #[command(long_about = NOTARIZE_ABOUT, alias = "notarize")]
@@ -2675,6 +3731,10 @@ enum Subcommands {
     /// Create signatures initiated from a remote signing operation
     RemoteSign(RemoteSign),
 
+    /// Run a relay server for remote signing sessions
+    #[command(long_about = REMOTE_SIGNING_RELAY_ABOUT)]
+    RemoteSigningRelay(RemoteSigningRelay),
+
     /// Sign a Mach-O binary or bundle
     #[command(long_about = SIGN_ABOUT)]
     Sign(Sign),
@@ -2682,7 +3742,16 @@ enum Subcommands {
     /// Staples a notarization ticket to an entity
     Staple(Staple),
 
+    /// Run an RFC 3161 time-stamp server
+    #[command(long_about = TIMESTAMP_SERVER_ABOUT)]
+    TimestampServer(TimestampServer),
+
+    /// Remove code signatures from an entity
+    #[command(long_about = UNSIGN_ABOUT)]
+    Unsign(Unsign),
+
     /// Verifies code signature data
+    #[command(long_about = VERIFY_ABOUT)]
     Verify(Verify),
 
     /// Print information about X.509 OIDs related to Apple code signing
@@ -2749,8 +3818,14 @@ pub fn main_impl() -> Result<(), AppleCodesignError> {
             command_keychain_export_certificate_chain(args)
         }
         Subcommands::KeychainPrintCertificates(args) => command_keychain_print_certificates(args),
+        Subcommands::MachoUniversalCreate(args) => command_macho_universal_create(args),
+        Subcommands::MachoUniversalExtract(args) => command_macho_universal_extract(args),
+        Subcommands::MachoUniversalList(args) => command_macho_universal_list(args),
+        Subcommands::MachoUniversalRemove(args) => command_macho_universal_remove(args),
+        Subcommands::MachoUniversalReplace(args) => command_macho_universal_replace(args),
         #[cfg(feature = "notarize")]
         Subcommands::NotaryLog(args) => command_notary_log(args),
+        Subcommands::NotaryPreflight(args) => command_notary_preflight(args),
         #[cfg(feature = "notarize")]
         Subcommands::NotarySubmit(args) => command_notary_submit(args),
         #[cfg(feature = "notarize")]
@@ -2763,8 +3838,11 @@ pub fn main_impl() -> Result<(), AppleCodesignError> {
         Subcommands::SmartcardGenerateKey(args) => command_smartcard_generate_key(args),
         Subcommands::SmartcardImport(args) => command_smartcard_import(args),
         Subcommands::RemoteSign(args) => command_remote_sign(args),
+        Subcommands::RemoteSigningRelay(args) => command_remote_signing_relay(args),
         Subcommands::Sign(args) => command_sign(args),
         Subcommands::Staple(args) => command_staple(args),
+        Subcommands::TimestampServer(args) => command_timestamp_server(args),
+        Subcommands::Unsign(args) => command_unsign(args),
         Subcommands::Verify(args) => command_verify(args),
         Subcommands::X509Oids => command_x509_oids(),
     }
@@ -8,6 +8,14 @@ Code signatures contain a binary encoded expression tree denoting requirements.
 There is a human friendly DSL that can be turned into these binary expressions
 using the `csreq` Apple tool. This module reimplements that language.
 
+# Code Signing Requirement Language
+
+[CodeRequirementExpression] and [CodeRequirementMatchExpression] implement
+[Display] to render expressions in the human friendly language. Text in this
+language can be compiled to expressions via [CodeRequirements::parse_str] or
+by parsing a [CodeRequirementExpression] from a string. Rendered expressions
+can always be parsed back into an equivalent expression.
+
 # Binary Encoding
 
 Requirement expressions consist of opcodes. An opcode is defined by a u32 where
@@ -39,6 +47,7 @@ use {
         fmt::{Debug, Display},
         io::Write,
         ops::{Deref, DerefMut},
+        str::FromStr,
     },
 };
 
@@ -164,6 +173,35 @@ fn format_certificate_slot(slot: i32) -> String {
     }
 }
 
+/// Whether a character can appear in an unquoted word in the requirement language.
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+}
+
+/// Format a string as a quoted string literal, escaping as necessary.
+fn format_quoted_string(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res.push('"');
+
+    res
+}
+
+/// Format a dictionary key or certificate field name, quoting it if necessary.
+fn format_key(key: &str) -> Cow<'_, str> {
+    if !key.is_empty() && key.chars().all(is_word_char) {
+        Cow::Borrowed(key)
+    } else {
+        Cow::Owned(format_quoted_string(key))
+    }
+}
+
 /// A value in a code requirement expression.
 ///
 /// The value can be various primitive types. This type exists to make it
@@ -216,6 +254,16 @@ impl<'a> Display for CodeRequirementValue<'a> {
 }
 
 impl<'a> CodeRequirementValue<'a> {
+    /// Format this value as a constant in the Code Signing Requirement Language.
+    ///
+    /// Strings are quoted. Binary data is rendered as a `H"<hex>"` constant.
+    fn to_constant_string(&self) -> String {
+        match self {
+            Self::String(s) => format_quoted_string(s),
+            Self::Bytes(data) => format!("H\"{}\"", hex::encode(data)),
+        }
+    }
+
     /// Write the encoded version of this value somewhere.
     ///
     /// Binary encoding is u32 of length, then raw bytes, then NULL padding to next u32.
@@ -418,7 +466,7 @@ impl RequirementOpCode {
                 let (expr, data) = CodeRequirementMatchExpression::from_bytes(data)?;
 
                 Ok((
-                    CodeRequirementExpression::CertificateGeneric(slot, Oid(oid), expr),
+                    CodeRequirementExpression::CertificateGeneric(slot, Oid(oid.into()), expr),
                     data,
                 ))
             }
@@ -445,7 +493,7 @@ impl RequirementOpCode {
                 let (expr, data) = CodeRequirementMatchExpression::from_bytes(data)?;
 
                 Ok((
-                    CodeRequirementExpression::CertificatePolicy(slot, Oid(oid), expr),
+                    CodeRequirementExpression::CertificatePolicy(slot, Oid(oid.into()), expr),
                     data,
                 ))
             }
@@ -481,7 +529,7 @@ impl RequirementOpCode {
                 let (expr, data) = CodeRequirementMatchExpression::from_bytes(data)?;
 
                 Ok((
-                    CodeRequirementExpression::CertificateFieldDate(slot, Oid(oid), expr),
+                    CodeRequirementExpression::CertificateFieldDate(slot, Oid(oid.into()), expr),
                     data,
                 ))
             }
@@ -533,6 +581,10 @@ pub enum CodeRequirementExpression<'a> {
     /// `info[<key>] = <value>`
     ///
     /// 2 pairs of (length + value).
+    ///
+    /// The requirement language has no syntax of its own for this opcode. The
+    /// rendered form parses back as an equivalent [Self::InfoPlistKeyField]
+    /// equality match, so converting through the language is lossy.
     InfoKeyValueLegacy(Cow<'a, str>, Cow<'a, str>),
 
     /// Logical and.
@@ -604,7 +656,7 @@ pub enum CodeRequirementExpression<'a> {
     /// `certificate <slot> [field.<oid>] match expression`
     ///
     /// Slot i32, 4 bytes OID length, OID raw bytes, match expression.
-    CertificateGeneric(i32, Oid<&'a [u8]>, CodeRequirementMatchExpression<'a>),
+    CertificateGeneric(i32, Oid<Cow<'a, [u8]>>, CodeRequirementMatchExpression<'a>),
 
     /// For code signed by Apple, including from code signing certificates issued by Apple.
     ///
@@ -625,7 +677,7 @@ pub enum CodeRequirementExpression<'a> {
     /// It is unknown what the OID means.
     ///
     /// `certificate <slot> [policy.<oid>] match expression`
-    CertificatePolicy(i32, Oid<&'a [u8]>, CodeRequirementMatchExpression<'a>),
+    CertificatePolicy(i32, Oid<Cow<'a, [u8]>>, CodeRequirementMatchExpression<'a>),
 
     /// A named Apple anchor.
     ///
@@ -660,7 +712,7 @@ pub enum CodeRequirementExpression<'a> {
     /// Unknown what the OID corresponds to.
     ///
     /// `certificate <slot> [timestamp.<oid>] match expression`
-    CertificateFieldDate(i32, Oid<&'a [u8]>, CodeRequirementMatchExpression<'a>),
+    CertificateFieldDate(i32, Oid<Cow<'a, [u8]>>, CodeRequirementMatchExpression<'a>),
 
     /// Legacy developer ID used.
     LegacyDeveloperId,
@@ -671,14 +723,18 @@ impl<'a> Display for CodeRequirementExpression<'a> {
         match self {
             Self::False => f.write_str("never"),
             Self::True => f.write_str("always"),
-            Self::Identifier(value) => f.write_fmt(format_args!("identifier \"{value}\"")),
+            Self::Identifier(value) => {
+                f.write_fmt(format_args!("identifier {}", format_quoted_string(value)))
+            }
             Self::AnchorApple => f.write_str("anchor apple"),
             Self::AnchorCertificateHash(slot, digest) => {
                 f.write_fmt(format_args!("anchor {} H\"{}\"", slot, hex::encode(digest)))
             }
-            Self::InfoKeyValueLegacy(key, value) => {
-                f.write_fmt(format_args!("info[{key}] = \"{value}\""))
-            }
+            Self::InfoKeyValueLegacy(key, value) => f.write_fmt(format_args!(
+                "info[{}] = {}",
+                format_key(key),
+                format_quoted_string(value)
+            )),
             Self::And(a, b) => f.write_fmt(format_args!("({a}) and ({b})")),
             Self::Or(a, b) => f.write_fmt(format_args!("({a}) or ({b})")),
             Self::CodeDirectoryHash(digest) => {
@@ -686,12 +742,12 @@ impl<'a> Display for CodeRequirementExpression<'a> {
             }
             Self::Not(expr) => f.write_fmt(format_args!("!({expr})")),
             Self::InfoPlistKeyField(key, expr) => {
-                f.write_fmt(format_args!("info [{key}] {expr}"))
+                f.write_fmt(format_args!("info [{}] {}", format_key(key), expr))
             }
             Self::CertificateField(slot, field, expr) => f.write_fmt(format_args!(
                 "certificate {}[{}] {}",
                 format_certificate_slot(*slot),
-                field,
+                format_key(field),
                 expr
             )),
             Self::CertificateTrusted(slot) => {
@@ -706,7 +762,7 @@ impl<'a> Display for CodeRequirementExpression<'a> {
             )),
             Self::AnchorAppleGeneric => f.write_str("anchor apple generic"),
             Self::EntitlementsKey(key, expr) => {
-                f.write_fmt(format_args!("entitlement [{key}] {expr}"))
+                f.write_fmt(format_args!("entitlement [{}] {}", format_key(key), expr))
             }
             Self::CertificatePolicy(slot, oid, expr) => f.write_fmt(format_args!(
                 "certificate {}[policy.{}] {}",
@@ -714,8 +770,10 @@ impl<'a> Display for CodeRequirementExpression<'a> {
                 oid,
                 expr
             )),
-            Self::NamedAnchor(name) => f.write_fmt(format_args!("anchor apple {name}")),
-            Self::NamedCode(name) => f.write_fmt(format_args!("({name})")),
+            Self::NamedAnchor(name) => {
+                f.write_fmt(format_args!("anchor apple {}", format_key(name)))
+            }
+            Self::NamedCode(name) => f.write_fmt(format_args!("({})", format_key(name))),
             Self::Platform(platform) => f.write_fmt(format_args!("platform = {platform}")),
             Self::Notarized => f.write_str("notarized"),
             Self::CertificateFieldDate(slot, oid, expr) => f.write_fmt(format_args!(
@@ -1166,19 +1224,27 @@ impl<'a> Display for CodeRequirementMatchExpression<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Exists => f.write_str("/* exists */"),
-            Self::Equal(value) => f.write_fmt(format_args!("= \"{value}\"")),
-            Self::Contains(value) => f.write_fmt(format_args!("~ \"{value}\"")),
-            Self::BeginsWith(value) => f.write_fmt(format_args!("= \"{value}*\"")),
-            Self::EndsWith(value) => f.write_fmt(format_args!("= \"*{value}\"")),
-            Self::LessThan(value) => f.write_fmt(format_args!("< \"{value}\"")),
-            Self::GreaterThan(value) => f.write_fmt(format_args!("> \"{value}\"")),
-            Self::LessThanEqual(value) => f.write_fmt(format_args!("<= \"{value}\"")),
-            Self::GreaterThanEqual(value) => f.write_fmt(format_args!(">= \"{value}\"")),
-            Self::On(value) => f.write_fmt(format_args!("= \"{value}\"")),
-            Self::Before(value) => f.write_fmt(format_args!("< \"{value}\"")),
-            Self::After(value) => f.write_fmt(format_args!("> \"{value}\"")),
-            Self::OnOrBefore(value) => f.write_fmt(format_args!("<= \"{value}\"")),
-            Self::OnOrAfter(value) => f.write_fmt(format_args!(">= \"{value}\"")),
+            Self::Equal(value) => f.write_fmt(format_args!("= {}", value.to_constant_string())),
+            Self::Contains(value) => f.write_fmt(format_args!("~ {}", value.to_constant_string())),
+            Self::BeginsWith(value) => {
+                f.write_fmt(format_args!("= {}*", value.to_constant_string()))
+            }
+            Self::EndsWith(value) => f.write_fmt(format_args!("= *{}", value.to_constant_string())),
+            Self::LessThan(value) => f.write_fmt(format_args!("< {}", value.to_constant_string())),
+            Self::GreaterThan(value) => {
+                f.write_fmt(format_args!("> {}", value.to_constant_string()))
+            }
+            Self::LessThanEqual(value) => {
+                f.write_fmt(format_args!("<= {}", value.to_constant_string()))
+            }
+            Self::GreaterThanEqual(value) => {
+                f.write_fmt(format_args!(">= {}", value.to_constant_string()))
+            }
+            Self::On(value) => f.write_fmt(format_args!("= timestamp \"{value}\"")),
+            Self::Before(value) => f.write_fmt(format_args!("< timestamp \"{value}\"")),
+            Self::After(value) => f.write_fmt(format_args!("> timestamp \"{value}\"")),
+            Self::OnOrBefore(value) => f.write_fmt(format_args!("<= timestamp \"{value}\"")),
+            Self::OnOrAfter(value) => f.write_fmt(format_args!(">= timestamp \"{value}\"")),
             Self::Absent => f.write_str("absent"),
         }
     }
@@ -1370,9 +1436,695 @@ impl<'a> TryFrom<&CodeRequirements<'a>> for RequirementBlob<'static> {
     }
 }
 
+impl CodeRequirements<'static> {
+    /// Parse Code Signing Requirement Language text into expressions.
+    ///
+    /// This is the equivalent of `csreq -r <text>`. The text can optionally begin
+    /// with `=` or `designated =>`, as is commonly seen when requirements are
+    /// specified to Apple's tools.
+    ///
+    /// The returned value can be serialized to the binary form via
+    /// [CodeRequirements::to_blob_data] or have its expressions registered with
+    /// signing settings.
+    pub fn parse_str(s: &str) -> Result<Self, AppleCodesignError> {
+        let mut parser = RequirementParser::new(s)?;
+
+        if !parser.consume(&RequirementToken::Equal) && parser.consume_word("designated") {
+            parser.expect(&RequirementToken::Arrow, "=>")?;
+        }
+
+        let expr = parser.parse_or()?;
+        parser.consume(&RequirementToken::Semicolon);
+        parser.expect_end()?;
+
+        Ok(Self(vec![expr]))
+    }
+}
+
+impl FromStr for CodeRequirementExpression<'static> {
+    type Err = AppleCodesignError;
+
+    /// Parse a single expression in the Code Signing Requirement Language.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = RequirementParser::new(s)?;
+
+        let expr = parser.parse_or()?;
+        parser.expect_end()?;
+
+        Ok(expr)
+    }
+}
+
+/// A lexical token in the Code Signing Requirement Language.
+#[derive(Clone, Debug, PartialEq)]
+enum RequirementToken {
+    /// An unquoted word, such as a keyword, dotted name, or number.
+    Word(String),
+    /// A quoted string, with escapes resolved.
+    String(String),
+    /// A `H"<hex>"` hash constant.
+    Hash(Vec<u8>),
+    Equal,
+    Arrow,
+    Tilde,
+    LessThan,
+    GreaterThan,
+    LessThanEqual,
+    GreaterThanEqual,
+    Not,
+    Star,
+    Semicolon,
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+}
+
+/// Split Code Signing Requirement Language text into tokens.
+///
+/// Each token is paired with its byte offset in the source string.
+fn tokenize_requirement(s: &str) -> Result<Vec<(usize, RequirementToken)>, AppleCodesignError> {
+    let mut tokens = vec![];
+    let mut chars = s.char_indices().peekable();
+
+    let error = |offset: usize, message: &str| {
+        AppleCodesignError::RequirementParse(offset, message.to_string())
+    };
+
+    while let Some((offset, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '/' if matches!(chars.peek(), Some((_, '*'))) => {
+                chars.next();
+
+                let mut previous = None;
+                loop {
+                    match chars.next() {
+                        Some((_, '/')) if previous == Some('*') => break,
+                        Some((_, c)) => previous = Some(c),
+                        None => return Err(error(offset, "unterminated comment")),
+                    }
+                }
+
+                continue;
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+
+                continue;
+            }
+            '"' => {
+                let mut value = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => value.push(c),
+                            None => return Err(error(offset, "unterminated string")),
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => return Err(error(offset, "unterminated string")),
+                    }
+                }
+
+                RequirementToken::String(value)
+            }
+            'H' | 'h' if matches!(chars.peek(), Some((_, '"'))) => {
+                chars.next();
+
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, c)) if c.is_whitespace() => {}
+                        Some((_, c)) => value.push(c),
+                        None => return Err(error(offset, "unterminated hash constant")),
+                    }
+                }
+
+                RequirementToken::Hash(
+                    hex::decode(&value).map_err(|_| error(offset, "invalid hash constant"))?,
+                )
+            }
+            '=' if matches!(chars.peek(), Some((_, '>'))) => {
+                chars.next();
+                RequirementToken::Arrow
+            }
+            '=' => RequirementToken::Equal,
+            '<' if matches!(chars.peek(), Some((_, '='))) => {
+                chars.next();
+                RequirementToken::LessThanEqual
+            }
+            '<' => RequirementToken::LessThan,
+            '>' if matches!(chars.peek(), Some((_, '='))) => {
+                chars.next();
+                RequirementToken::GreaterThanEqual
+            }
+            '>' => RequirementToken::GreaterThan,
+            '~' => RequirementToken::Tilde,
+            '!' => RequirementToken::Not,
+            '*' => RequirementToken::Star,
+            ';' => RequirementToken::Semicolon,
+            '(' => RequirementToken::LeftParen,
+            ')' => RequirementToken::RightParen,
+            '[' => RequirementToken::LeftBracket,
+            ']' => RequirementToken::RightBracket,
+            c if is_word_char(c) => {
+                let mut value = String::from(c);
+
+                while let Some((_, c)) = chars.peek() {
+                    if is_word_char(*c) {
+                        value.push(*c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                RequirementToken::Word(value)
+            }
+            _ => return Err(error(offset, &format!("unexpected character: {c}"))),
+        };
+
+        tokens.push((offset, token));
+    }
+
+    Ok(tokens)
+}
+
+/// Words that begin a primary expression.
+///
+/// Used to disambiguate parenthesized expressions from named code references.
+const PRIMARY_KEYWORDS: &[&str] = &[
+    "always",
+    "anchor",
+    "cdhash",
+    "cert",
+    "certificate",
+    "entitlement",
+    "false",
+    "identifier",
+    "info",
+    "legacy",
+    "never",
+    "notarized",
+    "platform",
+    "true",
+];
+
+/// A recursive descent parser for the Code Signing Requirement Language.
+///
+/// `!` binds tightest, followed by `and`, then `or`. Binary operators are left
+/// associative, matching the expression trees produced by `csreq`.
+struct RequirementParser {
+    tokens: Vec<(usize, RequirementToken)>,
+    index: usize,
+    end: usize,
+}
+
+impl RequirementParser {
+    fn new(s: &str) -> Result<Self, AppleCodesignError> {
+        Ok(Self {
+            tokens: tokenize_requirement(s)?,
+            index: 0,
+            end: s.len(),
+        })
+    }
+
+    /// Byte offset of the next token in the source string.
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map(|(offset, _)| *offset)
+            .unwrap_or(self.end)
+    }
+
+    fn error(&self, message: impl ToString) -> AppleCodesignError {
+        AppleCodesignError::RequirementParse(self.offset(), message.to_string())
+    }
+
+    fn peek_at(&self, distance: usize) -> Option<&RequirementToken> {
+        self.tokens
+            .get(self.index + distance)
+            .map(|(_, token)| token)
+    }
+
+    fn peek(&self) -> Option<&RequirementToken> {
+        self.peek_at(0)
+    }
+
+    fn next_token(&mut self) -> Option<RequirementToken> {
+        let token = self.peek().cloned();
+
+        if token.is_some() {
+            self.index += 1;
+        }
+
+        token
+    }
+
+    /// Consume the next token if it equals the one specified.
+    fn consume(&mut self, token: &RequirementToken) -> bool {
+        if self.peek() == Some(token) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume the next token if it is the given word.
+    fn consume_word(&mut self, word: &str) -> bool {
+        if matches!(self.peek(), Some(RequirementToken::Word(w)) if w == word) {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &RequirementToken, what: &str) -> Result<(), AppleCodesignError> {
+        if self.consume(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected {what}")))
+        }
+    }
+
+    fn expect_end(&self) -> Result<(), AppleCodesignError> {
+        if self.peek().is_none() {
+            Ok(())
+        } else {
+            Err(self.error("unexpected content after expression"))
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<CodeRequirementExpression<'static>, AppleCodesignError> {
+        let mut expr = self.parse_and()?;
+
+        while self.consume_word("or") {
+            let rhs = self.parse_and()?;
+            expr = CodeRequirementExpression::Or(Box::new(expr), Box::new(rhs));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<CodeRequirementExpression<'static>, AppleCodesignError> {
+        let mut expr = self.parse_unary()?;
+
+        while self.consume_word("and") {
+            let rhs = self.parse_unary()?;
+            expr = CodeRequirementExpression::And(Box::new(expr), Box::new(rhs));
+        }
+
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<CodeRequirementExpression<'static>, AppleCodesignError> {
+        if self.consume(&RequirementToken::Not) {
+            Ok(CodeRequirementExpression::Not(Box::new(
+                self.parse_unary()?,
+            )))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<CodeRequirementExpression<'static>, AppleCodesignError> {
+        let offset = self.offset();
+
+        let word = match self.next_token() {
+            Some(RequirementToken::LeftParen) => {
+                // `(<name>)` references named code. Anything else is a grouped expression.
+                let named = match (self.peek(), self.peek_at(1)) {
+                    (Some(RequirementToken::String(name)), Some(RequirementToken::RightParen)) => {
+                        Some(name.clone())
+                    }
+                    (Some(RequirementToken::Word(name)), Some(RequirementToken::RightParen))
+                        if !PRIMARY_KEYWORDS.contains(&name.as_str()) =>
+                    {
+                        Some(name.clone())
+                    }
+                    _ => None,
+                };
+
+                return if let Some(name) = named {
+                    self.index += 2;
+                    Ok(CodeRequirementExpression::NamedCode(name.into()))
+                } else {
+                    let expr = self.parse_or()?;
+                    self.expect(&RequirementToken::RightParen, ")")?;
+                    Ok(expr)
+                };
+            }
+            Some(RequirementToken::Word(word)) => word,
+            Some(token) => {
+                return Err(AppleCodesignError::RequirementParse(
+                    offset,
+                    format!("unexpected token: {token:?}"),
+                ))
+            }
+            None => return Err(self.error("unexpected end of expression")),
+        };
+
+        match word.as_str() {
+            "always" | "true" => Ok(CodeRequirementExpression::True),
+            "never" | "false" => Ok(CodeRequirementExpression::False),
+            "identifier" => {
+                self.consume(&RequirementToken::Equal);
+                Ok(CodeRequirementExpression::Identifier(
+                    self.parse_string("identifier string")?.into(),
+                ))
+            }
+            "cdhash" => {
+                self.consume(&RequirementToken::Equal);
+                Ok(CodeRequirementExpression::CodeDirectoryHash(
+                    self.parse_hash()?.into(),
+                ))
+            }
+            "platform" => {
+                self.consume(&RequirementToken::Equal);
+                let value = self.parse_string("platform number")?;
+                let value = value.parse::<u32>().map_err(|_| {
+                    AppleCodesignError::RequirementParse(
+                        offset,
+                        format!("invalid platform value: {value}"),
+                    )
+                })?;
+
+                Ok(CodeRequirementExpression::Platform(value))
+            }
+            "notarized" => Ok(CodeRequirementExpression::Notarized),
+            "legacy" => Ok(CodeRequirementExpression::LegacyDeveloperId),
+            "info" => {
+                let key = self.parse_bracketed_key()?;
+                Ok(CodeRequirementExpression::InfoPlistKeyField(
+                    key.into(),
+                    self.parse_match(false)?,
+                ))
+            }
+            "entitlement" => {
+                let key = self.parse_bracketed_key()?;
+                Ok(CodeRequirementExpression::EntitlementsKey(
+                    key.into(),
+                    self.parse_match(false)?,
+                ))
+            }
+            "anchor" => self.parse_anchor(),
+            "certificate" | "cert" => {
+                let slot = self
+                    .parse_certificate_slot()?
+                    .ok_or_else(|| self.error("expected certificate position"))?;
+
+                self.parse_certificate(slot)
+            }
+            _ => Err(AppleCodesignError::RequirementParse(
+                offset,
+                format!("unknown expression: {word}"),
+            )),
+        }
+    }
+
+    /// Parse the remainder of an `anchor` expression.
+    fn parse_anchor(&mut self) -> Result<CodeRequirementExpression<'static>, AppleCodesignError> {
+        if self.consume_word("apple") {
+            if self.consume_word("generic") {
+                return Ok(CodeRequirementExpression::AnchorAppleGeneric);
+            }
+
+            let name = match self.peek() {
+                Some(RequirementToken::String(name)) => Some(name.clone()),
+                Some(RequirementToken::Word(name)) if name != "and" && name != "or" => {
+                    Some(name.clone())
+                }
+                _ => None,
+            };
+
+            Ok(if let Some(name) = name {
+                self.index += 1;
+                CodeRequirementExpression::NamedAnchor(name.into())
+            } else {
+                CodeRequirementExpression::AnchorApple
+            })
+        } else if self.consume_word("trusted") {
+            Ok(CodeRequirementExpression::AnchorTrusted)
+        } else {
+            // `anchor` is shorthand for the root certificate.
+            let slot = self.parse_certificate_slot()?.unwrap_or(-1);
+
+            self.parse_certificate(slot)
+        }
+    }
+
+    /// Parse the remainder of an expression operating on the certificate at `slot`.
+    fn parse_certificate(
+        &mut self,
+        slot: i32,
+    ) -> Result<CodeRequirementExpression<'static>, AppleCodesignError> {
+        if self.consume_word("trusted") {
+            return Ok(CodeRequirementExpression::CertificateTrusted(slot));
+        }
+
+        if self.peek() != Some(&RequirementToken::LeftBracket) {
+            self.consume(&RequirementToken::Equal);
+            return Ok(CodeRequirementExpression::AnchorCertificateHash(
+                slot,
+                self.parse_hash()?.into(),
+            ));
+        }
+
+        let offset = self.offset();
+        let field = self.parse_bracketed_key()?;
+
+        let parse_oid = |oid: &str| {
+            Oid::<Cow<'static, [u8]>>::from_str(oid).map_err(|_| {
+                AppleCodesignError::RequirementParse(offset, format!("invalid OID: {oid}"))
+            })
+        };
+
+        Ok(if let Some(oid) = field.strip_prefix("field.") {
+            CodeRequirementExpression::CertificateGeneric(
+                slot,
+                parse_oid(oid)?,
+                self.parse_match(false)?,
+            )
+        } else if let Some(oid) = field.strip_prefix("policy.") {
+            CodeRequirementExpression::CertificatePolicy(
+                slot,
+                parse_oid(oid)?,
+                self.parse_match(false)?,
+            )
+        } else if let Some(oid) = field.strip_prefix("timestamp.") {
+            CodeRequirementExpression::CertificateFieldDate(
+                slot,
+                parse_oid(oid)?,
+                self.parse_match(true)?,
+            )
+        } else {
+            CodeRequirementExpression::CertificateField(
+                slot,
+                field.into(),
+                self.parse_match(false)?,
+            )
+        })
+    }
+
+    /// Parse a certificate position, if present.
+    ///
+    /// `leaf` is 0. `root` and `anchor` are -1. Integers are taken literally.
+    fn parse_certificate_slot(&mut self) -> Result<Option<i32>, AppleCodesignError> {
+        let slot = match self.peek() {
+            Some(RequirementToken::Word(word)) => match word.as_str() {
+                "leaf" => Some(0),
+                "root" | "anchor" => Some(-1),
+                word if word.starts_with(|c: char| c == '-' || c.is_ascii_digit()) => Some(
+                    word.parse::<i32>()
+                        .map_err(|_| self.error(format!("invalid certificate position: {word}")))?,
+                ),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if slot.is_some() {
+            self.index += 1;
+        }
+
+        Ok(slot)
+    }
+
+    /// Parse a `[<key>]` expression, returning the key.
+    fn parse_bracketed_key(&mut self) -> Result<String, AppleCodesignError> {
+        self.expect(&RequirementToken::LeftBracket, "[")?;
+        let key = self.parse_string("key")?;
+        self.expect(&RequirementToken::RightBracket, "]")?;
+
+        Ok(key)
+    }
+
+    /// Parse a string value, which may be quoted or a bare word.
+    fn parse_string(&mut self, what: &str) -> Result<String, AppleCodesignError> {
+        match self.peek() {
+            Some(RequirementToken::String(s) | RequirementToken::Word(s)) => {
+                let s = s.clone();
+                self.index += 1;
+                Ok(s)
+            }
+            _ => Err(self.error(format!("expected {what}"))),
+        }
+    }
+
+    /// Parse a hash constant.
+    ///
+    /// Quoted hex strings are accepted in addition to `H"<hex>"` constants.
+    fn parse_hash(&mut self) -> Result<Vec<u8>, AppleCodesignError> {
+        match self.peek() {
+            Some(RequirementToken::Hash(data)) => {
+                let data = data.clone();
+                self.index += 1;
+                Ok(data)
+            }
+            Some(RequirementToken::String(s)) => {
+                let data = hex::decode(s).map_err(|_| self.error("invalid hash constant"))?;
+                self.index += 1;
+                Ok(data)
+            }
+            _ => Err(self.error("expected hash constant")),
+        }
+    }
+
+    /// Parse a value in a match expression.
+    fn parse_value(&mut self) -> Result<CodeRequirementValue<'static>, AppleCodesignError> {
+        match self.peek() {
+            Some(RequirementToken::Hash(data)) => {
+                let value = CodeRequirementValue::Bytes(Cow::Owned(data.clone()));
+                self.index += 1;
+                Ok(value)
+            }
+            _ => Ok(self.parse_string("value")?.into()),
+        }
+    }
+
+    /// Parse a timestamp value in a match expression.
+    ///
+    /// Accepts RFC 3339 strings and the `YYYY-MM-DD HH:MM:SS UTC` form used when
+    /// rendering expressions.
+    fn parse_date(&mut self) -> Result<chrono::DateTime<chrono::Utc>, AppleCodesignError> {
+        let offset = self.offset();
+        self.consume_word("timestamp");
+        let value = self.parse_string("timestamp")?;
+
+        if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(&value) {
+            Ok(dt.with_timezone(&chrono::Utc))
+        } else if let Ok(dt) =
+            chrono::NaiveDateTime::parse_from_str(&value, "%Y-%m-%d %H:%M:%S UTC")
+        {
+            Ok(chrono::Utc.from_utc_datetime(&dt))
+        } else {
+            Err(AppleCodesignError::RequirementParse(
+                offset,
+                format!("invalid timestamp: {value}"),
+            ))
+        }
+    }
+
+    /// Parse a match expression.
+    ///
+    /// If `dates` is true, values are parsed as timestamps. Timestamps are also
+    /// recognized when values are preceded by the `timestamp` keyword.
+    ///
+    /// An empty match expression is equivalent to `exists`.
+    fn parse_match(
+        &mut self,
+        dates: bool,
+    ) -> Result<CodeRequirementMatchExpression<'static>, AppleCodesignError> {
+        let operator = match self.peek() {
+            Some(RequirementToken::Word(word)) if word == "exists" => {
+                self.index += 1;
+                return Ok(CodeRequirementMatchExpression::Exists);
+            }
+            Some(RequirementToken::Word(word)) if word == "absent" => {
+                self.index += 1;
+                return Ok(CodeRequirementMatchExpression::Absent);
+            }
+            Some(
+                token @ (RequirementToken::Equal
+                | RequirementToken::Tilde
+                | RequirementToken::LessThan
+                | RequirementToken::GreaterThan
+                | RequirementToken::LessThanEqual
+                | RequirementToken::GreaterThanEqual),
+            ) => token.clone(),
+            _ => return Ok(CodeRequirementMatchExpression::Exists),
+        };
+        self.index += 1;
+
+        let dates = dates
+            || matches!(self.peek(), Some(RequirementToken::Word(word)) if word == "timestamp");
+
+        if dates {
+            let value = self.parse_date()?;
+
+            return match operator {
+                RequirementToken::Equal => Ok(CodeRequirementMatchExpression::On(value)),
+                RequirementToken::LessThan => Ok(CodeRequirementMatchExpression::Before(value)),
+                RequirementToken::GreaterThan => Ok(CodeRequirementMatchExpression::After(value)),
+                RequirementToken::LessThanEqual => {
+                    Ok(CodeRequirementMatchExpression::OnOrBefore(value))
+                }
+                RequirementToken::GreaterThanEqual => {
+                    Ok(CodeRequirementMatchExpression::OnOrAfter(value))
+                }
+                _ => Err(self.error("operator not supported for timestamps")),
+            };
+        }
+
+        Ok(match operator {
+            RequirementToken::Equal => {
+                // Wildcards turn equality into prefix, suffix, or substring matching.
+                let leading = self.consume(&RequirementToken::Star);
+                let value = self.parse_value()?;
+                let trailing = self.consume(&RequirementToken::Star);
+
+                match (leading, trailing) {
+                    (false, false) => CodeRequirementMatchExpression::Equal(value),
+                    (false, true) => CodeRequirementMatchExpression::BeginsWith(value),
+                    (true, false) => CodeRequirementMatchExpression::EndsWith(value),
+                    (true, true) => CodeRequirementMatchExpression::Contains(value),
+                }
+            }
+            RequirementToken::Tilde => {
+                CodeRequirementMatchExpression::Contains(self.parse_value()?)
+            }
+            RequirementToken::LessThan => {
+                CodeRequirementMatchExpression::LessThan(self.parse_value()?)
+            }
+            RequirementToken::GreaterThan => {
+                CodeRequirementMatchExpression::GreaterThan(self.parse_value()?)
+            }
+            RequirementToken::LessThanEqual => {
+                CodeRequirementMatchExpression::LessThanEqual(self.parse_value()?)
+            }
+            RequirementToken::GreaterThanEqual => {
+                CodeRequirementMatchExpression::GreaterThanEqual(self.parse_value()?)
+            }
+            _ => unreachable!("operator matched above"),
+        })
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::*;
+    use {
+        super::*,
+        crate::{certificate::CertificateAuthorityExtension, policy::ExecutionPolicy},
+    };
 
     fn verify_roundtrip(reqs: &CodeRequirements, source: &[u8]) {
         let mut dest = Vec::<u8>::new();
@@ -1601,7 +2353,7 @@ mod test {
             els,
             CodeRequirements(vec![CodeRequirementExpression::CertificateGeneric(
                 -1,
-                Oid(&[0x55, 4, 3]),
+                Oid([0x55, 4, 3].as_ref().into()),
                 CodeRequirementMatchExpression::Exists
             )])
         );
@@ -1650,7 +2402,7 @@ mod test {
             els,
             CodeRequirements(vec![CodeRequirementExpression::CertificatePolicy(
                 -1,
-                Oid(&[0x55, 4, 3]),
+                Oid([0x55, 4, 3].as_ref().into()),
                 CodeRequirementMatchExpression::Exists
             )])
         );
@@ -1724,7 +2476,7 @@ mod test {
             els,
             CodeRequirements(vec![CodeRequirementExpression::CertificateFieldDate(
                 -1,
-                Oid(&[0x55, 4, 3]),
+                Oid([0x55, 4, 3].as_ref().into()),
                 CodeRequirementMatchExpression::Exists,
             )])
         );
@@ -2047,4 +2799,363 @@ mod test {
         assert!(data.is_empty());
         verify_roundtrip(&els, &source);
     }
+
+    fn parse_expression(s: &str) -> CodeRequirementExpression<'static> {
+        CodeRequirementExpression::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn language_primitives() {
+        assert_eq!(parse_expression("always"), CodeRequirementExpression::True);
+        assert_eq!(parse_expression("never"), CodeRequirementExpression::False);
+        assert_eq!(
+            parse_expression("identifier com.example.foo"),
+            CodeRequirementExpression::Identifier("com.example.foo".into())
+        );
+        assert_eq!(
+            parse_expression("identifier = \"com.example.foo\""),
+            CodeRequirementExpression::Identifier("com.example.foo".into())
+        );
+        assert_eq!(
+            parse_expression("anchor apple"),
+            CodeRequirementExpression::AnchorApple
+        );
+        assert_eq!(
+            parse_expression("anchor apple generic"),
+            CodeRequirementExpression::AnchorAppleGeneric
+        );
+        assert_eq!(
+            parse_expression("anchor trusted"),
+            CodeRequirementExpression::AnchorTrusted
+        );
+        assert_eq!(
+            parse_expression("anchor = H\"deadbeef\""),
+            CodeRequirementExpression::AnchorCertificateHash(
+                -1,
+                hex::decode("deadbeef").unwrap().into()
+            )
+        );
+        assert_eq!(
+            parse_expression("certificate leaf = H\"deadbeef\""),
+            CodeRequirementExpression::AnchorCertificateHash(
+                0,
+                hex::decode("deadbeef").unwrap().into()
+            )
+        );
+        assert_eq!(
+            parse_expression("cdhash H\"deadbeef\""),
+            CodeRequirementExpression::CodeDirectoryHash(hex::decode("deadbeef").unwrap().into())
+        );
+        assert_eq!(
+            parse_expression("certificate 1 trusted"),
+            CodeRequirementExpression::CertificateTrusted(1)
+        );
+        assert_eq!(
+            parse_expression("certificate leaf[subject.CN] = \"Developer ID Application: Foo\""),
+            CodeRequirementExpression::CertificateField(
+                0,
+                "subject.CN".into(),
+                CodeRequirementMatchExpression::Equal("Developer ID Application: Foo".into())
+            )
+        );
+        assert_eq!(
+            parse_expression("certificate 1[field.1.2.840.113635.100.6.2.6]"),
+            CodeRequirementExpression::CertificateGeneric(
+                1,
+                CertificateAuthorityExtension::DeveloperId.as_oid_cow(),
+                CodeRequirementMatchExpression::Exists
+            )
+        );
+        assert_eq!(
+            parse_expression("certificate root[policy.2.5.4.3] exists"),
+            CodeRequirementExpression::CertificatePolicy(
+                -1,
+                Oid([0x55, 4, 3].as_ref().into()),
+                CodeRequirementMatchExpression::Exists
+            )
+        );
+        assert_eq!(
+            parse_expression("certificate leaf[timestamp.2.5.4.3] < \"2021-03-28T00:13:36Z\""),
+            CodeRequirementExpression::CertificateFieldDate(
+                0,
+                Oid([0x55, 4, 3].as_ref().into()),
+                CodeRequirementMatchExpression::Before(
+                    chrono::Utc.timestamp_opt(1616890416, 0).unwrap()
+                )
+            )
+        );
+        assert_eq!(
+            parse_expression("info[CFBundleVersion] >= 42"),
+            CodeRequirementExpression::InfoPlistKeyField(
+                "CFBundleVersion".into(),
+                CodeRequirementMatchExpression::GreaterThanEqual("42".into())
+            )
+        );
+        assert_eq!(
+            parse_expression("entitlement[\"com.apple.security.get-task-allow\"] absent"),
+            CodeRequirementExpression::EntitlementsKey(
+                "com.apple.security.get-task-allow".into(),
+                CodeRequirementMatchExpression::Absent
+            )
+        );
+        assert_eq!(
+            parse_expression("anchor apple foo"),
+            CodeRequirementExpression::NamedAnchor("foo".into())
+        );
+        assert_eq!(
+            parse_expression("(com.example.foo)"),
+            CodeRequirementExpression::NamedCode("com.example.foo".into())
+        );
+        assert_eq!(
+            parse_expression("platform = 2"),
+            CodeRequirementExpression::Platform(2)
+        );
+        assert_eq!(
+            parse_expression("notarized"),
+            CodeRequirementExpression::Notarized
+        );
+        assert_eq!(
+            parse_expression("legacy"),
+            CodeRequirementExpression::LegacyDeveloperId
+        );
+    }
+
+    #[test]
+    fn language_match_wildcards() {
+        let value = |s: &str| match parse_expression(s) {
+            CodeRequirementExpression::InfoPlistKeyField(_, m) => m,
+            _ => panic!("expected info expression"),
+        };
+
+        assert_eq!(
+            value("info[key] = foo*"),
+            CodeRequirementMatchExpression::BeginsWith("foo".into())
+        );
+        assert_eq!(
+            value("info[key] = *\"foo\""),
+            CodeRequirementMatchExpression::EndsWith("foo".into())
+        );
+        assert_eq!(
+            value("info[key] = *foo*"),
+            CodeRequirementMatchExpression::Contains("foo".into())
+        );
+        assert_eq!(
+            value("info[key] ~ foo"),
+            CodeRequirementMatchExpression::Contains("foo".into())
+        );
+        assert_eq!(
+            value("info[key] = \"foo*\""),
+            CodeRequirementMatchExpression::Equal("foo*".into())
+        );
+        assert_eq!(
+            value("info[key] = H\"00ff\""),
+            CodeRequirementMatchExpression::Equal(CodeRequirementValue::Bytes(
+                vec![0x00, 0xff].into()
+            ))
+        );
+    }
+
+    #[test]
+    fn language_operator_precedence() {
+        assert_eq!(
+            parse_expression("always or never and !always"),
+            CodeRequirementExpression::Or(
+                Box::new(CodeRequirementExpression::True),
+                Box::new(CodeRequirementExpression::And(
+                    Box::new(CodeRequirementExpression::False),
+                    Box::new(CodeRequirementExpression::Not(Box::new(
+                        CodeRequirementExpression::True
+                    )))
+                ))
+            )
+        );
+        assert_eq!(
+            parse_expression("always and never and notarized"),
+            CodeRequirementExpression::And(
+                Box::new(CodeRequirementExpression::And(
+                    Box::new(CodeRequirementExpression::True),
+                    Box::new(CodeRequirementExpression::False)
+                )),
+                Box::new(CodeRequirementExpression::Notarized)
+            )
+        );
+        assert_eq!(
+            parse_expression("(always or never) and /* comment */ notarized // trailing"),
+            CodeRequirementExpression::And(
+                Box::new(CodeRequirementExpression::Or(
+                    Box::new(CodeRequirementExpression::True),
+                    Box::new(CodeRequirementExpression::False)
+                )),
+                Box::new(CodeRequirementExpression::Notarized)
+            )
+        );
+    }
+
+    #[test]
+    fn language_requirements_prefix() {
+        let expected = CodeRequirements(vec![CodeRequirementExpression::Identifier("foo".into())]);
+
+        assert_eq!(
+            CodeRequirements::parse_str("identifier foo").unwrap(),
+            expected
+        );
+        assert_eq!(
+            CodeRequirements::parse_str("=identifier foo").unwrap(),
+            expected
+        );
+        assert_eq!(
+            CodeRequirements::parse_str("designated => identifier foo;").unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn language_errors() {
+        for (s, offset) in [
+            ("", 0),
+            ("identifier", 10),
+            ("identifier \"foo", 11),
+            ("always always", 7),
+            ("(always", 7),
+            ("certificate leaf[field.foo]", 16),
+            ("bogus", 0),
+            ("info[key] = H\"zz\"", 12),
+        ] {
+            match CodeRequirementExpression::from_str(s) {
+                Err(AppleCodesignError::RequirementParse(actual, _)) => {
+                    assert_eq!(actual, offset, "{s}")
+                }
+                res => panic!("expected parse error for {s}; got {res:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn language_roundtrip() {
+        // Every binary expression exercised above should survive rendering and
+        // compiling back to binary. Opcode 5 can't and is covered by
+        // `language_info_key_value_legacy`.
+        let sources = [
+            "0000000100000000",
+            "0000000100000001",
+            "000000010000000200000007666f6f2e62617200",
+            "0000000100000003",
+            "0000000100000004ffffffff00000014deadbeefdeadbeefdeadbeefdeadbeefdeadbeef",
+            "00000001000000060000000100000000",
+            "00000001000000070000000100000000",
+            "000000010000000800000014deadbeefdeadbeefdeadbeefdeadbeefdeadbeef",
+            "000000010000000900000001",
+            "000000010000000a000000036b65790000000000",
+            "000000010000000bffffffff0000000a7375626a6563742e434e000000000000",
+            "000000010000000cffffffff",
+            "000000010000000d",
+            "000000010000000effffffff000000035504030000000000",
+            "000000010000000f",
+            "0000000100000010000000036b65790000000000",
+            "0000000100000011ffffffff000000035504030000000000",
+            "000000010000001200000003666f6f00",
+            "000000010000001300000003666f6f00",
+            "00000001000000140000000a",
+            "0000000100000015",
+            "0000000100000016ffffffff000000035504030000000000",
+            "0000000100000017",
+            "000000010000000a000000036b6579000000000e",
+            "000000010000000a000000036b657900000000010000000576616c7565000000",
+            "000000010000000a000000036b657900000000020000000576616c7565000000",
+            "000000010000000a000000036b657900000000030000000576616c7565000000",
+            "000000010000000a000000036b657900000000040000000576616c7565000000",
+            "000000010000000a000000036b657900000000050000000576616c7565000000",
+            "000000010000000a000000036b657900000000060000000576616c7565000000",
+            "000000010000000a000000036b657900000000070000000576616c7565000000",
+            "000000010000000a000000036b657900000000080000000576616c7565000000",
+            "000000010000000a000000036b6579000000000900000000605fca30",
+            "000000010000000a000000036b6579000000000a00000000605fca30",
+            "000000010000000a000000036b6579000000000b00000000605fca30",
+            "000000010000000a000000036b6579000000000c00000000605fca30",
+            "000000010000000a000000036b6579000000000d00000000605fca30",
+        ];
+
+        for source in sources {
+            let source = hex::decode(source).unwrap();
+            let (els, _) = CodeRequirements::parse_binary(&source).unwrap();
+            let text = els[0].to_string();
+
+            let parsed = CodeRequirements::parse_str(&text).unwrap();
+            assert_eq!(parsed, els, "{text}");
+            verify_roundtrip(&parsed, &source);
+        }
+    }
+
+    #[test]
+    fn language_info_key_value_legacy() {
+        let source =
+            hex::decode("0000000100000005000000036b6579000000000576616c7565000000").unwrap();
+        let (els, _) = CodeRequirements::parse_binary(&source).unwrap();
+        assert_eq!(
+            els,
+            CodeRequirements(vec![CodeRequirementExpression::InfoKeyValueLegacy(
+                "key".into(),
+                "value".into()
+            )])
+        );
+        verify_roundtrip(&els, &source);
+
+        // The language can't express opcode 5. It compiles to the equivalent
+        // Info.plist equality match instead.
+        let text = els[0].to_string();
+        assert_eq!(text, "info[key] = \"value\"");
+        assert_eq!(
+            parse_expression(&text),
+            CodeRequirementExpression::InfoPlistKeyField(
+                "key".into(),
+                CodeRequirementMatchExpression::Equal(CodeRequirementValue::String("value".into())),
+            )
+        );
+    }
+
+    #[test]
+    fn language_roundtrip_escaping() {
+        for expr in [
+            CodeRequirementExpression::Identifier("has \"quotes\" and \\".into()),
+            CodeRequirementExpression::InfoPlistKeyField(
+                "key with spaces".into(),
+                CodeRequirementMatchExpression::BeginsWith("a b".into()),
+            ),
+            CodeRequirementExpression::EntitlementsKey(
+                "key".into(),
+                CodeRequirementMatchExpression::Equal(CodeRequirementValue::Bytes(
+                    vec![0, 1, 2].into(),
+                )),
+            ),
+        ] {
+            assert_eq!(parse_expression(&expr.to_string()), expr);
+        }
+    }
+
+    #[test]
+    fn language_policies() {
+        assert_eq!(
+            &parse_expression(
+                "anchor apple generic and certificate 1[field.1.2.840.113635.100.6.2.6] exists and \
+                (certificate leaf[field.1.2.840.113635.100.6.1.14] or certificate \
+                leaf[field.1.2.840.113635.100.6.1.13])"
+            ),
+            ExecutionPolicy::DeveloperIdSigned.deref()
+        );
+        assert_eq!(
+            &parse_expression(
+                "anchor apple generic and certificate 1[field.1.2.840.113635.100.6.2.6] exists and \
+                certificate leaf[field.1.2.840.113635.100.6.1.13] exists and notarized"
+            ),
+            ExecutionPolicy::DeveloperIdNotarizedExecutable.deref()
+        );
+
+        for policy in [
+            ExecutionPolicy::DeveloperIdSigned,
+            ExecutionPolicy::DeveloperIdNotarizedExecutable,
+            ExecutionPolicy::DeveloperIdNotarizedInstaller,
+        ] {
+            assert_eq!(&parse_expression(&policy.to_string()), policy.deref());
+        }
+    }
 }
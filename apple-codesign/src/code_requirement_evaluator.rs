@@ -0,0 +1,927 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! Evaluation of code requirement expressions.
+
+This module answers the question *does this signature satisfy this code
+requirement?* It is the equivalent of `codesign --verify -R <requirement>`.
+
+A [CodeRequirementEvaluator] holds the facts about signed code that requirement
+expressions can reference: the signing identifier, code directory hashes, the
+CMS certificate chain, entitlements, Info.plist, and notarization status.
+Instances are typically derived from an [EmbeddedSignature] via
+[CodeRequirementEvaluator::from_signature].
+
+# Evaluation Caveats
+
+Some requirement primitives rely on state that only exists on an Apple machine,
+such as the system trust settings consulted by `anchor trusted`. These primitives
+can't be evaluated and always cause evaluation to fail. This is preferable to
+returning a false positive.
+
+The certificate chain is resolved from certificates embedded in the CMS signature
+plus our built-in list of Apple certificates. Certificate signatures in the chain
+are verified during chain resolution. However, certificate validity periods and
+revocation status are not considered.
+*/
+
+use {
+    crate::{
+        apple_certificates::KnownCertificate,
+        certificate::{asn1_time_to_datetime, cms_signer_certificate, AppleCertificate},
+        code_requirement::{
+            CodeRequirementExpression, CodeRequirementMatchExpression, CodeRequirementValue,
+            CodeRequirements,
+        },
+        embedded_signature::{CodeSigningSlot, EmbeddedSignature},
+        error::AppleCodesignError,
+    },
+    bcder::{decode::Constructed, Mode, Oid},
+    std::{borrow::Cow, cmp::Ordering, fmt::Display},
+    x509_certificate::{asn1time::Time, rfc4519, CapturedX509Certificate},
+};
+
+/// Length of a code directory hash as referenced by requirement expressions.
+const CDHASH_LENGTH: usize = 20;
+
+/// Common name of leaf certificates used by Apple to sign its own software.
+const APPLE_SOFTWARE_SIGNING_CN: &str = "Software Signing";
+
+/// X.520 description (2.5.4.13).
+const OID_DESCRIPTION: bcder::ConstOid = Oid(&[85, 4, 13]);
+
+/// X.520 street address (2.5.4.9).
+const OID_STREET_ADDRESS: bcder::ConstOid = Oid(&[85, 4, 9]);
+
+/// RFC 4519 user ID (0.9.2342.19200300.100.1.1).
+const OID_USER_ID: bcder::ConstOid = Oid(&[9, 146, 38, 137, 147, 242, 44, 100, 1, 1]);
+
+/// PKCS #9 email address (1.2.840.113549.1.9.1).
+const OID_EMAIL_ADDRESS: bcder::ConstOid = Oid(&[42, 134, 72, 134, 247, 13, 1, 9, 1]);
+
+/// X.509 certificate policies extension (2.5.29.32).
+const OID_CERTIFICATE_POLICIES: bcder::ConstOid = Oid(&[85, 29, 32]);
+
+/// Describes why a code requirement expression was not satisfied.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CodeRequirementFailure {
+    /// The sub-expression that wasn't satisfied, in the Code Signing Requirement Language.
+    pub expression: String,
+
+    /// Human readable reason why the sub-expression wasn't satisfied.
+    pub reason: String,
+}
+
+impl Display for CodeRequirementFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}: {}", self.expression, self.reason))
+    }
+}
+
+/// The result of evaluating a single expression.
+enum Outcome {
+    Satisfied,
+    Unsatisfied(CodeRequirementFailure),
+    /// The expression couldn't be evaluated. Treated as unsatisfied, even when negated.
+    Indeterminate(CodeRequirementFailure),
+}
+
+impl Outcome {
+    fn unsatisfied(expr: &CodeRequirementExpression, reason: impl ToString) -> Self {
+        Self::Unsatisfied(CodeRequirementFailure {
+            expression: expr.to_string(),
+            reason: reason.to_string(),
+        })
+    }
+
+    fn indeterminate(expr: &CodeRequirementExpression, reason: impl ToString) -> Self {
+        Self::Indeterminate(CodeRequirementFailure {
+            expression: expr.to_string(),
+            reason: reason.to_string(),
+        })
+    }
+
+    fn from_bool(expr: &CodeRequirementExpression, value: bool, reason: impl ToString) -> Self {
+        if value {
+            Self::Satisfied
+        } else {
+            Self::unsatisfied(expr, reason)
+        }
+    }
+}
+
+/// A value that a match expression can be evaluated against.
+///
+/// `None` means the entity doesn't exist. Otherwise, the entity exists and has
+/// zero or more candidate values. An array plist value results in multiple
+/// candidates and the match succeeds if any candidate matches.
+type MatchCandidates<'a> = Option<Vec<Cow<'a, [u8]>>>;
+
+/// Evaluates code requirement expressions against facts about signed code.
+#[derive(Clone, Debug, Default)]
+pub struct CodeRequirementEvaluator {
+    identifier: Option<String>,
+    cdhashes: Vec<Vec<u8>>,
+    platform: Option<u32>,
+    certificates: Vec<CapturedX509Certificate>,
+    entitlements: Option<plist::Dictionary>,
+    info_plist: Option<plist::Dictionary>,
+    notarized: bool,
+}
+
+impl CodeRequirementEvaluator {
+    /// Construct an instance from an embedded signature.
+    ///
+    /// The identifier, code directory hashes, and platform are taken from the code
+    /// directories. The certificate chain is resolved from the CMS signature. The
+    /// signature is considered notarized if it has a notarization ticket slot.
+    ///
+    /// The Info.plist isn't part of the embedded signature and must be registered
+    /// separately via [Self::set_info_plist] if requirements reference it.
+    pub fn from_signature(signature: &EmbeddedSignature) -> Result<Self, AppleCodesignError> {
+        let mut evaluator = Self::default();
+
+        for (slot, cd) in signature.all_code_directories()? {
+            if evaluator.identifier.is_none() {
+                evaluator.identifier = Some(cd.ident.to_string());
+            }
+            if evaluator.platform.is_none() {
+                evaluator.platform = Some(cd.platform as u32);
+            }
+
+            if let Some(entry) = signature.find_slot(slot) {
+                evaluator.cdhashes.push(entry.digest_with(cd.digest_type)?);
+            }
+        }
+
+        if let Some(signed_data) = signature.signed_data()? {
+            let certs = signed_data.certificates().cloned().collect::<Vec<_>>();
+
+            let leaf = signed_data
+                .signers()
+                .find_map(|signer| cms_signer_certificate(&signed_data, signer));
+
+            if let Some(leaf) = leaf {
+                evaluator.set_signing_certificate(leaf, certs.iter());
+            }
+        }
+
+        if let Some(entitlements) = signature.entitlements()? {
+            let value = plist::Value::from_reader_xml(entitlements.as_str().as_bytes())
+                .map_err(AppleCodesignError::PlistParseXml)?;
+
+            if let plist::Value::Dictionary(dict) = value {
+                evaluator.entitlements = Some(dict);
+            }
+        }
+
+        evaluator.notarized = signature.find_slot(CodeSigningSlot::Ticket).is_some();
+
+        Ok(evaluator)
+    }
+
+    /// Set the signing identifier.
+    pub fn set_identifier(&mut self, identifier: impl ToString) {
+        self.identifier = Some(identifier.to_string());
+    }
+
+    /// Register the digest of a code directory.
+    ///
+    /// `cdhash` requirements match against the digest truncated to 20 bytes.
+    pub fn add_code_directory_digest(&mut self, digest: impl Into<Vec<u8>>) {
+        self.cdhashes.push(digest.into());
+    }
+
+    /// Set the platform identifier of the signed code.
+    pub fn set_platform(&mut self, platform: u32) {
+        self.platform = Some(platform);
+    }
+
+    /// Set the certificate chain from the certificate that signed the code.
+    ///
+    /// The issuing chain is resolved from `additional` and our built-in list of
+    /// Apple certificates. The resolved chain ends at the first self-signed
+    /// certificate, which is considered the anchor.
+    pub fn set_signing_certificate<'a>(
+        &mut self,
+        cert: &CapturedX509Certificate,
+        additional: impl Iterator<Item = &'a CapturedX509Certificate>,
+    ) {
+        let candidates = additional
+            .chain(KnownCertificate::all().iter().copied())
+            .collect::<Vec<_>>();
+
+        let mut chain = vec![cert.clone()];
+
+        if !cert.subject_is_issuer() {
+            for issuer in cert.resolve_signing_chain(candidates.into_iter()) {
+                chain.push(issuer.clone());
+
+                if issuer.subject_is_issuer() {
+                    break;
+                }
+            }
+        }
+
+        self.certificates = chain;
+    }
+
+    /// Obtain the resolved certificate chain, starting at the leaf certificate.
+    pub fn certificates(&self) -> &[CapturedX509Certificate] {
+        &self.certificates
+    }
+
+    /// Set the entitlements of the signed code.
+    pub fn set_entitlements(&mut self, entitlements: plist::Dictionary) {
+        self.entitlements = Some(entitlements);
+    }
+
+    /// Set the Info.plist of the signed code.
+    pub fn set_info_plist(&mut self, info_plist: plist::Dictionary) {
+        self.info_plist = Some(info_plist);
+    }
+
+    /// Set the Info.plist of the signed code from plist data.
+    ///
+    /// Both XML and binary plists are accepted.
+    pub fn set_info_plist_data(&mut self, data: &[u8]) -> Result<(), AppleCodesignError> {
+        let value = plist::Value::from_reader(std::io::Cursor::new(data))
+            .map_err(AppleCodesignError::PlistParseXml)?;
+
+        if let plist::Value::Dictionary(dict) = value {
+            self.info_plist = Some(dict);
+        }
+
+        Ok(())
+    }
+
+    /// Set whether the signed code is notarized.
+    pub fn set_notarized(&mut self, notarized: bool) {
+        self.notarized = notarized;
+    }
+
+    /// Evaluate an expression.
+    ///
+    /// Returns `Ok(())` if the expression is satisfied. Otherwise returns the
+    /// failing sub-expression and the reason it failed.
+    pub fn evaluate(&self, expr: &CodeRequirementExpression) -> Result<(), CodeRequirementFailure> {
+        match self.evaluate_expression(expr) {
+            Outcome::Satisfied => Ok(()),
+            Outcome::Unsatisfied(failure) | Outcome::Indeterminate(failure) => Err(failure),
+        }
+    }
+
+    /// Evaluate all expressions in a [CodeRequirements].
+    ///
+    /// All expressions must be satisfied. The first failure is returned.
+    pub fn evaluate_requirements(
+        &self,
+        requirements: &CodeRequirements,
+    ) -> Result<(), CodeRequirementFailure> {
+        for expr in requirements.iter() {
+            self.evaluate(expr)?;
+        }
+
+        Ok(())
+    }
+
+    fn evaluate_expression(&self, expr: &CodeRequirementExpression) -> Outcome {
+        match expr {
+            CodeRequirementExpression::False => Outcome::unsatisfied(expr, "never satisfied"),
+            CodeRequirementExpression::True => Outcome::Satisfied,
+            CodeRequirementExpression::Identifier(value) => match &self.identifier {
+                Some(identifier) => Outcome::from_bool(
+                    expr,
+                    identifier == value,
+                    format!("identifier is {identifier}"),
+                ),
+                None => Outcome::unsatisfied(expr, "no identifier"),
+            },
+            CodeRequirementExpression::AnchorApple => {
+                let anchored = self.anchor_is_apple_root();
+                let leaf_cn = self
+                    .certificates
+                    .first()
+                    .and_then(|cert| cert.subject_common_name());
+
+                if !anchored {
+                    Outcome::unsatisfied(expr, "certificate chain does not anchor to an Apple root")
+                } else if leaf_cn.as_deref() != Some(APPLE_SOFTWARE_SIGNING_CN) {
+                    Outcome::unsatisfied(expr, "code is not signed by Apple itself")
+                } else {
+                    Outcome::Satisfied
+                }
+            }
+            CodeRequirementExpression::AnchorAppleGeneric => Outcome::from_bool(
+                expr,
+                self.anchor_is_apple_root(),
+                "certificate chain does not anchor to an Apple root",
+            ),
+            CodeRequirementExpression::AnchorCertificateHash(slot, digest) => {
+                match self.certificate(*slot) {
+                    Some(cert) => {
+                        // Digest the captured encoding. Re-encoding certificates parsed
+                        // from BER isn't always possible.
+                        let actual = ring::digest::digest(
+                            &ring::digest::SHA1_FOR_LEGACY_USE_ONLY,
+                            cert.constructed_data(),
+                        );
+
+                        Outcome::from_bool(
+                            expr,
+                            actual.as_ref() == digest.as_ref(),
+                            format!(
+                                "certificate SHA-1 fingerprint is {}",
+                                hex::encode(actual.as_ref())
+                            ),
+                        )
+                    }
+                    None => Outcome::unsatisfied(expr, "no certificate in slot"),
+                }
+            }
+            CodeRequirementExpression::InfoKeyValueLegacy(key, value) => {
+                let candidates = self
+                    .info_plist
+                    .as_ref()
+                    .and_then(|dict| dict.get(key.as_ref()))
+                    .map(plist_match_candidates);
+
+                Outcome::from_bool(
+                    expr,
+                    match_candidates(
+                        &CodeRequirementMatchExpression::Equal(CodeRequirementValue::String(
+                            value.clone(),
+                        )),
+                        &candidates,
+                    ),
+                    "Info.plist value does not match",
+                )
+            }
+            CodeRequirementExpression::And(a, b) => match self.evaluate_expression(a) {
+                Outcome::Satisfied => self.evaluate_expression(b),
+                outcome => outcome,
+            },
+            CodeRequirementExpression::Or(a, b) => {
+                match (self.evaluate_expression(a), self.evaluate_expression(b)) {
+                    (Outcome::Satisfied, _) | (_, Outcome::Satisfied) => Outcome::Satisfied,
+                    (Outcome::Unsatisfied(_), Outcome::Unsatisfied(_)) => {
+                        Outcome::unsatisfied(expr, "neither alternative is satisfied")
+                    }
+                    (Outcome::Indeterminate(failure), _) | (_, Outcome::Indeterminate(failure)) => {
+                        Outcome::Indeterminate(failure)
+                    }
+                }
+            }
+            CodeRequirementExpression::CodeDirectoryHash(digest) => Outcome::from_bool(
+                expr,
+                self.cdhashes.iter().any(|actual| {
+                    actual.as_slice() == digest.as_ref()
+                        || (actual.len() >= CDHASH_LENGTH
+                            && &actual[0..CDHASH_LENGTH] == digest.as_ref())
+                }),
+                "no code directory has this digest",
+            ),
+            CodeRequirementExpression::Not(inner) => match self.evaluate_expression(inner) {
+                Outcome::Satisfied => Outcome::unsatisfied(expr, "negated expression is satisfied"),
+                Outcome::Unsatisfied(_) => Outcome::Satisfied,
+                outcome => outcome,
+            },
+            CodeRequirementExpression::InfoPlistKeyField(key, m) => {
+                let candidates = self
+                    .info_plist
+                    .as_ref()
+                    .and_then(|dict| dict.get(key.as_ref()))
+                    .map(plist_match_candidates);
+
+                self.evaluate_match(expr, m, &candidates, "Info.plist value")
+            }
+            CodeRequirementExpression::CertificateField(slot, field, m) => {
+                let cert = match self.certificate(*slot) {
+                    Some(cert) => cert,
+                    None => return Outcome::unsatisfied(expr, "no certificate in slot"),
+                };
+
+                match certificate_field_candidates(cert, field) {
+                    Some(candidates) => {
+                        self.evaluate_match(expr, m, &candidates, "certificate field")
+                    }
+                    None => Outcome::indeterminate(
+                        expr,
+                        format!("unsupported certificate field: {field}"),
+                    ),
+                }
+            }
+            CodeRequirementExpression::CertificateGeneric(slot, oid, m) => {
+                let cert = match self.certificate(*slot) {
+                    Some(cert) => cert,
+                    None => return Outcome::unsatisfied(expr, "no certificate in slot"),
+                };
+
+                // Extensions are matched by their raw value.
+                let candidates = cert
+                    .iter_extensions()
+                    .find(|ext| ext.id.as_ref() == oid.as_ref())
+                    .map(|ext| vec![Cow::Owned(ext.value.to_bytes().to_vec())]);
+
+                self.evaluate_match(expr, m, &candidates, "certificate extension")
+            }
+            CodeRequirementExpression::CertificatePolicy(slot, oid, m) => {
+                let cert = match self.certificate(*slot) {
+                    Some(cert) => cert,
+                    None => return Outcome::unsatisfied(expr, "no certificate in slot"),
+                };
+
+                let candidates = if certificate_policies(cert)
+                    .iter()
+                    .any(|policy| policy.as_ref() == oid.as_ref())
+                {
+                    Some(vec![])
+                } else {
+                    None
+                };
+
+                self.evaluate_match(expr, m, &candidates, "certificate policy")
+            }
+            CodeRequirementExpression::CertificateFieldDate(slot, oid, m) => {
+                let cert = match self.certificate(*slot) {
+                    Some(cert) => cert,
+                    None => return Outcome::unsatisfied(expr, "no certificate in slot"),
+                };
+
+                let candidates = cert
+                    .iter_extensions()
+                    .find(|ext| ext.id.as_ref() == oid.as_ref())
+                    .map(|ext| {
+                        Constructed::decode(ext.value.to_bytes(), Mode::Der, Time::take_from)
+                            .ok()
+                            .map(|time| {
+                                Cow::Owned(asn1_time_to_datetime(&time).to_rfc3339().into_bytes())
+                            })
+                            .into_iter()
+                            .collect::<Vec<_>>()
+                    });
+
+                self.evaluate_match(expr, m, &candidates, "certificate date field")
+            }
+            CodeRequirementExpression::EntitlementsKey(key, m) => {
+                let candidates = self
+                    .entitlements
+                    .as_ref()
+                    .and_then(|dict| dict.get(key.as_ref()))
+                    .map(plist_match_candidates);
+
+                self.evaluate_match(expr, m, &candidates, "entitlement value")
+            }
+            CodeRequirementExpression::Platform(platform) => match self.platform {
+                Some(actual) => {
+                    Outcome::from_bool(expr, actual == *platform, format!("platform is {actual}"))
+                }
+                None => Outcome::unsatisfied(expr, "no platform"),
+            },
+            CodeRequirementExpression::Notarized => {
+                Outcome::from_bool(expr, self.notarized, "no notarization ticket found")
+            }
+            CodeRequirementExpression::CertificateTrusted(_)
+            | CodeRequirementExpression::AnchorTrusted => {
+                Outcome::indeterminate(expr, "system trust settings are not available")
+            }
+            CodeRequirementExpression::NamedAnchor(_)
+            | CodeRequirementExpression::NamedCode(_)
+            | CodeRequirementExpression::LegacyDeveloperId => {
+                Outcome::indeterminate(expr, "expression cannot be evaluated by this crate")
+            }
+        }
+    }
+
+    fn evaluate_match(
+        &self,
+        expr: &CodeRequirementExpression,
+        m: &CodeRequirementMatchExpression,
+        candidates: &MatchCandidates,
+        entity: &str,
+    ) -> Outcome {
+        if match_candidates(m, candidates) {
+            Outcome::Satisfied
+        } else if candidates.is_some() {
+            Outcome::unsatisfied(expr, format!("{entity} does not match"))
+        } else {
+            Outcome::unsatisfied(expr, format!("{entity} does not exist"))
+        }
+    }
+
+    /// Resolve the certificate in a requirement slot.
+    ///
+    /// Non-negative slots count from the leaf. Negative slots count from the anchor.
+    fn certificate(&self, slot: i32) -> Option<&CapturedX509Certificate> {
+        let index = if slot >= 0 {
+            slot as usize
+        } else {
+            self.certificates
+                .len()
+                .checked_sub(slot.unsigned_abs() as usize)?
+        };
+
+        self.certificates.get(index)
+    }
+
+    fn anchor_is_apple_root(&self) -> bool {
+        self.certificates.len() > 1
+            && self
+                .certificates
+                .last()
+                .map(|cert| cert.is_apple_root_ca())
+                .unwrap_or_default()
+    }
+}
+
+/// Convert a plist value to match candidates.
+fn plist_match_candidates(value: &plist::Value) -> Vec<Cow<'static, [u8]>> {
+    match value {
+        plist::Value::Array(values) => values
+            .iter()
+            .filter(|v| !matches!(v, plist::Value::Array(_)))
+            .flat_map(plist_match_candidates)
+            .collect(),
+        plist::Value::Boolean(v) => vec![Cow::Borrowed(if *v {
+            b"true".as_ref()
+        } else {
+            b"false".as_ref()
+        })],
+        plist::Value::Data(v) => vec![Cow::Owned(v.clone())],
+        plist::Value::Date(v) => vec![Cow::Owned(v.to_xml_format().into_bytes())],
+        plist::Value::Integer(v) => vec![Cow::Owned(v.to_string().into_bytes())],
+        plist::Value::Real(v) => vec![Cow::Owned(v.to_string().into_bytes())],
+        plist::Value::String(v) => vec![Cow::Owned(v.clone().into_bytes())],
+        _ => vec![],
+    }
+}
+
+/// Resolve match candidates for a `subject.*` or `issuer.*` certificate field.
+///
+/// Returns `None` if the field isn't known.
+fn certificate_field_candidates(
+    cert: &CapturedX509Certificate,
+    field: &str,
+) -> Option<MatchCandidates<'static>> {
+    let (name, attribute) = field.split_once('.')?;
+
+    let name = match name {
+        "subject" => cert.subject_name(),
+        "issuer" => cert.issuer_name(),
+        _ => return None,
+    };
+
+    let oid = match attribute {
+        "C" => rfc4519::OID_COUNTRY_NAME,
+        "CN" => rfc4519::OID_COMMON_NAME,
+        "D" => OID_DESCRIPTION,
+        "L" => rfc4519::OID_LOCALITY_NAME,
+        "O" => rfc4519::OID_ORGANIZATION_NAME,
+        "OU" => rfc4519::OID_ORGANIZATIONAL_UNIT_NAME,
+        "ST" => rfc4519::OID_STATE_PROVINCE_NAME,
+        "STREET" => OID_STREET_ADDRESS,
+        "UID" => OID_USER_ID,
+        "email" => OID_EMAIL_ADDRESS,
+        _ => return None,
+    };
+
+    let values = name
+        .iter_by_oid(Oid(oid.0.into()))
+        .filter_map(|atv| atv.to_string().ok())
+        .map(|s| Cow::Owned(s.into_bytes()))
+        .collect::<Vec<_>>();
+
+    Some(if values.is_empty() {
+        None
+    } else {
+        Some(values)
+    })
+}
+
+/// Obtain the policy OIDs in a certificate's certificate policies extension.
+fn certificate_policies(cert: &CapturedX509Certificate) -> Vec<Oid> {
+    let mut policies = vec![];
+
+    for ext in cert.iter_extensions() {
+        if ext.id.as_ref() != OID_CERTIFICATE_POLICIES.as_ref() {
+            continue;
+        }
+
+        // CertificatePolicies ::= SEQUENCE OF PolicyInformation
+        // PolicyInformation ::= SEQUENCE { policyIdentifier, policyQualifiers OPTIONAL }
+        let _ = Constructed::decode(ext.value.to_bytes(), Mode::Der, |cons| {
+            cons.take_sequence(|cons| {
+                while let Some(oid) = cons.take_opt_sequence(|cons| {
+                    let oid = Oid::take_from(cons)?;
+                    cons.skip_all()?;
+
+                    Ok(oid)
+                })? {
+                    policies.push(oid);
+                }
+
+                Ok(())
+            })
+        });
+    }
+
+    policies
+}
+
+/// Evaluate a match expression against candidate values.
+fn match_candidates(m: &CodeRequirementMatchExpression, candidates: &MatchCandidates) -> bool {
+    let values = match (m, candidates) {
+        (CodeRequirementMatchExpression::Exists, candidates) => return candidates.is_some(),
+        (CodeRequirementMatchExpression::Absent, candidates) => return candidates.is_none(),
+        (_, None) => return false,
+        (_, Some(values)) => values,
+    };
+
+    values.iter().any(|value| match_value(m, value))
+}
+
+fn match_value(m: &CodeRequirementMatchExpression, value: &[u8]) -> bool {
+    let expected = |v: &CodeRequirementValue| -> Vec<u8> {
+        match v {
+            CodeRequirementValue::String(s) => s.as_bytes().to_vec(),
+            CodeRequirementValue::Bytes(b) => b.to_vec(),
+        }
+    };
+
+    let compare = |v: &CodeRequirementValue| -> Ordering {
+        compare_numerically(
+            &String::from_utf8_lossy(value),
+            &String::from_utf8_lossy(&expected(v)),
+        )
+    };
+
+    let date = || -> Option<chrono::DateTime<chrono::Utc>> {
+        chrono::DateTime::parse_from_rfc3339(std::str::from_utf8(value).ok()?)
+            .ok()
+            .map(|dt| dt.with_timezone(&chrono::Utc))
+    };
+
+    match m {
+        CodeRequirementMatchExpression::Exists => true,
+        CodeRequirementMatchExpression::Absent => false,
+        CodeRequirementMatchExpression::Equal(v) => value == expected(v),
+        CodeRequirementMatchExpression::Contains(v) => {
+            let needle = expected(v);
+            needle.is_empty() || value.windows(needle.len()).any(|w| w == needle)
+        }
+        CodeRequirementMatchExpression::BeginsWith(v) => value.starts_with(&expected(v)),
+        CodeRequirementMatchExpression::EndsWith(v) => value.ends_with(&expected(v)),
+        CodeRequirementMatchExpression::LessThan(v) => compare(v) == Ordering::Less,
+        CodeRequirementMatchExpression::GreaterThan(v) => compare(v) == Ordering::Greater,
+        CodeRequirementMatchExpression::LessThanEqual(v) => compare(v) != Ordering::Greater,
+        CodeRequirementMatchExpression::GreaterThanEqual(v) => compare(v) != Ordering::Less,
+        CodeRequirementMatchExpression::On(t) => date().map(|d| d == *t).unwrap_or_default(),
+        CodeRequirementMatchExpression::Before(t) => date().map(|d| d < *t).unwrap_or_default(),
+        CodeRequirementMatchExpression::After(t) => date().map(|d| d > *t).unwrap_or_default(),
+        CodeRequirementMatchExpression::OnOrBefore(t) => {
+            date().map(|d| d <= *t).unwrap_or_default()
+        }
+        CodeRequirementMatchExpression::OnOrAfter(t) => date().map(|d| d >= *t).unwrap_or_default(),
+    }
+}
+
+/// Compare strings, treating runs of digits as numbers.
+///
+/// This mirrors the numeric comparison Apple uses for ordering operators, so
+/// `"10.2" > "9.1"`.
+fn compare_numerically(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let take_number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.peek().copied().filter(char::is_ascii_digit) {
+                        digits.push(c);
+                        chars.next();
+                    }
+
+                    digits.trim_start_matches('0').to_string()
+                };
+
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+
+                match x.len().cmp(&y.len()).then_with(|| x.cmp(&y)) {
+                    Ordering::Equal => {}
+                    ordering => return ordering,
+                }
+            }
+            (Some(x), Some(y)) => match x.cmp(&y) {
+                Ordering::Equal => {
+                    a.next();
+                    b.next();
+                }
+                ordering => return ordering,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{certificate::CertificateProfile, policy::ExecutionPolicy},
+        std::str::FromStr,
+        x509_certificate::KeyAlgorithm,
+    };
+
+    fn developer_id_evaluator() -> CodeRequirementEvaluator {
+        let cert = CapturedX509Certificate::from_der(
+            include_bytes!("testdata/apple-signed-developer-id-application.cer").to_vec(),
+        )
+        .unwrap();
+
+        let mut evaluator = CodeRequirementEvaluator::default();
+        evaluator.set_identifier("com.example.app");
+        evaluator.set_signing_certificate(&cert, std::iter::empty());
+
+        evaluator
+    }
+
+    fn evaluate(
+        evaluator: &CodeRequirementEvaluator,
+        s: &str,
+    ) -> Result<(), CodeRequirementFailure> {
+        evaluator.evaluate(&CodeRequirementExpression::from_str(s).unwrap())
+    }
+
+    #[test]
+    fn compare_numbers() {
+        assert_eq!(compare_numerically("10.2", "9.1"), Ordering::Greater);
+        assert_eq!(compare_numerically("1.02", "1.2"), Ordering::Equal);
+        assert_eq!(compare_numerically("abc", "abd"), Ordering::Less);
+        assert_eq!(compare_numerically("1.2", "1.2.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn certificate_chain() {
+        let evaluator = developer_id_evaluator();
+
+        assert_eq!(evaluator.certificates().len(), 3);
+        assert!(evaluator.anchor_is_apple_root());
+        assert_eq!(evaluator.certificate(0), evaluator.certificates().first());
+        assert_eq!(evaluator.certificate(-1), evaluator.certificates().last());
+        assert!(evaluator.certificate(3).is_none());
+        assert!(evaluator.certificate(-4).is_none());
+    }
+
+    #[test]
+    fn developer_id() {
+        let mut evaluator = developer_id_evaluator();
+
+        evaluate(&evaluator, "anchor apple generic").unwrap();
+        evaluate(&evaluator, "identifier \"com.example.app\"").unwrap();
+        evaluate(&evaluator, "certificate leaf[subject.OU] = MK22MZP987").unwrap();
+        evaluate(
+            &evaluator,
+            "certificate leaf[subject.CN] = \"Developer ID Application\"*",
+        )
+        .unwrap();
+        evaluate(
+            &evaluator,
+            "certificate 1[field.1.2.840.113635.100.6.2.6] exists",
+        )
+        .unwrap();
+        evaluator
+            .evaluate(&ExecutionPolicy::DeveloperIdSigned)
+            .unwrap();
+
+        let failure = evaluate(&evaluator, "anchor apple").unwrap_err();
+        assert_eq!(failure.expression, "anchor apple");
+
+        let failure = evaluate(
+            &evaluator,
+            "anchor apple generic and identifier \"com.example.other\"",
+        )
+        .unwrap_err();
+        assert_eq!(failure.expression, "identifier \"com.example.other\"");
+        assert_eq!(failure.reason, "identifier is com.example.app");
+
+        let failure = evaluator
+            .evaluate(&ExecutionPolicy::DeveloperIdNotarizedExecutable)
+            .unwrap_err();
+        assert_eq!(failure.expression, "notarized");
+
+        evaluator.set_notarized(true);
+        evaluator
+            .evaluate(&ExecutionPolicy::DeveloperIdNotarizedExecutable)
+            .unwrap();
+    }
+
+    #[test]
+    fn self_signed() {
+        let (cert, _, _) = crate::create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::DeveloperIdApplication,
+            "team1",
+            "Joe Developer",
+            "US",
+            chrono::Duration::hours(1),
+        )
+        .unwrap();
+
+        let mut evaluator = CodeRequirementEvaluator::default();
+        evaluator.set_signing_certificate(&cert, std::iter::empty());
+
+        assert_eq!(evaluator.certificates().len(), 1);
+        assert!(evaluate(&evaluator, "anchor apple generic").is_err());
+        assert!(evaluator
+            .evaluate(&ExecutionPolicy::DeveloperIdSigned)
+            .is_err());
+        evaluate(&evaluator, "certificate leaf[subject.OU] = team1").unwrap();
+        evaluate(
+            &evaluator,
+            &format!(
+                "anchor = H\"{}\"",
+                hex::encode(cert.sha1_fingerprint().unwrap().as_ref())
+            ),
+        )
+        .unwrap();
+        evaluate(&evaluator, "certificate leaf[subject.CN] ~ Developer").unwrap();
+    }
+
+    #[test]
+    fn plists() {
+        let mut evaluator = CodeRequirementEvaluator::default();
+
+        let mut entitlements = plist::Dictionary::new();
+        entitlements.insert("com.apple.security.app-sandbox".into(), true.into());
+        entitlements.insert(
+            "com.apple.security.application-groups".into(),
+            plist::Value::Array(vec!["group.a".into(), "group.b".into()]),
+        );
+        evaluator.set_entitlements(entitlements);
+
+        evaluator
+            .set_info_plist_data(
+                br#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+<key>CFBundleShortVersionString</key><string>10.2</string>
+</dict></plist>"#,
+            )
+            .unwrap();
+
+        evaluate(
+            &evaluator,
+            "entitlement[\"com.apple.security.app-sandbox\"] = true",
+        )
+        .unwrap();
+        evaluate(
+            &evaluator,
+            "entitlement[\"com.apple.security.application-groups\"] = group.b",
+        )
+        .unwrap();
+        evaluate(
+            &evaluator,
+            "entitlement[\"com.apple.security.cs.debugger\"] absent",
+        )
+        .unwrap();
+        evaluate(&evaluator, "info[CFBundleShortVersionString] >= 9.1").unwrap();
+        evaluate(&evaluator, "info[CFBundleShortVersionString] < 10.10").unwrap();
+        evaluate(&evaluator, "!info[CFBundleIdentifier] exists").unwrap();
+
+        let failure = evaluate(&evaluator, "info[CFBundleIdentifier] = foo").unwrap_err();
+        assert_eq!(failure.reason, "Info.plist value does not exist");
+    }
+
+    #[test]
+    fn indeterminate() {
+        let evaluator = developer_id_evaluator();
+
+        assert!(evaluate(&evaluator, "anchor trusted").is_err());
+        // Negating an expression we can't evaluate must not satisfy.
+        assert!(evaluate(&evaluator, "!anchor trusted").is_err());
+        evaluate(&evaluator, "anchor trusted or anchor apple generic").unwrap();
+        assert!(evaluate(&evaluator, "anchor trusted or anchor apple").is_err());
+    }
+
+    #[test]
+    fn cdhash() {
+        let mut evaluator = CodeRequirementEvaluator::default();
+        evaluator.add_code_directory_digest(vec![0x42; 32]);
+
+        evaluate(
+            &evaluator,
+            &format!("cdhash H\"{}\"", hex::encode([0x42; 20])),
+        )
+        .unwrap();
+        assert!(evaluate(
+            &evaluator,
+            &format!("cdhash H\"{}\"", hex::encode([0x41; 20]))
+        )
+        .is_err());
+    }
+}
@@ -397,6 +397,16 @@ impl TryFrom<&Value> for Rules2Value {
     type Error = AppleCodesignError;
 
     fn try_from(v: &Value) -> Result<Self, Self::Error> {
+        // A rule without any attributes is serialized as `<true/>`.
+        if let Value::Boolean(true) = v {
+            return Ok(Self {
+                nested: None,
+                omit: None,
+                optional: None,
+                weight: None,
+            });
+        }
+
         let dict = v.as_dictionary().ok_or_else(|| {
             AppleCodesignError::ResourcesPlistParse("rules2 value should be a dict".to_string())
         })?;
@@ -607,6 +617,87 @@ pub enum FilesFlavor {
     Rules2WithSha1,
 }
 
+/// A resource sealed in a `CodeResources` file.
+///
+/// This is a normalized view of entries in the `<files>` and `<files2>`
+/// sections.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SealedResource {
+    /// A regular file.
+    RegularFile {
+        /// Digests of the file content.
+        ///
+        /// `<files>` entries only have SHA-1 digests. `<files2>` entries
+        /// can have both SHA-1 and SHA-256 digests.
+        digests: Vec<(DigestType, Vec<u8>)>,
+        /// Whether the file is optional.
+        optional: bool,
+    },
+    /// A symlink and the path it points to.
+    Symlink(String),
+    /// Nested code identified by the digest of its code directory.
+    Code {
+        /// The code directory hash (cdhash) of the nested code.
+        cdhash: Vec<u8>,
+        /// The designated requirement of the nested code.
+        requirement: Option<String>,
+        /// Whether the nested code is optional.
+        optional: bool,
+    },
+}
+
+impl SealedResource {
+    /// Whether this resource is allowed to not exist.
+    pub fn is_optional(&self) -> bool {
+        match self {
+            Self::RegularFile { optional, .. } | Self::Code { optional, .. } => *optional,
+            Self::Symlink(_) => false,
+        }
+    }
+}
+
+impl From<&FilesValue> for SealedResource {
+    fn from(v: &FilesValue) -> Self {
+        match v {
+            FilesValue::Required(digest) => Self::RegularFile {
+                digests: vec![(DigestType::Sha1, digest.clone())],
+                optional: false,
+            },
+            FilesValue::Optional(digest) => Self::RegularFile {
+                digests: vec![(DigestType::Sha1, digest.clone())],
+                optional: true,
+            },
+        }
+    }
+}
+
+impl From<&Files2Value> for SealedResource {
+    fn from(v: &Files2Value) -> Self {
+        let optional = v.optional.unwrap_or_default();
+
+        if let Some(target) = &v.symlink {
+            Self::Symlink(target.clone())
+        } else if let Some(cdhash) = &v.cdhash {
+            Self::Code {
+                cdhash: cdhash.clone(),
+                requirement: v.requirement.clone(),
+                optional,
+            }
+        } else {
+            let mut digests = vec![];
+
+            if let Some(hash2) = &v.hash2 {
+                digests.push((DigestType::Sha256, hash2.clone()));
+            }
+            if let Some(hash) = &v.hash {
+                digests.push((DigestType::Sha1, hash.clone()));
+            }
+
+            Self::RegularFile { digests, optional }
+        }
+    }
+}
+
 /// Represents a `_CodeSignature/CodeResources` XML plist.
 ///
 /// This file/type represents a collection of file-based resources whose
@@ -697,6 +788,67 @@ impl CodeResources {
         })
     }
 
+    /// Obtain the resources sealed in the `<files>` section, keyed by path.
+    pub fn sealed_files(&self) -> BTreeMap<String, SealedResource> {
+        self.files
+            .iter()
+            .map(|(path, value)| (path.clone(), SealedResource::from(value)))
+            .collect()
+    }
+
+    /// Obtain the resources sealed in the `<files2>` section, keyed by path.
+    pub fn sealed_files2(&self) -> BTreeMap<String, SealedResource> {
+        self.files2
+            .iter()
+            .map(|(path, value)| (path.clone(), SealedResource::from(value)))
+            .collect()
+    }
+
+    /// Obtain the rules in the `<rules>` section.
+    ///
+    /// Rules are sorted by decreasing priority.
+    pub fn rules(&self) -> Result<Vec<CodeResourcesRule>, AppleCodesignError> {
+        let mut rules = self
+            .rules
+            .iter()
+            .map(|(pattern, value)| {
+                let mut rule = CodeResourcesRule::new(pattern)?;
+                rule.omit = value.omit;
+                rule.optional = !value.required;
+                rule.weight = value.weight.map(|x| x as u32);
+
+                Ok(rule)
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()?;
+
+        rules.sort();
+
+        Ok(rules)
+    }
+
+    /// Obtain the rules in the `<rules2>` section.
+    ///
+    /// Rules are sorted by decreasing priority.
+    pub fn rules2(&self) -> Result<Vec<CodeResourcesRule>, AppleCodesignError> {
+        let mut rules = self
+            .rules2
+            .iter()
+            .map(|(pattern, value)| {
+                let mut rule = CodeResourcesRule::new(pattern)?;
+                rule.nested = value.nested.unwrap_or_default();
+                rule.omit = value.omit.unwrap_or_default();
+                rule.optional = value.optional.unwrap_or_default();
+                rule.weight = value.weight.map(|x| x as u32);
+
+                Ok(rule)
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()?;
+
+        rules.sort();
+
+        Ok(rules)
+    }
+
     /// Serialize an instance to XML.
     pub fn to_writer_xml(&self, mut writer: impl Write) -> Result<(), AppleCodesignError> {
         let value = Value::from(self);
@@ -906,7 +1058,7 @@ impl From<&CodeResources> for Value {
 }
 
 #[derive(Clone, Debug)]
-enum RulesEvaluation {
+pub(crate) enum RulesEvaluation {
     /// File should be ignored completely.
     Exclude,
 
@@ -1138,7 +1290,7 @@ impl CodeResourcesBuilder {
         }
     }
 
-    fn evaluate_rules(
+    pub(crate) fn evaluate_rules(
         rules: &[CodeResourcesRule],
         relative_path: impl AsRef<Path>,
         symlink_target: Option<PathBuf>,
@@ -1288,6 +1440,30 @@ impl CodeResourcesBuilder {
         self.process_file_rules(file)
     }
 
+    /// Whether a file will be sealed as nested code by [Self::process_file].
+    ///
+    /// Files for which this returns true will be signed via
+    /// [BundleFileHandler::sign_and_install_macho] when processed.
+    pub fn is_nested_code(&self, file: &DirectoryBundleFile) -> Result<bool, AppleCodesignError> {
+        Ok(matches!(
+            self.evaluate_file(file)?,
+            RulesEvaluation::SealNested(..)
+        ))
+    }
+
+    /// Evaluate how [Self::process_file] will handle a file.
+    pub(crate) fn evaluate_file(
+        &self,
+        file: &DirectoryBundleFile,
+    ) -> Result<RulesEvaluation, AppleCodesignError> {
+        Self::evaluate_rules(
+            &self.rules2,
+            file.relative_path(),
+            file.symlink_target()
+                .map_err(AppleCodesignError::DirectoryBundle)?,
+        )
+    }
+
     /// Process a nested bundle for inclusion in resource handling.
     ///
     /// This will attempt to seal the main digest of the bundle into this resources file.
@@ -1453,4 +1629,62 @@ mod tests {
 
         assert_eq!(resources, resources2);
     }
+
+    #[test]
+    fn sealed_resources() {
+        let resources = CodeResources::from_xml(FIREFOX_SNIPPET.as_bytes()).unwrap();
+
+        let files = resources.sealed_files();
+        assert_eq!(files.len(), 3);
+        assert!(files["Resources/en.lproj/InfoPlist.strings"].is_optional());
+
+        let files2 = resources.sealed_files2();
+        assert!(matches!(
+            &files2["Library/LaunchServices/org.mozilla.updater"],
+            SealedResource::RegularFile { digests, optional: false }
+                if digests.len() == 1 && digests[0].0 == DigestType::Sha256
+        ));
+        assert!(matches!(
+            &files2["MacOS/XUL"],
+            SealedResource::Code {
+                requirement: Some(_),
+                ..
+            }
+        ));
+        assert_eq!(
+            files2["MacOS/SafariForWebKitDevelopment"],
+            SealedResource::Symlink(
+                "/Library/Application Support/Apple/Safari/SafariForWebKitDevelopment".into()
+            )
+        );
+
+        let rules2 = resources.rules2().unwrap();
+        assert_eq!(rules2.len(), 4);
+        assert_eq!(rules2[0].pattern, "^(.*/)?\\.DS_Store$");
+        assert!(rules2[0].omit);
+    }
+
+    #[test]
+    fn parse_default_rules() {
+        let builder = CodeResourcesBuilder::default_resources_rules().unwrap();
+
+        let mut buffer = Vec::<u8>::new();
+        builder.write_code_resources(&mut buffer).unwrap();
+
+        let resources = CodeResources::from_xml(&buffer).unwrap();
+        assert_eq!(resources, builder.resources);
+    }
+
+    #[test]
+    fn nested_bundle_rules() {
+        let rules = CodeResourcesBuilder::default_resources_rules()
+            .unwrap()
+            .rules2;
+
+        assert!(matches!(
+            CodeResourcesBuilder::evaluate_rules(&rules, "Contents/Frameworks/libfoo.dylib", None)
+                .unwrap(),
+            RulesEvaluation::SealNested(..)
+        ));
+    }
 }
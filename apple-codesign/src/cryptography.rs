@@ -10,17 +10,23 @@ use {
         AppleCodesignError,
     },
     bytes::Bytes,
+    cbc::cipher::{block_padding::Pkcs7, BlockCipher, BlockDecryptMut, KeyInit, KeyIvInit},
     der::{asn1, Decode, Document, Encode, SecretDocument},
     elliptic_curve::{
         sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint},
         AffinePoint, Curve, CurveArithmetic, FieldBytesSize, SecretKey as ECSecretKey,
     },
+    md5::Digest,
     oid_registry::{
         OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY, OID_PKCS1_RSAENCRYPTION, OID_SIG_ED25519,
     },
     p256::NistP256,
     pkcs1::RsaPrivateKey,
-    pkcs8::{EncodePrivateKey, ObjectIdentifier, PrivateKeyInfo},
+    pkcs8::{
+        pkcs5::{pbes2, scrypt},
+        EncodePrivateKey, EncryptedPrivateKeyInfo, ObjectIdentifier, PrivateKeyInfo,
+    },
+    rand::RngCore,
     ring::signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair},
     rsa::{
         algorithms::mgf1_xor, pkcs1::DecodeRsaPrivateKey, BigUint, Oaep,
@@ -37,8 +43,8 @@ use {
 };
 
 /// A supertrait generically describing a private key capable of signing and possibly decryption.
-pub trait PrivateKey: KeyInfoSigner {
-    fn as_key_info_signer(&self) -> &dyn KeyInfoSigner;
+pub trait PrivateKey: KeyInfoSigner + Sync {
+    fn as_key_info_signer(&self) -> &(dyn KeyInfoSigner + Sync);
 
     fn to_public_key_peer_decrypt(
         &self,
@@ -422,7 +428,7 @@ impl PublicKeyPeerDecrypt for InMemoryPrivateKey {
 }
 
 impl PrivateKey for InMemoryPrivateKey {
-    fn as_key_info_signer(&self) -> &dyn KeyInfoSigner {
+    fn as_key_info_signer(&self) -> &(dyn KeyInfoSigner + Sync) {
         self
     }
 
@@ -459,6 +465,149 @@ impl InMemoryPrivateKey {
             ))
         })
     }
+
+    /// Construct an instance by decrypting and parsing PKCS#8 `EncryptedPrivateKeyInfo` DER data.
+    ///
+    /// PBES2 encryption using PBKDF2 or scrypt key derivation and AES-CBC or
+    /// DES-EDE3-CBC ciphers is supported.
+    pub fn from_encrypted_pkcs8_der(
+        data: impl AsRef<[u8]>,
+        password: impl AsRef<[u8]>,
+    ) -> Result<Self, AppleCodesignError> {
+        let epki = EncryptedPrivateKeyInfo::try_from(data.as_ref()).map_err(|e| {
+            AppleCodesignError::CertificateGeneric(format!(
+                "when parsing encrypted PKCS#8 data: {e}"
+            ))
+        })?;
+
+        let document = epki.decrypt(password).map_err(|e| {
+            AppleCodesignError::PrivateKeyDecrypt(format!("{e}; is the password correct?"))
+        })?;
+
+        Self::from_pkcs8_der(document.as_bytes())
+    }
+}
+
+/// Encrypt PKCS#8 private key DER data to PKCS#8 `EncryptedPrivateKeyInfo` DER data.
+///
+/// PBES2 with scrypt key derivation and AES-256-CBC is used. The scrypt cost
+/// parameters match the defaults of `openssl pkcs8 -scrypt` so the result can be
+/// read by OpenSSL, which limits the memory scrypt may use.
+pub fn encrypt_pkcs8_der(
+    data: impl AsRef<[u8]>,
+    password: impl AsRef<[u8]>,
+) -> Result<SecretDocument, AppleCodesignError> {
+    let pki = PrivateKeyInfo::try_from(data.as_ref()).map_err(|e| {
+        AppleCodesignError::CertificateGeneric(format!("when parsing PKCS#8 data: {e}"))
+    })?;
+
+    let mut salt = [0u8; 16];
+    let mut iv = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut iv);
+
+    let params = pbes2::Parameters::scrypt_aes256cbc(
+        scrypt::Params::new(14, 8, 1, 32).expect("scrypt parameters should be valid"),
+        &salt,
+        &iv,
+    )
+    .map_err(|e| AppleCodesignError::CertificateGeneric(format!("scrypt parameters: {e}")))?;
+
+    pki.encrypt_with_params(params, password).map_err(|e| {
+        AppleCodesignError::CertificateGeneric(format!("when encrypting PKCS#8 data: {e}"))
+    })
+}
+
+/// Whether PEM data is encrypted using OpenSSL's legacy `Proc-Type` and `DEK-Info` headers.
+pub fn is_legacy_encrypted_pem(pem: &pem::Pem) -> bool {
+    pem.headers()
+        .get("Proc-Type")
+        .map_or(false, |value| value.trim() == "4,ENCRYPTED")
+}
+
+/// OpenSSL's `EVP_BytesToKey()` key derivation with MD5 and a single iteration.
+fn evp_bytes_to_key(password: &[u8], salt: &[u8], len: usize) -> Zeroizing<Vec<u8>> {
+    let mut key = Zeroizing::new(Vec::with_capacity(len + 16));
+    let mut block = Zeroizing::new(vec![]);
+
+    while key.len() < len {
+        let mut hasher = md5::Md5::new();
+        hasher.update(block.as_slice());
+        hasher.update(password);
+        hasher.update(salt);
+        *block = hasher.finalize().to_vec();
+
+        key.extend_from_slice(&block);
+    }
+
+    key.truncate(len);
+
+    key
+}
+
+fn cbc_decrypt<C>(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, AppleCodesignError>
+where
+    C: BlockCipher + BlockDecryptMut + KeyInit,
+{
+    cbc::Decryptor::<C>::new_from_slices(key, iv)
+        .map_err(|_| AppleCodesignError::PrivateKeyDecrypt("invalid key or IV length".into()))?
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|_| {
+            AppleCodesignError::PrivateKeyDecrypt("bad decrypt; is the password correct?".into())
+        })
+}
+
+/// Decrypt the content of PEM data encrypted using OpenSSL's legacy `Proc-Type` and `DEK-Info` headers.
+///
+/// These are typically encountered in `RSA PRIVATE KEY` data written by older
+/// versions of OpenSSL. AES-128-CBC, AES-192-CBC, AES-256-CBC, and DES-EDE3-CBC
+/// ciphers are supported.
+pub fn decrypt_legacy_pem(
+    pem: &pem::Pem,
+    password: impl AsRef<[u8]>,
+) -> Result<Zeroizing<Vec<u8>>, AppleCodesignError> {
+    let dek_info = pem
+        .headers()
+        .get("DEK-Info")
+        .ok_or_else(|| AppleCodesignError::PrivateKeyDecrypt("missing DEK-Info header".into()))?;
+
+    let (cipher, iv) = dek_info.split_once(',').ok_or_else(|| {
+        AppleCodesignError::PrivateKeyDecrypt(format!("malformed DEK-Info header: {dek_info}"))
+    })?;
+    let cipher = cipher.trim();
+    let iv = hex::decode(iv.trim()).map_err(|e| {
+        AppleCodesignError::PrivateKeyDecrypt(format!("malformed DEK-Info IV: {e}"))
+    })?;
+
+    let (key_len, iv_len) = match cipher {
+        "AES-128-CBC" => (16, 16),
+        "AES-192-CBC" => (24, 16),
+        "AES-256-CBC" => (32, 16),
+        "DES-EDE3-CBC" => (24, 8),
+        _ => {
+            return Err(AppleCodesignError::PrivateKeyDecrypt(format!(
+                "unsupported cipher: {cipher}"
+            )))
+        }
+    };
+
+    if iv.len() != iv_len {
+        return Err(AppleCodesignError::PrivateKeyDecrypt(format!(
+            "expected {iv_len} byte IV for {cipher}; got {}",
+            iv.len()
+        )));
+    }
+
+    // The first 8 bytes of the IV double as the key derivation salt.
+    let key = evp_bytes_to_key(password.as_ref(), &iv[0..8], key_len);
+    let data = pem.contents();
+
+    Ok(Zeroizing::new(match cipher {
+        "AES-128-CBC" => cbc_decrypt::<aes::Aes128>(&key, &iv, data)?,
+        "AES-192-CBC" => cbc_decrypt::<aes::Aes192>(&key, &iv, data)?,
+        "AES-256-CBC" => cbc_decrypt::<aes::Aes256>(&key, &iv, data)?,
+        _ => cbc_decrypt::<des::TdesEde3>(&key, &iv, data)?,
+    }))
 }
 
 fn bmp_string(s: &str) -> Vec<u8> {
@@ -744,4 +893,41 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn encrypted_pkcs8() -> Result<(), AppleCodesignError> {
+        let pem = pem::parse(include_bytes!("testdata/rsa-2048-pbes2.pem")).unwrap();
+
+        let key = InMemoryPrivateKey::from_encrypted_pkcs8_der(pem.contents(), "password123")?;
+        assert_eq!(key.to_pkcs8_der().unwrap().as_bytes(), RSA_2048_PKCS8_DER);
+
+        assert!(matches!(
+            InMemoryPrivateKey::from_encrypted_pkcs8_der(pem.contents(), "bad-password"),
+            Err(AppleCodesignError::PrivateKeyDecrypt(_))
+        ));
+
+        let encrypted = encrypt_pkcs8_der(SECP256_PKCS8_DER, "password123")?;
+        let key =
+            InMemoryPrivateKey::from_encrypted_pkcs8_der(encrypted.as_bytes(), "password123")?;
+        assert_eq!(key.to_pkcs8_der().unwrap().as_bytes(), SECP256_PKCS8_DER);
+
+        Ok(())
+    }
+
+    #[test]
+    fn legacy_encrypted_pem() -> Result<(), AppleCodesignError> {
+        let pem = pem::parse(include_bytes!("testdata/rsa-2048-legacy-aes256.pem")).unwrap();
+        assert!(is_legacy_encrypted_pem(&pem));
+
+        let pkcs1 = decrypt_legacy_pem(&pem, "password123")?;
+        let key = InMemoryPrivateKey::from_pkcs1_der(pkcs1.as_slice())?;
+        assert_eq!(key.to_pkcs8_der().unwrap().as_bytes(), RSA_2048_PKCS8_DER);
+
+        assert!(matches!(
+            decrypt_legacy_pem(&pem, "bad-password"),
+            Err(AppleCodesignError::PrivateKeyDecrypt(_))
+        ));
+
+        Ok(())
+    }
 }
@@ -300,6 +300,42 @@ impl DmgSigner {
         Self::write_embedded_signature(fh, koly, &signature)
     }
 
+    /// Remove the code signature from a DMG.
+    ///
+    /// The signature data is truncated from the file and the code signature
+    /// fields in the koly trailer are cleared. Returns whether a signature was
+    /// present.
+    pub fn remove_signature(&self, fh: &mut File) -> Result<bool, AppleCodesignError> {
+        let mut koly = DmgReader::new(fh)?.koly().clone();
+
+        if koly.code_signature_offset == 0 && koly.code_signature_size == 0 {
+            return Ok(false);
+        }
+
+        // We only support the layout we write: the signature is the last thing
+        // before the trailer. Otherwise truncating could drop other data.
+        let signature_start = koly.code_signature_offset;
+        let trailer_start = fh.seek(SeekFrom::End(-KOLY_SIZE))?;
+
+        if signature_start + koly.code_signature_size != trailer_start {
+            return Err(AppleCodesignError::DmgSignatureNotAtEnd);
+        }
+
+        warn!("removing {} byte DMG signature", koly.code_signature_size);
+
+        koly.code_signature_offset = 0;
+        koly.code_signature_size = 0;
+
+        let mut trailer = [0u8; KOLY_SIZE as usize];
+        trailer.pwrite_with(&koly, 0, scroll::BE)?;
+
+        fh.seek(SeekFrom::Start(signature_start))?;
+        fh.write_all(&trailer)?;
+        fh.set_len(signature_start + KOLY_SIZE as u64)?;
+
+        Ok(true)
+    }
+
     fn write_embedded_signature(
         fh: &mut File,
         mut koly: KolyTrailer,
@@ -339,11 +375,13 @@ impl DmgSigner {
             self.create_code_directory(settings, fh)?,
         )?;
 
+        builder.set_signing_time(settings.signing_time().copied());
+
         if let Some((signing_key, signing_cert)) = settings.signing_key() {
             builder.create_cms_signature(
                 signing_key,
                 signing_cert,
-                settings.time_stamp_url(),
+                settings.effective_time_stamp_url(),
                 settings.certificate_chain().iter().cloned(),
             )?;
         }
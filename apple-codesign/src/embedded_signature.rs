@@ -939,9 +939,13 @@ impl<'a> EntitlementsDerBlob<'a> {
 }
 
 /// A detached signature.
+///
+/// This is a SuperBlob whose entries are embedded signature SuperBlobs. Each
+/// entry is indexed by the CPU type of the Mach-O binary it applies to. This
+/// is the format written by `codesign --detached`.
 #[derive(Debug)]
 pub struct DetachedSignatureBlob<'a> {
-    data: &'a [u8],
+    data: Cow<'a, [u8]>,
 }
 
 impl<'a> Blob<'a> for DetachedSignatureBlob<'a> {
@@ -951,7 +955,8 @@ impl<'a> Blob<'a> for DetachedSignatureBlob<'a> {
 
     fn from_blob_bytes(data: &'a [u8]) -> Result<Self, AppleCodesignError> {
         Ok(Self {
-            data: read_and_validate_blob_header(data, Self::magic(), "detached signature blob")?,
+            data: read_and_validate_blob_header(data, Self::magic(), "detached signature blob")?
+                .into(),
         })
     }
 
@@ -960,6 +965,71 @@ impl<'a> Blob<'a> for DetachedSignatureBlob<'a> {
     }
 }
 
+impl DetachedSignatureBlob<'static> {
+    /// Construct an instance from embedded signature SuperBlobs keyed by CPU type.
+    pub fn from_signatures(
+        signatures: impl IntoIterator<Item = (u32, Vec<u8>)>,
+    ) -> Result<Self, AppleCodesignError> {
+        let blobs = signatures
+            .into_iter()
+            .map(|(cpu_type, data)| (CodeSigningSlot::from(cpu_type), data))
+            .collect::<Vec<_>>();
+
+        let data = create_superblob(CodeSigningMagic::DetachedSignature, blobs.iter())?;
+
+        Ok(Self {
+            data: data[8..].to_vec().into(),
+        })
+    }
+}
+
+impl<'a> DetachedSignatureBlob<'a> {
+    /// Resolve the embedded signatures within, keyed by CPU type.
+    pub fn signatures(&self) -> Result<Vec<(u32, EmbeddedSignature<'_>)>, AppleCodesignError> {
+        let offset = &mut 0;
+        let count = self.data.gread_with::<u32>(offset, scroll::BE)?;
+
+        let mut indices = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            indices.push(self.data.gread_with::<BlobIndex>(offset, scroll::BE)?);
+        }
+
+        indices
+            .into_iter()
+            .map(|index| {
+                // Offsets are relative to the start of the blob header, which we don't retain.
+                let start = (index.offset as usize)
+                    .checked_sub(8)
+                    .ok_or(AppleCodesignError::SuperblobMalformed)?;
+                let data = self
+                    .data
+                    .get(start..)
+                    .ok_or(AppleCodesignError::SuperblobMalformed)?;
+                let (_, length, _) = read_blob_header(data)?;
+                let data = data
+                    .get(..length)
+                    .ok_or(AppleCodesignError::SuperblobMalformed)?;
+
+                Ok((index.typ, EmbeddedSignature::from_bytes(data)?))
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()
+    }
+
+    /// Find the embedded signature for a given CPU type.
+    pub fn signature_for_cpu_type(
+        &self,
+        cpu_type: u32,
+    ) -> Result<Option<EmbeddedSignature<'_>>, AppleCodesignError> {
+        Ok(self.signatures()?.into_iter().find_map(|(typ, signature)| {
+            if typ == cpu_type {
+                Some(signature)
+            } else {
+                None
+            }
+        }))
+    }
+}
+
 /// Represents a generic blob wrapper.
 pub struct BlobWrapperBlob<'a> {
     data: Cow<'a, [u8]>,
@@ -13,14 +13,25 @@ use {
         },
         error::AppleCodesignError,
     },
-    bcder::{encode::PrimitiveContent, Oid},
+    bcder::{
+        encode::{PrimitiveContent, Values},
+        Mode, Oid, Tag,
+    },
     bytes::Bytes,
-    cryptographic_message_syntax::{asn1::rfc5652::OID_ID_DATA, SignedDataBuilder, SignerBuilder},
+    chrono::{DateTime, Datelike, Utc},
+    cryptographic_message_syntax::{
+        asn1::rfc5652::{
+            SignatureValue, SignedAttributesDer, SignedData, OID_ID_DATA, OID_SIGNING_TIME,
+        },
+        CmsError, SignedDataBuilder, SignerBuilder,
+    },
     log::{info, warn},
     reqwest::Url,
+    signature::Signer,
     std::collections::BTreeMap,
     x509_certificate::{
-        rfc5652::AttributeValue, CapturedX509Certificate, DigestAlgorithm, KeyInfoSigner,
+        rfc5652::AttributeValue, CapturedX509Certificate, DigestAlgorithm, KeyAlgorithm,
+        KeyInfoSigner, Sign, Signature, SignatureAlgorithm, X509CertificateError,
     },
 };
 
@@ -56,6 +67,117 @@ impl Default for BlobsState {
 pub struct EmbeddedSignatureBuilder<'a> {
     state: BlobsState,
     blobs: BTreeMap<CodeSigningSlot, BlobData<'a>>,
+    signing_time: Option<DateTime<Utc>>,
+}
+
+/// A [KeyInfoSigner] producing empty signatures on behalf of another signer.
+///
+/// Used to build CMS structures whose signature is created afterwards.
+struct DeferredSigner<'a>(&'a dyn KeyInfoSigner);
+
+impl<'a> Signer<Signature> for DeferredSigner<'a> {
+    fn try_sign(&self, _message: &[u8]) -> Result<Signature, signature::Error> {
+        Ok(Signature::from(vec![]))
+    }
+}
+
+impl<'a> Sign for DeferredSigner<'a> {
+    fn sign(&self, _message: &[u8]) -> Result<(Vec<u8>, SignatureAlgorithm), X509CertificateError> {
+        Ok((vec![], self.0.signature_algorithm()?))
+    }
+
+    fn key_algorithm(&self) -> Option<KeyAlgorithm> {
+        self.0.key_algorithm()
+    }
+
+    fn public_key_data(&self) -> Bytes {
+        self.0.public_key_data()
+    }
+
+    fn signature_algorithm(&self) -> Result<SignatureAlgorithm, X509CertificateError> {
+        self.0.signature_algorithm()
+    }
+
+    fn private_key_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn rsa_primes(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>, X509CertificateError> {
+        Ok(None)
+    }
+}
+
+impl<'a> KeyInfoSigner for DeferredSigner<'a> {}
+
+/// Pin the signing time of CMS signed data and (re)create its signatures.
+///
+/// Signed attributes are sorted by their DER encoding, as DER requires for a
+/// `SET OF`. Other CMS writers may only sort by attribute values.
+fn sign_cms_with_signing_time(
+    der: &[u8],
+    signing_key: &dyn KeyInfoSigner,
+    signing_time: &DateTime<Utc>,
+) -> Result<Vec<u8>, AppleCodesignError> {
+    // UTCTime can only express years 1950 through 2049.
+    if !(1950..2050).contains(&signing_time.year()) {
+        return Err(AppleCodesignError::SignatureBuilder(
+            "signing time must be between 1950 and 2049",
+        ));
+    }
+
+    // Decode in DER mode so captured values can be re-encoded as DER.
+    let mut signed_data = bcder::decode::Constructed::decode(der, Mode::Der, SignedData::decode)
+        .map_err(CmsError::from)?;
+
+    let time = signing_time.format("%y%m%d%H%M%SZ").to_string();
+    let time = AttributeValue::new(bcder::Captured::from_values(
+        Mode::Der,
+        time.as_bytes().encode_as(Tag::UTC_TIME),
+    ));
+
+    for signer_info in signed_data.signer_infos.iter_mut() {
+        let attributes =
+            signer_info
+                .signed_attributes
+                .as_mut()
+                .ok_or(AppleCodesignError::SignatureBuilder(
+                    "CMS signer lacks signed attributes",
+                ))?;
+
+        for attribute in attributes.iter_mut() {
+            if attribute.typ == OID_SIGNING_TIME {
+                attribute.values = vec![time.clone()];
+            }
+        }
+
+        attributes.sort_by_cached_key(|attribute| {
+            let mut encoded = vec![];
+            attribute
+                .clone()
+                .encode()
+                .write_encoded(Mode::Der, &mut encoded)
+                .expect("writing to a Vec should not fail");
+            encoded
+        });
+
+        signer_info.signed_attributes_data = None;
+
+        // signed_attributes_digested_content() re-sorts attributes by their values
+        // only. So encode the attributes in our order, as they will be emitted.
+        let mut content = vec![];
+        SignedAttributesDer::new(attributes.clone(), None)
+            .write_encoded(Mode::Der, &mut content)?;
+
+        let signature = signing_key.try_sign(&content).map_err(CmsError::from)?;
+        signer_info.signature = SignatureValue::new(Bytes::from(Vec::<u8>::from(signature)));
+    }
+
+    let mut der = vec![];
+    signed_data
+        .encode_ref()
+        .write_encoded(Mode::Der, &mut der)?;
+
+    Ok(der)
 }
 
 impl<'a> EmbeddedSignatureBuilder<'a> {
@@ -78,9 +200,18 @@ impl<'a> EmbeddedSignatureBuilder<'a> {
         Ok(Self {
             state: BlobsState::CodeDirectoryAdded,
             blobs,
+            signing_time: None,
         })
     }
 
+    /// Pin the signing time recorded in CMS signatures.
+    ///
+    /// When set, CMS signatures are deterministic for deterministic signature
+    /// algorithms and no Time-Stamp Token is requested.
+    pub fn set_signing_time(&mut self, time: Option<DateTime<Utc>>) {
+        self.signing_time = time;
+    }
+
     /// Obtain the code directory registered with this instance.
     pub fn code_directory(&self) -> Option<&CodeDirectoryBlob> {
         self.blobs.get(&CodeSigningSlot::CodeDirectory).map(|blob| {
@@ -204,11 +335,13 @@ impl<'a> EmbeddedSignatureBuilder<'a> {
     /// cryptographic signature.
     ///
     /// `time_stamp_url` is an optional time-stamp protocol server to use to record
-    /// the signature in.
+    /// the signature in. It can't be combined with a pinned signing time, as
+    /// the token wouldn't be reproducible.
     ///
     /// `certificates` are extra X.509 certificates to register in the signing chain.
     ///
-    /// This method errors if called before a code directory is registered.
+    /// This method errors if called before a code directory is registered or if
+    /// `time_stamp_url` is given with a pinned signing time.
     pub fn create_cms_signature(
         &mut self,
         signing_key: &dyn KeyInfoSigner,
@@ -222,6 +355,12 @@ impl<'a> EmbeddedSignatureBuilder<'a> {
                 "cannot create CMS signature unless code directory is present",
             ))?;
 
+        if self.signing_time.is_some() && time_stamp_url.is_some() {
+            return Err(AppleCodesignError::SignatureBuilder(
+                "cannot request a time-stamp token with a pinned signing time",
+            ));
+        }
+
         if let Some(cn) = signing_cert.subject_common_name() {
             warn!("creating cryptographic signature with certificate {}", cn);
         }
@@ -269,7 +408,16 @@ impl<'a> EmbeddedSignatureBuilder<'a> {
         // writer.
         plist_xml.push(b'\n');
 
-        let signer = SignerBuilder::new(signing_key, signing_cert.clone())
+        // With a pinned signing time, the signature is created after the signed
+        // attributes are adjusted. So defer signing until then.
+        let deferred = DeferredSigner(signing_key);
+        let cms_key: &dyn KeyInfoSigner = if self.signing_time.is_some() {
+            &deferred
+        } else {
+            signing_key
+        };
+
+        let signer = SignerBuilder::new(cms_key, signing_cert.clone())
             .message_id_content(main_cd.to_blob_bytes()?)
             .signed_attribute_octet_string(
                 Oid(Bytes::copy_from_slice(CD_DIGESTS_PLIST_OID.as_ref())),
@@ -294,6 +442,12 @@ impl<'a> EmbeddedSignatureBuilder<'a> {
             .certificates(certificates)
             .build_der()?;
 
+        let der = if let Some(signing_time) = &self.signing_time {
+            sign_cms_with_signing_time(&der, signing_key, signing_time)?
+        } else {
+            der
+        };
+
         self.blobs.insert(
             CodeSigningSlot::Signature,
             BlobData::BlobWrapper(Box::new(BlobWrapperBlob::from_data_owned(der))),
@@ -340,3 +494,64 @@ impl<'a> EmbeddedSignatureBuilder<'a> {
         create_superblob(CodeSigningMagic::EmbeddedSignature, blobs.iter())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::certificate::{create_self_signed_code_signing_certificate, CertificateProfile},
+        chrono::TimeZone,
+    };
+
+    #[test]
+    fn pinned_signing_time() -> Result<(), AppleCodesignError> {
+        let (cert, signing_key, _) = create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::DeveloperIdApplication,
+            "team",
+            "Joe Developer",
+            "US",
+            chrono::Duration::hours(1),
+        )?;
+
+        let signing_time = Utc.timestamp_opt(1700000000, 0).unwrap();
+        let content = b"hello, world";
+
+        let sign = || -> Result<Vec<u8>, AppleCodesignError> {
+            let der = SignedDataBuilder::default()
+                .content_type(Oid(OID_ID_DATA.as_ref().into()))
+                .content_external(content.to_vec())
+                .signer(SignerBuilder::new(
+                    &DeferredSigner(&signing_key),
+                    cert.clone(),
+                ))
+                .build_der()?;
+
+            sign_cms_with_signing_time(&der, &signing_key, &signing_time)
+        };
+
+        let der = sign()?;
+
+        let signed_data = cryptographic_message_syntax::SignedData::parse_ber(&der)?;
+
+        for signer in signed_data.signers() {
+            assert_eq!(
+                signer
+                    .signed_attributes()
+                    .and_then(|attributes| attributes.signing_time()),
+                Some(&signing_time)
+            );
+            signer.verify_signature_with_signed_data(&signed_data)?;
+            signer.verify_message_digest_with_content(content)?;
+        }
+
+        assert!(sign_cms_with_signing_time(
+            &der,
+            &signing_key,
+            &Utc.timestamp_opt(4102444800, 0).unwrap()
+        )
+        .is_err());
+
+        Ok(())
+    }
+}
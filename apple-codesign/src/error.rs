@@ -34,6 +34,9 @@ pub enum AppleCodesignError {
     #[error("invalid binary index within Mach-O: {0}")]
     InvalidMachOIndex(usize),
 
+    #[error("architecture not present in Mach-O: {0}")]
+    MachOArchitectureNotFound(String),
+
     #[error("binary does not have code signature data")]
     BinaryNoCodeSignature,
 
@@ -46,6 +49,12 @@ pub enum AppleCodesignError {
     #[error("CMS error: {0}")]
     Cms(#[from] CmsError),
 
+    #[error("malformed time-stamp request: {0}")]
+    TimeStampRequestMalformed(String),
+
+    #[error("time-stamp authority error: {0}")]
+    TimeStampAuthority(String),
+
     #[error("JSON serialization error: {0}")]
     SerdeJson(#[from] serde_json::Error),
 
@@ -55,9 +64,15 @@ pub enum AppleCodesignError {
     #[error("glob error: {0}")]
     GlobPattern(#[from] glob::PatternError),
 
+    #[error("error building thread pool: {0}")]
+    ThreadPoolBuild(#[from] rayon::ThreadPoolBuildError),
+
     #[error("problems reported during verification")]
     VerificationProblems,
 
+    #[error("notarization preflight found issues")]
+    NotaryPreflightIssues,
+
     #[error("certificate error: {0}")]
     CertificateGeneric(String),
 
@@ -145,6 +160,9 @@ pub enum AppleCodesignError {
     #[error("code requirement data malformed: {0}")]
     RequirementMalformed(&'static str),
 
+    #[error("error parsing code requirement expression at offset {0}: {1}")]
+    RequirementParse(usize, String),
+
     #[error("plist error in code resources: {0}")]
     ResourcesPlist(plist::Error),
 
@@ -163,6 +181,9 @@ pub enum AppleCodesignError {
     #[error("__LINKEDIT segment contains data after signature")]
     DataAfterSignature,
 
+    #[error("Mach-O binary contains data after __LINKEDIT segment")]
+    DataAfterLinkedit,
+
     #[error("insufficient room to write code signature load command")]
     LoadCommandNoRoom,
 
@@ -213,12 +234,21 @@ pub enum AppleCodesignError {
     #[error("unable to parse settings scope: {0}")]
     ParseSettingsScope(String),
 
+    #[error("error parsing signing configuration: {0}")]
+    SigningConfigParse(String),
+
+    #[error("invalid signing configuration at {0}: {1}")]
+    SigningConfigInvalid(String, String),
+
     #[error("incorrect password given when decrypting PFX data")]
     PfxBadPassword,
 
     #[error("error parsing PFX data: {0}")]
     PfxParseError(String),
 
+    #[error("error decrypting private key: {0}")]
+    PrivateKeyDecrypt(String),
+
     #[cfg(target_os = "macos")]
     #[error("SecurityFramework error: {0}")]
     SecurityFramework(#[from] security_framework::base::Error),
@@ -256,6 +286,18 @@ pub enum AppleCodesignError {
     #[error("zip error: {0}")]
     ZipError(#[from] zip::result::ZipError),
 
+    #[error("zip archive entry has unsafe path: {0}")]
+    ZipUnsafePath(String),
+
+    #[error("zip archive does not contain any bundles to sign")]
+    ZipNoBundles,
+
+    #[error("extended attributes are not supported on this platform")]
+    XattrUnsupported,
+
+    #[error("signature slot cannot be stored in an extended attribute: {0:?}")]
+    XattrUnsupportedSlot(crate::embedded_signature::CodeSigningSlot),
+
     #[error("error writing app metadata XML: {0}")]
     AppMetadataXml(xml::writer::Error),
 
@@ -265,6 +307,9 @@ pub enum AppleCodesignError {
     #[error("signing XAR archives requires a signing certificate")]
     XarNoAdhoc,
 
+    #[error("deterministic signing of XAR archives is not supported")]
+    XarDeterministic,
+
     #[error("App Store Connect API Key error: {0}")]
     AppStoreConnectApiKey(String),
 
@@ -331,6 +376,9 @@ pub enum AppleCodesignError {
     #[error("cannot staple DMG without an embedded signature")]
     DmgStapleNoSignature,
 
+    #[error("cannot remove DMG signature not located before the koly trailer")]
+    DmgSignatureNotAtEnd,
+
     #[error("failed to find certificate in smartcard slot {0}")]
     SmartcardNoCertificate(String),
 
@@ -341,6 +389,25 @@ pub enum AppleCodesignError {
     #[error("YubiKey error: {0}")]
     YubiKey(#[from] yubikey::Error),
 
+    #[cfg(feature = "pkcs11")]
+    #[error("error loading PKCS#11 module: {0}")]
+    Pkcs11Library(#[from] libloading::Error),
+
+    #[error("PKCS#11 function {0} failed: CKR 0x{1:08x}")]
+    Pkcs11Function(&'static str, std::ffi::c_ulong),
+
+    #[error("failed to find PKCS#11 token {0}")]
+    Pkcs11NoToken(String),
+
+    #[error("failed to find PKCS#11 private key with {0}")]
+    Pkcs11NoKey(String),
+
+    #[error("failed to find PKCS#11 certificate with {0}")]
+    Pkcs11NoCertificate(String),
+
+    #[error("external signer error: {0}")]
+    ExternalSigner(String),
+
     #[error("poisoned lock")]
     PoisonedLock,
 
@@ -364,6 +431,21 @@ pub enum AppleCodesignError {
     #[error("bad time value")]
     BadTime,
 
+    #[error("provisioning profile is malformed: {0}")]
+    ProvisioningProfileMalformed(String),
+
+    #[error("provisioning profile expired at {0}")]
+    ProvisioningProfileExpired(chrono::DateTime<chrono::Utc>),
+
+    #[error("provisioning profile does not allow signing certificate {0}")]
+    ProvisioningProfileCertificateNotAllowed(String),
+
+    #[error("provisioning profile does not grant entitlement {0}")]
+    ProvisioningProfileEntitlementNotAllowed(String),
+
+    #[error("embedding a provisioning profile requires a signing certificate")]
+    ProvisioningProfileNoSigningCertificate,
+
     #[error("{0}")]
     Anyhow(#[from] anyhow::Error),
 }
@@ -0,0 +1,531 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! External signer plugin support.
+//!
+//! An external signer is a program creating signatures on behalf of this
+//! crate. It allows integrating signing backends like cloud key management
+//! services without building support for them into this crate.
+//!
+//! The program is spawned once and is spoken to over its stdin and stdout
+//! using a line delimited JSON protocol. Each request is a single line JSON
+//! object having a `version` (currently `1`) and a `method` key. The program
+//! answers each request with a single line JSON object. Failures are reported
+//! with a response having an `error` key. stderr is inherited so the program
+//! can log to it. The program should exit when its stdin is closed. It is
+//! killed if it hasn't exited 5 seconds later.
+//!
+//! The following methods are defined:
+//!
+//! `get-certificates`
+//!    Response has a `certificates` array of base64 encoded DER certificates.
+//!    The first certificate is the signing certificate. Any remaining ones form
+//!    its issuing chain.
+//!
+//! `sign`
+//!    Request has `signature_algorithm` (`rsa-sha256`, `ecdsa-sha256`, or
+//!    `ecdsa-sha384`), `digest_algorithm` (`sha256` or `sha384`), and the base64
+//!    encoded `digest` to sign. Response has a base64 encoded `signature`. RSA
+//!    signatures are PKCS#1 v1.5. ECDSA signatures are a DER encoded
+//!    `ECDSA-Sig-Value`.
+
+use {
+    crate::{
+        cryptography::PrivateKey,
+        remote_signing::{session_negotiation::PublicKeyPeerDecrypt, RemoteSignError},
+        AppleCodesignError,
+    },
+    base64::{engine::general_purpose::STANDARD as STANDARD_ENGINE, Engine},
+    bytes::Bytes,
+    log::warn,
+    serde::{Deserialize, Serialize},
+    signature::Signer,
+    std::{
+        ffi::OsStr,
+        io::{BufRead, BufReader, Write},
+        process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+    x509_certificate::{
+        CapturedX509Certificate, DigestAlgorithm, EcdsaCurve, KeyAlgorithm, KeyInfoSigner, Sign,
+        Signature, SignatureAlgorithm, X509CertificateError,
+    },
+};
+
+/// Version of the external signer protocol.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How long to wait for the process to exit after closing its stdin.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "method", rename_all = "kebab-case")]
+enum Request {
+    GetCertificates,
+    Sign {
+        signature_algorithm: &'static str,
+        digest_algorithm: &'static str,
+        digest: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct RequestEnvelope {
+    version: u32,
+    #[serde(flatten)]
+    request: Request,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+struct Response {
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    certificates: Vec<String>,
+    #[serde(default)]
+    signature: Option<String>,
+}
+
+fn decode_base64(field: &str, value: &str) -> Result<Vec<u8>, AppleCodesignError> {
+    STANDARD_ENGINE
+        .decode(value)
+        .map_err(|e| AppleCodesignError::ExternalSigner(format!("invalid base64 in {field}: {e}")))
+}
+
+/// A running external signer process.
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Process {
+    fn request(&mut self, request: Request) -> Result<Response, AppleCodesignError> {
+        let mut line = serde_json::to_vec(&RequestEnvelope {
+            version: PROTOCOL_VERSION,
+            request,
+        })?;
+        line.push(b'\n');
+
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| AppleCodesignError::ExternalSigner("process stdin closed".into()))?;
+        stdin.write_all(&line)?;
+        stdin.flush()?;
+
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(AppleCodesignError::ExternalSigner(
+                "process exited without responding".into(),
+            ));
+        }
+
+        let response = serde_json::from_str::<Response>(&line)?;
+
+        if let Some(error) = response.error {
+            Err(AppleCodesignError::ExternalSigner(error))
+        } else {
+            Ok(response)
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // Closing stdin tells the process to exit.
+        self.stdin.take();
+
+        // But don't hang if it doesn't.
+        let deadline = Instant::now() + EXIT_TIMEOUT;
+        loop {
+            match self.child.try_wait() {
+                Ok(Some(_)) => return,
+                Ok(None) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Ok(None) => {
+                    warn!("external signer process did not exit; killing it");
+                    if let Err(e) = self.child.kill() {
+                        warn!("error killing external signer process: {}", e);
+                    }
+                    break;
+                }
+                Err(e) => {
+                    warn!("error waiting on external signer process: {}", e);
+                    return;
+                }
+            }
+        }
+
+        if let Err(e) = self.child.wait() {
+            warn!("error waiting on external signer process: {}", e);
+        }
+    }
+}
+
+/// Entity for creating signatures using an external signer program.
+#[derive(Clone)]
+pub struct ExternalSigner {
+    process: Arc<Mutex<Process>>,
+    cert: CapturedX509Certificate,
+    chain: Vec<CapturedX509Certificate>,
+}
+
+impl ExternalSigner {
+    /// Spawn an external signer program and obtain its certificates.
+    pub fn spawn<S: AsRef<OsStr>>(
+        program: impl AsRef<OsStr>,
+        args: impl IntoIterator<Item = S>,
+    ) -> Result<Self, AppleCodesignError> {
+        let program = program.as_ref();
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|e| {
+                AppleCodesignError::ExternalSigner(format!(
+                    "failed to spawn {}: {}",
+                    program.to_string_lossy(),
+                    e
+                ))
+            })?;
+
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().expect("stdout should be piped"));
+
+        let mut process = Process {
+            child,
+            stdin,
+            stdout,
+        };
+
+        let mut certs = process
+            .request(Request::GetCertificates)?
+            .certificates
+            .iter()
+            .map(|cert| {
+                Ok(CapturedX509Certificate::from_der(decode_base64(
+                    "certificates",
+                    cert,
+                )?)?)
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()?
+            .into_iter();
+
+        let cert = certs
+            .next()
+            .ok_or_else(|| AppleCodesignError::ExternalSigner("no certificates returned".into()))?;
+
+        Ok(Self {
+            process: Arc::new(Mutex::new(process)),
+            cert,
+            chain: certs.collect(),
+        })
+    }
+
+    /// The signing certificate.
+    pub fn certificate(&self) -> &CapturedX509Certificate {
+        &self.cert
+    }
+
+    /// Certificates in the signing certificate's issuing chain.
+    pub fn certificate_chain(&self) -> &[CapturedX509Certificate] {
+        &self.chain
+    }
+
+    fn key_algorithm_required(&self) -> Result<KeyAlgorithm, X509CertificateError> {
+        self.cert
+            .key_algorithm()
+            .ok_or(X509CertificateError::UnknownKeyAlgorithm(format!(
+                "{:?}",
+                self.cert.key_algorithm_oid()
+            )))
+    }
+}
+
+impl Signer<Signature> for ExternalSigner {
+    fn try_sign(&self, message: &[u8]) -> Result<Signature, signature::Error> {
+        let (signature_algorithm, digest_name, digest_algorithm) = match self
+            .signature_algorithm()
+            .map_err(signature::Error::from_source)?
+        {
+            SignatureAlgorithm::RsaSha256 => ("rsa-sha256", "sha256", DigestAlgorithm::Sha256),
+            SignatureAlgorithm::EcdsaSha256 => ("ecdsa-sha256", "sha256", DigestAlgorithm::Sha256),
+            SignatureAlgorithm::EcdsaSha384 => ("ecdsa-sha384", "sha384", DigestAlgorithm::Sha384),
+            alg => {
+                return Err(signature::Error::from_source(format!(
+                    "unsupported signature algorithm: {alg}"
+                )))
+            }
+        };
+
+        let request = Request::Sign {
+            signature_algorithm,
+            digest_algorithm: digest_name,
+            digest: STANDARD_ENGINE.encode(digest_algorithm.digest_data(message)),
+        };
+
+        let mut process = self.process.lock().map_err(|_| {
+            signature::Error::from_source("unable to acquire lock on external signer")
+        })?;
+
+        let signature = process
+            .request(request)
+            .map_err(signature::Error::from_source)?
+            .signature
+            .ok_or_else(|| {
+                signature::Error::from_source("external signer returned no signature")
+            })?;
+
+        Ok(Signature::from(
+            decode_base64("signature", &signature).map_err(signature::Error::from_source)?,
+        ))
+    }
+}
+
+impl Sign for ExternalSigner {
+    fn sign(&self, message: &[u8]) -> Result<(Vec<u8>, SignatureAlgorithm), X509CertificateError> {
+        let algorithm = self.signature_algorithm()?;
+
+        Ok((self.try_sign(message)?.into(), algorithm))
+    }
+
+    fn key_algorithm(&self) -> Option<KeyAlgorithm> {
+        self.cert.key_algorithm()
+    }
+
+    fn public_key_data(&self) -> Bytes {
+        self.cert.public_key_data()
+    }
+
+    fn signature_algorithm(&self) -> Result<SignatureAlgorithm, X509CertificateError> {
+        match self.key_algorithm_required()? {
+            KeyAlgorithm::Rsa => Ok(SignatureAlgorithm::RsaSha256),
+            KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1) => Ok(SignatureAlgorithm::EcdsaSha256),
+            KeyAlgorithm::Ecdsa(EcdsaCurve::Secp384r1) => Ok(SignatureAlgorithm::EcdsaSha384),
+            KeyAlgorithm::Ed25519 => Err(X509CertificateError::UnknownKeyAlgorithm(
+                "unable to use ed25519 keys with external signers".into(),
+            )),
+        }
+    }
+
+    fn private_key_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn rsa_primes(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>, X509CertificateError> {
+        Ok(None)
+    }
+}
+
+impl KeyInfoSigner for ExternalSigner {}
+
+impl PublicKeyPeerDecrypt for ExternalSigner {
+    fn decrypt(&self, _ciphertext: &[u8]) -> Result<Vec<u8>, RemoteSignError> {
+        Err(RemoteSignError::Crypto(
+            "decryption is not supported with external signers".into(),
+        ))
+    }
+}
+
+impl PrivateKey for ExternalSigner {
+    fn as_key_info_signer(&self) -> &(dyn KeyInfoSigner + Sync) {
+        self
+    }
+
+    fn to_public_key_peer_decrypt(
+        &self,
+    ) -> Result<Box<dyn PublicKeyPeerDecrypt>, AppleCodesignError> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn finish(&self) -> Result<(), AppleCodesignError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::testutil::rsa_signing_identity,
+        cryptographic_message_syntax::{SignedData, SignedDataBuilder, SignerBuilder},
+    };
+
+    /// An external signer implemented as a shell script returning a fixed signature.
+    ///
+    /// The signature is only returned for the expected digest.
+    const FIXED_SIGNER: &str = r#"
+while read -r request; do
+    case "$request" in
+        *get-certificates*)
+            printf '{"certificates": ["%s"]}\n' "$0"
+            ;;
+        *"\"digest\":\"$1\""*)
+            printf '{"signature": "%s"}\n' "$2"
+            ;;
+        *)
+            echo '{"error": "unexpected request"}'
+            ;;
+    esac
+done
+"#;
+
+    /// An external signer implemented as a shell script signing with OpenSSL.
+    const OPENSSL_SIGNER: &str = r#"
+while read -r request; do
+    case "$request" in
+        *get-certificates*)
+            printf '{"certificates": ["%s"]}\n' "$0"
+            ;;
+        *)
+            digest=$(printf '%s' "$request" | sed 's/.*"digest":"\([^"]*\)".*/\1/')
+            signature=$(printf '%s\n' "$digest" | openssl base64 -d -A |
+                openssl pkeyutl -sign -inkey "$1" -keyform DER -pkeyopt digest:sha256 |
+                openssl base64 -A)
+            printf '{"signature": "%s"}\n' "$signature"
+            ;;
+    esac
+done
+"#;
+
+    #[test]
+    fn request_serialization() -> Result<(), AppleCodesignError> {
+        let envelope = RequestEnvelope {
+            version: PROTOCOL_VERSION,
+            request: Request::Sign {
+                signature_algorithm: "ecdsa-sha256",
+                digest_algorithm: "sha256",
+                digest: "AAAA".into(),
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_string(&envelope)?,
+            r#"{"version":1,"method":"sign","signature_algorithm":"ecdsa-sha256","digest_algorithm":"sha256","digest":"AAAA"}"#
+        );
+
+        let envelope = RequestEnvelope {
+            version: PROTOCOL_VERSION,
+            request: Request::GetCertificates,
+        };
+
+        assert_eq!(
+            serde_json::to_string(&envelope)?,
+            r#"{"version":1,"method":"get-certificates"}"#
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn error_response() {
+        let res = ExternalSigner::spawn(
+            "sh",
+            ["-c", r#"read request; echo '{"error": "key unavailable"}'"#],
+        );
+
+        assert!(matches!(
+            res,
+            Err(AppleCodesignError::ExternalSigner(message)) if message == "key unavailable"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sign_fixed() -> Result<(), AppleCodesignError> {
+        let (signing_key, cert) = rsa_signing_identity();
+        let message = b"hello, world";
+        let expected = signing_key
+            .try_sign(message)
+            .map_err(X509CertificateError::from)?;
+
+        let signer = ExternalSigner::spawn(
+            "sh",
+            [
+                "-c".to_string(),
+                FIXED_SIGNER.to_string(),
+                STANDARD_ENGINE.encode(cert.encode_der()?),
+                STANDARD_ENGINE.encode(DigestAlgorithm::Sha256.digest_data(message)),
+                STANDARD_ENGINE.encode(&expected),
+            ],
+        )?;
+        assert_eq!(signer.certificate(), &cert);
+
+        let signature = signer
+            .try_sign(message)
+            .map_err(X509CertificateError::from)?;
+        assert_eq!(signature.as_ref(), expected.as_ref());
+
+        assert!(signer.try_sign(b"other message").is_err());
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kill_lingering_process() -> Result<(), AppleCodesignError> {
+        let cert = include_bytes!("testdata/rsa-2048.cer");
+
+        // Answer the first request, then ignore stdin being closed.
+        let signer = ExternalSigner::spawn(
+            "sh",
+            [
+                "-c",
+                r#"read -r request; printf '{"certificates": ["%s"]}\n' "$0"; exec sleep 60"#,
+                &STANDARD_ENGINE.encode(cert),
+            ],
+        )?;
+
+        let start = Instant::now();
+        drop(signer);
+        assert!(start.elapsed() < Duration::from_secs(30));
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "requires openssl"]
+    fn sign_cms() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let key_path = td.path().join("key.pk8");
+        std::fs::write(&key_path, include_bytes!("testdata/rsa-2048.pk8"))?;
+
+        let cert = include_bytes!("testdata/rsa-2048.cer");
+
+        let signer = ExternalSigner::spawn(
+            "sh",
+            [
+                OsStr::new("-c"),
+                OsStr::new(OPENSSL_SIGNER),
+                OsStr::new(&STANDARD_ENGINE.encode(cert)),
+                key_path.as_os_str(),
+            ],
+        )?;
+        assert_eq!(signer.certificate().encode_der()?, cert);
+        assert!(signer.certificate_chain().is_empty());
+        assert_eq!(signer.signature_algorithm()?, SignatureAlgorithm::RsaSha256);
+
+        let der = SignedDataBuilder::default()
+            .content_inline(b"hello, world".to_vec())
+            .signer(SignerBuilder::new(&signer, signer.certificate().clone()))
+            .build_der()?;
+
+        let signed_data = SignedData::parse_ber(&der)?;
+        for signer in signed_data.signers() {
+            signer.verify_signature_with_signed_data(&signed_data)?;
+            signer.verify_message_digest_with_signed_data(&signed_data)?;
+        }
+        assert_eq!(signed_data.signers().count(), 1);
+
+        Ok(())
+    }
+}
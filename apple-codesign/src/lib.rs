@@ -33,6 +33,9 @@
 //!   you provide. (See [MachOSigner] and [BundleSigner].)
 //! * Writing a new Mach-O file containing new signature data. (See
 //!   [MachOSigner].)
+//! * Parse and compile the Code Signing Requirements language into binary
+//!   requirement expressions. (See [CodeRequirements::parse_str].) Requirements
+//!   can also be rendered back to the human friendly language.
 //! * Parse `CodeResources` XML plist files defining information on nested/signed
 //!   resources within bundles. This includes parsing and applying the filtering
 //!   rules defining in these files.
@@ -44,16 +47,16 @@
 //!   DMGs, and `.pkg` installers are all supported.)
 //! * Retrieve notarization tickets from Apple and staple. All formats supporting
 //!   notarization can be stapled.
+//! * Check content for common notarization rejections without talking to
+//!   Apple. (See [notary_preflight_path()].)
+//! * Remove signatures from Mach-O binaries, bundles, DMGs, and `.pkg`
+//!   installers. (See [unsign_path()].)
+//! * Run a local RFC 3161 time-stamp authority so signatures can be time-stamped
+//!   without access to Apple's servers. (See [time_stamp_authority].)
 //!
 //! There are a number of missing features and capabilities from this crate
 //! that we hope are eventually implemented:
 //!
-//! * No parsing of the Code Signing Requirements DSL. We support parsing the binary
-//!   requirements to Rust structs, serializing back to binary, and rendering to the
-//!   human friendly DSL. You will need to use the `csreq` tool to compile an
-//!   expression to binary and then give that binary blob to this crate. Alternatively,
-//!   you can write Rust code to construct a code requirements expression and serialize
-//!   that to binary.
 //! * No turnkey support for signing keys. We want to make it easier for obtaining
 //!   signing keys (and their X.509 certificate chain) for use with this crate. It
 //!   should be possible to easily integrate with the OS's key store or hardware
@@ -95,6 +98,11 @@
 //!
 //! [dmg::DmgSigner] signs DMG files.
 //!
+//! [ZipSigner] signs the bundles within ZIP archives and `.ipa` files.
+//!
+//! [XattrSigner] signs other files, such as scripts, by storing the signature in
+//! extended attributes.
+//!
 //! The [EmbeddedSignature] represents a parsed Apple code signature and provides API
 //! for data retrieval.
 //!
@@ -120,6 +128,8 @@ mod code_directory;
 pub use code_directory::*;
 pub mod code_requirement;
 pub use code_requirement::*;
+mod code_requirement_evaluator;
+pub use code_requirement_evaluator::*;
 mod code_resources;
 pub use code_resources::*;
 pub mod cryptography;
@@ -131,6 +141,7 @@ pub use embedded_signature_builder::*;
 pub mod entitlements;
 mod error;
 pub use error::*;
+pub mod external_signer;
 mod macho;
 pub use macho::*;
 #[cfg(target_os = "macos")]
@@ -138,6 +149,8 @@ pub use macho::*;
 mod macos;
 #[cfg(target_os = "macos")]
 pub use macos::*;
+mod macho_rewrite;
+pub use macho_rewrite::*;
 mod macho_signing;
 pub use macho_signing::*;
 mod macho_universal;
@@ -146,19 +159,38 @@ pub use macho_universal::UniversalBinaryBuilder;
 pub mod notarization;
 #[cfg(feature = "notarize")]
 pub use notarization::*;
+mod notary_preflight;
+pub use notary_preflight::*;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
 mod policy;
 pub use policy::*;
+mod provisioning_profile;
+pub use provisioning_profile::*;
 mod reader;
 pub use reader::*;
 pub mod remote_signing;
+mod signing_config;
+pub use signing_config::*;
 mod signing_settings;
 pub use signing_settings::*;
 mod signing;
 pub use signing::*;
+mod signing_plan;
+pub use signing_plan::*;
 pub mod specification;
 pub mod stapling;
+#[cfg(test)]
+mod testutil;
 pub mod ticket_lookup;
+pub mod time_stamp_authority;
+mod unsign;
+pub use unsign::*;
 mod verify;
 pub use verify::*;
+mod xattr_signing;
+pub use xattr_signing::*;
 #[cfg(feature = "yubikey")]
 pub mod yubikey;
+mod zip_signing;
+pub use zip_signing::*;
@@ -16,11 +16,13 @@ use {
     crate::{
         embedded_signature::{DigestType, EmbeddedSignature},
         error::AppleCodesignError,
+        macho_universal::create_universal_macho,
         signing_settings::{SettingsScope, SigningSettings},
     },
     cryptographic_message_syntax::time_stamp_message_http,
     goblin::mach::{
         constants::{SEG_LINKEDIT, SEG_TEXT},
+        cputype::get_arch_name_from_types,
         header::MH_EXECUTE,
         load_command::{
             CommandVariant, LinkeditDataCommand, LC_BUILD_VERSION, SIZEOF_LINKEDIT_DATA_COMMAND,
@@ -150,6 +152,17 @@ impl<'a> MachOBinary<'a> {
         Ok((segment.fileoff, segment.fileoff + segment.data.len() as u64))
     }
 
+    /// The name of the architecture of this binary, such as `x86_64` or `arm64`.
+    ///
+    /// Unknown architectures are rendered as `cpu_type:<N>`.
+    pub fn architecture_name(&self) -> String {
+        let header = &self.macho.header;
+
+        get_arch_name_from_types(header.cputype(), header.cpusubtype())
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| format!("cpu_type:{}", header.cputype()))
+    }
+
     /// Whether this is an executable Mach-O file.
     pub fn is_executable(&self) -> bool {
         self.macho.header.filetype == MH_EXECUTE
@@ -315,6 +328,13 @@ impl<'a> MachOBinary<'a> {
     /// embedded signature. Often linked binaries automatically contain an embedded
     /// signature containing just the code directory (without a cryptographically
     /// signed signature), so this limitation hopefully isn't impactful.
+    ///
+    /// Layout errors ([AppleCodesignError::LinkeditNotLast],
+    /// [AppleCodesignError::DataAfterSignature],
+    /// [AppleCodesignError::DataAfterLinkedit], and
+    /// [AppleCodesignError::LoadCommandNoRoom]) can often be resolved by
+    /// [crate::rewrite_macho_layout()], which [crate::MachOSigner] does
+    /// automatically.
     pub fn check_signing_capability(&self) -> Result<(), AppleCodesignError> {
         let last_segment = self
             .segments_by_file_offset()
@@ -336,44 +356,51 @@ impl<'a> MachOBinary<'a> {
         //    the last load command and the first section to write a new load
         //    command for the signature.
 
+        if self.data.len() as u64 > last_segment.fileoff + last_segment.filesize {
+            return Err(AppleCodesignError::DataAfterLinkedit);
+        }
+
         if let Some(offset) = self.code_signature_linkedit_end_offset() {
             if offset as usize == last_segment.data.len() {
                 Ok(())
             } else {
                 Err(AppleCodesignError::DataAfterSignature)
             }
+        } else if self.load_command_room()? >= SIZEOF_LINKEDIT_DATA_COMMAND {
+            Ok(())
         } else {
-            let last_load_command = self
-                .macho
-                .load_commands
-                .iter()
-                .last()
-                .ok_or_else(|| AppleCodesignError::InvalidBinary("no load commands".into()))?;
-
-            let first_section = self
-                .macho
-                .segments
-                .iter()
-                .map(|segment| segment.sections())
-                .collect::<Result<Vec<_>, _>>()?
-                .into_iter()
-                .flatten()
-                .next()
-                .ok_or_else(|| AppleCodesignError::InvalidBinary("no sections".into()))?;
-
-            let load_commands_end_offset =
-                last_load_command.offset + last_load_command.command.cmdsize();
-
-            if first_section.0.offset as usize - load_commands_end_offset
-                >= SIZEOF_LINKEDIT_DATA_COMMAND
-            {
-                Ok(())
-            } else {
-                Err(AppleCodesignError::LoadCommandNoRoom)
-            }
+            Err(AppleCodesignError::LoadCommandNoRoom)
         }
     }
 
+    /// Obtain the number of unused bytes between the load commands and the first section.
+    ///
+    /// This is the space available for writing new load commands.
+    pub fn load_command_room(&self) -> Result<usize, AppleCodesignError> {
+        let last_load_command = self
+            .macho
+            .load_commands
+            .iter()
+            .last()
+            .ok_or_else(|| AppleCodesignError::InvalidBinary("no load commands".into()))?;
+
+        let first_section = self
+            .macho
+            .segments
+            .iter()
+            .map(|segment| segment.sections())
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .flatten()
+            .next()
+            .ok_or_else(|| AppleCodesignError::InvalidBinary("no sections".into()))?;
+
+        let load_commands_end_offset =
+            last_load_command.offset + last_load_command.command.cmdsize();
+
+        Ok((first_section.0.offset as usize).saturating_sub(load_commands_end_offset))
+    }
+
     /// Estimate the size in bytes of an embedded code signature.
     pub fn estimate_embedded_signature_size(
         &self,
@@ -416,7 +443,7 @@ impl<'a> MachOBinary<'a> {
         // Obtain an actual timestamp token of placeholder data and use its length.
         // This may be excessive to actually query the time-stamp server and issue
         // a token. But these operations should be "cheap."
-        if let Some(timestamp_url) = settings.time_stamp_url() {
+        if let Some(timestamp_url) = settings.effective_time_stamp_url() {
             let message = b"deadbeef".repeat(32);
 
             if let Ok(response) =
@@ -695,6 +722,76 @@ impl<'a> MachFile<'a> {
             .get(index)
             .ok_or(AppleCodesignError::InvalidMachOIndex(index))
     }
+
+    /// Names of the architectures in this data, in the order they occur.
+    pub fn architectures(&self) -> Vec<String> {
+        self.machos
+            .iter()
+            .map(|macho| macho.architecture_name())
+            .collect()
+    }
+
+    /// Find the Mach-O binary for an architecture name, such as `arm64`.
+    pub fn find_architecture(&self, name: &str) -> Result<&MachOBinary<'a>, AppleCodesignError> {
+        self.machos
+            .iter()
+            .find(|macho| macho.architecture_name() == name)
+            .ok_or_else(|| AppleCodesignError::MachOArchitectureNotFound(name.to_string()))
+    }
+
+    /// Obtain a thin Mach-O binary for a single architecture.
+    ///
+    /// The binary's data is copied verbatim, so an existing signature is preserved.
+    pub fn extract_architecture(&self, name: &str) -> Result<Vec<u8>, AppleCodesignError> {
+        Ok(self.find_architecture(name)?.data.to_vec())
+    }
+
+    /// Obtain a universal Mach-O binary without the given architecture.
+    ///
+    /// Signatures of the remaining binaries are preserved.
+    pub fn remove_architecture(&self, name: &str) -> Result<Vec<u8>, AppleCodesignError> {
+        let removed = self.find_architecture(name)?;
+
+        let binaries = self
+            .machos
+            .iter()
+            .filter(|macho| !std::ptr::eq(*macho, removed))
+            .map(|macho| macho.data)
+            .collect::<Vec<_>>();
+
+        if binaries.is_empty() {
+            return Err(AppleCodesignError::MachOWrite(format!(
+                "cannot remove {name}: it is the only architecture"
+            )));
+        }
+
+        let mut data = vec![];
+        create_universal_macho(&mut data, binaries.into_iter())?;
+
+        Ok(data)
+    }
+
+    /// Obtain a universal Mach-O binary with a binary replaced.
+    ///
+    /// `data` is a thin Mach-O binary. It replaces the binary having the same
+    /// architecture. Signatures of all binaries are preserved.
+    pub fn replace_architecture(&self, data: &[u8]) -> Result<Vec<u8>, AppleCodesignError> {
+        let replacement = MachOBinary::parse(data)?;
+        let replaced = self.find_architecture(&replacement.architecture_name())?;
+
+        let binaries = self.machos.iter().map(|macho| {
+            if std::ptr::eq(macho, replaced) {
+                data
+            } else {
+                macho.data
+            }
+        });
+
+        let mut out = vec![];
+        create_universal_macho(&mut out, binaries)?;
+
+        Ok(out)
+    }
 }
 
 impl<'a> IntoIterator for MachFile<'a> {
@@ -881,4 +978,58 @@ mod tests {
             12 << 16 | 1 << 8 | 2
         );
     }
+
+    /// A minimal 64-bit Mach-O binary without load commands.
+    fn thin_macho(cputype: u32, cpusubtype: u32, content: &[u8]) -> Vec<u8> {
+        let mut data = [0xfeedfacf, cputype, cpusubtype, 2, 0, 0, 0, 0]
+            .iter()
+            .flat_map(|x: &u32| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        data.extend_from_slice(content);
+
+        data
+    }
+
+    #[test]
+    fn universal_architectures() -> Result<(), AppleCodesignError> {
+        let x86 = thin_macho(0x01000007, 3, b"x86");
+        let arm = thin_macho(0x0100000c, 0, b"arm");
+
+        let mut data = vec![];
+        create_universal_macho(&mut data, [x86.as_slice(), arm.as_slice()].into_iter())?;
+
+        let mach = MachFile::parse(&data)?;
+        assert_eq!(mach.architectures(), vec!["x86_64", "arm64"]);
+
+        // Binaries are aligned per CPU type.
+        let offsets = mach
+            .iter_macho()
+            .map(|macho| macho.data.as_ptr() as usize - data.as_ptr() as usize)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, vec![4096, 16384]);
+
+        assert_eq!(mach.extract_architecture("arm64")?, arm);
+        assert!(matches!(
+            mach.extract_architecture("ppc"),
+            Err(AppleCodesignError::MachOArchitectureNotFound(_))
+        ));
+
+        let arm2 = thin_macho(0x0100000c, 0, b"replaced");
+        let replaced = mach.replace_architecture(&arm2)?;
+        let replaced = MachFile::parse(&replaced)?;
+        assert_eq!(replaced.extract_architecture("arm64")?, arm2);
+        assert_eq!(replaced.extract_architecture("x86_64")?, x86);
+
+        let removed = mach.remove_architecture("x86_64")?;
+        let removed = MachFile::parse(&removed)?;
+        assert_eq!(removed.architectures(), vec!["arm64"]);
+        assert!(removed.remove_architecture("arm64").is_err());
+
+        assert!(
+            create_universal_macho(&mut vec![], [x86.as_slice(), x86.as_slice()].into_iter())
+                .is_err()
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,689 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Rewriting of Mach-O layouts so binaries can be signed.
+//!
+//! Our Mach-O writer makes assumptions about the layout of binaries (see
+//! [MachOBinary::check_signing_capability()]): `__LINKEDIT` must be the final
+//! segment, an existing signature must be at the end of `__LINKEDIT`, and there
+//! must be room after the load commands for an `LC_CODE_SIGNATURE` command
+//! if one doesn't exist. Binaries produced by some linkers or post-processed
+//! by tools like packers don't always satisfy these assumptions.
+//!
+//! [rewrite_macho_layout()] produces an equivalent Mach-O binary meeting
+//! these assumptions:
+//!
+//! * If `__LINKEDIT` isn't the final segment in the file, it is relocated to
+//!   the end of the file. If it also isn't the final segment in the virtual
+//!   address space, its virtual address is moved after the final segment.
+//! * An existing signature is removed from `__LINKEDIT` along with its
+//!   `LC_CODE_SIGNATURE` load command. Data after the signature is shifted
+//!   down to fill the hole.
+//! * Data in the file after the end of all segments is moved into
+//!   `__LINKEDIT`, growing it.
+//! * If there is no room for a new `LC_CODE_SIGNATURE` load command, the
+//!   obsolete `LC_DYLIB_CODE_SIGN_DRS` load command is removed, if present, to
+//!   make room.
+//!
+//! File offsets in segment, symbol table, dynamic symbol table, dyld info,
+//! two-level hints, and linkedit data load commands are adjusted to reflect
+//! the new location of data within `__LINKEDIT`. If data moves and the binary
+//! has load commands we don't know the layout of (such as `LC_NOTE`), the
+//! rewrite fails, as those commands could reference data that moved.
+//!
+//! Growing the space available for load commands isn't supported: that would
+//! require moving content of the `__TEXT` segment, which would invalidate
+//! addresses in the code.
+//!
+//! [remove_code_signature()] removes a signature without changing the layout
+//! when the signature is the last data in the file, falling back to
+//! [rewrite_macho_layout()] otherwise.
+
+use {
+    crate::{error::AppleCodesignError, macho::MachOBinary},
+    goblin::{
+        container::Ctx,
+        mach::{
+            constants::SEG_LINKEDIT,
+            header::{SIZEOF_HEADER_32, SIZEOF_HEADER_64},
+            load_command::{cmd_to_str, CommandVariant, SIZEOF_LINKEDIT_DATA_COMMAND},
+            parse_magic_and_ctx,
+        },
+    },
+    log::{info, warn},
+    scroll::Pwrite,
+};
+
+/// Alignment of relocated `__LINKEDIT` segments.
+///
+/// This is the page size on arm64, which is also a multiple of the x86_64
+/// page size.
+const SEGMENT_ALIGNMENT: u64 = 16384;
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    match value % alignment {
+        0 => value,
+        remainder => value + alignment - remainder,
+    }
+}
+
+/// Whether a signing capability error can be resolved by [rewrite_macho_layout()].
+pub(crate) fn is_rewritable_layout_error(e: &AppleCodesignError) -> bool {
+    matches!(
+        e,
+        AppleCodesignError::LinkeditNotLast
+            | AppleCodesignError::DataAfterSignature
+            | AppleCodesignError::DataAfterLinkedit
+            | AppleCodesignError::LoadCommandNoRoom
+    )
+}
+
+/// Maps file offsets of data in the original `__LINKEDIT` to the rewritten binary.
+///
+/// Data after the end of all segments is appended to `__LINKEDIT`. So offsets
+/// into it are mapped as well.
+struct LinkeditOffsetMapper {
+    original_start: u64,
+    original_end: u64,
+    /// Start offset of data after the end of all segments.
+    trailing_start: u64,
+    new_start: u64,
+    /// Start and end offsets of a removed signature relative to `__LINKEDIT`.
+    removed: Option<(u64, u64)>,
+}
+
+impl LinkeditOffsetMapper {
+    fn map(&self, offset: u32) -> Result<u32, AppleCodesignError> {
+        let offset = offset as u64;
+
+        // 0 means no data. Offsets into other segments don't move.
+        let mut relative = if offset == 0 || offset < self.original_start {
+            return Ok(offset as u32);
+        } else if offset <= self.original_end {
+            offset - self.original_start
+        } else if offset >= self.trailing_start {
+            self.original_end - self.original_start + offset - self.trailing_start
+        } else {
+            return Ok(offset as u32);
+        };
+
+        if let Some((start, end)) = self.removed {
+            if relative >= end {
+                relative -= end - start;
+            } else if relative > start {
+                return Err(AppleCodesignError::MachOWrite(format!(
+                    "load command references data at 0x{offset:x} within code signature"
+                )));
+            }
+        }
+
+        u32::try_from(self.new_start + relative).map_err(|_| {
+            AppleCodesignError::MachOWrite("rewritten Mach-O offset exceeds 32 bits".into())
+        })
+    }
+}
+
+/// Rewrite a Mach-O binary so its layout can be signed.
+///
+/// Returns data for a new Mach-O binary without a code signature that passes
+/// [MachOBinary::check_signing_capability()]. See the module documentation for
+/// details.
+pub fn rewrite_macho_layout(macho: &MachOBinary) -> Result<Vec<u8>, AppleCodesignError> {
+    let data = macho.data;
+
+    let ctx = parse_magic_and_ctx(data, 0)?
+        .1
+        .expect("context should have been parsed before");
+
+    let linkedit = macho
+        .macho
+        .segments
+        .iter()
+        .find(|segment| matches!(segment.name(), Ok(SEG_LINKEDIT)))
+        .ok_or(AppleCodesignError::MissingLinkedit)?;
+
+    if linkedit.nsects > 0 {
+        return Err(AppleCodesignError::MachOWrite(
+            "cannot rewrite __LINKEDIT segment containing sections".into(),
+        ));
+    }
+
+    let linkedit_start = linkedit.fileoff;
+    let linkedit_end = linkedit.fileoff + linkedit.filesize;
+
+    // Segments other than __LINKEDIT keep their file offsets and addresses.
+    let others = macho
+        .macho
+        .segments
+        .iter()
+        .filter(|segment| !matches!(segment.name(), Ok(SEG_LINKEDIT)));
+
+    let others_file_end = others
+        .clone()
+        .filter(|segment| segment.filesize > 0)
+        .map(|segment| segment.fileoff + segment.filesize)
+        .max()
+        .unwrap_or_default();
+    let others_vm_end = others
+        .map(|segment| segment.vmaddr + segment.vmsize)
+        .max()
+        .unwrap_or_default();
+
+    let segments_end = others_file_end.max(linkedit_end);
+    let trailing_data = data.get(segments_end as usize..).unwrap_or_default();
+
+    // Remove an existing signature from __LINKEDIT.
+    let removed = macho
+        .code_signature_load_command()
+        .map(|command| {
+            let start = command.dataoff as u64;
+            let end = start + command.datasize as u64;
+
+            if start < linkedit_start || end > linkedit_end {
+                Err(AppleCodesignError::MachOWrite(
+                    "code signature data outside __LINKEDIT segment".into(),
+                ))
+            } else {
+                Ok((start - linkedit_start, end - linkedit_start))
+            }
+        })
+        .transpose()?;
+
+    let mut linkedit_data = linkedit.data.to_vec();
+    if let Some((start, end)) = removed {
+        info!("removing existing {} byte code signature", end - start);
+        linkedit_data.drain(start as usize..end as usize);
+    }
+
+    if !trailing_data.is_empty() {
+        warn!(
+            "moving {} bytes after the end of Mach-O segments into __LINKEDIT",
+            trailing_data.len()
+        );
+        linkedit_data.extend_from_slice(trailing_data);
+    }
+
+    let relocate = others_file_end > linkedit_start;
+
+    let new_linkedit_start = if relocate {
+        warn!("relocating __LINKEDIT segment to the end of the Mach-O binary");
+        align_up(others_file_end, SEGMENT_ALIGNMENT)
+    } else {
+        linkedit_start
+    };
+
+    let new_linkedit_vmaddr = if linkedit.vmaddr < others_vm_end {
+        align_up(others_vm_end, SEGMENT_ALIGNMENT)
+    } else {
+        linkedit.vmaddr
+    };
+
+    let mapper = LinkeditOffsetMapper {
+        original_start: linkedit_start,
+        original_end: linkedit_end,
+        trailing_start: segments_end,
+        new_start: new_linkedit_start,
+        removed,
+    };
+
+    // Whether data in __LINKEDIT or after the segments ends up at a different offset.
+    let data_moves = relocate || removed.is_some();
+
+    // Build the file content with the new __LINKEDIT placement.
+    let mut out = data[..segments_end.min(data.len() as u64) as usize].to_vec();
+
+    if relocate {
+        // Clear the old __LINKEDIT so stale data (such as an old signature) isn't
+        // left behind.
+        out[linkedit_start as usize..linkedit_end as usize].fill(0);
+        out.resize(new_linkedit_start as usize, 0);
+    } else {
+        out.truncate(linkedit_start as usize);
+    }
+    out.extend_from_slice(&linkedit_data);
+
+    // Now rewrite the load commands. Commands are collected so some can be removed.
+    let room = macho.load_command_room()?;
+    let mut remove_drs = removed.is_none() && room < SIZEOF_LINKEDIT_DATA_COMMAND;
+
+    let mut commands = vec![];
+
+    for lc in &macho.macho.load_commands {
+        let mut command = data[lc.offset..lc.offset + lc.command.cmdsize()].to_vec();
+
+        match lc.command {
+            CommandVariant::Segment32(mut segment)
+                if matches!(segment.name(), Ok(SEG_LINKEDIT)) =>
+            {
+                segment.fileoff = new_linkedit_start as _;
+                segment.filesize = linkedit_data.len() as _;
+                segment.vmaddr = new_linkedit_vmaddr as _;
+                segment.vmsize = segment
+                    .vmsize
+                    .max(align_up(linkedit_data.len() as u64, SEGMENT_ALIGNMENT) as u32);
+                command.pwrite_with(segment, 0, ctx.le)?;
+            }
+            CommandVariant::Segment64(mut segment)
+                if matches!(segment.name(), Ok(SEG_LINKEDIT)) =>
+            {
+                segment.fileoff = new_linkedit_start;
+                segment.filesize = linkedit_data.len() as _;
+                segment.vmaddr = new_linkedit_vmaddr;
+                segment.vmsize = segment
+                    .vmsize
+                    .max(align_up(linkedit_data.len() as u64, SEGMENT_ALIGNMENT));
+                command.pwrite_with(segment, 0, ctx.le)?;
+            }
+            CommandVariant::Symtab(mut symtab) => {
+                symtab.symoff = mapper.map(symtab.symoff)?;
+                symtab.stroff = mapper.map(symtab.stroff)?;
+                command.pwrite_with(symtab, 0, ctx.le)?;
+            }
+            CommandVariant::Dysymtab(mut dysymtab) => {
+                dysymtab.tocoff = mapper.map(dysymtab.tocoff)?;
+                dysymtab.modtaboff = mapper.map(dysymtab.modtaboff)?;
+                dysymtab.extrefsymoff = mapper.map(dysymtab.extrefsymoff)?;
+                dysymtab.indirectsymoff = mapper.map(dysymtab.indirectsymoff)?;
+                dysymtab.extreloff = mapper.map(dysymtab.extreloff)?;
+                dysymtab.locreloff = mapper.map(dysymtab.locreloff)?;
+                command.pwrite_with(dysymtab, 0, ctx.le)?;
+            }
+            CommandVariant::DyldInfo(mut info) | CommandVariant::DyldInfoOnly(mut info) => {
+                info.rebase_off = mapper.map(info.rebase_off)?;
+                info.bind_off = mapper.map(info.bind_off)?;
+                info.weak_bind_off = mapper.map(info.weak_bind_off)?;
+                info.lazy_bind_off = mapper.map(info.lazy_bind_off)?;
+                info.export_off = mapper.map(info.export_off)?;
+                command.pwrite_with(info, 0, ctx.le)?;
+            }
+            CommandVariant::TwolevelHints(mut hints) => {
+                hints.offset = mapper.map(hints.offset)?;
+                command.pwrite_with(hints, 0, ctx.le)?;
+            }
+            CommandVariant::CodeSignature(_) => {
+                // The signer adds a new one.
+                continue;
+            }
+            CommandVariant::DylibCodeSignDrs(_) if remove_drs => {
+                warn!("removing LC_DYLIB_CODE_SIGN_DRS load command to make room for signature");
+                remove_drs = false;
+                continue;
+            }
+            CommandVariant::SegmentSplitInfo(mut command_data)
+            | CommandVariant::FunctionStarts(mut command_data)
+            | CommandVariant::DataInCode(mut command_data)
+            | CommandVariant::DylibCodeSignDrs(mut command_data)
+            | CommandVariant::LinkerOptimizationHint(mut command_data)
+            | CommandVariant::DyldExportsTrie(mut command_data)
+            | CommandVariant::DyldChainedFixups(mut command_data) => {
+                command_data.dataoff = mapper.map(command_data.dataoff)?;
+                command.pwrite_with(command_data, 0, ctx.le)?;
+            }
+            // Other commands known to goblin don't reference data that moves. But
+            // commands goblin doesn't know could.
+            CommandVariant::Symseg(_) | CommandVariant::Unimplemented(_) if data_moves => {
+                return Err(AppleCodesignError::MachOWrite(format!(
+                    "cannot move __LINKEDIT data with unsupported load command {} (0x{:x})",
+                    cmd_to_str(lc.command.cmd()),
+                    lc.command.cmd()
+                )));
+            }
+            _ => {}
+        }
+
+        commands.push(command);
+    }
+
+    let commands_size = commands.iter().map(|x| x.len()).sum::<usize>();
+    let original_commands_size = macho.macho.header.sizeofcmds as usize;
+
+    if removed.is_none()
+        && room + original_commands_size - commands_size < SIZEOF_LINKEDIT_DATA_COMMAND
+    {
+        return Err(AppleCodesignError::LoadCommandNoRoom);
+    }
+
+    write_load_commands(&mut out, macho, ctx, commands)?;
+
+    Ok(out)
+}
+
+/// Replace the load commands in `out` with `commands`.
+///
+/// The header is updated and space freed by removed commands is zeroed.
+fn write_load_commands(
+    out: &mut [u8],
+    macho: &MachOBinary,
+    ctx: Ctx,
+    commands: Vec<Vec<u8>>,
+) -> Result<(), AppleCodesignError> {
+    let header_size = if ctx.container.is_big() {
+        SIZEOF_HEADER_64
+    } else {
+        SIZEOF_HEADER_32
+    };
+    let original_commands_size = macho.macho.header.sizeofcmds as usize;
+
+    let mut header = macho.macho.header;
+    header.ncmds = commands.len() as _;
+    header.sizeofcmds = commands.iter().map(|x| x.len()).sum::<usize>() as _;
+    out.pwrite_with(header, 0, ctx)?;
+
+    let mut offset = header_size;
+    for command in commands {
+        out[offset..offset + command.len()].copy_from_slice(&command);
+        offset += command.len();
+    }
+    out[offset..header_size + original_commands_size].fill(0);
+
+    Ok(())
+}
+
+/// Remove the code signature from a Mach-O binary.
+///
+/// If the signature is the last data in the file, the `LC_CODE_SIGNATURE`
+/// load command is dropped and `__LINKEDIT` is truncated. No other data
+/// moves, so load commands we don't know the layout of are preserved.
+/// Otherwise the binary is rewritten with [rewrite_macho_layout()].
+pub fn remove_code_signature(macho: &MachOBinary) -> Result<Vec<u8>, AppleCodesignError> {
+    let data = macho.data;
+
+    let signature = macho
+        .code_signature_load_command()
+        .ok_or_else(|| AppleCodesignError::MachOWrite("binary is not signed".into()))?;
+    let signature_start = signature.dataoff as u64;
+    let signature_end = signature_start + signature.datasize as u64;
+
+    let linkedit = macho
+        .macho
+        .segments
+        .iter()
+        .find(|segment| matches!(segment.name(), Ok(SEG_LINKEDIT)))
+        .ok_or(AppleCodesignError::MissingLinkedit)?;
+
+    if signature_start < linkedit.fileoff
+        || signature_end != linkedit.fileoff + linkedit.filesize
+        || signature_end != data.len() as u64
+    {
+        return rewrite_macho_layout(macho);
+    }
+
+    let ctx = parse_magic_and_ctx(data, 0)?
+        .1
+        .expect("context should have been parsed before");
+
+    let mut out = data[..signature_start as usize].to_vec();
+    let linkedit_size = signature_start - linkedit.fileoff;
+
+    let mut commands = vec![];
+
+    for lc in &macho.macho.load_commands {
+        let mut command = data[lc.offset..lc.offset + lc.command.cmdsize()].to_vec();
+
+        match lc.command {
+            CommandVariant::Segment32(mut segment)
+                if matches!(segment.name(), Ok(SEG_LINKEDIT)) =>
+            {
+                segment.filesize = linkedit_size as _;
+                command.pwrite_with(segment, 0, ctx.le)?;
+            }
+            CommandVariant::Segment64(mut segment)
+                if matches!(segment.name(), Ok(SEG_LINKEDIT)) =>
+            {
+                segment.filesize = linkedit_size;
+                command.pwrite_with(segment, 0, ctx.le)?;
+            }
+            CommandVariant::CodeSignature(_) => {
+                continue;
+            }
+            _ => {}
+        }
+
+        commands.push(command);
+    }
+
+    write_load_commands(&mut out, macho, ctx, commands)?;
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            macho_signing::MachOSigner,
+            signing_settings::{SettingsScope, SigningSettings},
+            testutil::{macho_data, segment, u32s, u64s, VM_BASE},
+            verify::{verify_macho_data, VerificationProblemType},
+        },
+        goblin::mach::load_command::{
+            LC_DYLIB_CODE_SIGN_DRS, LC_FUNCTION_STARTS, LC_IDENT, LC_NOTE,
+        },
+    };
+
+    /// Resolve the symbol table data of a Mach-O binary.
+    fn symtab_data(macho: &MachOBinary) -> Vec<u8> {
+        let symtab = macho
+            .macho
+            .load_commands
+            .iter()
+            .find_map(|lc| match lc.command {
+                CommandVariant::Symtab(command) => Some(command),
+                _ => None,
+            })
+            .unwrap();
+
+        let mut res = macho.data[symtab.symoff as usize..symtab.symoff as usize + 16].to_vec();
+        res.extend(&macho.data[symtab.stroff as usize..(symtab.stroff + symtab.strsize) as usize]);
+
+        res
+    }
+
+    fn rewrite(data: &[u8]) -> Result<Vec<u8>, AppleCodesignError> {
+        let macho = MachOBinary::parse(data)?;
+        let symbols = symtab_data(&macho);
+
+        let rewritten = rewrite_macho_layout(&macho)?;
+        let new_macho = MachOBinary::parse(&rewritten)?;
+        new_macho.check_signing_capability()?;
+        assert_eq!(symtab_data(&new_macho), symbols);
+
+        Ok(rewritten)
+    }
+
+    #[test]
+    fn trailing_data() -> Result<(), AppleCodesignError> {
+        let mut data = macho_data(0x4000, &[]);
+        MachOBinary::parse(&data)?.check_signing_capability()?;
+
+        data.extend(b"trailer");
+        assert!(matches!(
+            MachOBinary::parse(&data)?.check_signing_capability(),
+            Err(AppleCodesignError::DataAfterLinkedit)
+        ));
+
+        let rewritten = rewrite(&data)?;
+        assert!(rewritten.ends_with(b"trailer"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn linkedit_not_last() -> Result<(), AppleCodesignError> {
+        let mut data = macho_data(
+            0x4000,
+            &[segment(
+                "__DATA",
+                (VM_BASE + 0x8000, 0x4000),
+                (0x8000, 0x10),
+                &[],
+            )],
+        );
+        data.resize(0x8000, 0);
+        data.extend(b"data".repeat(4));
+
+        assert!(matches!(
+            MachOBinary::parse(&data)?.check_signing_capability(),
+            Err(AppleCodesignError::LinkeditNotLast)
+        ));
+
+        let rewritten = rewrite(&data)?;
+        let macho = MachOBinary::parse(&rewritten)?;
+
+        let linkedit = macho.segments_by_file_offset().last().copied().unwrap();
+        assert_eq!(linkedit.fileoff, 0xc000);
+        assert_eq!(linkedit.vmaddr, VM_BASE + 0xc000);
+        assert_eq!(&rewritten[0x8000..0x8010], b"data".repeat(4).as_slice());
+
+        Ok(())
+    }
+
+    /// Construct a binary whose `__LINKEDIT` needs relocating and with `trailer` after it.
+    fn relocatable_macho_data(extra_commands: &[Vec<u8>], trailer: &[u8]) -> Vec<u8> {
+        let mut commands = vec![segment(
+            "__DATA",
+            (VM_BASE + 0x8000, 0x4000),
+            (0x8000, 0x10),
+            &[],
+        )];
+        commands.extend(extra_commands.iter().cloned());
+
+        let mut data = macho_data(0x4000, &commands);
+        data.resize(0x8000, 0);
+        data.extend(b"data".repeat(4));
+        data.extend(trailer);
+
+        data
+    }
+
+    #[test]
+    fn trailing_data_offsets() -> Result<(), AppleCodesignError> {
+        // Function starts data is after the end of all segments.
+        let data =
+            relocatable_macho_data(&[u32s(&[LC_FUNCTION_STARTS, 16, 0x8010, 7])], b"trailer");
+
+        let rewritten = rewrite(&data)?;
+        let macho = MachOBinary::parse(&rewritten)?;
+
+        let function_starts = macho
+            .macho
+            .load_commands
+            .iter()
+            .find_map(|lc| match lc.command {
+                CommandVariant::FunctionStarts(command) => Some(command),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(function_starts.dataoff, 0xc000 + 0x18);
+        assert_eq!(
+            &rewritten[function_starts.dataoff as usize..][..7],
+            b"trailer"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_load_command() -> Result<(), AppleCodesignError> {
+        // LC_NOTE referencing data in __LINKEDIT. goblin doesn't know its layout.
+        let mut note = u32s(&[LC_NOTE, 40]);
+        note.extend(b"owner".iter().chain([0u8; 11].iter()));
+        note.extend(u64s(&[0x4000, 8]));
+
+        // Data is moved. So the command can't be preserved.
+        let data = relocatable_macho_data(&[note.clone()], b"");
+        let res = rewrite_macho_layout(&MachOBinary::parse(&data)?);
+        assert!(matches!(res, Err(AppleCodesignError::MachOWrite(_))));
+
+        // But it can if existing data doesn't move.
+        let mut data = macho_data(0x4000, &[note.clone()]);
+        data.extend(b"trailer");
+        let rewritten = rewrite(&data)?;
+        assert!(rewritten
+            .windows(note.len())
+            .any(|window| window == note.as_slice()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_load_command_room() -> Result<(), AppleCodesignError> {
+        let filler = |size: usize| {
+            let mut res = u32s(&[LC_IDENT, size as u32]);
+            res.resize(size, 0);
+            res
+        };
+
+        let room = MachOBinary::parse(&macho_data(0x4000, &[]))?.load_command_room()?;
+
+        let data = macho_data(0x4000, &[filler(room)]);
+        assert!(matches!(
+            MachOBinary::parse(&data)?.check_signing_capability(),
+            Err(AppleCodesignError::LoadCommandNoRoom)
+        ));
+        assert!(matches!(
+            rewrite_macho_layout(&MachOBinary::parse(&data)?),
+            Err(AppleCodesignError::LoadCommandNoRoom)
+        ));
+
+        let data = macho_data(
+            0x4000,
+            &[u32s(&[LC_DYLIB_CODE_SIGN_DRS, 16, 0, 0]), filler(room - 16)],
+        );
+        let rewritten = rewrite(&data)?;
+        assert_eq!(
+            MachOBinary::parse(&rewritten)?.load_command_room()?,
+            SIZEOF_LINKEDIT_DATA_COMMAND
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_rewritten() -> Result<(), AppleCodesignError> {
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "test");
+
+        let sign = |data: &[u8]| -> Result<Vec<u8>, AppleCodesignError> {
+            let mut res = vec![];
+            MachOSigner::new(data)?.write_signed_binary(&settings, &mut res)?;
+
+            // Ad-hoc signatures are otherwise valid.
+            let problems = verify_macho_data(&res);
+            assert_eq!(problems.len(), 1);
+            assert!(matches!(
+                problems[0].problem,
+                VerificationProblemType::NoCryptographicSignature
+            ));
+
+            Ok(res)
+        };
+
+        let mut data = macho_data(
+            0x4000,
+            &[segment(
+                "__DATA",
+                (VM_BASE + 0x8000, 0x4000),
+                (0x8000, 0x10),
+                &[],
+            )],
+        );
+        data.resize(0x8000, 0);
+        data.extend(b"data".repeat(4));
+
+        let mut signed = sign(&data)?;
+
+        // Appending data forces removal of the existing signature.
+        signed.extend(b"trailer");
+        let signed = sign(&signed)?;
+        let macho = MachOBinary::parse(&signed)?;
+        assert!(macho
+            .linkedit_data_before_signature()
+            .unwrap()
+            .ends_with(b"trailer"));
+
+        Ok(())
+    }
+}
@@ -8,16 +8,18 @@
 
 use {
     crate::{
+        certificate::cms_signer_certificate,
         code_directory::{CodeDirectoryBlob, CodeSignatureFlags, ExecutableSegmentFlags},
         code_requirement::{CodeRequirementExpression, CodeRequirements, RequirementType},
         embedded_signature::{
-            BlobData, CodeSigningSlot, Digest, EntitlementsBlob, EntitlementsDerBlob,
-            RequirementSetBlob,
+            Blob, BlobData, CodeSigningSlot, DetachedSignatureBlob, Digest, EmbeddedSignature,
+            EntitlementsBlob, EntitlementsDerBlob, RequirementSetBlob,
         },
         embedded_signature_builder::EmbeddedSignatureBuilder,
         entitlements::plist_to_executable_segment_flags,
         error::AppleCodesignError,
         macho::{semver_to_macho_target_version, MachFile, MachOBinary},
+        macho_rewrite::{is_rewritable_layout_error, rewrite_macho_layout},
         macho_universal::create_universal_macho,
         policy::derive_designated_requirements,
         signing_settings::{DesignatedRequirementMode, SettingsScope, SigningSettings},
@@ -251,6 +253,53 @@ pub fn write_macho_file(
     Ok(())
 }
 
+/// Create the `RequirementSet` blob for the current configuration.
+///
+/// Designated requirements are taken from the settings or derived from the
+/// signing certificate.
+pub(crate) fn create_requirement_set_blob(
+    settings: &SigningSettings,
+) -> Result<RequirementSetBlob<'static>, AppleCodesignError> {
+    let mut requirements = CodeRequirements::default();
+
+    match settings.designated_requirement(SettingsScope::Main) {
+        DesignatedRequirementMode::Auto => {
+            // If we are using an Apple-issued cert, this should automatically
+            // derive appropriate designated requirements.
+            if let Some((_, cert)) = settings.signing_key() {
+                info!("attempting to derive code requirements from signing certificate");
+                let identifier = Some(
+                    settings
+                        .binary_identifier(SettingsScope::Main)
+                        .ok_or(AppleCodesignError::NoIdentifier)?
+                        .to_string(),
+                );
+
+                if let Some(expr) = derive_designated_requirements(cert, identifier)? {
+                    requirements.push(expr);
+                }
+            }
+        }
+        DesignatedRequirementMode::Explicit(exprs) => {
+            info!("using provided code requirements");
+            for expr in exprs {
+                requirements.push(CodeRequirementExpression::from_bytes(expr)?.0);
+            }
+        }
+    }
+
+    // Always emit a RequirementSet blob, even if empty. Without it, validation fails
+    // with `the sealed resource directory is invalid`.
+    let mut blob = RequirementSetBlob::default();
+
+    if !requirements.is_empty() {
+        info!("code requirements: {}", requirements);
+        requirements.add_to_requirement_set(&mut blob, RequirementType::Designated)?;
+    }
+
+    Ok(blob)
+}
+
 /// Mach-O binary signer.
 ///
 /// This type provides a high-level interface for signing Mach-O binaries.
@@ -306,6 +355,22 @@ impl<'data> MachOSigner<'data> {
                 let settings =
                     settings.as_nested_macho_settings(index, original_macho.macho.header.cputype());
 
+                // Binaries whose layout we can't write a signature into are rewritten
+                // to a layout we can.
+                let rewritten_data = match original_macho.check_signing_capability() {
+                    Ok(()) => None,
+                    Err(e) if is_rewritable_layout_error(&e) => {
+                        warn!("{}; rewriting Mach-O layout to make room for signature", e);
+                        Some(rewrite_macho_layout(original_macho)?)
+                    }
+                    Err(e) => return Err(e),
+                };
+                let rewritten_macho = rewritten_data
+                    .as_deref()
+                    .map(MachOBinary::parse)
+                    .transpose()?;
+                let original_macho = rewritten_macho.as_ref().unwrap_or(original_macho);
+
                 let signature_len = original_macho.estimate_embedded_signature_size(&settings)?;
 
                 // Derive an intermediate Mach-O with placeholder NULLs for signature
@@ -348,19 +413,156 @@ impl<'data> MachOSigner<'data> {
         Ok(())
     }
 
-    /// Create data constituting the SuperBlob to be embedded in the `__LINKEDIT` segment.
+    /// Write a detached signature for the Mach-O data to the given writer.
     ///
-    /// The superblob contains the code directory, any extra blobs, and an optional
-    /// CMS structure containing a cryptographic signature.
+    /// The Mach-O binary is not modified. Instead, an embedded signature SuperBlob
+    /// is created for each Mach-O within and these are wrapped in a
+    /// [DetachedSignatureBlob] keyed by CPU type, as `codesign --detached` does.
     ///
-    /// This takes an explicit Mach-O to operate on due to a circular dependency
-    /// between writing out the Mach-O and digesting its content. See the note
-    /// in [MachOSigner] for details.
-    pub fn create_superblob(
+    /// Code digests cover the binary as it exists. If the binary already has an
+    /// embedded signature, digests stop at the start of that signature.
+    pub fn write_detached_signature(
+        &self,
+        settings: &SigningSettings,
+        writer: &mut impl Write,
+    ) -> Result<(), AppleCodesignError> {
+        let signatures = self
+            .machos
+            .iter()
+            .enumerate()
+            .map(|(index, macho)| {
+                info!(
+                    "creating detached signature for Mach-O binary at index {}",
+                    index
+                );
+                let cpu_type = macho.macho.header.cputype();
+                let settings = settings.as_nested_macho_settings(index, cpu_type);
+
+                let signature_data = self.create_superblob(&settings, macho)?;
+                info!("signature size: {} bytes", signature_data.len());
+
+                Ok((cpu_type, signature_data))
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()?;
+
+        writer.write_all(&DetachedSignatureBlob::from_signatures(signatures)?.to_blob_bytes()?)?;
+
+        Ok(())
+    }
+
+    /// Whether the Mach-O data is already signed as it would be with the given settings.
+    ///
+    /// This is true if the embedded signature of every Mach-O binary holds exactly
+    /// the code directories and blobs that signing would produce and the CMS
+    /// signature (if any) is valid and made by the configured signing certificate.
+    /// When true, signing can be skipped and the existing data used as-is.
+    pub fn is_signed_with(&self, settings: &SigningSettings) -> Result<bool, AppleCodesignError> {
+        for (index, macho) in self.machos.iter().enumerate() {
+            let settings = settings.as_nested_macho_settings(index, macho.macho.header.cputype());
+
+            if macho.check_signing_capability().is_err() {
+                return Ok(false);
+            }
+
+            let (signature, command) =
+                match (macho.code_signature()?, macho.code_signature_load_command()) {
+                    (Some(signature), Some(command)) => (signature, command),
+                    _ => return Ok(false),
+                };
+
+            // Signing would rewrite load commands if the signature size differs. And
+            // code digests cover load commands.
+            if command.datasize as usize != macho.estimate_embedded_signature_size(&settings)? {
+                debug!("existing signature size differs from signing estimate");
+                return Ok(false);
+            }
+
+            let expected = self
+                .create_signature_builder(&settings, macho)?
+                .create_superblob()?;
+            let expected = EmbeddedSignature::from_bytes(&expected)?;
+
+            let existing_slots = signature
+                .blobs
+                .iter()
+                .filter(|blob| blob.slot != CodeSigningSlot::Signature)
+                .count();
+
+            if existing_slots != expected.blobs.len()
+                || !expected.blobs.iter().all(|blob| {
+                    matches!(signature.find_slot(blob.slot), Some(existing) if existing.data == blob.data)
+                })
+            {
+                debug!("existing signature blobs differ from what signing would produce");
+                return Ok(false);
+            }
+
+            let signed_data = signature.signed_data()?;
+
+            match (settings.signing_key(), signed_data) {
+                (None, None) => {}
+                (Some((_, cert)), Some(signed_data)) => {
+                    if settings.effective_time_stamp_url().is_some()
+                        && signed_data.signers().any(|signer| {
+                            !matches!(signer.time_stamp_token_signed_data(), Ok(Some(_)))
+                        })
+                    {
+                        debug!("existing signature lacks a time-stamp token");
+                        return Ok(false);
+                    }
+
+                    // The CMS signature is over the primary code directory.
+                    let cd_data = signature
+                        .find_slot(CodeSigningSlot::CodeDirectory)
+                        .map(|entry| entry.data)
+                        .unwrap_or_default();
+
+                    for signer in signed_data.signers() {
+                        if let Some(signing_time) = settings.signing_time() {
+                            if signer
+                                .signed_attributes()
+                                .and_then(|attributes| attributes.signing_time())
+                                != Some(signing_time)
+                            {
+                                debug!("existing signature has a different signing time");
+                                return Ok(false);
+                            }
+                        }
+
+                        if !matches!(
+                            cms_signer_certificate(&signed_data, signer),
+                            Some(signer_cert) if signer_cert.constructed_data() == cert.constructed_data()
+                        ) {
+                            debug!("existing signature was made by a different certificate");
+                            return Ok(false);
+                        }
+
+                        if signer
+                            .verify_signature_with_signed_data(&signed_data)
+                            .is_err()
+                            || signer.verify_message_digest_with_content(cd_data).is_err()
+                        {
+                            debug!("existing CMS signature does not verify");
+                            return Ok(false);
+                        }
+                    }
+                }
+                _ => {
+                    debug!("existing signature differs in presence of a CMS signature");
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Create a builder holding all the signature blobs except the CMS signature.
+    fn create_signature_builder(
         &self,
         settings: &SigningSettings,
         macho: &MachOBinary,
-    ) -> Result<Vec<u8>, AppleCodesignError> {
+    ) -> Result<EmbeddedSignatureBuilder<'static>, AppleCodesignError> {
         let mut builder = EmbeddedSignatureBuilder::default();
 
         for (slot, blob) in self.create_special_blobs(settings, macho.is_executable())? {
@@ -389,11 +591,31 @@ impl<'data> MachOSigner<'data> {
             }
         }
 
+        Ok(builder)
+    }
+
+    /// Create data constituting the SuperBlob to be embedded in the `__LINKEDIT` segment.
+    ///
+    /// The superblob contains the code directory, any extra blobs, and an optional
+    /// CMS structure containing a cryptographic signature.
+    ///
+    /// This takes an explicit Mach-O to operate on due to a circular dependency
+    /// between writing out the Mach-O and digesting its content. See the note
+    /// in [MachOSigner] for details.
+    pub fn create_superblob(
+        &self,
+        settings: &SigningSettings,
+        macho: &MachOBinary,
+    ) -> Result<Vec<u8>, AppleCodesignError> {
+        let mut builder = self.create_signature_builder(settings, macho)?;
+
+        builder.set_signing_time(settings.signing_time().copied());
+
         if let Some((signing_key, signing_cert)) = settings.signing_key() {
             builder.create_cms_signature(
                 signing_key,
                 signing_cert,
-                settings.time_stamp_url(),
+                settings.effective_time_stamp_url(),
                 settings.certificate_chain().iter().cloned(),
             )?;
         }
@@ -599,46 +821,10 @@ impl<'data> MachOSigner<'data> {
         settings: &SigningSettings,
         is_executable: bool,
     ) -> Result<Vec<(CodeSigningSlot, BlobData<'static>)>, AppleCodesignError> {
-        let mut res = Vec::new();
-
-        let mut requirements = CodeRequirements::default();
-
-        match settings.designated_requirement(SettingsScope::Main) {
-            DesignatedRequirementMode::Auto => {
-                // If we are using an Apple-issued cert, this should automatically
-                // derive appropriate designated requirements.
-                if let Some((_, cert)) = settings.signing_key() {
-                    info!("attempting to derive code requirements from signing certificate");
-                    let identifier = Some(
-                        settings
-                            .binary_identifier(SettingsScope::Main)
-                            .ok_or(AppleCodesignError::NoIdentifier)?
-                            .to_string(),
-                    );
-
-                    if let Some(expr) = derive_designated_requirements(cert, identifier)? {
-                        requirements.push(expr);
-                    }
-                }
-            }
-            DesignatedRequirementMode::Explicit(exprs) => {
-                info!("using provided code requirements");
-                for expr in exprs {
-                    requirements.push(CodeRequirementExpression::from_bytes(expr)?.0);
-                }
-            }
-        }
-
-        // Always emit a RequirementSet blob, even if empty. Without it, validation fails
-        // with `the sealed resource directory is invalid`.
-        let mut blob = RequirementSetBlob::default();
-
-        if !requirements.is_empty() {
-            info!("code requirements: {}", requirements);
-            requirements.add_to_requirement_set(&mut blob, RequirementType::Designated)?;
-        }
-
-        res.push((CodeSigningSlot::RequirementSet, blob.into()));
+        let mut res = vec![(
+            CodeSigningSlot::RequirementSet,
+            create_requirement_set_blob(settings)?.into(),
+        )];
 
         if let Some(entitlements) = settings.entitlements_xml(SettingsScope::Main)? {
             info!("adding entitlements XML");
@@ -664,3 +850,136 @@ impl<'data> MachOSigner<'data> {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            certificate::{create_self_signed_code_signing_certificate, CertificateProfile},
+            embedded_signature::DigestType,
+            signing_settings::SettingsScope,
+            testutil::{macho_data, rsa_signing_identity},
+        },
+        x509_certificate::KeyAlgorithm,
+    };
+
+    fn sign(data: &[u8], settings: &SigningSettings) -> Result<Vec<u8>, AppleCodesignError> {
+        let mut signed = vec![];
+        MachOSigner::new(data)?.write_signed_binary(settings, &mut signed)?;
+
+        Ok(signed)
+    }
+
+    #[test]
+    fn is_signed_with() -> Result<(), AppleCodesignError> {
+        let (signing_key, cert) = rsa_signing_identity();
+
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+        settings.set_binary_identifier(SettingsScope::Main, "com.example.test");
+        settings.set_entitlements_xml(
+            SettingsScope::Main,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>com.apple.security.app-sandbox</key>
+  <true/>
+</dict>
+</plist>"#,
+        )?;
+
+        let unsigned = macho_data(0x4000, &[]);
+        assert!(!MachOSigner::new(&unsigned)?.is_signed_with(&settings)?);
+
+        let signed = sign(&unsigned, &settings)?;
+        assert!(MachOSigner::new(&signed)?.is_signed_with(&settings)?);
+
+        let mut changed = settings.clone();
+        changed.set_binary_identifier(SettingsScope::Main, "com.example.other");
+        assert!(!MachOSigner::new(&signed)?.is_signed_with(&changed)?);
+
+        let mut changed = settings.clone();
+        changed.set_entitlements_xml(
+            SettingsScope::Main,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>com.apple.security.app-sandbox</key>
+  <false/>
+</dict>
+</plist>"#,
+        )?;
+        assert!(!MachOSigner::new(&signed)?.is_signed_with(&changed)?);
+
+        let mut changed = settings.clone();
+        changed.set_code_signature_flags(SettingsScope::Main, CodeSignatureFlags::RUNTIME);
+        assert!(!MachOSigner::new(&signed)?.is_signed_with(&changed)?);
+
+        let mut changed = settings.clone();
+        changed.set_digest_type(DigestType::Sha384);
+        assert!(!MachOSigner::new(&signed)?.is_signed_with(&changed)?);
+
+        // Ad-hoc signatures don't satisfy settings with a signing key and vice versa.
+        let mut ad_hoc = SigningSettings::default();
+        ad_hoc.set_binary_identifier(SettingsScope::Main, "com.example.test");
+        let ad_hoc_signed = sign(&unsigned, &ad_hoc)?;
+        assert!(MachOSigner::new(&ad_hoc_signed)?.is_signed_with(&ad_hoc)?);
+        assert!(!MachOSigner::new(&ad_hoc_signed)?.is_signed_with(&settings)?);
+        assert!(!MachOSigner::new(&signed)?.is_signed_with(&ad_hoc)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn is_signed_with_other_certificate() -> Result<(), AppleCodesignError> {
+        let (signing_key, cert) = rsa_signing_identity();
+        let (other_cert, other_key, _) = create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::DeveloperIdApplication,
+            "team",
+            "Joe Developer",
+            "US",
+            chrono::Duration::hours(1),
+        )?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+        settings.set_binary_identifier(SettingsScope::Main, "com.example.test");
+
+        let signed = sign(&macho_data(0x4000, &[]), &settings)?;
+
+        let mut changed = settings.clone();
+        changed.set_signing_key(&other_key, other_cert);
+        assert!(!MachOSigner::new(&signed)?.is_signed_with(&changed)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn is_signed_with_invalid_cms() -> Result<(), AppleCodesignError> {
+        let (signing_key, cert) = rsa_signing_identity();
+
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+        settings.set_binary_identifier(SettingsScope::Main, "com.example.test");
+
+        let mut signed = sign(&macho_data(0x4000, &[]), &settings)?;
+
+        // The RSA signature value is at the end of the CMS blob.
+        let offset = {
+            let mach = MachFile::parse(&signed)?;
+            let signature = mach.nth_macho(0)?.code_signature()?.unwrap();
+            let cms = signature.find_slot(CodeSigningSlot::Signature).unwrap();
+
+            cms.data.as_ptr() as usize - signed.as_ptr() as usize + cms.data.len() - 1
+        };
+        signed[offset] ^= 0xff;
+
+        assert!(!MachOSigner::new(&signed)?.is_signed_with(&settings)?);
+
+        Ok(())
+    }
+}
@@ -5,11 +5,15 @@
 use {
     anyhow::Result,
     goblin::mach::{
+        cputype::{
+            get_arch_name_from_types, CpuType, CPU_SUBTYPE_MASK, CPU_TYPE_ARM, CPU_TYPE_ARM64,
+            CPU_TYPE_ARM64_32,
+        },
         fat::{FatArch, FAT_MAGIC, SIZEOF_FAT_ARCH, SIZEOF_FAT_HEADER},
         Mach,
     },
     scroll::{IOwrite, Pwrite},
-    std::io::Write,
+    std::{collections::HashSet, io::Write},
     thiserror::Error,
 };
 
@@ -23,6 +27,9 @@ pub enum UniversalMachOError {
 
     #[error("scroll error: {0}")]
     Scroll(#[from] scroll::Error),
+
+    #[error("architecture occurs multiple times: {0}")]
+    DuplicateArchitecture(String),
 }
 
 /// Interface for constructing a universal Mach-O binary.
@@ -60,9 +67,22 @@ impl UniversalBinaryBuilder {
     }
 }
 
+/// The alignment of a binary within a universal Mach-O, as a power of 2.
+///
+/// This matches what `lipo` uses: ARM binaries are aligned to 16k pages and
+/// everything else to 4k pages.
+pub fn universal_alignment(cputype: CpuType) -> u32 {
+    match cputype {
+        CPU_TYPE_ARM | CPU_TYPE_ARM64 | CPU_TYPE_ARM64_32 => 14,
+        _ => 12,
+    }
+}
+
 /// Create a universal mach-o binary from existing mach-o binaries.
 ///
-/// The binaries will be parsed as Mach-O.
+/// The binaries will be parsed as Mach-O. Each binary is aligned according to
+/// its CPU type (see [universal_alignment()]). Each architecture can only occur
+/// once.
 ///
 /// Because the size of the individual Mach-O binaries must be written into a
 /// header, all content is buffered internally.
@@ -70,20 +90,31 @@ pub fn create_universal_macho<'a>(
     writer: &mut impl Write,
     binaries: impl Iterator<Item = &'a [u8]>,
 ) -> Result<(), UniversalMachOError> {
-    // Binaries are aligned on page boundaries. x86-64 appears to use
-    // 4k. aarch64 16k. It really doesn't appear to matter unless you want
-    // to minimize binary size, so we always use 16k.
-    const ALIGN_VALUE: u32 = 14;
-    let align: u32 = 2u32.pow(ALIGN_VALUE);
+    let binaries = binaries.collect::<Vec<_>>();
 
     let mut records = vec![];
+    let mut seen = HashSet::new();
 
-    let mut offset: u32 = align;
+    // Binaries begin after the fat header and fat arch records.
+    let mut offset = (SIZEOF_FAT_HEADER + binaries.len() * SIZEOF_FAT_ARCH) as u32;
 
     for binary in binaries {
         let macho = goblin::mach::MachO::parse(binary, 0)?;
 
-        // This will be 0 for the 1st binary.
+        let cputype = macho.header.cputype;
+        let cpusubtype = macho.header.cpusubtype;
+
+        if !seen.insert((cputype, cpusubtype & !CPU_SUBTYPE_MASK)) {
+            return Err(UniversalMachOError::DuplicateArchitecture(
+                get_arch_name_from_types(cputype, cpusubtype & !CPU_SUBTYPE_MASK)
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| format!("cpu_type:{cputype}")),
+            ));
+        }
+
+        let align_value = universal_alignment(cputype);
+        let align = 2u32.pow(align_value);
+
         let pad_bytes = match offset % align {
             0 => 0,
             x => align - x,
@@ -92,11 +123,11 @@ pub fn create_universal_macho<'a>(
         offset += pad_bytes;
 
         let arch = FatArch {
-            cputype: macho.header.cputype,
-            cpusubtype: macho.header.cpusubtype,
+            cputype,
+            cpusubtype,
             offset,
             size: binary.len() as u32,
-            align: ALIGN_VALUE,
+            align: align_value,
         };
 
         offset += arch.size;
@@ -114,13 +145,6 @@ pub fn create_universal_macho<'a>(
         writer.write_all(&buffer)?;
     }
 
-    // Pad NULL until first mach-o binary.
-    let current_offset = SIZEOF_FAT_HEADER + records.len() * SIZEOF_FAT_ARCH;
-    writer.write_all(&b"\0".repeat(align as usize - current_offset % align as usize))?;
-
-    // This input would be nonsensical. Let's not even support it.
-    assert!(current_offset <= align as usize, "too many mach-o entries");
-
     for (_, pad_bytes, macho_data) in records {
         writer.write_all(&b"\0".repeat(pad_bytes))?;
         writer.write_all(macho_data)?;
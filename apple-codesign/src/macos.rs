@@ -173,7 +173,7 @@ impl PublicKeyPeerDecrypt for KeychainCertificate {
 }
 
 impl PrivateKey for KeychainCertificate {
-    fn as_key_info_signer(&self) -> &dyn KeyInfoSigner {
+    fn as_key_info_signer(&self) -> &(dyn KeyInfoSigner + Sync) {
         self
     }
 
@@ -186,9 +186,9 @@ impl Notarizer {
             PathType::Xar => self.notarize_flat_package(path, wait_limit),
             PathType::Zip => self.notarize_flat_package(path, wait_limit),
             PathType::Dmg => self.notarize_dmg(path, wait_limit),
-            PathType::MachO | PathType::Other => Err(AppleCodesignError::NotarizeUnsupportedPath(
-                path.to_path_buf(),
-            )),
+            PathType::MachO | PathType::File | PathType::Other => Err(
+                AppleCodesignError::NotarizeUnsupportedPath(path.to_path_buf()),
+            ),
         }
     }
 
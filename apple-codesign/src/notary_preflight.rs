@@ -0,0 +1,681 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Offline checks for common notarization rejections.
+//!
+//! Apple's notary service rejects submissions for a handful of well-known
+//! reasons. Every rejection costs an upload and a wait for Apple to process
+//! the submission. The functionality in this module walks the content that
+//! would be submitted and reports the issues Apple's notary service is known
+//! to reject, without talking to Apple.
+//!
+//! The following is checked for every Mach-O binary (including each
+//! architecture in fat binaries and binaries nested in bundles, ZIP archives,
+//! and flat package payloads):
+//!
+//! * The binary is signed.
+//! * The signature isn't ad-hoc.
+//! * The signing certificate is a Developer ID Application certificate
+//!   chaining to Apple.
+//! * The signature has a secure time-stamp token.
+//! * Executables have the hardened runtime enabled.
+//! * The `com.apple.security.get-task-allow` entitlement isn't granted.
+//! * macOS binaries are built against the 10.9 SDK or newer.
+//!
+//! Flat packages must be signed by a Developer ID Installer certificate with a
+//! secure time-stamp token. Signed DMGs must be signed by a Developer ID
+//! Application certificate. The content of DMGs can't be read offline, so it
+//! isn't checked.
+//!
+//! Issues are reported in a structure resembling the JSON notarization log
+//! retrievable from Apple. Passing these checks doesn't guarantee that Apple
+//! will notarize the content.
+
+use {
+    crate::{
+        certificate::{cms_signer_certificate, AppleCertificate, CodeSigningCertificateExtension},
+        code_directory::CodeSignatureFlags,
+        embedded_signature::EmbeddedSignature,
+        error::AppleCodesignError,
+        macho::{MachFile, MachOBinary, Platform},
+        reader::{MachOType, SignatureReader},
+        zip_signing::ExtractedZip,
+    },
+    apple_bundles::DirectoryBundle,
+    apple_flat_package::PkgReader,
+    apple_xar::reader::XarReader,
+    cryptographic_message_syntax::SignedData,
+    serde::Serialize,
+    std::{fs::File, io::Read, path::Path},
+};
+
+/// URL of Apple's documentation describing notarization issues.
+const NOTARIZATION_ISSUES_URL: &str = "https://developer.apple.com/documentation/security/notarizing_macos_software_before_distribution/resolving_common_notarization_issues";
+
+/// Entitlement allowing other processes to attach to a process.
+const GET_TASK_ALLOW_ENTITLEMENT: &str = "com.apple.security.get-task-allow";
+
+/// Oldest macOS SDK accepted by the notary service.
+const MINIMUM_MACOS_SDK: semver::Version = semver::Version::new(10, 9, 0);
+
+/// Severity of a notarization preflight issue.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotaryPreflightSeverity {
+    /// Apple will reject the submission.
+    Error,
+    /// The submission may be accepted but something warrants attention.
+    Warning,
+}
+
+/// The type of a notarization preflight issue.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotaryPreflightIssueType {
+    /// A Mach-O binary or flat package isn't signed.
+    Unsigned,
+    /// A signature is ad-hoc and has no signing certificate.
+    AdHocSignature,
+    /// The signing certificate isn't a Developer ID certificate issued by Apple.
+    NotDeveloperId,
+    /// The signature doesn't have a secure time-stamp token.
+    NoSecureTimestamp,
+    /// An executable doesn't have the hardened runtime enabled.
+    NoHardenedRuntime,
+    /// The `com.apple.security.get-task-allow` entitlement is granted.
+    GetTaskAllowEntitlement,
+    /// A macOS binary was built against an SDK older than the minimum.
+    SdkTooOld,
+    /// The content of a DMG wasn't examined.
+    DmgContentNotChecked,
+}
+
+/// An issue that would likely cause Apple's notary service to reject a submission.
+///
+/// Field names mirror entries in the `issues` array of Apple's notarization log.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotaryPreflightIssue {
+    pub severity: NotaryPreflightSeverity,
+    pub code: NotaryPreflightIssueType,
+    /// Path of the problematic entity, starting with the name of the submitted path.
+    pub path: String,
+    pub message: String,
+    pub doc_url: Option<String>,
+    /// Architecture of the Mach-O binary the issue applies to.
+    pub architecture: Option<String>,
+}
+
+impl NotaryPreflightIssue {
+    fn new(
+        severity: NotaryPreflightSeverity,
+        code: NotaryPreflightIssueType,
+        path: &str,
+        architecture: Option<&str>,
+        message: impl ToString,
+    ) -> Self {
+        Self {
+            severity,
+            code,
+            path: path.to_string(),
+            message: message.to_string(),
+            doc_url: Some(NOTARIZATION_ISSUES_URL.to_string()),
+            architecture: architecture.map(|x| x.to_string()),
+        }
+    }
+
+    fn error(
+        code: NotaryPreflightIssueType,
+        path: &str,
+        architecture: Option<&str>,
+        message: impl ToString,
+    ) -> Self {
+        Self::new(
+            NotaryPreflightSeverity::Error,
+            code,
+            path,
+            architecture,
+            message,
+        )
+    }
+}
+
+impl std::fmt::Display for NotaryPreflightIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = match self.severity {
+            NotaryPreflightSeverity::Error => "error",
+            NotaryPreflightSeverity::Warning => "warning",
+        };
+
+        if let Some(arch) = &self.architecture {
+            write!(
+                f,
+                "{}: {} ({}): {}",
+                severity, self.path, arch, self.message
+            )
+        } else {
+            write!(f, "{}: {}: {}", severity, self.path, self.message)
+        }
+    }
+}
+
+/// The result of an offline notarization preflight check.
+///
+/// Serializes to JSON resembling Apple's notarization log.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotaryPreflightReport {
+    pub log_format_version: u32,
+    /// `Accepted` if no errors were found, `Invalid` otherwise.
+    pub status: String,
+    pub status_summary: String,
+    pub archive_filename: String,
+    pub issues: Vec<NotaryPreflightIssue>,
+}
+
+impl NotaryPreflightReport {
+    fn new(archive_filename: String, issues: Vec<NotaryPreflightIssue>) -> Self {
+        let errors = issues
+            .iter()
+            .filter(|issue| issue.severity == NotaryPreflightSeverity::Error)
+            .count();
+
+        let (status, status_summary) = if errors == 0 {
+            ("Accepted", "No known notarization issues found".to_string())
+        } else {
+            ("Invalid", format!("Found {errors} notarization issues"))
+        };
+
+        Self {
+            log_format_version: 1,
+            status: status.to_string(),
+            status_summary,
+            archive_filename,
+            issues,
+        }
+    }
+
+    /// Whether any issues would cause the notary service to reject the submission.
+    pub fn has_errors(&self) -> bool {
+        self.issues
+            .iter()
+            .any(|issue| issue.severity == NotaryPreflightSeverity::Error)
+    }
+}
+
+/// Check a filesystem path for issues that would cause notarization to fail.
+///
+/// Bundles, DMGs, flat packages, ZIP archives, and Mach-O binaries are
+/// supported.
+pub fn notary_preflight_path(
+    path: impl AsRef<Path>,
+) -> Result<NotaryPreflightReport, AppleCodesignError> {
+    let path = path.as_ref();
+
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string());
+
+    let issues = match SignatureReader::from_path(path)? {
+        SignatureReader::MachO(_, data) => check_macho_data(&name, &data)?,
+        SignatureReader::Bundle(bundle) => check_bundle(&bundle, bundle.root_dir(), &name)?,
+        SignatureReader::Dmg(_, dmg) => {
+            let mut issues = vec![];
+
+            if let Some(signature) = dmg.embedded_signature()? {
+                issues.extend(check_signature(&name, None, &signature)?);
+            }
+
+            issues.push(NotaryPreflightIssue::new(
+                NotaryPreflightSeverity::Warning,
+                NotaryPreflightIssueType::DmgContentNotChecked,
+                &name,
+                None,
+                "The content of the disk image was not checked.",
+            ));
+
+            issues
+        }
+        SignatureReader::FlatPackage(path) => check_flat_package(&path, &name)?,
+        SignatureReader::Zip(path) => {
+            let zip = ExtractedZip::from_path(path)?;
+
+            let mut issues = vec![];
+            for bundle_path in zip.bundle_paths()? {
+                let bundle = DirectoryBundle::new_from_path(&bundle_path)
+                    .map_err(AppleCodesignError::DirectoryBundle)?;
+
+                issues.extend(check_bundle(&bundle, zip.root_dir(), &name)?);
+            }
+
+            issues
+        }
+        SignatureReader::File(_) => {
+            return Err(AppleCodesignError::NotarizeUnsupportedPath(
+                path.to_path_buf(),
+            ));
+        }
+    };
+
+    Ok(NotaryPreflightReport::new(name, issues))
+}
+
+/// Check all Mach-O binaries in a bundle, including in nested bundles.
+///
+/// Reported paths are relative to `base_dir` and prefixed with `prefix`.
+fn check_bundle(
+    bundle: &DirectoryBundle,
+    base_dir: &Path,
+    prefix: &str,
+) -> Result<Vec<NotaryPreflightIssue>, AppleCodesignError> {
+    let mut issues = vec![];
+
+    for file in bundle
+        .files(true)
+        .map_err(AppleCodesignError::DirectoryBundle)?
+    {
+        let metadata = std::fs::symlink_metadata(file.absolute_path())?;
+
+        if !metadata.is_file() || MachOType::from_path(file.absolute_path())?.is_none() {
+            continue;
+        }
+
+        let relative_path = file
+            .absolute_path()
+            .strip_prefix(base_dir)
+            .map(|p| p.to_path_buf())
+            .unwrap_or_else(|_| file.absolute_path().to_path_buf());
+
+        let report_path = join_report_path(prefix, &relative_path);
+        let data = std::fs::read(file.absolute_path())?;
+
+        issues.extend(check_macho_data(&report_path, &data)?);
+    }
+
+    Ok(issues)
+}
+
+/// Check a flat package's signature and all Mach-O binaries in its payloads.
+fn check_flat_package(
+    path: &Path,
+    name: &str,
+) -> Result<Vec<NotaryPreflightIssue>, AppleCodesignError> {
+    let mut issues = vec![];
+
+    match XarReader::new(File::open(path)?)?.cms_signature()? {
+        Some(signed_data) => {
+            issues.extend(check_cms_signature(
+                name,
+                None,
+                &signed_data,
+                CodeSigningCertificateExtension::DeveloperIdInstaller,
+            ));
+        }
+        None => {
+            issues.push(NotaryPreflightIssue::error(
+                NotaryPreflightIssueType::Unsigned,
+                name,
+                None,
+                "The package is not signed with a Developer ID Installer certificate.",
+            ));
+        }
+    }
+
+    let mut pkg = PkgReader::new(File::open(path)?)?;
+
+    for component in pkg.component_packages()? {
+        let component_name = component
+            .package_info()
+            .map(|info| info.identifier.clone())
+            .unwrap_or_default();
+        let prefix = join_report_path(name, Path::new(&component_name));
+
+        if let Some(mut cpio) = component.payload_reader()? {
+            while let Some(header) = cpio.read_next().map_err(apple_flat_package::Error::from)? {
+                if header.file_size() == 0 {
+                    continue;
+                }
+
+                let mut data = vec![];
+                cpio.read_to_end(&mut data)?;
+
+                if MachFile::parse(&data).is_err() {
+                    continue;
+                }
+
+                let entry_path = header.name().trim_start_matches("./");
+                let report_path = join_report_path(&prefix, Path::new(entry_path));
+
+                issues.extend(check_macho_data(&report_path, &data)?);
+            }
+        }
+    }
+
+    Ok(issues)
+}
+
+fn join_report_path(prefix: &str, path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+
+    if path.is_empty() {
+        prefix.to_string()
+    } else {
+        format!("{prefix}/{path}")
+    }
+}
+
+/// Check every Mach-O binary in Mach-O data.
+fn check_macho_data(
+    report_path: &str,
+    data: &[u8],
+) -> Result<Vec<NotaryPreflightIssue>, AppleCodesignError> {
+    let mut issues = vec![];
+
+    for macho in MachFile::parse(data)?.into_iter() {
+        issues.extend(check_macho(report_path, &macho)?);
+    }
+
+    Ok(issues)
+}
+
+/// Check a single Mach-O binary.
+fn check_macho(
+    report_path: &str,
+    macho: &MachOBinary,
+) -> Result<Vec<NotaryPreflightIssue>, AppleCodesignError> {
+    let arch = macho.architecture_name();
+    let arch = Some(arch.as_str());
+
+    let mut issues = vec![];
+
+    if let Some(target) = macho.find_targeting()? {
+        if matches!(target.platform, Platform::MacOs) && target.sdk_version < MINIMUM_MACOS_SDK {
+            issues.push(NotaryPreflightIssue::error(
+                NotaryPreflightIssueType::SdkTooOld,
+                report_path,
+                arch,
+                format!(
+                    "The binary uses an SDK ({}) older than the {} SDK.",
+                    target.sdk_version, MINIMUM_MACOS_SDK
+                ),
+            ));
+        }
+    }
+
+    let signature = if let Some(signature) = macho.code_signature()? {
+        signature
+    } else {
+        issues.push(NotaryPreflightIssue::error(
+            NotaryPreflightIssueType::Unsigned,
+            report_path,
+            arch,
+            "The binary is not signed.",
+        ));
+
+        return Ok(issues);
+    };
+
+    issues.extend(check_signature(report_path, arch, &signature)?);
+
+    if macho.is_executable() {
+        let runtime = signature
+            .code_directory()?
+            .map(|cd| cd.flags.contains(CodeSignatureFlags::RUNTIME))
+            .unwrap_or_default();
+
+        if !runtime {
+            issues.push(NotaryPreflightIssue::error(
+                NotaryPreflightIssueType::NoHardenedRuntime,
+                report_path,
+                arch,
+                "The executable does not have the hardened runtime enabled.",
+            ));
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Check an embedded signature for issues.
+///
+/// This checks the signature type, certificate, time-stamp token, and
+/// entitlements. Properties of the signed code itself are not checked.
+fn check_signature(
+    report_path: &str,
+    arch: Option<&str>,
+    signature: &EmbeddedSignature,
+) -> Result<Vec<NotaryPreflightIssue>, AppleCodesignError> {
+    let mut issues = vec![];
+
+    let adhoc = signature
+        .code_directory()?
+        .map(|cd| cd.flags.contains(CodeSignatureFlags::ADHOC))
+        .unwrap_or_default();
+
+    match signature.signed_data()? {
+        Some(signed_data) if !adhoc => {
+            issues.extend(check_cms_signature(
+                report_path,
+                arch,
+                &signed_data,
+                CodeSigningCertificateExtension::DeveloperIdApplication,
+            ));
+        }
+        _ => {
+            issues.push(NotaryPreflightIssue::error(
+                NotaryPreflightIssueType::AdHocSignature,
+                report_path,
+                arch,
+                "The binary is ad-hoc signed. It must be signed with a Developer ID certificate.",
+            ));
+        }
+    }
+
+    if let Some(blob) = signature.entitlements()? {
+        let entitlements = plist::Value::from_reader_xml(blob.as_str().as_bytes())
+            .map_err(AppleCodesignError::PlistParseXml)?;
+
+        let get_task_allow = entitlements
+            .as_dictionary()
+            .and_then(|d| d.get(GET_TASK_ALLOW_ENTITLEMENT))
+            .and_then(|v| v.as_boolean())
+            .unwrap_or_default();
+
+        if get_task_allow {
+            issues.push(NotaryPreflightIssue::error(
+                NotaryPreflightIssueType::GetTaskAllowEntitlement,
+                report_path,
+                arch,
+                format!("The executable requests the {GET_TASK_ALLOW_ENTITLEMENT} entitlement."),
+            ));
+        }
+    }
+
+    Ok(issues)
+}
+
+/// Check the CMS signature of an entity.
+///
+/// `extension` is the Developer ID certificate extension the signing
+/// certificate must have.
+fn check_cms_signature(
+    report_path: &str,
+    arch: Option<&str>,
+    signed_data: &SignedData,
+    extension: CodeSigningCertificateExtension,
+) -> Vec<NotaryPreflightIssue> {
+    let mut issues = vec![];
+
+    for signer in signed_data.signers() {
+        let developer_id = cms_signer_certificate(signed_data, signer)
+            .map(|cert| {
+                cert.apple_code_signing_extensions().contains(&extension)
+                    && cert.chains_to_apple_root_ca()
+            })
+            .unwrap_or_default();
+
+        if !developer_id {
+            let kind = match extension {
+                CodeSigningCertificateExtension::DeveloperIdInstaller => "Installer",
+                _ => "Application",
+            };
+
+            issues.push(NotaryPreflightIssue::error(
+                NotaryPreflightIssueType::NotDeveloperId,
+                report_path,
+                arch,
+                format!("The signature is not from a valid Developer ID {kind} certificate issued by Apple."),
+            ));
+        }
+
+        if !matches!(signer.time_stamp_token_signed_data(), Ok(Some(_))) {
+            issues.push(NotaryPreflightIssue::error(
+                NotaryPreflightIssueType::NoSecureTimestamp,
+                report_path,
+                arch,
+                "The signature does not include a secure timestamp.",
+            ));
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            certificate::{create_self_signed_code_signing_certificate, CertificateProfile},
+            code_directory::CodeDirectoryBlob,
+            embedded_signature::{CodeSigningSlot, DigestType, EntitlementsBlob},
+            embedded_signature_builder::EmbeddedSignatureBuilder,
+        },
+        x509_certificate::KeyAlgorithm,
+    };
+
+    const ENTITLEMENTS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>com.apple.security.get-task-allow</key>
+    <true/>
+</dict>
+</plist>"#;
+
+    fn issue_types(
+        signed: bool,
+        entitlements: Option<&str>,
+    ) -> Result<Vec<NotaryPreflightIssueType>, AppleCodesignError> {
+        let mut builder = EmbeddedSignatureBuilder::default();
+
+        if let Some(entitlements) = entitlements {
+            builder.add_blob(
+                CodeSigningSlot::Entitlements,
+                EntitlementsBlob::from_string(entitlements).into(),
+            )?;
+        }
+
+        builder.add_code_directory(
+            CodeSigningSlot::CodeDirectory,
+            CodeDirectoryBlob {
+                flags: if signed {
+                    CodeSignatureFlags::empty()
+                } else {
+                    CodeSignatureFlags::ADHOC
+                },
+                ident: "com.example.test".into(),
+                digest_size: 32,
+                digest_type: DigestType::Sha256,
+                page_size: 4096,
+                ..Default::default()
+            },
+        )?;
+
+        if signed {
+            let (cert, signing_key, _) = create_self_signed_code_signing_certificate(
+                KeyAlgorithm::Ed25519,
+                CertificateProfile::DeveloperIdApplication,
+                "team",
+                "Joe Developer",
+                "US",
+                chrono::Duration::hours(1),
+            )?;
+
+            builder.create_cms_signature(&signing_key, &cert, None, std::iter::empty())?;
+        }
+
+        let data = builder.create_superblob()?;
+        let signature = EmbeddedSignature::from_bytes(&data)?;
+
+        Ok(check_signature("test", None, &signature)?
+            .into_iter()
+            .map(|issue| issue.code)
+            .collect())
+    }
+
+    #[test]
+    fn signature_issues() -> Result<(), AppleCodesignError> {
+        assert_eq!(
+            issue_types(false, None)?,
+            vec![NotaryPreflightIssueType::AdHocSignature]
+        );
+
+        // Self-signed certificates don't chain to Apple, even with the Developer ID extension.
+        assert_eq!(
+            issue_types(true, None)?,
+            vec![
+                NotaryPreflightIssueType::NotDeveloperId,
+                NotaryPreflightIssueType::NoSecureTimestamp
+            ]
+        );
+
+        assert_eq!(
+            issue_types(false, Some(ENTITLEMENTS))?,
+            vec![
+                NotaryPreflightIssueType::AdHocSignature,
+                NotaryPreflightIssueType::GetTaskAllowEntitlement
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn report_status() {
+        let report = NotaryPreflightReport::new("test.app".into(), vec![]);
+        assert_eq!(report.status, "Accepted");
+        assert!(!report.has_errors());
+
+        let report = NotaryPreflightReport::new(
+            "test.app".into(),
+            vec![NotaryPreflightIssue::new(
+                NotaryPreflightSeverity::Warning,
+                NotaryPreflightIssueType::DmgContentNotChecked,
+                "test.dmg",
+                None,
+                "warning",
+            )],
+        );
+        assert_eq!(report.status, "Accepted");
+        assert!(!report.has_errors());
+
+        let report = NotaryPreflightReport::new(
+            "test.app".into(),
+            vec![NotaryPreflightIssue::error(
+                NotaryPreflightIssueType::Unsigned,
+                "test.app/Contents/MacOS/test",
+                Some("arm64"),
+                "error",
+            )],
+        );
+        assert_eq!(report.status, "Invalid");
+        assert!(report.has_errors());
+        assert_eq!(
+            serde_json::to_value(&report.issues[0]).unwrap()["code"],
+            "unsigned"
+        );
+    }
+}
@@ -0,0 +1,909 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! PKCS#11 signing key support.
+//!
+//! This module allows signing with keys held in hardware security modules
+//! and other devices exposing a PKCS#11 interface. The vendor provided module
+//! (a shared library) is loaded at run-time.
+//!
+//! Only the small subset of the PKCS#11 API needed to find keys and
+//! certificates and create signatures is bound. Digests are computed locally
+//! and the token signs them with the `CKM_RSA_PKCS` or `CKM_ECDSA` mechanisms.
+
+use {
+    crate::{
+        cryptography::PrivateKey,
+        remote_signing::{session_negotiation::PublicKeyPeerDecrypt, RemoteSignError},
+        AppleCodesignError,
+    },
+    bcder::{encode::Values, OctetString},
+    bytes::Bytes,
+    libloading::Library,
+    log::warn,
+    signature::Signer,
+    std::{
+        ffi::{c_ulong, c_void, OsStr},
+        fmt::{Display, Formatter},
+        sync::{Arc, Mutex, MutexGuard},
+    },
+    x509_certificate::{
+        rfc3447::DigestInfo, CapturedX509Certificate, EcdsaCurve, KeyAlgorithm, KeyInfoSigner,
+        Sign, Signature, SignatureAlgorithm, X509CertificateError,
+    },
+};
+
+type CkUlong = c_ulong;
+type CkRv = CkUlong;
+type CkSlotId = CkUlong;
+type CkSessionHandle = CkUlong;
+type CkObjectHandle = CkUlong;
+
+const CKR_OK: CkRv = 0;
+const CKR_USER_ALREADY_LOGGED_IN: CkRv = 0x100;
+const CKR_CRYPTOKI_ALREADY_INITIALIZED: CkRv = 0x191;
+
+const CKF_RW_SESSION: CkUlong = 0x2;
+const CKF_SERIAL_SESSION: CkUlong = 0x4;
+
+const CKU_USER: CkUlong = 1;
+
+const CKA_CLASS: CkUlong = 0x0;
+const CKA_LABEL: CkUlong = 0x3;
+const CKA_VALUE: CkUlong = 0x11;
+const CKA_ID: CkUlong = 0x102;
+
+const CKO_CERTIFICATE: CkUlong = 1;
+const CKO_PRIVATE_KEY: CkUlong = 3;
+
+const CKM_RSA_PKCS: CkUlong = 0x1;
+const CKM_ECDSA: CkUlong = 0x1041;
+
+const CK_UNAVAILABLE_INFORMATION: CkUlong = !0;
+
+// Structures are packed on Windows.
+#[repr(C)]
+#[cfg_attr(windows, repr(packed))]
+struct CkVersion {
+    major: u8,
+    minor: u8,
+}
+
+#[repr(C)]
+#[cfg_attr(windows, repr(packed))]
+struct CkAttribute {
+    typ: CkUlong,
+    value: *mut c_void,
+    value_len: CkUlong,
+}
+
+#[repr(C)]
+#[cfg_attr(windows, repr(packed))]
+struct CkMechanism {
+    mechanism: CkUlong,
+    parameter: *mut c_void,
+    parameter_len: CkUlong,
+}
+
+#[repr(C)]
+#[cfg_attr(windows, repr(packed))]
+struct CkTokenInfo {
+    label: [u8; 32],
+    manufacturer_id: [u8; 32],
+    model: [u8; 16],
+    serial_number: [u8; 16],
+    flags: CkUlong,
+    max_session_count: CkUlong,
+    session_count: CkUlong,
+    max_rw_session_count: CkUlong,
+    rw_session_count: CkUlong,
+    max_pin_len: CkUlong,
+    min_pin_len: CkUlong,
+    total_public_memory: CkUlong,
+    free_public_memory: CkUlong,
+    total_private_memory: CkUlong,
+    free_private_memory: CkUlong,
+    hardware_version: CkVersion,
+    firmware_version: CkVersion,
+    utc_time: [u8; 16],
+}
+
+type Unused = *const c_void;
+
+/// Leading entries of `CK_FUNCTION_LIST`.
+///
+/// Only functions up to `C_Sign` are declared. We only ever access the
+/// structure through the pointer handed out by the module.
+#[repr(C)]
+#[cfg_attr(windows, repr(packed))]
+struct CkFunctionList {
+    version: CkVersion,
+    initialize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    finalize: unsafe extern "C" fn(*mut c_void) -> CkRv,
+    _get_info: Unused,
+    _get_function_list: Unused,
+    get_slot_list: unsafe extern "C" fn(u8, *mut CkSlotId, *mut CkUlong) -> CkRv,
+    _get_slot_info: Unused,
+    get_token_info: unsafe extern "C" fn(CkSlotId, *mut CkTokenInfo) -> CkRv,
+    _get_mechanism_list: Unused,
+    _get_mechanism_info: Unused,
+    _init_token: Unused,
+    _init_pin: Unused,
+    _set_pin: Unused,
+    open_session: unsafe extern "C" fn(
+        CkSlotId,
+        CkUlong,
+        *mut c_void,
+        *const c_void,
+        *mut CkSessionHandle,
+    ) -> CkRv,
+    close_session: unsafe extern "C" fn(CkSessionHandle) -> CkRv,
+    _close_all_sessions: Unused,
+    _get_session_info: Unused,
+    _get_operation_state: Unused,
+    _set_operation_state: Unused,
+    login: unsafe extern "C" fn(CkSessionHandle, CkUlong, *const u8, CkUlong) -> CkRv,
+    _logout: Unused,
+    _create_object: Unused,
+    _copy_object: Unused,
+    _destroy_object: Unused,
+    _get_object_size: Unused,
+    get_attribute_value:
+        unsafe extern "C" fn(CkSessionHandle, CkObjectHandle, *mut CkAttribute, CkUlong) -> CkRv,
+    _set_attribute_value: Unused,
+    find_objects_init: unsafe extern "C" fn(CkSessionHandle, *mut CkAttribute, CkUlong) -> CkRv,
+    find_objects:
+        unsafe extern "C" fn(CkSessionHandle, *mut CkObjectHandle, CkUlong, *mut CkUlong) -> CkRv,
+    find_objects_final: unsafe extern "C" fn(CkSessionHandle) -> CkRv,
+    _encrypt_init: Unused,
+    _encrypt: Unused,
+    _encrypt_update: Unused,
+    _encrypt_final: Unused,
+    _decrypt_init: Unused,
+    _decrypt: Unused,
+    _decrypt_update: Unused,
+    _decrypt_final: Unused,
+    _digest_init: Unused,
+    _digest: Unused,
+    _digest_update: Unused,
+    _digest_key: Unused,
+    _digest_final: Unused,
+    sign_init: unsafe extern "C" fn(CkSessionHandle, *mut CkMechanism, CkObjectHandle) -> CkRv,
+    sign: unsafe extern "C" fn(CkSessionHandle, *const u8, CkUlong, *mut u8, *mut CkUlong) -> CkRv,
+}
+
+fn check(function: &'static str, rv: CkRv) -> Result<(), AppleCodesignError> {
+    if rv == CKR_OK {
+        Ok(())
+    } else {
+        Err(AppleCodesignError::Pkcs11Function(function, rv))
+    }
+}
+
+/// Convert a PKCS#11 space padded string to a Rust string.
+fn padded_string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).trim_end().to_string()
+}
+
+/// Describes how to find objects on a token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ObjectSelector {
+    /// Match the `CKA_LABEL` attribute.
+    Label(String),
+    /// Match the `CKA_ID` attribute.
+    Id(Vec<u8>),
+}
+
+impl Display for ObjectSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Label(label) => f.write_fmt(format_args!("label {label}")),
+            Self::Id(id) => f.write_fmt(format_args!("ID {}", hex::encode(id))),
+        }
+    }
+}
+
+/// Pointer to the function list of a loaded module.
+struct Functions(*const CkFunctionList);
+
+impl std::ops::Deref for Functions {
+    type Target = CkFunctionList;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.0 }
+    }
+}
+
+/// A loaded PKCS#11 module.
+pub struct Pkcs11Module {
+    functions: Mutex<Functions>,
+    // Must outlive `functions`.
+    _library: Library,
+}
+
+// Modules are initialized without locking callbacks, so they may not be called
+// from multiple threads at once. Every call into the module holds the lock on
+// `functions`, which serializes access across all sessions and threads.
+unsafe impl Send for Pkcs11Module {}
+unsafe impl Sync for Pkcs11Module {}
+
+impl Drop for Pkcs11Module {
+    fn drop(&mut self) {
+        unsafe {
+            (self.functions().finalize)(std::ptr::null_mut());
+        }
+    }
+}
+
+impl Pkcs11Module {
+    /// Load and initialize the PKCS#11 module at the given path.
+    pub fn load(path: impl AsRef<OsStr>) -> Result<Self, AppleCodesignError> {
+        let library = unsafe { Library::new(path)? };
+
+        let mut functions = std::ptr::null::<CkFunctionList>();
+        unsafe {
+            let get_function_list = library.get::<unsafe extern "C" fn(
+                *mut *const CkFunctionList,
+            ) -> CkRv>(b"C_GetFunctionList\0")?;
+
+            check("C_GetFunctionList", get_function_list(&mut functions))?;
+        }
+
+        if functions.is_null() {
+            return Err(AppleCodesignError::Pkcs11Function("C_GetFunctionList", 0));
+        }
+
+        let rv = unsafe { ((*functions).initialize)(std::ptr::null_mut()) };
+        if rv != CKR_CRYPTOKI_ALREADY_INITIALIZED {
+            check("C_Initialize", rv)?;
+        }
+
+        Ok(Self {
+            functions: Mutex::new(Functions(functions)),
+            _library: library,
+        })
+    }
+
+    /// Obtain exclusive access to the module's functions.
+    ///
+    /// The lock must be held for the duration of every call into the module.
+    fn functions(&self) -> MutexGuard<'_, Functions> {
+        // A panic while holding the lock doesn't leave any Rust state inconsistent.
+        self.functions
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Obtain the slot IDs and labels of tokens present in the module.
+    pub fn tokens(&self) -> Result<Vec<(c_ulong, String)>, AppleCodesignError> {
+        let f = self.functions();
+
+        let mut count = 0;
+        check("C_GetSlotList", unsafe {
+            (f.get_slot_list)(1, std::ptr::null_mut(), &mut count)
+        })?;
+
+        let mut slots = vec![0; count as usize];
+        check("C_GetSlotList", unsafe {
+            (f.get_slot_list)(1, slots.as_mut_ptr(), &mut count)
+        })?;
+        slots.truncate(count as usize);
+
+        slots
+            .into_iter()
+            .map(|slot| {
+                let mut info = std::mem::MaybeUninit::<CkTokenInfo>::zeroed();
+                check("C_GetTokenInfo", unsafe {
+                    (f.get_token_info)(slot, info.as_mut_ptr())
+                })?;
+                let info = unsafe { info.assume_init() };
+
+                Ok((slot, padded_string(&info.label)))
+            })
+            .collect()
+    }
+
+    /// Open a session with the token having the given label.
+    ///
+    /// If no label is given, the first token is used. If a PIN is given, the
+    /// session is logged in as the normal user.
+    pub fn open_session(
+        self: &Arc<Self>,
+        token_label: Option<&str>,
+        pin: Option<&[u8]>,
+    ) -> Result<Pkcs11Session, AppleCodesignError> {
+        let (slot, label) = self
+            .tokens()?
+            .into_iter()
+            .find(|(_, label)| token_label.map_or(true, |wanted| wanted == label))
+            .ok_or_else(|| {
+                AppleCodesignError::Pkcs11NoToken(token_label.unwrap_or("(any)").to_string())
+            })?;
+
+        warn!("using PKCS#11 token {} in slot {}", label, slot);
+
+        let f = self.functions();
+
+        let mut handle = 0;
+        check("C_OpenSession", unsafe {
+            (f.open_session)(
+                slot,
+                CKF_SERIAL_SESSION | CKF_RW_SESSION,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &mut handle,
+            )
+        })?;
+
+        let login_rv = pin
+            .map(|pin| unsafe { (f.login)(handle, CKU_USER, pin.as_ptr(), pin.len() as CkUlong) });
+        drop(f);
+
+        // Closing the session on error needs the lock released above.
+        let session = Pkcs11Session {
+            module: self.clone(),
+            handle,
+        };
+
+        if let Some(rv) = login_rv {
+            if rv != CKR_USER_ALREADY_LOGGED_IN {
+                check("C_Login", rv)?;
+            }
+        }
+
+        Ok(session)
+    }
+}
+
+/// A session with a PKCS#11 token.
+pub struct Pkcs11Session {
+    module: Arc<Pkcs11Module>,
+    handle: CkSessionHandle,
+}
+
+impl Drop for Pkcs11Session {
+    fn drop(&mut self) {
+        unsafe {
+            (self.module.functions().close_session)(self.handle);
+        }
+    }
+}
+
+impl Pkcs11Session {
+    fn find_objects(
+        &self,
+        class: CkUlong,
+        selector: &ObjectSelector,
+    ) -> Result<Vec<CkObjectHandle>, AppleCodesignError> {
+        let f = self.module.functions();
+
+        let mut class = class;
+        let mut value = match selector {
+            ObjectSelector::Label(label) => label.as_bytes().to_vec(),
+            ObjectSelector::Id(id) => id.clone(),
+        };
+
+        let mut template = [
+            CkAttribute {
+                typ: CKA_CLASS,
+                value: &mut class as *mut CkUlong as *mut c_void,
+                value_len: std::mem::size_of::<CkUlong>() as CkUlong,
+            },
+            CkAttribute {
+                typ: match selector {
+                    ObjectSelector::Label(_) => CKA_LABEL,
+                    ObjectSelector::Id(_) => CKA_ID,
+                },
+                value: value.as_mut_ptr() as *mut c_void,
+                value_len: value.len() as CkUlong,
+            },
+        ];
+
+        check("C_FindObjectsInit", unsafe {
+            (f.find_objects_init)(
+                self.handle,
+                template.as_mut_ptr(),
+                template.len() as CkUlong,
+            )
+        })?;
+
+        let mut objects = vec![];
+        let res = loop {
+            let mut batch = [0; 16];
+            let mut count = 0;
+
+            let rv = unsafe {
+                (f.find_objects)(
+                    self.handle,
+                    batch.as_mut_ptr(),
+                    batch.len() as CkUlong,
+                    &mut count,
+                )
+            };
+            if let Err(e) = check("C_FindObjects", rv) {
+                break Err(e);
+            }
+
+            if count == 0 {
+                break Ok(());
+            }
+
+            objects.extend_from_slice(&batch[0..count as usize]);
+        };
+
+        // The search must be finalized even if it failed.
+        let rv = unsafe { (f.find_objects_final)(self.handle) };
+        res?;
+        check("C_FindObjectsFinal", rv)?;
+
+        Ok(objects)
+    }
+
+    fn attribute(
+        &self,
+        object: CkObjectHandle,
+        typ: CkUlong,
+    ) -> Result<Vec<u8>, AppleCodesignError> {
+        let f = self.module.functions();
+
+        let mut template = CkAttribute {
+            typ,
+            value: std::ptr::null_mut(),
+            value_len: 0,
+        };
+
+        check("C_GetAttributeValue", unsafe {
+            (f.get_attribute_value)(self.handle, object, &mut template, 1)
+        })?;
+
+        if template.value_len == CK_UNAVAILABLE_INFORMATION {
+            return Ok(vec![]);
+        }
+
+        let mut value = vec![0u8; template.value_len as usize];
+        template.value = value.as_mut_ptr() as *mut c_void;
+
+        check("C_GetAttributeValue", unsafe {
+            (f.get_attribute_value)(self.handle, object, &mut template, 1)
+        })?;
+        value.truncate(template.value_len as usize);
+
+        Ok(value)
+    }
+
+    fn sign(
+        &self,
+        key: CkObjectHandle,
+        mechanism: CkUlong,
+        data: &[u8],
+    ) -> Result<Vec<u8>, AppleCodesignError> {
+        let f = self.module.functions();
+
+        let mut mechanism = CkMechanism {
+            mechanism,
+            parameter: std::ptr::null_mut(),
+            parameter_len: 0,
+        };
+
+        check("C_SignInit", unsafe {
+            (f.sign_init)(self.handle, &mut mechanism, key)
+        })?;
+
+        // Query the signature length, then sign.
+        let mut len = 0;
+        check("C_Sign", unsafe {
+            (f.sign)(
+                self.handle,
+                data.as_ptr(),
+                data.len() as CkUlong,
+                std::ptr::null_mut(),
+                &mut len,
+            )
+        })?;
+
+        let mut signature = vec![0u8; len as usize];
+        check("C_Sign", unsafe {
+            (f.sign)(
+                self.handle,
+                data.as_ptr(),
+                data.len() as CkUlong,
+                signature.as_mut_ptr(),
+                &mut len,
+            )
+        })?;
+        signature.truncate(len as usize);
+
+        Ok(signature)
+    }
+
+    /// Obtain a signer for a private key and its certificate.
+    ///
+    /// The certificate is found using the same selector as the key. Failing
+    /// that, a certificate having the `CKA_ID` of the key is used.
+    pub fn certificate_signer(
+        self,
+        selector: &ObjectSelector,
+    ) -> Result<CertificateSigner, AppleCodesignError> {
+        let key = *self
+            .find_objects(CKO_PRIVATE_KEY, selector)?
+            .first()
+            .ok_or_else(|| AppleCodesignError::Pkcs11NoKey(selector.to_string()))?;
+
+        let mut certificates = self.find_objects(CKO_CERTIFICATE, selector)?;
+        if certificates.is_empty() {
+            let id = self.attribute(key, CKA_ID)?;
+
+            if !id.is_empty() {
+                certificates = self.find_objects(CKO_CERTIFICATE, &ObjectSelector::Id(id))?;
+            }
+        }
+
+        let certificate = *certificates
+            .first()
+            .ok_or_else(|| AppleCodesignError::Pkcs11NoCertificate(selector.to_string()))?;
+
+        let cert = CapturedX509Certificate::from_der(self.attribute(certificate, CKA_VALUE)?)?;
+
+        Ok(CertificateSigner {
+            session: Arc::new(Mutex::new(self)),
+            key,
+            cert,
+        })
+    }
+}
+
+/// Encode a PKCS#11 ECDSA signature (`r || s`) as a DER `ECDSA-Sig-Value`.
+fn ecdsa_signature_der(signature: &[u8]) -> Vec<u8> {
+    let (r, s) = signature.split_at(signature.len() / 2);
+
+    let integer = |value: &[u8]| {
+        let value = match value.iter().position(|x| *x != 0) {
+            Some(start) => &value[start..],
+            None => &[0u8][..],
+        };
+
+        let mut res = vec![0x02];
+        if value[0] & 0x80 != 0 {
+            res.extend(der_length(value.len() + 1));
+            res.push(0);
+        } else {
+            res.extend(der_length(value.len()));
+        }
+        res.extend_from_slice(value);
+
+        res
+    };
+
+    let body = [integer(r), integer(s)].concat();
+
+    let mut res = vec![0x30];
+    res.extend(der_length(body.len()));
+    res.extend(body);
+
+    res
+}
+
+fn der_length(len: usize) -> Vec<u8> {
+    if len < 0x80 {
+        vec![len as u8]
+    } else {
+        let bytes = len.to_be_bytes();
+        let start = bytes
+            .iter()
+            .position(|x| *x != 0)
+            .unwrap_or(bytes.len() - 1);
+
+        let mut res = vec![0x80 | (bytes.len() - start) as u8];
+        res.extend_from_slice(&bytes[start..]);
+
+        res
+    }
+}
+
+/// Entity for creating signatures using a private key on a PKCS#11 token.
+#[derive(Clone)]
+pub struct CertificateSigner {
+    session: Arc<Mutex<Pkcs11Session>>,
+    key: CkObjectHandle,
+    cert: CapturedX509Certificate,
+}
+
+impl CertificateSigner {
+    pub fn certificate(&self) -> &CapturedX509Certificate {
+        &self.cert
+    }
+
+    fn key_algorithm_required(&self) -> Result<KeyAlgorithm, X509CertificateError> {
+        self.cert
+            .key_algorithm()
+            .ok_or(X509CertificateError::UnknownKeyAlgorithm(format!(
+                "{:?}",
+                self.cert.key_algorithm_oid()
+            )))
+    }
+}
+
+impl Signer<Signature> for CertificateSigner {
+    fn try_sign(&self, message: &[u8]) -> Result<Signature, signature::Error> {
+        let key_algorithm = self
+            .key_algorithm_required()
+            .map_err(signature::Error::from_source)?;
+        let digest_algorithm = self
+            .signature_algorithm()
+            .map_err(signature::Error::from_source)?
+            .digest_algorithm()
+            .ok_or(X509CertificateError::UnknownDigestAlgorithm(
+                "unable to resolve digest algorithm from signature algorithm".into(),
+            ))
+            .map_err(signature::Error::from_source)?;
+
+        let digest = digest_algorithm.digest_data(message);
+
+        // The token applies PKCS#1 padding to the DigestInfo for RSA. ECDSA signs
+        // the raw digest.
+        let (mechanism, data) = match key_algorithm {
+            KeyAlgorithm::Rsa => {
+                let digest_info = DigestInfo {
+                    algorithm: digest_algorithm.into(),
+                    digest: OctetString::new(digest.into()),
+                };
+
+                let mut der = vec![];
+                digest_info
+                    .write_encoded(bcder::Mode::Der, &mut der)
+                    .map_err(signature::Error::from_source)?;
+
+                (CKM_RSA_PKCS, der)
+            }
+            KeyAlgorithm::Ecdsa(_) => (CKM_ECDSA, digest),
+            KeyAlgorithm::Ed25519 => {
+                return Err(signature::Error::from_source(
+                    X509CertificateError::UnknownKeyAlgorithm(
+                        "unable to use ed25519 keys with PKCS#11".into(),
+                    ),
+                ))
+            }
+        };
+
+        let session = self.session.lock().map_err(|_| {
+            signature::Error::from_source("unable to acquire lock on PKCS#11 session")
+        })?;
+
+        let signature = session
+            .sign(self.key, mechanism, &data)
+            .map_err(signature::Error::from_source)?;
+
+        Ok(Signature::from(match key_algorithm {
+            KeyAlgorithm::Ecdsa(_) => ecdsa_signature_der(&signature),
+            _ => signature,
+        }))
+    }
+}
+
+impl Sign for CertificateSigner {
+    fn sign(&self, message: &[u8]) -> Result<(Vec<u8>, SignatureAlgorithm), X509CertificateError> {
+        let algorithm = self.signature_algorithm()?;
+
+        Ok((self.try_sign(message)?.into(), algorithm))
+    }
+
+    fn key_algorithm(&self) -> Option<KeyAlgorithm> {
+        self.cert.key_algorithm()
+    }
+
+    fn public_key_data(&self) -> Bytes {
+        self.cert.public_key_data()
+    }
+
+    fn signature_algorithm(&self) -> Result<SignatureAlgorithm, X509CertificateError> {
+        match self.key_algorithm_required()? {
+            KeyAlgorithm::Rsa => Ok(SignatureAlgorithm::RsaSha256),
+            KeyAlgorithm::Ecdsa(EcdsaCurve::Secp256r1) => Ok(SignatureAlgorithm::EcdsaSha256),
+            KeyAlgorithm::Ecdsa(EcdsaCurve::Secp384r1) => Ok(SignatureAlgorithm::EcdsaSha384),
+            KeyAlgorithm::Ed25519 => Err(X509CertificateError::UnknownKeyAlgorithm(
+                "unable to use ed25519 keys with PKCS#11".into(),
+            )),
+        }
+    }
+
+    fn private_key_data(&self) -> Option<Vec<u8>> {
+        // We never have access to private keys stored on tokens.
+        None
+    }
+
+    fn rsa_primes(&self) -> Result<Option<(Vec<u8>, Vec<u8>)>, X509CertificateError> {
+        Ok(None)
+    }
+}
+
+impl KeyInfoSigner for CertificateSigner {}
+
+impl PublicKeyPeerDecrypt for CertificateSigner {
+    fn decrypt(&self, _ciphertext: &[u8]) -> Result<Vec<u8>, RemoteSignError> {
+        Err(RemoteSignError::Crypto(
+            "decryption is not supported with PKCS#11 keys".into(),
+        ))
+    }
+}
+
+impl PrivateKey for CertificateSigner {
+    fn as_key_info_signer(&self) -> &(dyn KeyInfoSigner + Sync) {
+        self
+    }
+
+    fn to_public_key_peer_decrypt(
+        &self,
+    ) -> Result<Box<dyn PublicKeyPeerDecrypt>, AppleCodesignError> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn finish(&self) -> Result<(), AppleCodesignError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::testutil::rsa_signing_identity,
+        cryptographic_message_syntax::{SignedData, SignedDataBuilder, SignerBuilder},
+        std::{path::PathBuf, process::Command},
+    };
+
+    const SOFTHSM2_MODULES: &[&str] = &[
+        "/usr/lib/softhsm/libsofthsm2.so",
+        "/usr/lib/x86_64-linux-gnu/softhsm/libsofthsm2.so",
+        "/usr/lib64/pkcs11/libsofthsm2.so",
+        "/usr/local/lib/softhsm/libsofthsm2.so",
+        "/opt/homebrew/lib/softhsm/libsofthsm2.so",
+    ];
+
+    /// Locate the SoftHSM2 module, preferring `SOFTHSM2_MODULE`.
+    fn softhsm2_module() -> PathBuf {
+        std::env::var_os("SOFTHSM2_MODULE")
+            .map(PathBuf::from)
+            .or_else(|| {
+                SOFTHSM2_MODULES
+                    .iter()
+                    .map(PathBuf::from)
+                    .find(|path| path.exists())
+            })
+            .expect("libsofthsm2 not found; set SOFTHSM2_MODULE to its path")
+    }
+
+    fn softhsm2_util(conf: &std::path::Path, args: &[&str]) {
+        let status = Command::new("softhsm2-util")
+            .env("SOFTHSM2_CONF", conf)
+            .args(args)
+            .status()
+            .expect("unable to run softhsm2-util");
+
+        assert!(status.success(), "softhsm2-util {:?} failed", args);
+    }
+
+    /// Sign through a SoftHSM2 token.
+    ///
+    /// Run with `cargo test --features pkcs11 -- --ignored softhsm2`. The test
+    /// fails rather than passing if SoftHSM2 can't be found.
+    #[test]
+    #[ignore = "requires SoftHSM2 (libsofthsm2 and softhsm2-util)"]
+    fn softhsm2_cms_signing() {
+        let module_path = softhsm2_module();
+        let td = tempfile::TempDir::new().unwrap();
+
+        let tokens = td.path().join("tokens");
+        std::fs::create_dir(&tokens).unwrap();
+        let conf = td.path().join("softhsm2.conf");
+        std::fs::write(
+            &conf,
+            format!(
+                "directories.tokendir = {}\nobjectstore.backend = file\nlog.level = ERROR\n",
+                tokens.display()
+            ),
+        )
+        .unwrap();
+
+        let (_, cert) = rsa_signing_identity();
+        let key_path = td.path().join("key.pem");
+        std::fs::write(
+            &key_path,
+            pem::encode(&pem::Pem::new(
+                "PRIVATE KEY",
+                include_bytes!("testdata/rsa-2048.pk8").to_vec(),
+            )),
+        )
+        .unwrap();
+        let cert_path = td.path().join("cert.pem");
+        std::fs::write(&cert_path, cert.encode_pem()).unwrap();
+
+        softhsm2_util(
+            &conf,
+            &[
+                "--init-token",
+                "--free",
+                "--label",
+                "test",
+                "--so-pin",
+                "5678",
+                "--pin",
+                "1234",
+            ],
+        );
+        for (path, import_type) in [(&key_path, "keypair"), (&cert_path, "cert")] {
+            softhsm2_util(
+                &conf,
+                &[
+                    "--import",
+                    path.to_str().unwrap(),
+                    "--import-type",
+                    import_type,
+                    "--token",
+                    "test",
+                    "--label",
+                    "signer",
+                    "--id",
+                    "01",
+                    "--pin",
+                    "1234",
+                ],
+            );
+        }
+
+        // The module reads its configuration when initialized.
+        std::env::set_var("SOFTHSM2_CONF", &conf);
+        let module = Arc::new(Pkcs11Module::load(&module_path).unwrap());
+
+        assert!(module
+            .tokens()
+            .unwrap()
+            .iter()
+            .any(|(_, label)| label == "test"));
+
+        let signer = module
+            .open_session(Some("test"), Some(b"1234"))
+            .unwrap()
+            .certificate_signer(&ObjectSelector::Label("signer".into()))
+            .unwrap();
+        assert_eq!(signer.certificate(), &cert);
+
+        let cms = SignedDataBuilder::default()
+            .certificate(cert.clone())
+            .content_inline(b"hello, world".to_vec())
+            .signer(SignerBuilder::new(&signer, cert))
+            .build_der()
+            .unwrap();
+
+        let signed_data = SignedData::parse_ber(&cms).unwrap();
+        for signer in signed_data.signers() {
+            signer
+                .verify_signature_with_signed_data(&signed_data)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn ecdsa_signature_encoding() {
+        // High bits require a leading zero. Leading zeros are stripped.
+        let mut signature = vec![0x80; 32];
+        signature.extend([0x00, 0x00, 0x01]);
+        signature.extend([0x7f; 29]);
+
+        let der = ecdsa_signature_der(&signature);
+
+        let mut expected = vec![0x30, 0x43, 0x02, 0x21, 0x00];
+        expected.extend([0x80; 32]);
+        expected.extend([0x02, 0x1e, 0x01]);
+        expected.extend([0x7f; 29]);
+
+        assert_eq!(der, expected);
+    }
+
+    #[test]
+    fn der_lengths() {
+        assert_eq!(der_length(0x7f), vec![0x7f]);
+        assert_eq!(der_length(0x80), vec![0x81, 0x80]);
+        assert_eq!(der_length(0x1234), vec![0x82, 0x12, 0x34]);
+    }
+}
@@ -43,19 +43,19 @@ static POLICY_MAC_DEVELOPER_ID: Lazy<CodeRequirementExpression<'static>> = Lazy:
             Box::new(CodeRequirementExpression::AnchorAppleGeneric),
             Box::new(CodeRequirementExpression::CertificateGeneric(
                 1,
-                CertificateAuthorityExtension::DeveloperId.as_oid(),
+                CertificateAuthorityExtension::DeveloperId.as_oid_cow(),
                 CodeRequirementMatchExpression::Exists,
             )),
         )),
         Box::new(CodeRequirementExpression::Or(
             Box::new(CodeRequirementExpression::CertificateGeneric(
                 0,
-                CodeSigningCertificateExtension::DeveloperIdInstaller.as_oid(),
+                CodeSigningCertificateExtension::DeveloperIdInstaller.as_oid_cow(),
                 CodeRequirementMatchExpression::Exists,
             )),
             Box::new(CodeRequirementExpression::CertificateGeneric(
                 0,
-                CodeSigningCertificateExtension::DeveloperIdApplication.as_oid(),
+                CodeSigningCertificateExtension::DeveloperIdApplication.as_oid_cow(),
                 CodeRequirementMatchExpression::Exists,
             )),
         )),
@@ -74,13 +74,13 @@ static POLICY_NOTARIZED_EXECUTABLE: Lazy<CodeRequirementExpression<'static>> = L
                 Box::new(CodeRequirementExpression::AnchorAppleGeneric),
                 Box::new(CodeRequirementExpression::CertificateGeneric(
                     1,
-                    CertificateAuthorityExtension::DeveloperId.as_oid(),
+                    CertificateAuthorityExtension::DeveloperId.as_oid_cow(),
                     CodeRequirementMatchExpression::Exists,
                 )),
             )),
             Box::new(CodeRequirementExpression::CertificateGeneric(
                 0,
-                CodeSigningCertificateExtension::DeveloperIdApplication.as_oid(),
+                CodeSigningCertificateExtension::DeveloperIdApplication.as_oid_cow(),
                 CodeRequirementMatchExpression::Exists,
             )),
         )),
@@ -100,19 +100,19 @@ static POLICY_NOTARIZED_INSTALLER: Lazy<CodeRequirementExpression<'static>> = La
                 Box::new(CodeRequirementExpression::AnchorAppleGeneric),
                 Box::new(CodeRequirementExpression::CertificateGeneric(
                     1,
-                    CertificateAuthorityExtension::DeveloperId.as_oid(),
+                    CertificateAuthorityExtension::DeveloperId.as_oid_cow(),
                     CodeRequirementMatchExpression::Exists,
                 )),
             )),
             Box::new(CodeRequirementExpression::Or(
                 Box::new(CodeRequirementExpression::CertificateGeneric(
                     0,
-                    CodeSigningCertificateExtension::DeveloperIdInstaller.as_oid(),
+                    CodeSigningCertificateExtension::DeveloperIdInstaller.as_oid_cow(),
                     CodeRequirementMatchExpression::Exists,
                 )),
                 Box::new(CodeRequirementExpression::CertificateGeneric(
                     0,
-                    CodeSigningCertificateExtension::DeveloperIdApplication.as_oid(),
+                    CodeSigningCertificateExtension::DeveloperIdApplication.as_oid_cow(),
                     CodeRequirementMatchExpression::Exists,
                 )),
             )),
@@ -167,6 +167,17 @@ impl Deref for ExecutionPolicy {
     }
 }
 
+impl ExecutionPolicy {
+    /// Obtain the string values that variants are recognized as.
+    pub fn str_names() -> [&'static str; 3] {
+        [
+            "developer-id-signed",
+            "developer-id-notarized-executable",
+            "developer-id-notarized-installer",
+        ]
+    }
+}
+
 impl TryFrom<&str> for ExecutionPolicy {
     type Error = AppleCodesignError;
 
@@ -236,7 +247,8 @@ pub fn derive_designated_requirements(
                     // That cert was signed by a CA with WWDR extension.
                     Box::new(CodeRequirementExpression::CertificateGeneric(
                         1,
-                        CertificateAuthorityExtension::AppleWorldwideDeveloperRelations.as_oid(),
+                        CertificateAuthorityExtension::AppleWorldwideDeveloperRelations
+                            .as_oid_cow(),
                         CodeRequirementMatchExpression::Exists,
                     )),
                 )),
@@ -265,14 +277,14 @@ pub fn derive_designated_requirements(
                     // Certificate issued by CA with Developer ID extension.
                     Box::new(CodeRequirementExpression::CertificateGeneric(
                         1,
-                        CertificateAuthorityExtension::DeveloperId.as_oid(),
+                        CertificateAuthorityExtension::DeveloperId.as_oid_cow(),
                         CodeRequirementMatchExpression::Exists,
                     )),
                     Box::new(CodeRequirementExpression::And(
                         // A certificate entrusted with Developer ID Application signing rights.
                         Box::new(CodeRequirementExpression::CertificateGeneric(
                             0,
-                            CodeSigningCertificateExtension::DeveloperIdApplication.as_oid(),
+                            CodeSigningCertificateExtension::DeveloperIdApplication.as_oid_cow(),
                             CodeRequirementMatchExpression::Exists,
                         )),
                         // Signed by this team ID.
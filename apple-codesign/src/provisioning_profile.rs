@@ -0,0 +1,512 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Provisioning profiles.
+//!
+//! Provisioning profiles (`.mobileprovision` and `.provisionprofile` files) are
+//! CMS signed data structures whose content is an XML plist. The plist defines
+//! which certificates may sign an application, which entitlements the application
+//! may use, and which devices the application may run on.
+//!
+//! Profiles are issued by Apple and can be obtained from the Apple Developer
+//! portal or via the App Store Connect API. When signing, a profile is embedded
+//! in the bundle as `embedded.mobileprovision` (iOS and other shallow bundles) or
+//! `Contents/embedded.provisionprofile` (macOS bundles).
+
+use {
+    crate::error::AppleCodesignError,
+    chrono::{DateTime, Utc},
+    cryptographic_message_syntax::SignedData,
+    std::path::Path,
+    x509_certificate::CapturedX509Certificate,
+};
+
+/// Filename of an embedded provisioning profile in shallow bundles.
+pub const EMBEDDED_MOBILE_PROVISION: &str = "embedded.mobileprovision";
+
+/// Filename of an embedded provisioning profile in `Contents/` of macOS bundles.
+pub const EMBEDDED_PROVISION_PROFILE: &str = "embedded.provisionprofile";
+
+/// Entitlement key prefixes that don't need to be granted by a profile.
+///
+/// These are the App Sandbox and Hardened Runtime entitlements, which any
+/// application may request.
+const UNRESTRICTED_ENTITLEMENT_PREFIXES: &[&str] = &["com.apple.security."];
+
+/// A parsed provisioning profile.
+#[derive(Clone, Debug)]
+pub struct ProvisioningProfile {
+    /// Raw CMS data constituting the profile.
+    data: Vec<u8>,
+    /// The plist that is the signed content of the CMS data.
+    plist: plist::Dictionary,
+}
+
+impl ProvisioningProfile {
+    /// Construct an instance from raw profile data.
+    ///
+    /// The data is the BER encoded CMS signed data of a `.mobileprovision` or
+    /// `.provisionprofile` file. The CMS signature is not verified. Call
+    /// [Self::verify_signature] to do that.
+    pub fn from_der(data: impl Into<Vec<u8>>) -> Result<Self, AppleCodesignError> {
+        let data = data.into();
+
+        let signed_data = SignedData::parse_ber(&data)?;
+
+        let content = signed_data.signed_content().ok_or_else(|| {
+            AppleCodesignError::ProvisioningProfileMalformed("no signed content".into())
+        })?;
+
+        let plist = plist::Value::from_reader_xml(content)
+            .map_err(AppleCodesignError::PlistParseXml)?
+            .into_dictionary()
+            .ok_or_else(|| {
+                AppleCodesignError::ProvisioningProfileMalformed(
+                    "content is not a plist dictionary".into(),
+                )
+            })?;
+
+        Ok(Self { data, plist })
+    }
+
+    /// Construct an instance by reading a file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, AppleCodesignError> {
+        Self::from_der(std::fs::read(path.as_ref())?)
+    }
+
+    /// Obtain the raw data constituting this profile.
+    ///
+    /// This is the content that is embedded in bundles.
+    pub fn as_der(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Obtain the parsed CMS signed data wrapping the profile.
+    pub fn signed_data(&self) -> Result<SignedData, AppleCodesignError> {
+        Ok(SignedData::parse_ber(&self.data)?)
+    }
+
+    /// Verify the CMS signature of this profile.
+    ///
+    /// This verifies that the signature is internally consistent. It doesn't
+    /// verify that the signer is trusted.
+    pub fn verify_signature(&self) -> Result<(), AppleCodesignError> {
+        let signed_data = self.signed_data()?;
+
+        for signer in signed_data.signers() {
+            signer.verify_signature_with_signed_data(&signed_data)?;
+            signer.verify_message_digest_with_signed_data(&signed_data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Obtain the plist that is the content of the profile.
+    pub fn plist(&self) -> &plist::Dictionary {
+        &self.plist
+    }
+
+    fn string(&self, key: &str) -> Option<&str> {
+        self.plist.get(key).and_then(|v| v.as_string())
+    }
+
+    fn strings(&self, key: &str) -> Vec<&str> {
+        self.plist
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|values| values.iter().filter_map(|v| v.as_string()).collect())
+            .unwrap_or_default()
+    }
+
+    fn date(&self, key: &str) -> Option<DateTime<Utc>> {
+        self.plist
+            .get(key)
+            .and_then(|v| v.as_date())
+            .map(|date| DateTime::<Utc>::from(std::time::SystemTime::from(date)))
+    }
+
+    /// The name of the profile.
+    pub fn name(&self) -> Option<&str> {
+        self.string("Name")
+    }
+
+    /// The UUID of the profile.
+    pub fn uuid(&self) -> Option<&str> {
+        self.string("UUID")
+    }
+
+    /// The name of the App ID the profile is for.
+    pub fn app_id_name(&self) -> Option<&str> {
+        self.string("AppIDName")
+    }
+
+    /// The team identifiers the profile is issued to.
+    pub fn team_identifiers(&self) -> Vec<&str> {
+        self.strings("TeamIdentifier")
+    }
+
+    /// The name of the team the profile is issued to.
+    pub fn team_name(&self) -> Option<&str> {
+        self.string("TeamName")
+    }
+
+    /// The platforms the profile is valid for (e.g. `iOS` or `OSX`).
+    pub fn platforms(&self) -> Vec<&str> {
+        self.strings("Platform")
+    }
+
+    /// The entitlements the profile grants.
+    pub fn entitlements(&self) -> Option<&plist::Dictionary> {
+        self.plist
+            .get("Entitlements")
+            .and_then(|v| v.as_dictionary())
+    }
+
+    /// The certificates that may sign code using this profile.
+    pub fn developer_certificates(
+        &self,
+    ) -> Result<Vec<CapturedX509Certificate>, AppleCodesignError> {
+        self.plist
+            .get("DeveloperCertificates")
+            .and_then(|v| v.as_array())
+            .map(|values| values.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|v| {
+                let data = v.as_data().ok_or_else(|| {
+                    AppleCodesignError::ProvisioningProfileMalformed(
+                        "DeveloperCertificates entry is not data".into(),
+                    )
+                })?;
+
+                Ok(CapturedX509Certificate::from_der(data.to_vec())?)
+            })
+            .collect()
+    }
+
+    /// Device identifiers the profile is restricted to.
+    ///
+    /// Empty for profiles that aren't restricted to specific devices.
+    pub fn provisioned_devices(&self) -> Vec<&str> {
+        self.strings("ProvisionedDevices")
+    }
+
+    /// Whether the profile is valid for all devices.
+    pub fn provisions_all_devices(&self) -> bool {
+        self.plist
+            .get("ProvisionsAllDevices")
+            .and_then(|v| v.as_boolean())
+            .unwrap_or(false)
+    }
+
+    /// When the profile was created.
+    pub fn creation_date(&self) -> Option<DateTime<Utc>> {
+        self.date("CreationDate")
+    }
+
+    /// When the profile expires.
+    pub fn expiration_date(&self) -> Option<DateTime<Utc>> {
+        self.date("ExpirationDate")
+    }
+
+    /// Whether the profile is expired at the given time.
+    pub fn is_expired_at(&self, time: DateTime<Utc>) -> bool {
+        self.expiration_date()
+            .map(|expiration| expiration < time)
+            .unwrap_or(false)
+    }
+
+    /// Whether the profile allows code to be signed by a certificate.
+    pub fn allows_certificate(
+        &self,
+        cert: &CapturedX509Certificate,
+    ) -> Result<bool, AppleCodesignError> {
+        Ok(self
+            .developer_certificates()?
+            .iter()
+            .any(|c| c.constructed_data() == cert.constructed_data()))
+    }
+
+    /// Verify that requested entitlements are granted by this profile.
+    ///
+    /// String values in the profile ending with `*` match any value with
+    /// that prefix. Arrays of requested values must have each value matched by
+    /// the profile. App Sandbox and Hardened Runtime entitlements
+    /// (`com.apple.security.*`) don't need to be granted by the profile.
+    pub fn check_entitlements(
+        &self,
+        entitlements: &plist::Value,
+    ) -> Result<(), AppleCodesignError> {
+        let requested = entitlements.as_dictionary().ok_or_else(|| {
+            AppleCodesignError::ProvisioningProfileMalformed(
+                "entitlements are not a dictionary".into(),
+            )
+        })?;
+
+        let empty = plist::Dictionary::new();
+        let allowed = self.entitlements().unwrap_or(&empty);
+
+        for (key, value) in requested {
+            if UNRESTRICTED_ENTITLEMENT_PREFIXES
+                .iter()
+                .any(|prefix| key.starts_with(prefix))
+            {
+                continue;
+            }
+
+            match allowed.get(key) {
+                Some(allowed) if value_allowed(allowed, value) => {}
+                _ => {
+                    return Err(
+                        AppleCodesignError::ProvisioningProfileEntitlementNotAllowed(key.clone()),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Verify that the profile can be used to sign with the given certificate and entitlements.
+    ///
+    /// The profile must not be expired, must allow the certificate, and must grant
+    /// the entitlements.
+    pub fn check_signing(
+        &self,
+        cert: &CapturedX509Certificate,
+        entitlements: Option<&plist::Value>,
+    ) -> Result<(), AppleCodesignError> {
+        if let Some(expiration) = self.expiration_date() {
+            if self.is_expired_at(Utc::now()) {
+                return Err(AppleCodesignError::ProvisioningProfileExpired(expiration));
+            }
+        }
+
+        if !self.allows_certificate(cert)? {
+            return Err(
+                AppleCodesignError::ProvisioningProfileCertificateNotAllowed(
+                    cert.subject_common_name().unwrap_or_default(),
+                ),
+            );
+        }
+
+        if let Some(entitlements) = entitlements {
+            self.check_entitlements(entitlements)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn value_allowed(allowed: &plist::Value, requested: &plist::Value) -> bool {
+    match (allowed, requested) {
+        // Requesting that an entitlement not be granted is always allowed.
+        (_, plist::Value::Boolean(false)) => true,
+        (plist::Value::String(allowed), plist::Value::String(requested)) => {
+            if let Some(prefix) = allowed.strip_suffix('*') {
+                requested.starts_with(prefix)
+            } else {
+                allowed == requested
+            }
+        }
+        (plist::Value::Array(allowed), plist::Value::String(_)) => allowed
+            .iter()
+            .any(|allowed| value_allowed(allowed, requested)),
+        (allowed, plist::Value::Array(requested)) => requested
+            .iter()
+            .all(|requested| value_allowed(allowed, requested)),
+        (allowed, requested) => allowed == requested,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{certificate::CertificateProfile, create_self_signed_code_signing_certificate},
+        cryptographic_message_syntax::{SignedDataBuilder, SignerBuilder},
+        x509_certificate::KeyAlgorithm,
+    };
+
+    const ENTITLEMENTS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>application-identifier</key>
+    <string>TEAM123.com.example.app</string>
+    <key>keychain-access-groups</key>
+    <array>
+        <string>TEAM123.com.example.shared</string>
+    </array>
+    <key>get-task-allow</key>
+    <false/>
+    <key>com.apple.security.app-sandbox</key>
+    <true/>
+</dict>
+</plist>
+"#;
+
+    fn profile_data(cert: &CapturedX509Certificate, validity: chrono::Duration) -> Vec<u8> {
+        let (signer_cert, signing_key, _) = create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::DeveloperIdApplication,
+            "apple",
+            "Profile Signer",
+            "US",
+            chrono::Duration::days(1),
+        )
+        .unwrap();
+
+        let mut entitlements = plist::Dictionary::new();
+        entitlements.insert(
+            "application-identifier".into(),
+            "TEAM123.com.example.*".into(),
+        );
+        entitlements.insert(
+            "keychain-access-groups".into(),
+            plist::Value::Array(vec!["TEAM123.*".into()]),
+        );
+        entitlements.insert("get-task-allow".into(), true.into());
+
+        let mut dict = plist::Dictionary::new();
+        dict.insert("Name".into(), "Test Profile".into());
+        dict.insert(
+            "TeamIdentifier".into(),
+            plist::Value::Array(vec!["TEAM123".into()]),
+        );
+        dict.insert(
+            "ExpirationDate".into(),
+            plist::Value::Date(std::time::SystemTime::from(Utc::now() + validity).into()),
+        );
+        dict.insert("Entitlements".into(), entitlements.into());
+        dict.insert(
+            "DeveloperCertificates".into(),
+            plist::Value::Array(vec![plist::Value::Data(cert.encode_der().unwrap())]),
+        );
+
+        let mut content = vec![];
+        plist::Value::from(dict)
+            .to_writer_xml(&mut content)
+            .unwrap();
+
+        SignedDataBuilder::default()
+            .content_inline(content)
+            .signer(SignerBuilder::new(&signing_key, signer_cert))
+            .build_der()
+            .unwrap()
+    }
+
+    fn certificate() -> CapturedX509Certificate {
+        create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::AppleDevelopment,
+            "TEAM123",
+            "Joe Developer",
+            "US",
+            chrono::Duration::days(1),
+        )
+        .unwrap()
+        .0
+    }
+
+    #[test]
+    fn parse_and_check() -> Result<(), AppleCodesignError> {
+        let cert = certificate();
+        let other = certificate();
+
+        let profile =
+            ProvisioningProfile::from_der(profile_data(&cert, chrono::Duration::days(7)))?;
+        profile.verify_signature()?;
+
+        assert_eq!(profile.name(), Some("Test Profile"));
+        assert_eq!(profile.team_identifiers(), vec!["TEAM123"]);
+        assert!(!profile.provisions_all_devices());
+        assert_eq!(profile.developer_certificates()?, vec![cert.clone()]);
+
+        let entitlements = plist::Value::from_reader_xml(ENTITLEMENTS.as_bytes())
+            .map_err(AppleCodesignError::PlistParseXml)?;
+        profile.check_signing(&cert, Some(&entitlements))?;
+
+        assert!(matches!(
+            profile.check_signing(&other, None),
+            Err(AppleCodesignError::ProvisioningProfileCertificateNotAllowed(_))
+        ));
+
+        let mut denied = entitlements.as_dictionary().unwrap().clone();
+        denied.insert(
+            "application-identifier".into(),
+            "OTHER.com.example.app".into(),
+        );
+        denied.insert("aps-environment".into(), "production".into());
+        assert!(matches!(
+            profile.check_entitlements(&denied.into()),
+            Err(AppleCodesignError::ProvisioningProfileEntitlementNotAllowed(_))
+        ));
+
+        let expired =
+            ProvisioningProfile::from_der(profile_data(&cert, chrono::Duration::days(-1)))?;
+        assert!(matches!(
+            expired.check_signing(&cert, None),
+            Err(AppleCodesignError::ProvisioningProfileExpired(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn embed_in_bundle() -> Result<(), AppleCodesignError> {
+        let (cert, key, _) = create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::AppleDevelopment,
+            "TEAM123",
+            "Joe Developer",
+            "US",
+            chrono::Duration::days(1),
+        )?;
+        let other = certificate();
+
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("source.bundle");
+        let dest = temp_dir.path().join("dest.bundle");
+        std::fs::create_dir(&source)?;
+        std::fs::write(
+            source.join("Info.plist"),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>CFBundleIdentifier</key>
+    <string>com.example.app</string>
+</dict>
+</plist>
+"#,
+        )?;
+        std::fs::write(source.join(EMBEDDED_MOBILE_PROVISION), b"stale")?;
+
+        let signer = crate::BundleSigner::new_from_path(&source)?;
+
+        let mut settings = crate::SigningSettings::default();
+        settings.set_signing_key(&key, other.clone());
+        settings.set_provisioning_profile(
+            crate::SettingsScope::Main,
+            ProvisioningProfile::from_der(profile_data(&cert, chrono::Duration::days(7)))?,
+        );
+        assert!(matches!(
+            signer.write_signed_bundle(&dest, &settings),
+            Err(AppleCodesignError::ProvisioningProfileCertificateNotAllowed(_))
+        ));
+        assert!(!dest.exists());
+
+        settings.set_signing_key(&key, cert.clone());
+        signer.write_signed_bundle(&dest, &settings)?;
+
+        let embedded = ProvisioningProfile::from_path(dest.join(EMBEDDED_MOBILE_PROVISION))?;
+        assert_eq!(embedded.name(), Some("Test Profile"));
+
+        let resources = std::fs::read_to_string(dest.join("_CodeSignature/CodeResources"))?;
+        assert!(resources.contains(EMBEDDED_MOBILE_PROVISION));
+
+        Ok(())
+    }
+}
@@ -6,13 +6,16 @@
 
 use {
     crate::{
-        certificate::AppleCertificate,
+        certificate::{cms_signer_certificate, AppleCertificate},
         code_directory::CodeDirectoryBlob,
         dmg::{path_is_dmg, DmgReader},
         embedded_signature::{BlobEntry, DigestType, EmbeddedSignature},
         embedded_signature_builder::{CD_DIGESTS_OID, CD_DIGESTS_PLIST_OID},
         error::AppleCodesignError,
         macho::{MachFile, MachOBinary},
+        verify::time_stamp_token_info,
+        xattr_signing::read_signature_xattrs,
+        zip_signing::ExtractedZip,
     },
     apple_bundles::{DirectoryBundle, DirectoryBundleFile},
     apple_xar::{
@@ -31,10 +34,10 @@ use {
         ops::Deref,
         path::{Path, PathBuf},
     },
-    x509_certificate::{CapturedX509Certificate, DigestAlgorithm},
+    x509_certificate::{rfc3280::GeneralName, CapturedX509Certificate, DigestAlgorithm},
 };
 
-enum MachOType {
+pub(crate) enum MachOType {
     Mach,
     MachO,
 }
@@ -54,7 +57,9 @@ impl MachOType {
 
         match magic {
             FAT_MAGIC => Ok(Some(Self::Mach)),
-            _ if parse_magic_and_ctx(&header, 0).is_ok() => Ok(Some(Self::MachO)),
+            _ if matches!(parse_magic_and_ctx(&header, 0), Ok((_, Some(_)))) => {
+                Ok(Some(Self::MachO))
+            }
             _ => Ok(None),
         }
     }
@@ -97,6 +102,8 @@ pub enum PathType {
     Bundle,
     Xar,
     Zip,
+    /// A regular file that isn't any of the above, such as a script.
+    File,
     Other,
 }
 
@@ -115,7 +122,7 @@ impl PathType {
             } else {
                 match MachOType::from_path(path)? {
                     Some(MachOType::Mach | MachOType::MachO) => Ok(Self::MachO),
-                    None => Ok(Self::Other),
+                    None => Ok(Self::File),
                 }
             }
         } else if path.is_dir() {
@@ -263,6 +270,10 @@ pub struct CmsSigner {
     pub signature_verifies: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub time_stamp_token: Option<CmsSignature>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_stamp_time: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_stamp_authority: Option<String>,
 }
 
 impl CmsSigner {
@@ -275,6 +286,8 @@ impl CmsSigner {
         let mut message_digest = None;
         let mut signing_time = None;
         let mut time_stamp_token = None;
+        let mut time_stamp_time = None;
+        let mut time_stamp_authority = None;
         let mut cdhash_plist = vec![];
         let mut cdhash_digests = vec![];
 
@@ -316,10 +329,8 @@ impl CmsSigner {
                                     let oid = bcder::Oid::take_from(cons)?;
                                     let value = bcder::OctetString::take_from(cons)?;
 
-                                    cdhash_digests.push((
-                                        format!("{oid}"),
-                                        hex::encode(value.into_bytes()),
-                                    ));
+                                    cdhash_digests
+                                        .push((format!("{oid}"), hex::encode(value.into_bytes())));
 
                                     Ok(())
                                 })?;
@@ -342,6 +353,21 @@ impl CmsSigner {
         attributes.sort();
 
         if let Some(tsk) = signer_info.time_stamp_token_signed_data()? {
+            if let Some(tst_info) = time_stamp_token_info(&tsk) {
+                time_stamp_time = Some(tst_info.gen_time.into());
+
+                // Prefer the TSA name in the token, falling back to the subject of
+                // the certificate that signed the token.
+                time_stamp_authority = match tst_info.tsa {
+                    Some(GeneralName::DirectoryName(name)) => name.user_friendly_str().ok(),
+                    _ => tsk
+                        .signers()
+                        .next()
+                        .and_then(|signer| cms_signer_certificate(&tsk, signer))
+                        .and_then(|cert| cert.subject_name().user_friendly_str().ok()),
+                };
+            }
+
             time_stamp_token = Some(tsk.try_into()?);
         }
 
@@ -365,6 +391,8 @@ impl CmsSigner {
                 .is_ok(),
 
             time_stamp_token,
+            time_stamp_time,
+            time_stamp_authority,
         })
     }
 }
@@ -548,6 +576,11 @@ pub struct DmgEntity {
     pub signature: Option<CodeSignature>,
 }
 
+#[derive(Clone, Debug, Serialize)]
+pub struct XattrEntity {
+    pub signature: Option<CodeSignature>,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub enum CodeSignatureFile {
     ResourcesXml(Vec<String>),
@@ -748,6 +781,7 @@ pub enum SignatureEntity {
     BundleCodeSignatureFile(CodeSignatureFile),
     XarTableOfContents(XarTableOfContents),
     XarMember(XarFile),
+    Xattr(XattrEntity),
     Other,
 }
 
@@ -803,6 +837,8 @@ pub enum SignatureReader {
     MachO(PathBuf, Vec<u8>),
     Bundle(Box<DirectoryBundle>),
     FlatPackage(PathBuf),
+    Zip(PathBuf),
+    File(PathBuf),
 }
 
 impl SignatureReader {
@@ -828,7 +864,9 @@ impl SignatureReader {
                 Ok(Self::MachO(path.to_path_buf(), data))
             }
             PathType::Xar => Ok(Self::FlatPackage(path.to_path_buf())),
-            PathType::Zip | PathType::Other => Err(AppleCodesignError::UnrecognizedPathType),
+            PathType::Zip => Ok(Self::Zip(path.to_path_buf())),
+            PathType::File => Ok(Self::File(path.to_path_buf())),
+            PathType::Other => Err(AppleCodesignError::UnrecognizedPathType),
         }
     }
 
@@ -844,9 +882,26 @@ impl SignatureReader {
             Self::MachO(path, data) => Self::resolve_macho_entities_from_data(path, data, None),
             Self::Bundle(bundle) => Self::resolve_bundle_entities(bundle),
             Self::FlatPackage(path) => Self::resolve_flat_package_entities(path),
+            Self::Zip(path) => Self::resolve_zip_entities(path),
+            Self::File(path) => {
+                let mut entity = FileEntity::from_path(path, None)?;
+                entity.entity = SignatureEntity::Xattr(Self::resolve_xattr_entity(path)?);
+
+                Ok(vec![entity])
+            }
         }
     }
 
+    fn resolve_xattr_entity(path: &Path) -> Result<XattrEntity, AppleCodesignError> {
+        let signature = if let Some(data) = read_signature_xattrs(path)? {
+            Some(EmbeddedSignature::from_bytes(&data)?.try_into()?)
+        } else {
+            None
+        };
+
+        Ok(XattrEntity { signature })
+    }
+
     fn resolve_dmg_entity(dmg: &DmgReader) -> Result<DmgEntity, AppleCodesignError> {
         let signature = if let Some(sig) = dmg.embedded_signature()? {
             Some(sig.try_into()?)
@@ -986,7 +1041,13 @@ impl SignatureReader {
                     entities.extend(extra);
                 }
                 Err(_) => {
-                    // Just some extra file.
+                    // Just some extra file. Though it may be signed via extended attributes.
+                    if let Ok(Some(data)) = read_signature_xattrs(file.absolute_path()) {
+                        default_entity.entity = SignatureEntity::Xattr(XattrEntity {
+                            signature: Some(EmbeddedSignature::from_bytes(&data)?.try_into()?),
+                        });
+                    }
+
                     entities.push(default_entity);
                 }
             }
@@ -1018,4 +1079,33 @@ impl SignatureReader {
 
         Ok(entities)
     }
+
+    fn resolve_zip_entities(path: &Path) -> Result<Vec<FileEntity>, AppleCodesignError> {
+        let zip = ExtractedZip::from_path(path)?;
+
+        let bundles = zip.bundle_paths()?;
+        if bundles.is_empty() {
+            return Err(AppleCodesignError::ZipNoBundles);
+        }
+
+        let mut entities = vec![];
+
+        // Report paths are relative to the root of the archive.
+        for bundle_path in bundles {
+            let bundle = DirectoryBundle::new_from_path(&bundle_path)
+                .map_err(AppleCodesignError::DirectoryBundle)?;
+
+            for file in bundle
+                .files(true)
+                .map_err(AppleCodesignError::DirectoryBundle)?
+            {
+                entities.extend(Self::resolve_bundle_file_entity(
+                    zip.root_dir().to_path_buf(),
+                    file,
+                )?);
+            }
+        }
+
+        Ok(entities)
+    }
 }
@@ -4,6 +4,7 @@
 
 //! Remote signing support.
 
+pub mod relay_server;
 pub mod session_negotiation;
 
 use {
@@ -26,7 +27,10 @@ use {
     signature::Signer,
     std::{
         cell::{RefCell, RefMut},
+        collections::{HashMap, VecDeque},
         net::TcpStream,
+        sync::Mutex,
+        time::Duration,
     },
     thiserror::Error,
     tungstenite::{
@@ -58,6 +62,9 @@ pub enum RemoteSignError {
     #[error("cryptography error: {0}")]
     Crypto(String),
 
+    #[error("relay server closed connection")]
+    ServerClosed,
+
     #[error("bad client state: {0}")]
     ClientState(&'static str),
 
@@ -92,7 +99,7 @@ pub enum RemoteSignError {
     X509(#[from] X509CertificateError),
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "kebab-case")]
 enum ApiMethod {
     Hello,
@@ -100,6 +107,8 @@ enum ApiMethod {
     JoinSession,
     SendMessage,
     Goodbye,
+    ResumeSession,
+    Acknowledge,
 }
 
 /// A websocket message sent from the client to the server.
@@ -121,10 +130,16 @@ enum ClientPayload {
         session_id: String,
         ttl: u64,
         context: Option<String>,
+        /// Whether we acknowledge receipt of sequenced messages.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        acknowledge: bool,
     },
     JoinSession {
         session_id: String,
         context: Option<String>,
+        /// Whether we acknowledge receipt of sequenced messages.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        acknowledge: bool,
     },
     SendMessage {
         session_id: String,
@@ -134,9 +149,20 @@ enum ClientPayload {
         session_id: String,
         reason: Option<String>,
     },
+    ResumeSession {
+        session_id: String,
+        resume_token: String,
+        /// Sequence number of the last message received from the relay.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        last_sequence: Option<u64>,
+    },
+    Acknowledge {
+        session_id: String,
+        sequence: u64,
+    },
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "kebab-case")]
 enum ServerMessageType {
     Error,
@@ -146,10 +172,11 @@ enum ServerMessageType {
     MessageSent,
     PeerMessage,
     SessionClosed,
+    SessionResumed,
 }
 
 /// Websocket message sent from server to client.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct ServerMessage {
     /// ID of request responsible for this message.
     request_id: Option<String>,
@@ -158,6 +185,11 @@ struct ServerMessage {
     typ: ServerMessageType,
     ttl: Option<u64>,
     payload: Option<serde_json::Value>,
+    /// Sequence number of a message the relay retains until acknowledged.
+    ///
+    /// Only sent by relays supporting acknowledgements, to clients requesting them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sequence: Option<u64>,
 }
 
 impl ServerMessage {
@@ -200,6 +232,10 @@ impl ServerMessage {
         self.as_type::<ServerGreeting>(ServerMessageType::Greeting)
     }
 
+    fn as_session_created(&self) -> Result<ServerSessionCreated, RemoteSignError> {
+        self.as_type::<ServerSessionCreated>(ServerMessageType::SessionCreated)
+    }
+
     fn as_session_joined(&self) -> Result<ServerJoined, RemoteSignError> {
         self.as_type::<ServerJoined>(ServerMessageType::SessionJoined)
     }
@@ -224,29 +260,43 @@ enum ServerPayload {
     SessionClosed(ServerSessionClosed),
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct ServerError {
     code: String,
     message: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct ServerGreeting {
     apis: Vec<String>,
     motd: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct ServerSessionCreated {
+    /// Token for resuming the session after a dropped connection.
+    ///
+    /// Only sent by relays supporting session resumption.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resume_token: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct ServerJoined {
     context: Option<String>,
+    /// Token for resuming the session after a dropped connection.
+    ///
+    /// Only sent to the joining peer by relays supporting session resumption.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    resume_token: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct ServerPeerMessage {
     message: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 struct ServerSessionClosed {
     reason: Option<String>,
 }
@@ -317,7 +367,7 @@ impl PeerMessage {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct PeerCertificate {
     certificate: String,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     chain: Vec<String>,
 }
 
@@ -332,15 +382,24 @@ enum PeerPayload {
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct PeerSigningCertificate {
     certificates: Vec<PeerCertificate>,
+    /// Whether the signer accepts new sign requests before responding to earlier ones.
+    #[serde(default)]
+    pipelining: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct PeerSignRequest {
+    /// Identifies the request so the signature can be matched to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
     message: String,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 struct PeerSignature {
+    /// The `request_id` of the [PeerSignRequest] this responds to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
     message: String,
     signature: String,
     algorithm_oid: String,
@@ -348,6 +407,12 @@ struct PeerSignature {
 
 const REQUIRED_ACTIONS: [&str; 4] = ["create-session", "join-session", "send-message", "goodbye"];
 
+/// How many times to try resuming a session after losing the relay connection.
+const RESUME_ATTEMPTS: u32 = 5;
+
+/// Delay before the first attempt to resume a session. Doubles with each attempt.
+const RESUME_BACKOFF: Duration = Duration::from_millis(500);
+
 /// Represents the response from the server.
 enum ServerResponse {
     /// Server closed the connection.
@@ -414,7 +479,7 @@ fn wait_for_server_message(
     ws: &mut WebSocket<MaybeTlsStream<TcpStream>>,
 ) -> Result<ServerMessage, RemoteSignError> {
     match wait_for_server_response(ws)? {
-        ServerResponse::Closed => Err(RemoteSignError::ClientState("server closed connection")),
+        ServerResponse::Closed => Err(RemoteSignError::ServerClosed),
         ServerResponse::Message(m) => {
             debug!(
                 "received server message {:?}; remaining session TTL: {}",
@@ -447,13 +512,22 @@ fn wait_for_expected_server_message(
 /// Clients can perform both the initiator and signer roles.
 pub struct UnjoinedSigningClient {
     ws: WebSocket<MaybeTlsStream<TcpStream>>,
+    server_url: String,
+    /// Whether the relay supports acknowledging receipt of its messages.
+    acknowledge: bool,
 }
 
 impl UnjoinedSigningClient {
     fn new(req: impl IntoClientRequest) -> Result<Self, RemoteSignError> {
+        let req = req.into_client_request()?;
+        let server_url = req.uri().to_string();
         let ws = create_websocket(req)?;
 
-        let mut slf = Self { ws };
+        let mut slf = Self {
+            ws,
+            server_url,
+            acknowledge: false,
+        };
 
         slf.send_hello()?;
 
@@ -510,15 +584,22 @@ impl UnjoinedSigningClient {
                 context: initiator
                     .session_create_context()
                     .map(|x| STANDARD_ENGINE.encode(x)),
+                acknowledge: self.acknowledge,
             }),
         )?;
 
         let sjs_base64 = initiator.session_join_string_base64()?;
         let sjs_pem = initiator.session_join_string_pem()?;
 
-        wait_for_expected_server_message(&mut self.ws, ServerMessageType::SessionCreated)?;
+        let created =
+            wait_for_expected_server_message(&mut self.ws, ServerMessageType::SessionCreated)?;
         warn!("session successfully created on server");
 
+        let resume_token = created
+            .as_session_created()
+            .ok()
+            .and_then(|created| created.resume_token);
+
         if let Some(cb) = session_info_cb {
             cb(&sjs_base64, &sjs_pem)?;
         }
@@ -536,11 +617,10 @@ impl UnjoinedSigningClient {
 
         let keys = initiator.negotiate_session(context)?;
 
-        let mut client = PairedClient {
-            ws: self.ws,
-            session_id,
-            keys,
-        };
+        let mut client =
+            PairedClient::new(self.ws, self.server_url, session_id, keys, resume_token);
+        // The relay sends this on its own accord. So it is sequenced.
+        client.receive_sequence(res.sequence)?;
 
         client.send_ping()?;
 
@@ -551,7 +631,10 @@ impl UnjoinedSigningClient {
         }
 
         Ok(InitiatorClient {
-            client: RefCell::new(client),
+            pipelining: client.peer_pipelining,
+            client: Mutex::new(client),
+            exclusive: Mutex::new(()),
+            pending: Mutex::new(PendingSignatures::default()),
             signing_cert,
             signing_chain,
         })
@@ -575,20 +658,24 @@ impl UnjoinedSigningClient {
             Some(ClientPayload::JoinSession {
                 session_id: session_id.clone(),
                 context: join_context.peer_context.map(|x| STANDARD_ENGINE.encode(x)),
+                acknowledge: self.acknowledge,
             }),
         )?;
 
-        wait_for_expected_server_message(&mut self.ws, ServerMessageType::SessionJoined)?;
+        let joined =
+            wait_for_expected_server_message(&mut self.ws, ServerMessageType::SessionJoined)?;
 
         warn!("successfully joined signing session {}", session_id);
 
+        let resume_token = joined
+            .as_session_joined()
+            .ok()
+            .and_then(|joined| joined.resume_token);
+
         let keys = join_context.peer_handshake.negotiate_session()?;
 
-        let mut client = PairedClient {
-            ws: self.ws,
-            session_id,
-            keys,
-        };
+        let mut client =
+            PairedClient::new(self.ws, self.server_url, session_id, keys, resume_token);
 
         warn!("verifying encrypted communications with peer");
         client.send_ping()?;
@@ -630,6 +717,8 @@ impl UnjoinedSigningClient {
             warn!("message from remote server: {}", motd);
         }
 
+        self.acknowledge = greeting.apis.iter().any(|api| api == "acknowledge");
+
         for required in REQUIRED_ACTIONS {
             if !greeting.apis.contains(&required.to_string()) {
                 error!("server does not support required action {}", required);
@@ -644,8 +733,28 @@ impl UnjoinedSigningClient {
 /// A remote signing client that has joined a session and is ready to exchange messages.
 pub struct PairedClient {
     ws: WebSocket<MaybeTlsStream<TcpStream>>,
+    server_url: String,
     session_id: String,
     keys: PeerKeys,
+    /// Token for resuming the session on a new connection.
+    ///
+    /// Only present if the relay supports session resumption.
+    resume_token: Option<String>,
+    /// `send-message` requests not yet acknowledged by the relay, as request ID and body.
+    ///
+    /// Every peer message advances the encryption nonce, so each must be delivered
+    /// exactly once. These are resent after resuming the session and the relay
+    /// discards any it already forwarded.
+    unacknowledged: VecDeque<(String, String)>,
+    /// Messages received while waiting for acknowledgements.
+    pending: VecDeque<ServerMessage>,
+    /// Sequence number of the last sequenced message received from the relay.
+    ///
+    /// The relay retains sequenced messages until we acknowledge them and
+    /// replays those after this one when the session is resumed.
+    last_sequence: Option<u64>,
+    /// Whether the peer accepts pipelined sign requests.
+    peer_pipelining: bool,
 }
 
 impl Drop for PairedClient {
@@ -655,6 +764,26 @@ impl Drop for PairedClient {
 }
 
 impl PairedClient {
+    fn new(
+        ws: WebSocket<MaybeTlsStream<TcpStream>>,
+        server_url: String,
+        session_id: String,
+        keys: PeerKeys,
+        resume_token: Option<String>,
+    ) -> Self {
+        Self {
+            ws,
+            server_url,
+            session_id,
+            keys,
+            resume_token,
+            unacknowledged: VecDeque::new(),
+            pending: VecDeque::new(),
+            last_sequence: None,
+            peer_pipelining: false,
+        }
+    }
+
     fn send_request(
         &mut self,
         api: ApiMethod,
@@ -663,13 +792,145 @@ impl PairedClient {
         let request_id = uuid::Uuid::new_v4().to_string();
 
         let message = ClientMessage {
-            request_id,
+            request_id: request_id.clone(),
             api,
             payload,
         };
 
         let body = serde_json::to_string(&message)?;
-        self.ws.write_message(body.into())?;
+
+        if api == ApiMethod::SendMessage {
+            self.unacknowledged.push_back((request_id, body.clone()));
+        }
+
+        if let Err(e) = self.ws.write_message(body.clone().into()) {
+            // Resuming resends unacknowledged peer messages. Anything else we resend here.
+            self.resume(e.into())?;
+
+            if api != ApiMethod::SendMessage {
+                self.ws.write_message(body.into())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read the next message from the relay, resuming the session if the connection drops.
+    fn read_server_message(&mut self) -> Result<ServerMessage, RemoteSignError> {
+        loop {
+            match wait_for_server_message(&mut self.ws) {
+                Ok(message) => {
+                    if message.typ == ServerMessageType::MessageSent {
+                        self.acknowledge(message.request_id.as_deref());
+                    }
+
+                    if self.receive_sequence(message.sequence)? {
+                        return Ok(message);
+                    }
+                }
+                Err(e) => self.resume(e)?,
+            }
+        }
+    }
+
+    fn acknowledge(&mut self, request_id: Option<&str>) {
+        match request_id {
+            Some(request_id) => self.unacknowledged.retain(|(id, _)| id != request_id),
+            // Acknowledgements arrive in the order requests were sent.
+            None => {
+                self.unacknowledged.pop_front();
+            }
+        }
+    }
+
+    /// Record receipt of a message with the given sequence number.
+    ///
+    /// Receipt is acknowledged to the relay. Returns false if the message was
+    /// already received.
+    fn receive_sequence(&mut self, sequence: Option<u64>) -> Result<bool, RemoteSignError> {
+        let sequence = match sequence {
+            Some(sequence) => sequence,
+            None => return Ok(true),
+        };
+
+        if self.last_sequence.map_or(false, |last| sequence <= last) {
+            return Ok(false);
+        }
+        self.last_sequence = Some(sequence);
+
+        self.send_request(
+            ApiMethod::Acknowledge,
+            Some(ClientPayload::Acknowledge {
+                session_id: self.session_id.clone(),
+                sequence,
+            }),
+        )?;
+
+        Ok(true)
+    }
+
+    /// Resume the session after the relay connection failed with `error`.
+    ///
+    /// `error` is returned if the session cannot be resumed.
+    fn resume(&mut self, error: RemoteSignError) -> Result<(), RemoteSignError> {
+        let resume_token = match (&self.resume_token, &error) {
+            (
+                Some(token),
+                RemoteSignError::Websocket(_)
+                | RemoteSignError::Io(_)
+                | RemoteSignError::ServerClosed,
+            ) => token.clone(),
+            _ => return Err(error),
+        };
+
+        warn!("lost connection to relay server: {}", error);
+
+        let mut delay = RESUME_BACKOFF;
+        let mut error = error;
+
+        for attempt in 1..=RESUME_ATTEMPTS {
+            std::thread::sleep(delay);
+            delay *= 2;
+
+            warn!(
+                "resuming signing session (attempt {}/{})",
+                attempt, RESUME_ATTEMPTS
+            );
+            match self.try_resume(&resume_token) {
+                Ok(()) => {
+                    warn!("signing session resumed");
+                    return Ok(());
+                }
+                // The relay rejected us. Trying again won't help.
+                Err(e @ RemoteSignError::ServerError(_)) => return Err(e),
+                Err(e) => {
+                    warn!("failed to resume signing session: {}", e);
+                    error = e;
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    fn try_resume(&mut self, resume_token: &str) -> Result<(), RemoteSignError> {
+        let mut client = UnjoinedSigningClient::new(self.server_url.as_str())?;
+
+        client.send_request(
+            ApiMethod::ResumeSession,
+            Some(ClientPayload::ResumeSession {
+                session_id: self.session_id.clone(),
+                resume_token: resume_token.to_string(),
+                last_sequence: self.last_sequence,
+            }),
+        )?;
+        wait_for_expected_server_message(&mut client.ws, ServerMessageType::SessionResumed)?;
+
+        self.ws = client.ws;
+
+        for (_, body) in &self.unacknowledged {
+            self.ws.write_message(body.clone().into())?;
+        }
 
         Ok(())
     }
@@ -713,7 +974,18 @@ impl PairedClient {
     }
 
     fn wait_for_peer_message(&mut self) -> Result<Option<PeerMessage>, RemoteSignError> {
-        let res = wait_for_server_message(&mut self.ws)?.into_result()?;
+        let res = loop {
+            let res = if let Some(res) = self.pending.pop_front() {
+                res
+            } else {
+                self.read_server_message()?.into_result()?
+            };
+
+            // Acknowledgements were accounted for when they were read.
+            if res.typ != ServerMessageType::MessageSent {
+                break res;
+            }
+        };
 
         if let Ok(closed) = res.as_session_closed() {
             warn!(
@@ -731,35 +1003,43 @@ impl PairedClient {
         }
     }
 
-    fn wait_for_server_and_peer_response(&mut self) -> Result<PeerMessage, RemoteSignError> {
-        let mut response = None;
-
-        // We should get a server message acknowledging our request plus the response from
-        // the peer. The order they arrive in is random.
-        for _ in 0..2 {
-            let res = wait_for_server_message(&mut self.ws)?.into_result()?;
+    /// Wait until the relay has acknowledged all messages we sent.
+    ///
+    /// Messages from the peer arriving in the meantime are retained for
+    /// [Self::wait_for_peer_message()].
+    fn wait_for_acknowledgements(&mut self) -> Result<(), RemoteSignError> {
+        while !self.unacknowledged.is_empty() {
+            let res = self.read_server_message()?.into_result()?;
 
             match res.typ {
                 ServerMessageType::MessageSent => {}
-                ServerMessageType::PeerMessage => {
-                    let message = res.as_peer_message()?;
-
-                    response = Some(self.decrypt_peer_message(&message)?);
+                ServerMessageType::PeerMessage | ServerMessageType::SessionClosed => {
+                    self.pending.push_back(res);
                 }
                 m => return Err(RemoteSignError::ServerUnexpectedMessage(format!("{m:?}"))),
             }
         }
 
-        if let Some(response) = response {
-            Ok(response)
-        } else {
-            Err(RemoteSignError::ClientState(
+        Ok(())
+    }
+
+    fn wait_for_server_and_peer_response(&mut self) -> Result<PeerMessage, RemoteSignError> {
+        // We should get a server message acknowledging our request plus the response from
+        // the peer. The order they arrive in is random.
+        let response = self
+            .wait_for_peer_message()?
+            .ok_or(RemoteSignError::ClientState(
                 "failed to receive response from server or peer",
-            ))
-        }
+            ))?;
+
+        self.wait_for_acknowledgements()?;
+
+        Ok(response)
     }
 
     fn send_goodbye(&mut self, reason: Option<String>) -> Result<(), RemoteSignError> {
+        self.wait_for_acknowledgements()?;
+
         warn!("terminating signing session on relay");
         self.send_request(
             ApiMethod::Goodbye,
@@ -769,7 +1049,7 @@ impl PairedClient {
             }),
         )?;
 
-        wait_for_server_message(&mut self.ws)?.into_result()?;
+        self.read_server_message()?.into_result()?;
         info!("relay server confirmed session termination");
 
         Ok(())
@@ -808,6 +1088,7 @@ impl PairedClient {
             .require_type(PeerMessageType::SigningCertificate)?;
 
         let cert = res.as_signing_certificate()?;
+        self.peer_pipelining = cert.pipelining;
 
         if let Some(cert) = cert.certificates.get(0) {
             let cert_der = STANDARD_ENGINE.decode(&cert.certificate)?;
@@ -832,9 +1113,59 @@ impl PairedClient {
     }
 }
 
+/// Sign requests issued by an [InitiatorClient] that await a signature.
+#[derive(Default)]
+struct PendingSignatures {
+    /// IDs of requests not yet answered, in the order they were sent.
+    outstanding: VecDeque<String>,
+    /// Signatures received from the peer, keyed by request ID.
+    received: HashMap<String, PeerSignature>,
+}
+
+impl PendingSignatures {
+    fn receive(&mut self, signature: PeerSignature) -> Result<(), RemoteSignError> {
+        // Signers not echoing request IDs answer requests in order.
+        let request_id = match &signature.request_id {
+            Some(request_id) => request_id.clone(),
+            None => self
+                .outstanding
+                .front()
+                .cloned()
+                .ok_or(RemoteSignError::ClientState(
+                    "received unrequested signature",
+                ))?,
+        };
+
+        let position = self
+            .outstanding
+            .iter()
+            .position(|id| id == &request_id)
+            .ok_or(RemoteSignError::ClientState(
+                "received signature for unknown request",
+            ))?;
+        self.outstanding.remove(position);
+        self.received.insert(request_id, signature);
+
+        Ok(())
+    }
+}
+
 /// A client fulfilling the role of the initiator.
+///
+/// Sign requests can be issued concurrently from multiple threads. They are
+/// multiplexed over the session and responses are matched to requests by ID.
+///
+/// Bundle signing signs nested bundles and Mach-O files concurrently, so their
+/// signatures are pipelined this way. Each individual signing operation still
+/// waits for its signature, so signing sequentially (e.g. with `--jobs 1`) costs
+/// one round trip per signature.
 pub struct InitiatorClient {
-    client: RefCell<PairedClient>,
+    client: Mutex<PairedClient>,
+    /// Whether the peer accepts multiple outstanding sign requests.
+    pipelining: bool,
+    /// Held for the duration of a sign request if the peer doesn't support pipelining.
+    exclusive: Mutex<()>,
+    pending: Mutex<PendingSignatures>,
     signing_cert: CapturedX509Certificate,
     signing_chain: Vec<CapturedX509Certificate>,
 }
@@ -849,50 +1180,126 @@ impl InitiatorClient {
     pub fn certificate_chain(&self) -> &[CapturedX509Certificate] {
         &self.signing_chain
     }
-}
 
-impl Signer<Signature> for InitiatorClient {
-    fn try_sign(&self, message: &[u8]) -> Result<Signature, signature::Error> {
-        let mut client = self.client.borrow_mut();
+    /// Sign multiple messages.
+    ///
+    /// All sign requests are sent to the peer before waiting for any signature,
+    /// so the batch costs a single network round trip. Peers not supporting
+    /// pipelining are sent one request at a time.
+    ///
+    /// Signatures are returned in the order of `messages`.
+    pub fn sign_messages(&self, messages: &[&[u8]]) -> Result<Vec<Signature>, RemoteSignError> {
+        if self.pipelining {
+            self.sign_pipelined(messages)
+        } else {
+            let _exclusive = self.exclusive.lock().expect("lock should not be poisoned");
 
-        warn!("sending signing request to remote signer");
+            let mut signatures = Vec::with_capacity(messages.len());
+            for message in messages {
+                signatures.extend(self.sign_pipelined(&[message])?);
+            }
 
-        client
-            .send_encrypted_message(
-                PeerMessageType::SignRequest,
-                Some(PeerPayload::SignRequest(PeerSignRequest {
-                    message: STANDARD_ENGINE.encode(message),
-                })),
-            )
-            .map_err(signature::Error::from_source)?;
+            Ok(signatures)
+        }
+    }
 
-        let response = client
-            .wait_for_server_and_peer_response()
-            .map_err(signature::Error::from_source)?
-            .require_type(PeerMessageType::Signature)
-            .map_err(signature::Error::from_source)?;
+    fn sign_pipelined(&self, messages: &[&[u8]]) -> Result<Vec<Signature>, RemoteSignError> {
+        let request_ids = messages
+            .iter()
+            .map(|_| uuid::Uuid::new_v4().to_string())
+            .collect::<Vec<_>>();
 
-        let peer_signature = response
-            .as_signature()
-            .map_err(signature::Error::from_source)?;
+        {
+            let mut client = self.client.lock().expect("lock should not be poisoned");
 
-        warn!("received signature from remote signer");
+            warn!(
+                "sending {} signing request(s) to remote signer",
+                messages.len()
+            );
 
-        let signature = STANDARD_ENGINE
-            .decode(&peer_signature.signature)
-            .map_err(signature::Error::from_source)?;
-        let oid_der = STANDARD_ENGINE
-            .decode(&peer_signature.algorithm_oid)
-            .map_err(signature::Error::from_source)?;
+            for (request_id, message) in request_ids.iter().zip(messages) {
+                self.pending
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .outstanding
+                    .push_back(request_id.clone());
+
+                client.send_encrypted_message(
+                    PeerMessageType::SignRequest,
+                    Some(PeerPayload::SignRequest(PeerSignRequest {
+                        request_id: Some(request_id.clone()),
+                        message: STANDARD_ENGINE.encode(message),
+                    })),
+                )?;
+            }
+        }
+
+        let mut signatures = HashMap::new();
+
+        loop {
+            {
+                let mut pending = self.pending.lock().expect("lock should not be poisoned");
+                for request_id in &request_ids {
+                    if let Some(signature) = pending.received.remove(request_id) {
+                        signatures.insert(request_id.clone(), signature);
+                    }
+                }
+            }
+
+            if signatures.len() == request_ids.len() {
+                break;
+            }
+
+            let mut client = self.client.lock().expect("lock should not be poisoned");
+
+            // Another thread may have read our signatures while we waited for the client.
+            let received = {
+                let pending = self.pending.lock().expect("lock should not be poisoned");
+                request_ids
+                    .iter()
+                    .any(|request_id| pending.received.contains_key(request_id))
+            };
+            if received {
+                continue;
+            }
+
+            let response = client
+                .wait_for_peer_message()?
+                .ok_or(RemoteSignError::ClientState(
+                    "signing session closed before receiving signature",
+                ))?
+                .require_type(PeerMessageType::Signature)?;
+
+            self.pending
+                .lock()
+                .expect("lock should not be poisoned")
+                .receive(response.as_signature()?)?;
+        }
+
+        warn!(
+            "received {} signature(s) from remote signer",
+            messages.len()
+        );
+
+        request_ids
+            .iter()
+            .zip(messages)
+            .map(|(request_id, message)| self.verify_signature(message, &signatures[request_id]))
+            .collect()
+    }
+
+    fn verify_signature(
+        &self,
+        message: &[u8],
+        peer_signature: &PeerSignature,
+    ) -> Result<Signature, RemoteSignError> {
+        let signature = STANDARD_ENGINE.decode(&peer_signature.signature)?;
+        let oid_der = STANDARD_ENGINE.decode(&peer_signature.algorithm_oid)?;
 
         bcder::decode::Constructed::decode(oid_der.as_ref(), Mode::Der, |cons| {
             Oid::take_from(cons)
         })
-        .map_err(|_| {
-            signature::Error::from_source(RemoteSignError::Crypto(
-                "error parsing signature OID".into(),
-            ))
-        })?;
+        .map_err(|_| RemoteSignError::Crypto("error parsing signature OID".into()))?;
 
         // The peer could be acting maliciously (or just be buggy) and sign with a
         // certificate from the initial one presented. So verify the signature we
@@ -902,13 +1309,23 @@ impl Signer<Signature> for InitiatorClient {
             error!("The peer could be acting maliciously. Or it could just be buggy.");
             error!("Either way, it didn't issue a valid signature, so we're giving up.");
 
-            return Err(signature::Error::from_source(e));
+            return Err(e.into());
         }
 
         Ok(signature.into())
     }
 }
 
+impl Signer<Signature> for InitiatorClient {
+    fn try_sign(&self, message: &[u8]) -> Result<Signature, signature::Error> {
+        let mut signatures = self
+            .sign_messages(&[message])
+            .map_err(signature::Error::from_source)?;
+
+        Ok(signatures.remove(0))
+    }
+}
+
 impl Sign for InitiatorClient {
     fn sign(&self, message: &[u8]) -> Result<(Vec<u8>, SignatureAlgorithm), X509CertificateError> {
         let algorithm = self.signature_algorithm()?;
@@ -957,7 +1374,7 @@ impl PublicKeyPeerDecrypt for InitiatorClient {
 }
 
 impl PrivateKey for InitiatorClient {
-    fn as_key_info_signer(&self) -> &dyn KeyInfoSigner {
+    fn as_key_info_signer(&self) -> &(dyn KeyInfoSigner + Sync) {
         self
     }
 
@@ -974,7 +1391,8 @@ impl PrivateKey for InitiatorClient {
         // Tell the peer we're done so it disconnects
         Ok(self
             .client
-            .borrow_mut()
+            .lock()
+            .expect("lock should not be poisoned")
             .send_goodbye(Some("signing operations completed".into()))?)
     }
 }
@@ -1006,10 +1424,11 @@ impl<'key> SigningClient<'key> {
                         })
                         .collect::<Result<Vec<_>, RemoteSignError>>()?,
                 }],
+                pipelining: true,
             })),
         )?;
 
-        wait_for_expected_server_message(&mut client.ws, ServerMessageType::MessageSent)?;
+        client.wait_for_acknowledgements()?;
 
         Ok(())
     }
@@ -1039,13 +1458,14 @@ impl<'key> SigningClient<'key> {
         client.send_encrypted_message(
             PeerMessageType::Signature,
             Some(PeerPayload::Signature(PeerSignature {
+                request_id: request.request_id,
                 message: STANDARD_ENGINE.encode(message),
                 signature: STANDARD_ENGINE.encode(signature),
                 algorithm_oid: STANDARD_ENGINE.encode(oid_der),
             })),
         )?;
 
-        wait_for_expected_server_message(&mut client.ws, ServerMessageType::MessageSent)?;
+        client.wait_for_acknowledgements()?;
         info!("relay acknowledged signature message received");
 
         Ok(())
@@ -1067,7 +1487,7 @@ impl<'key> SigningClient<'key> {
             }
             PeerMessageType::Ping => {
                 client.send_encrypted_message(PeerMessageType::Pong, None)?;
-                wait_for_expected_server_message(&mut client.ws, ServerMessageType::MessageSent)?;
+                client.wait_for_acknowledgements()?;
             }
             PeerMessageType::Pong => {}
             PeerMessageType::SignRequest => {
@@ -1087,3 +1507,51 @@ impl<'key> SigningClient<'key> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn signature(request_id: Option<&str>) -> PeerSignature {
+        PeerSignature {
+            request_id: request_id.map(|x| x.to_string()),
+            message: String::new(),
+            signature: String::new(),
+            algorithm_oid: String::new(),
+        }
+    }
+
+    #[test]
+    fn pending_signatures() -> Result<(), RemoteSignError> {
+        let mut pending = PendingSignatures::default();
+        pending
+            .outstanding
+            .extend(["a".to_string(), "b".into(), "c".into()]);
+
+        // Signatures can arrive out of order.
+        pending.receive(signature(Some("b")))?;
+        assert_eq!(pending.outstanding, vec!["a".to_string(), "c".into()]);
+        assert!(pending.received.contains_key("b"));
+
+        // Signatures without request IDs answer the oldest outstanding request.
+        pending.receive(signature(None))?;
+        assert_eq!(pending.outstanding, vec!["c".to_string()]);
+        assert!(pending.received.contains_key("a"));
+
+        assert!(matches!(
+            pending.receive(signature(Some("b"))),
+            Err(RemoteSignError::ClientState(_))
+        ));
+
+        pending.receive(signature(Some("c")))?;
+        assert!(pending.outstanding.is_empty());
+        assert_eq!(pending.received.len(), 3);
+
+        assert!(matches!(
+            pending.receive(signature(None)),
+            Err(RemoteSignError::ClientState(_))
+        ));
+
+        Ok(())
+    }
+}
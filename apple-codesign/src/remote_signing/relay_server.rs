@@ -0,0 +1,1381 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Remote signing relay server.
+//!
+//! Remote signing peers never talk to each other directly. Instead, they both
+//! connect to a websocket relay server, which forwards (encrypted) messages
+//! between members of a signing session. This module implements that relay
+//! server so it can be run on infrastructure you control.
+//!
+//! The relay supports the following operations:
+//!
+//! * `hello` - advertises supported APIs.
+//! * `create-session` - the initiator creates a session with a time-to-live.
+//! * `join-session` - the signer joins an existing session. Both peers are
+//!   notified and exchange their key negotiation context.
+//! * `send-message` - a message is forwarded to the other member of the session.
+//! * `goodbye` - the session is terminated and the other member is notified.
+//! * `resume-session` - a member that lost its connection reattaches to its
+//!   session using the resume token it received when creating or joining it.
+//!   Messages the member did not receive are delivered again.
+//! * `acknowledge` - a member confirms receipt of messages up to a sequence
+//!   number.
+//!
+//! Messages the relay sends to a member on its own accord, such as messages
+//! from the peer, carry per-member sequence numbers. Members that opt in when
+//! creating or joining the session acknowledge them. The relay retains each
+//! message until it is acknowledged and replays messages after the last one
+//! received when the member resumes the session. For other members, a message
+//! counts as received once it is written to their connection.
+//!
+//! Sessions are closed when they expire or when a member disconnects and does
+//! not resume the session within a grace period.
+
+use {
+    crate::remote_signing::{
+        ApiMethod, RemoteSignError, ServerError, ServerGreeting, ServerJoined, ServerMessage,
+        ServerMessageType, ServerPeerMessage, ServerSessionClosed, ServerSessionCreated,
+    },
+    log::{info, warn},
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    std::{
+        collections::{HashMap, VecDeque},
+        net::{TcpListener, TcpStream},
+        sync::{
+            mpsc::{channel, Sender},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    },
+    tungstenite::{handshake::HandshakeError, protocol::Message},
+};
+
+/// Default maximum lifetime of a session.
+pub const DEFAULT_MAX_SESSION_TTL: Duration = Duration::from_secs(600);
+
+/// Default time a disconnected session member has to resume the session.
+pub const DEFAULT_RESUME_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// APIs advertised to clients.
+const SUPPORTED_APIS: [&str; 7] = [
+    "hello",
+    "create-session",
+    "join-session",
+    "send-message",
+    "goodbye",
+    "resume-session",
+    "acknowledge",
+];
+
+/// How many request IDs to remember per session member for discarding resent messages.
+const RECENT_REQUESTS: usize = 256;
+
+/// How long to block reading from a client before checking for messages to send it.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often to send websocket pings to keep idle connections alive.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long clients have to complete the websocket handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A websocket message sent from a client to the server.
+///
+/// The payload is decoded according to the API method.
+#[derive(Clone, Debug, Deserialize)]
+struct ClientRequest {
+    request_id: String,
+    api: ApiMethod,
+    payload: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct CreateSessionRequest {
+    session_id: String,
+    ttl: u64,
+    context: Option<String>,
+    #[serde(default)]
+    acknowledge: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct JoinSessionRequest {
+    session_id: String,
+    context: Option<String>,
+    #[serde(default)]
+    acknowledge: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct SendMessageRequest {
+    session_id: String,
+    message: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct GoodbyeRequest {
+    session_id: String,
+    reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ResumeSessionRequest {
+    session_id: String,
+    resume_token: String,
+    #[serde(default)]
+    last_sequence: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct AcknowledgeRequest {
+    session_id: String,
+    sequence: u64,
+}
+
+type ConnectionId = u64;
+
+/// A member of a signing session.
+struct Member {
+    /// Connection used by the member. `None` if the connection was lost.
+    connection: Option<ConnectionId>,
+    /// Secret presented by the member to resume the session on a new connection.
+    resume_token: String,
+    /// When the member's connection was lost.
+    detached: Option<Instant>,
+    /// Whether the member acknowledges receipt of sequenced messages.
+    acknowledges: bool,
+    /// Sequence number of the next message sent to the member.
+    next_sequence: u64,
+    /// Sequenced messages the member has not received yet, as far as we know.
+    ///
+    /// These are sent again when the member resumes the session.
+    outbox: VecDeque<ServerMessage>,
+    /// IDs of recently forwarded `send-message` requests.
+    ///
+    /// Clients resend unacknowledged messages after resuming a session. These
+    /// are used to forward each message only once.
+    recent_requests: VecDeque<String>,
+}
+
+impl Member {
+    fn new(connection: ConnectionId, acknowledges: bool) -> Self {
+        Self {
+            connection: Some(connection),
+            resume_token: uuid::Uuid::new_v4().to_string(),
+            detached: None,
+            acknowledges,
+            next_sequence: 0,
+            outbox: VecDeque::new(),
+            recent_requests: VecDeque::new(),
+        }
+    }
+
+    /// Forget messages up to and including `sequence`, as they were received.
+    fn received(&mut self, sequence: u64) {
+        self.outbox
+            .retain(|message| message.sequence.map_or(true, |s| s > sequence));
+    }
+}
+
+/// A signing session.
+struct Session {
+    initiator: Member,
+    signer: Option<Member>,
+    context: Option<String>,
+    expires: Instant,
+}
+
+impl Session {
+    fn members(&self) -> impl Iterator<Item = &Member> {
+        std::iter::once(&self.initiator).chain(self.signer.as_ref())
+    }
+
+    fn members_mut(&mut self) -> impl Iterator<Item = &mut Member> {
+        std::iter::once(&mut self.initiator).chain(self.signer.as_mut())
+    }
+
+    fn is_member(&self, connection: ConnectionId) -> bool {
+        self.members()
+            .any(|member| member.connection == Some(connection))
+    }
+
+    /// Resolve the member using a connection and the other member of the session.
+    fn member_and_peer(
+        &mut self,
+        connection: ConnectionId,
+    ) -> Option<(&mut Member, Option<&mut Member>)> {
+        if self.initiator.connection == Some(connection) {
+            Some((&mut self.initiator, self.signer.as_mut()))
+        } else if let Some(signer) = self
+            .signer
+            .as_mut()
+            .filter(|signer| signer.connection == Some(connection))
+        {
+            Some((signer, Some(&mut self.initiator)))
+        } else {
+            None
+        }
+    }
+
+    fn ttl(&self, now: Instant) -> u64 {
+        self.expires.saturating_duration_since(now).as_secs()
+    }
+}
+
+/// An error to send back to a client.
+struct RequestError {
+    code: &'static str,
+    message: String,
+}
+
+impl RequestError {
+    fn new(code: &'static str, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+fn server_message<T: Serialize>(
+    request_id: Option<String>,
+    typ: ServerMessageType,
+    ttl: Option<u64>,
+    payload: Option<T>,
+) -> ServerMessage {
+    ServerMessage {
+        request_id,
+        typ,
+        ttl,
+        payload: payload.map(|payload| {
+            serde_json::to_value(payload).expect("server payloads should serialize")
+        }),
+        sequence: None,
+    }
+}
+
+fn error_message(request_id: Option<String>, error: RequestError) -> ServerMessage {
+    server_message(
+        request_id,
+        ServerMessageType::Error,
+        None,
+        Some(ServerError {
+            code: error.code.to_string(),
+            message: error.message,
+        }),
+    )
+}
+
+fn session_closed_message(request_id: Option<String>, reason: Option<String>) -> ServerMessage {
+    server_message(
+        request_id,
+        ServerMessageType::SessionClosed,
+        None,
+        Some(ServerSessionClosed { reason }),
+    )
+}
+
+/// Send a sequenced message to a session member.
+///
+/// The message is retained until the member received it so it can be sent
+/// again if the member resumes the session.
+fn deliver(
+    connections: &HashMap<ConnectionId, Sender<ServerMessage>>,
+    member: &mut Member,
+    mut message: ServerMessage,
+) {
+    message.sequence = Some(member.next_sequence);
+    member.next_sequence += 1;
+    member.outbox.push_back(message.clone());
+
+    if let Some(sender) = member
+        .connection
+        .and_then(|connection| connections.get(&connection))
+    {
+        // The receiver is gone if the connection is shutting down. The message
+        // is sent again on resume.
+        let _ = sender.send(message);
+    }
+}
+
+/// State shared by all connections to the relay.
+struct RelayState {
+    motd: Option<String>,
+    max_session_ttl: Duration,
+    resume_grace_period: Duration,
+    next_connection_id: ConnectionId,
+    connections: HashMap<ConnectionId, Sender<ServerMessage>>,
+    sessions: HashMap<String, Session>,
+}
+
+impl RelayState {
+    fn new(motd: Option<String>, max_session_ttl: Duration) -> Self {
+        Self {
+            motd,
+            max_session_ttl,
+            resume_grace_period: DEFAULT_RESUME_GRACE_PERIOD,
+            next_connection_id: 0,
+            connections: HashMap::new(),
+            sessions: HashMap::new(),
+        }
+    }
+
+    fn register_connection(&mut self, sender: Sender<ServerMessage>) -> ConnectionId {
+        let id = self.next_connection_id;
+        self.next_connection_id += 1;
+        self.connections.insert(id, sender);
+
+        id
+    }
+
+    /// Remove a connection, detaching it from any sessions it is a member of.
+    fn unregister_connection(&mut self, connection: ConnectionId, now: Instant) {
+        self.connections.remove(&connection);
+
+        for (session_id, session) in self.sessions.iter_mut() {
+            for member in session.members_mut() {
+                if member.connection == Some(connection) {
+                    info!("member of session {} disconnected", session_id);
+
+                    member.connection = None;
+                    member.detached = Some(now);
+                }
+            }
+        }
+    }
+
+    /// Record that a sequenced message was written to a connection.
+    ///
+    /// Members not acknowledging messages are assumed to have received it.
+    fn message_written(&mut self, connection: ConnectionId, sequence: u64) {
+        for session in self.sessions.values_mut() {
+            for member in session.members_mut() {
+                if member.connection == Some(connection) && !member.acknowledges {
+                    member.received(sequence);
+                }
+            }
+        }
+    }
+
+    /// Close sessions that have outlived their time-to-live or whose members
+    /// did not resume them in time.
+    fn expire_sessions(&mut self, now: Instant) {
+        let grace_period = self.resume_grace_period;
+
+        let closing = self
+            .sessions
+            .iter()
+            .filter_map(|(id, session)| {
+                if session.expires <= now {
+                    Some((id.clone(), "session expired"))
+                } else if session.members().any(|member| {
+                    member.detached.map_or(false, |detached| {
+                        now.saturating_duration_since(detached) >= grace_period
+                    })
+                }) {
+                    Some((id.clone(), "peer disconnected"))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for (session_id, reason) in closing {
+            if let Some(mut session) = self.sessions.remove(&session_id) {
+                info!("closing session {}: {}", session_id, reason);
+
+                for member in session.members_mut() {
+                    deliver(
+                        &self.connections,
+                        member,
+                        session_closed_message(None, Some(reason.into())),
+                    );
+                }
+            }
+        }
+    }
+
+    fn send(&self, connection: ConnectionId, message: ServerMessage) {
+        // The receiver is gone if the connection is shutting down. That's fine.
+        if let Some(sender) = self.connections.get(&connection) {
+            let _ = sender.send(message);
+        }
+    }
+
+    /// Process a request from a client.
+    ///
+    /// Responses and messages for other clients are queued for delivery.
+    fn handle_request(&mut self, connection: ConnectionId, request: ClientRequest, now: Instant) {
+        let request_id = request.request_id.clone();
+
+        let res = match request.api {
+            ApiMethod::Hello => self.handle_hello(connection, request),
+            ApiMethod::CreateSession => self.handle_create_session(connection, request, now),
+            ApiMethod::JoinSession => self.handle_join_session(connection, request, now),
+            ApiMethod::SendMessage => self.handle_send_message(connection, request, now),
+            ApiMethod::Goodbye => self.handle_goodbye(connection, request),
+            ApiMethod::ResumeSession => self.handle_resume_session(connection, request, now),
+            ApiMethod::Acknowledge => self.handle_acknowledge(connection, request),
+        };
+
+        if let Err(e) = res {
+            self.send(connection, error_message(Some(request_id), e));
+        }
+    }
+
+    fn handle_hello(
+        &mut self,
+        connection: ConnectionId,
+        request: ClientRequest,
+    ) -> Result<(), RequestError> {
+        self.send(
+            connection,
+            server_message(
+                Some(request.request_id),
+                ServerMessageType::Greeting,
+                None,
+                Some(ServerGreeting {
+                    apis: SUPPORTED_APIS.iter().map(|x| x.to_string()).collect(),
+                    motd: self.motd.clone(),
+                }),
+            ),
+        );
+
+        Ok(())
+    }
+
+    fn handle_create_session(
+        &mut self,
+        connection: ConnectionId,
+        request: ClientRequest,
+        now: Instant,
+    ) -> Result<(), RequestError> {
+        let payload = request_payload::<CreateSessionRequest>(&request)?;
+
+        if self.sessions.contains_key(&payload.session_id) {
+            return Err(RequestError::new(
+                "session-exists",
+                format!("session {} already exists", payload.session_id),
+            ));
+        }
+
+        let ttl = Duration::from_secs(payload.ttl).min(self.max_session_ttl);
+
+        let session = Session {
+            initiator: Member::new(connection, payload.acknowledge),
+            signer: None,
+            context: payload.context,
+            expires: now + ttl,
+        };
+
+        info!("created session {}", payload.session_id);
+        self.send(
+            connection,
+            server_message(
+                Some(request.request_id),
+                ServerMessageType::SessionCreated,
+                Some(session.ttl(now)),
+                Some(ServerSessionCreated {
+                    resume_token: Some(session.initiator.resume_token.clone()),
+                }),
+            ),
+        );
+        self.sessions.insert(payload.session_id, session);
+
+        Ok(())
+    }
+
+    fn handle_join_session(
+        &mut self,
+        connection: ConnectionId,
+        request: ClientRequest,
+        now: Instant,
+    ) -> Result<(), RequestError> {
+        let payload = request_payload::<JoinSessionRequest>(&request)?;
+
+        let session = self
+            .sessions
+            .get_mut(&payload.session_id)
+            .ok_or_else(|| session_not_found(&payload.session_id))?;
+
+        if session.signer.is_some() || session.is_member(connection) {
+            return Err(RequestError::new(
+                "session-full",
+                format!("session {} cannot be joined", payload.session_id),
+            ));
+        }
+
+        let signer = Member::new(connection, payload.acknowledge);
+        let resume_token = signer.resume_token.clone();
+        session.signer = Some(signer);
+
+        let ttl = session.ttl(now);
+        let initiator_context = session.context.clone();
+
+        info!("peer joined session {}", payload.session_id);
+
+        // Each peer receives the other's context so they can derive encryption keys.
+        deliver(
+            &self.connections,
+            &mut session.initiator,
+            server_message(
+                None,
+                ServerMessageType::SessionJoined,
+                Some(ttl),
+                Some(ServerJoined {
+                    context: payload.context,
+                    resume_token: None,
+                }),
+            ),
+        );
+        self.send(
+            connection,
+            server_message(
+                Some(request.request_id),
+                ServerMessageType::SessionJoined,
+                Some(ttl),
+                Some(ServerJoined {
+                    context: initiator_context,
+                    resume_token: Some(resume_token),
+                }),
+            ),
+        );
+
+        Ok(())
+    }
+
+    fn handle_send_message(
+        &mut self,
+        connection: ConnectionId,
+        request: ClientRequest,
+        now: Instant,
+    ) -> Result<(), RequestError> {
+        let payload = request_payload::<SendMessageRequest>(&request)?;
+
+        let session = self
+            .sessions
+            .get_mut(&payload.session_id)
+            .ok_or_else(|| session_not_found(&payload.session_id))?;
+
+        let ttl = session.ttl(now);
+
+        let (member, peer) = session
+            .member_and_peer(connection)
+            .ok_or_else(|| not_session_member(&payload.session_id))?;
+        let peer = peer.ok_or_else(|| {
+            RequestError::new(
+                "peer-not-joined",
+                format!("no peer has joined session {}", payload.session_id),
+            )
+        })?;
+
+        if !member.recent_requests.contains(&request.request_id) {
+            if member.recent_requests.len() == RECENT_REQUESTS {
+                member.recent_requests.pop_front();
+            }
+            member.recent_requests.push_back(request.request_id.clone());
+
+            deliver(
+                &self.connections,
+                peer,
+                server_message(
+                    None,
+                    ServerMessageType::PeerMessage,
+                    Some(ttl),
+                    Some(ServerPeerMessage {
+                        message: payload.message,
+                    }),
+                ),
+            );
+        }
+
+        self.send(
+            connection,
+            server_message::<()>(
+                Some(request.request_id),
+                ServerMessageType::MessageSent,
+                Some(ttl),
+                None,
+            ),
+        );
+
+        Ok(())
+    }
+
+    fn handle_goodbye(
+        &mut self,
+        connection: ConnectionId,
+        request: ClientRequest,
+    ) -> Result<(), RequestError> {
+        let payload = request_payload::<GoodbyeRequest>(&request)?;
+
+        let session = self
+            .sessions
+            .get(&payload.session_id)
+            .ok_or_else(|| session_not_found(&payload.session_id))?;
+
+        if !session.is_member(connection) {
+            return Err(not_session_member(&payload.session_id));
+        }
+
+        if let Some(mut session) = self.sessions.remove(&payload.session_id) {
+            info!("session {} closed by peer", payload.session_id);
+
+            if let Some((_, Some(peer))) = session.member_and_peer(connection) {
+                deliver(
+                    &self.connections,
+                    peer,
+                    session_closed_message(None, payload.reason.clone()),
+                );
+            }
+        }
+
+        self.send(
+            connection,
+            session_closed_message(Some(request.request_id), payload.reason),
+        );
+
+        Ok(())
+    }
+
+    fn handle_resume_session(
+        &mut self,
+        connection: ConnectionId,
+        request: ClientRequest,
+        now: Instant,
+    ) -> Result<(), RequestError> {
+        let payload = request_payload::<ResumeSessionRequest>(&request)?;
+
+        let session = self
+            .sessions
+            .get_mut(&payload.session_id)
+            .ok_or_else(|| session_not_found(&payload.session_id))?;
+
+        let ttl = session.ttl(now);
+
+        let member = session
+            .members_mut()
+            .find(|member| member.resume_token == payload.resume_token)
+            .ok_or_else(|| {
+                RequestError::new(
+                    "invalid-resume-token",
+                    format!("cannot resume session {}", payload.session_id),
+                )
+            })?;
+
+        // The previous connection may not have noticed it is dead yet. Either way,
+        // the new connection takes over.
+        member.connection = Some(connection);
+        member.detached = None;
+
+        if let Some(sequence) = payload.last_sequence {
+            member.received(sequence);
+        }
+        let replay = member.outbox.iter().cloned().collect::<Vec<_>>();
+
+        info!("member resumed session {}", payload.session_id);
+
+        self.send(
+            connection,
+            server_message::<()>(
+                Some(request.request_id),
+                ServerMessageType::SessionResumed,
+                Some(ttl),
+                None,
+            ),
+        );
+        for message in replay {
+            self.send(connection, message);
+        }
+
+        Ok(())
+    }
+
+    fn handle_acknowledge(
+        &mut self,
+        connection: ConnectionId,
+        request: ClientRequest,
+    ) -> Result<(), RequestError> {
+        let payload = request_payload::<AcknowledgeRequest>(&request)?;
+
+        // Acknowledgements aren't answered. Including for sessions that have
+        // since closed, as their members have nothing left to receive.
+        if let Some((member, _)) = self
+            .sessions
+            .get_mut(&payload.session_id)
+            .and_then(|session| session.member_and_peer(connection))
+        {
+            member.received(payload.sequence);
+        }
+
+        Ok(())
+    }
+}
+
+fn request_payload<T: DeserializeOwned>(request: &ClientRequest) -> Result<T, RequestError> {
+    let payload = request
+        .payload
+        .clone()
+        .ok_or_else(|| RequestError::new("bad-request", "request payload missing"))?;
+
+    serde_json::from_value(payload)
+        .map_err(|e| RequestError::new("bad-request", format!("invalid request payload: {e}")))
+}
+
+fn session_not_found(session_id: &str) -> RequestError {
+    RequestError::new(
+        "session-not-found",
+        format!("session {session_id} does not exist"),
+    )
+}
+
+fn not_session_member(session_id: &str) -> RequestError {
+    RequestError::new(
+        "not-session-member",
+        format!("not a member of session {session_id}"),
+    )
+}
+
+/// A relay server for remote signing sessions.
+pub struct RelayServer {
+    state: Arc<Mutex<RelayState>>,
+}
+
+impl Default for RelayServer {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RelayState::new(None, DEFAULT_MAX_SESSION_TTL))),
+        }
+    }
+}
+
+impl RelayServer {
+    /// Set a message of the day sent to connecting clients.
+    pub fn set_motd(&mut self, motd: impl ToString) {
+        self.state.lock().expect("lock should not be poisoned").motd = Some(motd.to_string());
+    }
+
+    /// Set the maximum lifetime of sessions.
+    ///
+    /// Sessions requesting a longer time-to-live are truncated to this value.
+    pub fn set_max_session_ttl(&mut self, ttl: Duration) {
+        self.state
+            .lock()
+            .expect("lock should not be poisoned")
+            .max_session_ttl = ttl;
+    }
+
+    /// Set how long a session member that lost its connection has to resume the session.
+    ///
+    /// The session is closed if the member doesn't resume it in time.
+    pub fn set_resume_grace_period(&mut self, period: Duration) {
+        self.state
+            .lock()
+            .expect("lock should not be poisoned")
+            .resume_grace_period = period;
+    }
+
+    /// Serve clients connecting to a listening socket.
+    ///
+    /// Each connection is serviced by its own thread. This runs forever.
+    pub fn serve(&self, listener: &TcpListener) -> Result<(), RemoteSignError> {
+        let state = self.state.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            state
+                .lock()
+                .expect("lock should not be poisoned")
+                .expire_sessions(Instant::now());
+        });
+
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let state = self.state.clone();
+
+            std::thread::spawn(move || {
+                let peer = stream
+                    .peer_addr()
+                    .map(|addr| addr.to_string())
+                    .unwrap_or_else(|_| "unknown".into());
+
+                info!("accepted connection from {}", peer);
+                if let Err(e) = handle_connection(state, stream) {
+                    warn!("error servicing connection from {}: {}", peer, e);
+                }
+                info!("connection from {} closed", peer);
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn handle_connection(
+    state: Arc<Mutex<RelayState>>,
+    stream: TcpStream,
+) -> Result<(), RemoteSignError> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let mut ws = tungstenite::accept(stream).map_err(|e| match e {
+        HandshakeError::Failure(e) => RemoteSignError::Websocket(e),
+        HandshakeError::Interrupted(_) => RemoteSignError::Io(std::io::ErrorKind::TimedOut.into()),
+    })?;
+
+    ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let (sender, receiver) = channel();
+    let connection = state
+        .lock()
+        .expect("lock should not be poisoned")
+        .register_connection(sender);
+
+    let mut last_ping = Instant::now();
+
+    let res = loop {
+        match ws.read_message() {
+            Ok(Message::Text(text)) => match serde_json::from_str::<ClientRequest>(&text) {
+                Ok(request) => state
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .handle_request(connection, request, Instant::now()),
+                Err(e) => {
+                    let message = error_message(
+                        None,
+                        RequestError::new("bad-request", format!("invalid request: {e}")),
+                    );
+                    if let Err(e) = ws.write_message(serde_json::to_string(&message)?.into()) {
+                        break Err(e.into());
+                    }
+                }
+            },
+            Ok(Message::Binary(_)) => {
+                let message = error_message(
+                    None,
+                    RequestError::new("bad-request", "binary messages are not supported"),
+                );
+                if let Err(e) = ws.write_message(serde_json::to_string(&message)?.into()) {
+                    break Err(e.into());
+                }
+            }
+            Ok(Message::Close(_)) => break Ok(()),
+            // Pings are answered automatically.
+            Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) => {}
+            Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                break Ok(())
+            }
+            Err(e) => break Err(e.into()),
+        }
+
+        let mut res = Ok(());
+
+        while let Ok(message) = receiver.try_recv() {
+            res = serde_json::to_string(&message)
+                .map_err(RemoteSignError::from)
+                .and_then(|body| Ok(ws.write_message(body.into())?));
+
+            if res.is_err() {
+                break;
+            }
+
+            if let Some(sequence) = message.sequence {
+                state
+                    .lock()
+                    .expect("lock should not be poisoned")
+                    .message_written(connection, sequence);
+            }
+        }
+
+        if res.is_ok() && last_ping.elapsed() >= KEEPALIVE_INTERVAL {
+            last_ping = Instant::now();
+            res = ws.write_message(Message::Ping(vec![])).map_err(Into::into);
+        }
+
+        if let Err(e) = res {
+            break Err(e);
+        }
+    };
+
+    state
+        .lock()
+        .expect("lock should not be poisoned")
+        .unregister_connection(connection, Instant::now());
+
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            bundle_signing::BundleSigner,
+            cryptography::PrivateKey,
+            remote_signing::{
+                create_websocket,
+                session_negotiation::{
+                    create_session_joiner, SessionInitiatePeer, SessionJoinState,
+                    SharedSecretInitiator,
+                },
+                wait_for_server_message, ClientMessage, ClientPayload, UnjoinedSigningClient,
+            },
+            signing_settings::SigningSettings,
+            testutil::{rsa_signing_identity, write_app_bundle},
+            verify::{verify_bundle_with_settings, VerificationSettings},
+        },
+        signature::Signer,
+        std::sync::mpsc::Receiver,
+    };
+
+    fn request(api: ApiMethod, payload: serde_json::Value) -> ClientRequest {
+        ClientRequest {
+            request_id: "request".into(),
+            api,
+            payload: Some(payload),
+        }
+    }
+
+    fn types(receiver: &Receiver<ServerMessage>) -> Vec<ServerMessageType> {
+        receiver.try_iter().map(|message| message.typ).collect()
+    }
+
+    #[test]
+    fn session_lifecycle() {
+        let mut state = RelayState::new(None, Duration::from_secs(60));
+        let now = Instant::now();
+
+        let (initiator_tx, initiator_rx) = channel();
+        let (signer_tx, signer_rx) = channel();
+        let initiator = state.register_connection(initiator_tx);
+        let signer = state.register_connection(signer_tx);
+
+        // Messages can't be sent until the session is joined.
+        state.handle_request(
+            initiator,
+            request(
+                ApiMethod::CreateSession,
+                serde_json::json!({"session_id": "s", "ttl": 600}),
+            ),
+            now,
+        );
+        let created = initiator_rx.try_recv().unwrap();
+        assert_eq!(created.typ, ServerMessageType::SessionCreated);
+        assert_eq!(created.ttl, Some(60));
+
+        state.handle_request(
+            initiator,
+            request(
+                ApiMethod::SendMessage,
+                serde_json::json!({"session_id": "s", "message": "hello"}),
+            ),
+            now,
+        );
+        assert_eq!(types(&initiator_rx), vec![ServerMessageType::Error]);
+
+        state.handle_request(
+            signer,
+            request(
+                ApiMethod::JoinSession,
+                serde_json::json!({"session_id": "s", "context": "signer"}),
+            ),
+            now,
+        );
+        assert_eq!(types(&signer_rx), vec![ServerMessageType::SessionJoined]);
+        let joined = initiator_rx.try_recv().unwrap();
+        assert_eq!(
+            joined.as_session_joined().unwrap().context,
+            Some("signer".into())
+        );
+
+        state.handle_request(
+            signer,
+            request(
+                ApiMethod::SendMessage,
+                serde_json::json!({"session_id": "s", "message": "hello"}),
+            ),
+            now,
+        );
+        assert_eq!(types(&signer_rx), vec![ServerMessageType::MessageSent]);
+        let message = initiator_rx.try_recv().unwrap();
+        assert_eq!(message.as_peer_message().unwrap().message, "hello");
+
+        state.handle_request(
+            initiator,
+            request(
+                ApiMethod::Goodbye,
+                serde_json::json!({"session_id": "s", "reason": "done"}),
+            ),
+            now,
+        );
+        assert_eq!(types(&initiator_rx), vec![ServerMessageType::SessionClosed]);
+        let closed = signer_rx.try_recv().unwrap();
+        assert_eq!(
+            closed.as_session_closed().unwrap().reason,
+            Some("done".into())
+        );
+        assert!(state.sessions.is_empty());
+    }
+
+    #[test]
+    fn session_expiry() {
+        let mut state = RelayState::new(None, Duration::from_secs(60));
+        let now = Instant::now();
+
+        let (initiator_tx, initiator_rx) = channel();
+        let initiator = state.register_connection(initiator_tx);
+
+        state.handle_request(
+            initiator,
+            request(
+                ApiMethod::CreateSession,
+                serde_json::json!({"session_id": "s", "ttl": 10}),
+            ),
+            now,
+        );
+        assert_eq!(
+            types(&initiator_rx),
+            vec![ServerMessageType::SessionCreated]
+        );
+
+        state.expire_sessions(now + Duration::from_secs(5));
+        assert!(types(&initiator_rx).is_empty());
+
+        state.expire_sessions(now + Duration::from_secs(10));
+        assert_eq!(types(&initiator_rx), vec![ServerMessageType::SessionClosed]);
+        assert!(state.sessions.is_empty());
+    }
+
+    #[test]
+    fn session_resumption() {
+        let mut state = RelayState::new(None, Duration::from_secs(600));
+        let now = Instant::now();
+
+        let (initiator_tx, initiator_rx) = channel();
+        let (signer_tx, signer_rx) = channel();
+        let initiator = state.register_connection(initiator_tx);
+        let signer = state.register_connection(signer_tx);
+
+        state.handle_request(
+            initiator,
+            request(
+                ApiMethod::CreateSession,
+                serde_json::json!({"session_id": "s", "ttl": 600}),
+            ),
+            now,
+        );
+        let resume_token = initiator_rx
+            .try_recv()
+            .unwrap()
+            .as_session_created()
+            .unwrap()
+            .resume_token
+            .unwrap();
+
+        state.handle_request(
+            signer,
+            request(
+                ApiMethod::JoinSession,
+                serde_json::json!({"session_id": "s"}),
+            ),
+            now,
+        );
+        assert_eq!(types(&signer_rx), vec![ServerMessageType::SessionJoined]);
+        assert_eq!(types(&initiator_rx), vec![ServerMessageType::SessionJoined]);
+        // The initiator doesn't acknowledge messages. So writing one to its
+        // connection counts as receipt.
+        state.message_written(initiator, 0);
+
+        // Messages for a disconnected member are held. Resent messages are only
+        // forwarded once.
+        state.unregister_connection(initiator, now);
+
+        for _ in 0..2 {
+            state.handle_request(
+                signer,
+                request(
+                    ApiMethod::SendMessage,
+                    serde_json::json!({"session_id": "s", "message": "hello"}),
+                ),
+                now,
+            );
+            assert_eq!(types(&signer_rx), vec![ServerMessageType::MessageSent]);
+        }
+
+        state.expire_sessions(now + Duration::from_secs(30));
+        assert!(types(&signer_rx).is_empty());
+
+        let (resumed_tx, resumed_rx) = channel();
+        let resumed = state.register_connection(resumed_tx);
+
+        state.handle_request(
+            resumed,
+            request(
+                ApiMethod::ResumeSession,
+                serde_json::json!({"session_id": "s", "resume_token": "bad"}),
+            ),
+            now,
+        );
+        assert_eq!(types(&resumed_rx), vec![ServerMessageType::Error]);
+
+        state.handle_request(
+            resumed,
+            request(
+                ApiMethod::ResumeSession,
+                serde_json::json!({"session_id": "s", "resume_token": resume_token}),
+            ),
+            now,
+        );
+        assert_eq!(
+            resumed_rx.try_recv().unwrap().typ,
+            ServerMessageType::SessionResumed
+        );
+        let message = resumed_rx.try_recv().unwrap();
+        assert_eq!(message.as_peer_message().unwrap().message, "hello");
+        assert!(types(&resumed_rx).is_empty());
+
+        // The session is closed if a member doesn't resume in time.
+        state.unregister_connection(signer, now);
+        state.expire_sessions(now + DEFAULT_RESUME_GRACE_PERIOD);
+        let closed = resumed_rx.try_recv().unwrap();
+        assert_eq!(
+            closed.as_session_closed().unwrap().reason,
+            Some("peer disconnected".into())
+        );
+        assert!(state.sessions.is_empty());
+    }
+
+    #[test]
+    fn replay_written_messages_on_resume() -> Result<(), RemoteSignError> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("ws://{}/", listener.local_addr()?);
+
+        std::thread::spawn(move || RelayServer::default().serve(&listener));
+
+        let mut initiator = create_websocket(url.as_str())?;
+        let mut signer = create_websocket(url.as_str())?;
+
+        let send = |ws: &mut tungstenite::WebSocket<_>, api, payload| {
+            let message = ClientMessage {
+                request_id: uuid::Uuid::new_v4().to_string(),
+                api,
+                payload: Some(payload),
+            };
+
+            ws.write_message(serde_json::to_string(&message)?.into())
+                .map_err(RemoteSignError::from)
+        };
+
+        send(
+            &mut initiator,
+            ApiMethod::CreateSession,
+            ClientPayload::CreateSession {
+                session_id: "s".into(),
+                ttl: 60,
+                context: None,
+                acknowledge: true,
+            },
+        )?;
+        let resume_token = wait_for_server_message(&mut initiator)?
+            .as_session_created()?
+            .resume_token
+            .expect("relay should support resumption");
+
+        send(
+            &mut signer,
+            ApiMethod::JoinSession,
+            ClientPayload::JoinSession {
+                session_id: "s".into(),
+                context: None,
+                acknowledge: true,
+            },
+        )?;
+        assert_eq!(
+            wait_for_server_message(&mut signer)?.typ,
+            ServerMessageType::SessionJoined
+        );
+
+        let joined = wait_for_server_message(&mut initiator)?;
+        assert_eq!(joined.typ, ServerMessageType::SessionJoined);
+        assert_eq!(joined.sequence, Some(0));
+        send(
+            &mut initiator,
+            ApiMethod::Acknowledge,
+            ClientPayload::Acknowledge {
+                session_id: "s".into(),
+                sequence: 0,
+            },
+        )?;
+
+        send(
+            &mut signer,
+            ApiMethod::SendMessage,
+            ClientPayload::SendMessage {
+                session_id: "s".into(),
+                message: "hello".into(),
+            },
+        )?;
+        assert_eq!(
+            wait_for_server_message(&mut signer)?.typ,
+            ServerMessageType::MessageSent
+        );
+
+        // Wait for the relay to write the peer message. Then lose the connection
+        // without reading it.
+        if let tungstenite::stream::MaybeTlsStream::Plain(stream) = initiator.get_mut() {
+            stream.peek(&mut [0; 1])?;
+            stream.shutdown(std::net::Shutdown::Both)?;
+        }
+        drop(initiator);
+
+        let mut resumed = create_websocket(url.as_str())?;
+        if let tungstenite::stream::MaybeTlsStream::Plain(stream) = resumed.get_mut() {
+            stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        }
+        send(
+            &mut resumed,
+            ApiMethod::ResumeSession,
+            ClientPayload::ResumeSession {
+                session_id: "s".into(),
+                resume_token,
+                last_sequence: Some(0),
+            },
+        )?;
+        assert_eq!(
+            wait_for_server_message(&mut resumed)?.typ,
+            ServerMessageType::SessionResumed
+        );
+
+        let message = wait_for_server_message(&mut resumed)?;
+        assert_eq!(message.sequence, Some(1));
+        assert_eq!(message.as_peer_message()?.message, "hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn remote_signing() -> Result<(), RemoteSignError> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("ws://{}/", listener.local_addr()?);
+
+        std::thread::spawn(move || RelayServer::default().serve(&listener));
+
+        let initiator = SharedSecretInitiator::new(b"secret".to_vec())?;
+        let session_join_string = initiator.session_join_string_base64()?;
+
+        let signer_url = url.clone();
+        let signer = std::thread::spawn(move || -> Result<(), RemoteSignError> {
+            let (signing_key, cert) = rsa_signing_identity();
+
+            // The session may not have been created yet. So retry joining.
+            loop {
+                let mut joiner = create_session_joiner(&session_join_string)?;
+                joiner.register_state(SessionJoinState::SharedSecret(b"secret".to_vec()))?;
+
+                match UnjoinedSigningClient::new_signer(
+                    joiner,
+                    &signing_key,
+                    cert.clone(),
+                    vec![],
+                    signer_url.clone(),
+                ) {
+                    Ok(client) => return client.run(),
+                    Err(RemoteSignError::ServerError(e)) if e.starts_with("session-not-found") => {
+                        std::thread::sleep(Duration::from_millis(50));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        });
+
+        let client = UnjoinedSigningClient::new_initiator(url.as_str(), Box::new(initiator), None)?;
+
+        let signature = client
+            .try_sign(b"hello, world")
+            .map_err(|e| RemoteSignError::Crypto(e.to_string()))?;
+        client
+            .signing_certificate()
+            .verify_signed_data(b"hello, world", signature.as_ref())?;
+
+        // Batches are pipelined.
+        let messages: [&[u8]; 3] = [b"one", b"two", b"three"];
+        let signatures = client.sign_messages(&messages)?;
+        for (message, signature) in messages.iter().zip(signatures) {
+            client
+                .signing_certificate()
+                .verify_signed_data(message, signature.as_ref())?;
+        }
+
+        // Concurrent requests are multiplexed over the session.
+        std::thread::scope(|scope| -> Result<(), RemoteSignError> {
+            let threads = (0..4)
+                .map(|i| {
+                    let client = &client;
+                    scope.spawn(move || -> Result<(), RemoteSignError> {
+                        let message = format!("thread {i}");
+                        let signature = client
+                            .try_sign(message.as_bytes())
+                            .map_err(|e| RemoteSignError::Crypto(e.to_string()))?;
+
+                        Ok(client
+                            .signing_certificate()
+                            .verify_signed_data(message.as_bytes(), signature.as_ref())?)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for thread in threads {
+                thread.join().expect("signing thread should not panic")?;
+            }
+
+            Ok(())
+        })?;
+
+        // Bundle signing sends the signatures of nested code concurrently.
+        let temp_dir = tempfile::tempdir()?;
+        let bundle = temp_dir.path().join("test.app");
+        write_app_bundle(&bundle)?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(
+            client.as_key_info_signer(),
+            client.signing_certificate().clone(),
+        );
+        BundleSigner::new_from_path(&bundle)
+            .and_then(|signer| signer.write_signed_bundle(&bundle, &settings))
+            .map_err(|e| RemoteSignError::Crypto(e.to_string()))?;
+
+        let mut verify_settings = VerificationSettings::default();
+        verify_settings.add_trust_anchor(client.signing_certificate().clone());
+        let problems = verify_bundle_with_settings(&bundle, &verify_settings);
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        // Signing resumes the session after the connection drops.
+        if let tungstenite::stream::MaybeTlsStream::Plain(stream) =
+            client.client.lock().unwrap().ws.get_mut()
+        {
+            stream.shutdown(std::net::Shutdown::Both)?;
+        }
+
+        let signature = client
+            .try_sign(b"resumed")
+            .map_err(|e| RemoteSignError::Crypto(e.to_string()))?;
+        client
+            .signing_certificate()
+            .verify_signed_data(b"resumed", signature.as_ref())?;
+
+        client
+            .client
+            .lock()
+            .unwrap()
+            .send_goodbye(Some("done".into()))?;
+        signer.join().expect("signer thread should not panic")?;
+
+        Ok(())
+    }
+}
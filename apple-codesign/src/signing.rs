@@ -11,7 +11,10 @@ use {
         error::AppleCodesignError,
         macho_signing::{write_macho_file, MachOSigner},
         reader::PathType,
+        signing_plan::{CodeSignaturePlan, SigningPlan},
         signing_settings::{SettingsScope, SigningSettings},
+        xattr_signing::XattrSigner,
+        zip_signing::ZipSigner,
     },
     apple_xar::{reader::XarReader, signing::XarSigner},
     log::{info, warn},
@@ -42,7 +45,9 @@ impl<'key> UnifiedSigner<'key> {
             PathType::Dmg => self.sign_dmg(input_path, output_path),
             PathType::MachO => self.sign_macho(input_path, output_path),
             PathType::Xar => self.sign_xar(input_path, output_path),
-            PathType::Zip | PathType::Other => Err(AppleCodesignError::UnrecognizedPathType),
+            PathType::Zip => self.sign_zip(input_path, output_path),
+            PathType::File => self.sign_file(input_path, output_path),
+            PathType::Other => Err(AppleCodesignError::UnrecognizedPathType),
         }
     }
 
@@ -68,9 +73,64 @@ impl<'key> UnifiedSigner<'key> {
         warn!("signing {} as a Mach-O binary", input_path.display());
         let macho_data = std::fs::read(input_path)?;
 
+        let settings = self.macho_settings(input_path, &macho_data)?;
+
+        warn!("parsing Mach-O");
+        let signer = MachOSigner::new(&macho_data)?;
+
+        let mut macho_data = vec![];
+        signer.write_signed_binary(&settings, &mut macho_data)?;
+        warn!("writing Mach-O to {}", output_path.display());
+        write_macho_file(input_path, output_path, &macho_data)?;
+
+        Ok(())
+    }
+
+    /// Sign a Mach-O binary, writing a detached signature to `signature_path`.
+    ///
+    /// The Mach-O binary is not modified. See [MachOSigner::write_detached_signature()]
+    /// for the format of the written signature.
+    pub fn sign_macho_detached(
+        &self,
+        input_path: impl AsRef<Path>,
+        signature_path: impl AsRef<Path>,
+    ) -> Result<(), AppleCodesignError> {
+        let input_path = input_path.as_ref();
+        let signature_path = signature_path.as_ref();
+
+        warn!(
+            "creating detached signature for Mach-O binary {}",
+            input_path.display()
+        );
+        let macho_data = std::fs::read(input_path)?;
+
+        let settings = self.macho_settings(input_path, &macho_data)?;
+
+        warn!("parsing Mach-O");
+        let signer = MachOSigner::new(&macho_data)?;
+
+        let mut signature_data = vec![];
+        signer.write_detached_signature(&settings, &mut signature_data)?;
+
+        if let Some(parent) = signature_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        warn!("writing detached signature to {}", signature_path.display());
+        std::fs::write(signature_path, signature_data)?;
+
+        Ok(())
+    }
+
+    /// Resolve the settings to use for signing a Mach-O binary.
+    fn macho_settings(
+        &self,
+        input_path: &Path,
+        macho_data: &[u8],
+    ) -> Result<SigningSettings<'key>, AppleCodesignError> {
         let mut settings = self.settings.clone();
 
-        settings.import_settings_from_macho(&macho_data)?;
+        settings.import_settings_from_macho(macho_data)?;
 
         if settings.binary_identifier(SettingsScope::Main).is_none() {
             let identifier = input_path
@@ -86,15 +146,7 @@ impl<'key> UnifiedSigner<'key> {
             settings.set_binary_identifier(SettingsScope::Main, identifier);
         }
 
-        warn!("parsing Mach-O");
-        let signer = MachOSigner::new(&macho_data)?;
-
-        let mut macho_data = vec![];
-        signer.write_signed_binary(&settings, &mut macho_data)?;
-        warn!("writing Mach-O to {}", output_path.display());
-        write_macho_file(input_path, output_path, &macho_data)?;
-
-        Ok(())
+        Ok(settings)
     }
 
     /// Sign a `.dmg` file.
@@ -108,24 +160,7 @@ impl<'key> UnifiedSigner<'key> {
 
         warn!("signing {} as a DMG", input_path.display());
 
-        // There must be a binary identifier on the DMG. So try to derive one
-        // from the filename if one isn't present in the settings.
-        let mut settings = self.settings.clone();
-
-        if settings.binary_identifier(SettingsScope::Main).is_none() {
-            let file_name = input_path
-                .file_stem()
-                .ok_or_else(|| {
-                    AppleCodesignError::CliGeneralError("unable to resolve file name of DMG".into())
-                })?
-                .to_string_lossy();
-
-            warn!(
-                "setting binary identifier to {} (derived from file name)",
-                file_name
-            );
-            settings.set_binary_identifier(SettingsScope::Main, file_name);
-        }
+        let settings = self.dmg_settings(input_path)?;
 
         // The DMG signer signs in place because it needs a `File` handle. So if
         // the output path is different, copy the DMG first.
@@ -154,6 +189,30 @@ impl<'key> UnifiedSigner<'key> {
         Ok(())
     }
 
+    /// Resolve the settings to use for signing a DMG.
+    fn dmg_settings(&self, input_path: &Path) -> Result<SigningSettings<'key>, AppleCodesignError> {
+        // There must be a binary identifier on the DMG. So try to derive one
+        // from the filename if one isn't present in the settings.
+        let mut settings = self.settings.clone();
+
+        if settings.binary_identifier(SettingsScope::Main).is_none() {
+            let file_name = input_path
+                .file_stem()
+                .ok_or_else(|| {
+                    AppleCodesignError::CliGeneralError("unable to resolve file name of DMG".into())
+                })?
+                .to_string_lossy();
+
+            warn!(
+                "setting binary identifier to {} (derived from file name)",
+                file_name
+            );
+            settings.set_binary_identifier(SettingsScope::Main, file_name);
+        }
+
+        Ok(settings)
+    }
+
     /// Sign a bundle.
     pub fn sign_bundle(
         &self,
@@ -198,6 +257,11 @@ impl<'key> UnifiedSigner<'key> {
             .signing_key()
             .ok_or(AppleCodesignError::XarNoAdhoc)?;
 
+        // The XAR signer can't pin the CMS signing time.
+        if self.settings.signing_time().is_some() {
+            return Err(AppleCodesignError::XarDeterministic);
+        }
+
         {
             let reader = XarReader::new(File::open(input_path)?)?;
             let mut signer = XarSigner::new(reader);
@@ -207,7 +271,7 @@ impl<'key> UnifiedSigner<'key> {
                 &mut fh,
                 signing_key,
                 signing_cert,
-                self.settings.time_stamp_url(),
+                self.settings.effective_time_stamp_url(),
                 self.settings.certificate_chain().iter().cloned(),
             )?;
         }
@@ -226,4 +290,154 @@ impl<'key> UnifiedSigner<'key> {
 
         Ok(())
     }
+
+    /// Sign a ZIP archive, such as an `.ipa` file.
+    ///
+    /// Bundles within the archive are signed and a new archive is written.
+    pub fn sign_zip(
+        &self,
+        input_path: impl AsRef<Path>,
+        output_path: impl AsRef<Path>,
+    ) -> Result<(), AppleCodesignError> {
+        let input_path = input_path.as_ref();
+        warn!("signing ZIP archive at {}", input_path.display());
+
+        // The archive is fully extracted before the output is written. So
+        // signing in place is safe.
+        let signer = ZipSigner::new_from_path(input_path)?;
+        signer.write_signed_zip(output_path, &self.settings)?;
+
+        Ok(())
+    }
+
+    /// Sign a file that isn't any of the other supported types, such as a script.
+    ///
+    /// The signature is stored in extended attributes. See [XattrSigner].
+    pub fn sign_file(
+        &self,
+        input_path: impl AsRef<Path>,
+        output_path: impl AsRef<Path>,
+    ) -> Result<(), AppleCodesignError> {
+        let input_path = input_path.as_ref();
+        let output_path = output_path.as_ref();
+
+        let settings = self.file_settings(input_path)?;
+
+        // This is not robust same file detection.
+        if input_path != output_path {
+            info!(
+                "copying {} to {} in preparation for signing",
+                input_path.display(),
+                output_path.display()
+            );
+            if let Some(parent) = output_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::copy(input_path, output_path)?;
+        }
+
+        XattrSigner::default().sign_file(&settings, output_path)
+    }
+
+    /// Resolve the settings to use for signing a file via extended attributes.
+    fn file_settings(
+        &self,
+        input_path: &Path,
+    ) -> Result<SigningSettings<'key>, AppleCodesignError> {
+        let mut settings = self.settings.clone();
+
+        if settings.binary_identifier(SettingsScope::Main).is_none() {
+            let identifier = input_path
+                .file_name()
+                .ok_or_else(|| {
+                    AppleCodesignError::CliGeneralError("unable to resolve file name".into())
+                })?
+                .to_string_lossy();
+
+            warn!("setting binary identifier to {}", identifier);
+            settings.set_binary_identifier(SettingsScope::Main, identifier);
+        }
+
+        Ok(settings)
+    }
+
+    /// Describe how [Self::sign_path()] would sign `input_path`.
+    ///
+    /// Nothing is written. ZIP archives are extracted to a temporary directory
+    /// so the bundles within can be examined.
+    pub fn plan_path(
+        &self,
+        input_path: impl AsRef<Path>,
+    ) -> Result<SigningPlan, AppleCodesignError> {
+        let input_path = input_path.as_ref();
+        let path = input_path.display().to_string();
+
+        match PathType::from_path(input_path)? {
+            PathType::Bundle => {
+                let mut plan = SigningPlan::new(path, "bundle", &self.settings);
+                plan.bundles = BundleSigner::new_from_path(input_path)?.plan(&self.settings)?;
+
+                Ok(plan)
+            }
+            PathType::Dmg => {
+                let settings = self.dmg_settings(input_path)?;
+
+                let mut plan = SigningPlan::new(path, "dmg", &settings);
+                plan.signatures.push(CodeSignaturePlan {
+                    architecture: None,
+                    identifier: settings
+                        .binary_identifier(SettingsScope::Main)
+                        .map(|x| x.to_string()),
+                    team_id: None,
+                    code_signature_flags: settings
+                        .code_signature_flags(SettingsScope::Main)
+                        .map(|x| format!("{x:?}")),
+                    runtime_version: None,
+                    digests: vec![settings.digest_type().to_string()],
+                    designated_requirement: None,
+                    entitlements: None,
+                });
+
+                Ok(plan)
+            }
+            PathType::MachO => {
+                let macho_data = std::fs::read(input_path)?;
+                let settings = self.macho_settings(input_path, &macho_data)?;
+
+                let mut plan = SigningPlan::new(path, "macho", &settings);
+                plan.signatures = CodeSignaturePlan::from_macho(&settings, &macho_data)?;
+
+                Ok(plan)
+            }
+            PathType::Xar => {
+                self.settings
+                    .signing_key()
+                    .ok_or(AppleCodesignError::XarNoAdhoc)?;
+                if self.settings.signing_time().is_some() {
+                    return Err(AppleCodesignError::XarDeterministic);
+                }
+
+                Ok(SigningPlan::new(path, "xar", &self.settings))
+            }
+            PathType::Zip => {
+                let mut plan = SigningPlan::new(path, "zip", &self.settings);
+                plan.bundles = ZipSigner::new_from_path(input_path)?.plan(&self.settings)?;
+
+                Ok(plan)
+            }
+            PathType::File => {
+                let settings = self.file_settings(input_path)?;
+
+                let mut plan = SigningPlan::new(path, "file", &settings);
+                let mut signature = CodeSignaturePlan::from_settings(&settings, None)?;
+                signature.runtime_version = None;
+                signature.digests = vec![settings.digest_type().to_string()];
+                plan.signatures.push(signature);
+
+                Ok(plan)
+            }
+            PathType::Other => Err(AppleCodesignError::UnrecognizedPathType),
+        }
+    }
 }
@@ -0,0 +1,585 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Declarative signing configuration files.
+//!
+//! [SigningConfig] describes the settings of a signing operation in a TOML or
+//! YAML file instead of via command arguments. Settings that can be scoped
+//! (see [SettingsScope]) live in a map keyed by the same scope strings used on
+//! the command line (e.g. `@main`, `@[cpu_type=arm64]`, or
+//! `Contents/MacOS/foo`).
+//!
+//! An example YAML configuration:
+//!
+//! ```yaml
+//! digest: sha256
+//! team-name: MK22MZP987
+//! exclude:
+//!   - "Contents/Resources/*.plugin"
+//! certificate:
+//!   p12-file: developer-id.p12
+//!   p12-password-file: password.txt
+//! scopes:
+//!   "@main":
+//!     code-signature-flags: [runtime]
+//!     entitlements-xml-path: entitlements.plist
+//!   Contents/MacOS/helper:
+//!     binary-identifier: com.example.helper
+//! ```
+
+use {
+    crate::{
+        code_directory::CodeSignatureFlags,
+        code_requirement::CodeRequirements,
+        embedded_signature::DigestType,
+        provisioning_profile::ProvisioningProfile,
+        signing_settings::{SettingsScope, SigningSettings},
+        AppleCodesignError,
+    },
+    log::warn,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+    },
+};
+
+/// File format of a [SigningConfig].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SigningConfigFormat {
+    Toml,
+    Yaml,
+}
+
+impl SigningConfigFormat {
+    /// Resolve the format of a file from its extension.
+    ///
+    /// Files ending in `.toml` are TOML. Everything else is YAML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("toml") => Self::Toml,
+            _ => Self::Yaml,
+        }
+    }
+}
+
+/// Sources of the signing certificate and private key.
+///
+/// These mirror the certificate arguments of `rcodesign sign`. Passwords
+/// can only be given via files or environment variables.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CertificateConfig {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pem_source: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pem_password_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub der_source: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p12_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p12_password_file: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smartcard_slot: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smartcard_pin_env: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keychain_domain: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keychain_fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkcs11_module: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkcs11_token_label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkcs11_key_label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkcs11_key_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkcs11_pin_env: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub external_signer: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub external_signer_args: Vec<String>,
+}
+
+/// Settings applying to a single [SettingsScope].
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ScopeConfig {
+    /// Identifier string for the binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub binary_identifier: Option<String>,
+
+    /// Path to a file containing binary code requirements data to use as designated requirements.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_requirements_path: Option<PathBuf>,
+
+    /// Code Signing Requirement Language expression to use as designated requirements.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub designated_requirement: Option<String>,
+
+    /// Path to an XML plist file containing code resources.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_resources_path: Option<PathBuf>,
+
+    /// Code signature flags to set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_signature_flags: Option<Vec<String>>,
+
+    /// Path to a plist file containing entitlements.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entitlements_xml_path: Option<PathBuf>,
+
+    /// Hardened runtime version.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runtime_version: Option<String>,
+
+    /// Path to an Info.plist file whose digest to include in the signature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info_plist_path: Option<PathBuf>,
+
+    /// Path to a provisioning profile to embed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provisioning_profile: Option<PathBuf>,
+
+    /// Extra digests to include in signatures.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_digests: Vec<String>,
+}
+
+impl ScopeConfig {
+    /// Overlay settings from another instance on top of this one.
+    ///
+    /// Settings defined by `other` replace ours. Extra digests are combined.
+    pub fn merge(&mut self, other: ScopeConfig) {
+        fn overlay<T>(ours: &mut Option<T>, theirs: Option<T>) {
+            if theirs.is_some() {
+                *ours = theirs;
+            }
+        }
+
+        overlay(&mut self.binary_identifier, other.binary_identifier);
+        overlay(
+            &mut self.code_requirements_path,
+            other.code_requirements_path,
+        );
+        overlay(
+            &mut self.designated_requirement,
+            other.designated_requirement,
+        );
+        overlay(&mut self.code_resources_path, other.code_resources_path);
+        overlay(&mut self.code_signature_flags, other.code_signature_flags);
+        overlay(&mut self.entitlements_xml_path, other.entitlements_xml_path);
+        overlay(&mut self.runtime_version, other.runtime_version);
+        overlay(&mut self.info_plist_path, other.info_plist_path);
+        overlay(&mut self.provisioning_profile, other.provisioning_profile);
+        self.extra_digests.extend(other.extra_digests);
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        for path in [
+            &mut self.code_requirements_path,
+            &mut self.code_resources_path,
+            &mut self.entitlements_xml_path,
+            &mut self.info_plist_path,
+            &mut self.provisioning_profile,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base.join(&path);
+        }
+    }
+
+    fn apply(
+        &self,
+        key: &str,
+        scope: SettingsScope,
+        settings: &mut SigningSettings,
+    ) -> Result<(), AppleCodesignError> {
+        let invalid = |field: &str, message: String| {
+            AppleCodesignError::SigningConfigInvalid(format!("scopes.\"{key}\".{field}"), message)
+        };
+
+        if let Some(identifier) = &self.binary_identifier {
+            settings.set_binary_identifier(scope.clone(), identifier);
+        }
+
+        if let Some(path) = &self.code_requirements_path {
+            let data = std::fs::read(path).map_err(|e| {
+                invalid("code-requirements-path", format!("{}: {e}", path.display()))
+            })?;
+            let reqs = CodeRequirements::parse_blob(&data)
+                .map_err(|e| invalid("code-requirements-path", e.to_string()))?
+                .0;
+            for expr in reqs.iter() {
+                warn!(
+                    "setting designated code requirements for {}: {}",
+                    scope, expr
+                );
+                settings.set_designated_requirement_expression(scope.clone(), expr)?;
+            }
+        }
+
+        if let Some(expression) = &self.designated_requirement {
+            let reqs = CodeRequirements::parse_str(expression)
+                .map_err(|e| invalid("designated-requirement", e.to_string()))?;
+            for expr in reqs.iter() {
+                warn!(
+                    "setting designated code requirements for {}: {}",
+                    scope, expr
+                );
+                settings.set_designated_requirement_expression(scope.clone(), expr)?;
+            }
+        }
+
+        if let Some(path) = &self.code_resources_path {
+            warn!(
+                "setting code resources data for {} from path {}",
+                scope,
+                path.display()
+            );
+            let data = std::fs::read(path)
+                .map_err(|e| invalid("code-resources-path", format!("{}: {e}", path.display())))?;
+            settings.set_code_resources_data(scope.clone(), data);
+        }
+
+        if let Some(flags) = &self.code_signature_flags {
+            let flags = CodeSignatureFlags::from_strs(
+                &flags.iter().map(|x| x.as_str()).collect::<Vec<_>>(),
+            )
+            .map_err(|e| invalid("code-signature-flags", e.to_string()))?;
+            settings.set_code_signature_flags(scope.clone(), flags);
+        }
+
+        if let Some(path) = &self.entitlements_xml_path {
+            warn!(
+                "setting entitlments XML for {} from path {}",
+                scope,
+                path.display()
+            );
+            let data = std::fs::read_to_string(path).map_err(|e| {
+                invalid("entitlements-xml-path", format!("{}: {e}", path.display()))
+            })?;
+            settings
+                .set_entitlements_xml(scope.clone(), data)
+                .map_err(|e| invalid("entitlements-xml-path", e.to_string()))?;
+        }
+
+        if let Some(version) = &self.runtime_version {
+            let version = semver::Version::parse(version)
+                .map_err(|e| invalid("runtime-version", e.to_string()))?;
+            settings.set_runtime_version(scope.clone(), version);
+        }
+
+        if let Some(path) = &self.info_plist_path {
+            let data = std::fs::read(path)
+                .map_err(|e| invalid("info-plist-path", format!("{}: {e}", path.display())))?;
+            settings.set_info_plist_data(scope.clone(), data);
+        }
+
+        if let Some(path) = &self.provisioning_profile {
+            let profile = ProvisioningProfile::from_path(path)
+                .map_err(|e| invalid("provisioning-profile", e.to_string()))?;
+            warn!(
+                "embedding provisioning profile {} for {}",
+                profile
+                    .name()
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| path.display().to_string()),
+                scope
+            );
+            settings.set_provisioning_profile(scope.clone(), profile);
+        }
+
+        for value in &self.extra_digests {
+            let digest_type = DigestType::try_from(value.as_str())
+                .map_err(|e| invalid("extra-digests", e.to_string()))?;
+            settings.add_extra_digest(scope.clone(), digest_type);
+        }
+
+        Ok(())
+    }
+}
+
+/// Declarative description of a signing operation.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SigningConfig {
+    /// Digest algorithm to use.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+
+    /// Team name/identifier to include in code signatures.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team_name: Option<String>,
+
+    /// URL of the time-stamp server to use. `none` disables time-stamping.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_url: Option<String>,
+
+    /// Glob expressions of paths to exclude from signing.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+
+    /// Where to obtain the signing certificate and key from.
+    #[serde(skip_serializing_if = "is_default")]
+    pub certificate: CertificateConfig,
+
+    /// Settings keyed by [SettingsScope] strings.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub scopes: BTreeMap<String, ScopeConfig>,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    value == &T::default()
+}
+
+impl SigningConfig {
+    /// Parse an instance from TOML or YAML data.
+    pub fn from_data(data: &str, format: SigningConfigFormat) -> Result<Self, AppleCodesignError> {
+        match format {
+            SigningConfigFormat::Toml => toml::from_str(data)
+                .map_err(|e| AppleCodesignError::SigningConfigParse(e.to_string())),
+            SigningConfigFormat::Yaml => serde_yaml::from_str(data)
+                .map_err(|e| AppleCodesignError::SigningConfigParse(e.to_string())),
+        }
+    }
+
+    /// Load an instance from a file.
+    ///
+    /// The format is derived from the file extension. Relative paths in the file
+    /// are resolved against the directory containing it.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, AppleCodesignError> {
+        let path = path.as_ref();
+
+        let data = std::fs::read_to_string(path)?;
+        let mut config =
+            Self::from_data(&data, SigningConfigFormat::from_path(path)).map_err(|e| match e {
+                AppleCodesignError::SigningConfigParse(message) => {
+                    AppleCodesignError::SigningConfigParse(format!("{}: {message}", path.display()))
+                }
+                e => e,
+            })?;
+
+        config.resolve_paths(path.parent().unwrap_or_else(|| Path::new("")));
+
+        Ok(config)
+    }
+
+    /// Serialize the instance to TOML or YAML.
+    pub fn to_string(&self, format: SigningConfigFormat) -> Result<String, AppleCodesignError> {
+        match format {
+            SigningConfigFormat::Toml => toml::to_string(self)
+                .map_err(|e| AppleCodesignError::SigningConfigParse(e.to_string())),
+            SigningConfigFormat::Yaml => Ok(serde_yaml::to_string(self)?),
+        }
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        let cert = &mut self.certificate;
+
+        for path in cert.pem_source.iter_mut().chain(cert.der_source.iter_mut()) {
+            *path = base.join(&path);
+        }
+
+        for path in [
+            &mut cert.pem_password_file,
+            &mut cert.p12_file,
+            &mut cert.p12_password_file,
+            &mut cert.pkcs11_module,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base.join(&path);
+        }
+
+        // External signers may be resolved via PATH.
+        if let Some(path) = &mut cert.external_signer {
+            if path.components().count() > 1 {
+                *path = base.join(&path);
+            }
+        }
+
+        for scope in self.scopes.values_mut() {
+            scope.resolve_paths(base);
+        }
+    }
+
+    /// Obtain the settings for a scope, creating them if necessary.
+    ///
+    /// Scope strings referring to the same [SettingsScope] resolve to the same entry.
+    pub fn scope_mut(&mut self, key: &str) -> Result<&mut ScopeConfig, AppleCodesignError> {
+        let scope = SettingsScope::try_from(key)?;
+
+        let mut existing = None;
+        for candidate in self.scopes.keys() {
+            if SettingsScope::try_from(candidate.as_str()).ok().as_ref() == Some(&scope) {
+                existing = Some(candidate.clone());
+                break;
+            }
+        }
+
+        Ok(self
+            .scopes
+            .entry(existing.unwrap_or_else(|| key.to_string()))
+            .or_default())
+    }
+
+    /// Overlay settings from another instance on top of this one.
+    ///
+    /// Settings defined by `other` replace ours. Exclusions and extra digests
+    /// are combined. Certificate settings are replaced as a unit if `other`
+    /// defines any.
+    pub fn merge(&mut self, other: SigningConfig) -> Result<(), AppleCodesignError> {
+        if other.digest.is_some() {
+            self.digest = other.digest;
+        }
+        if other.team_name.is_some() {
+            self.team_name = other.team_name;
+        }
+        if other.timestamp_url.is_some() {
+            self.timestamp_url = other.timestamp_url;
+        }
+        self.exclude.extend(other.exclude);
+
+        if other.certificate != CertificateConfig::default() {
+            self.certificate = other.certificate;
+        }
+
+        for (key, scope) in other.scopes {
+            self.scope_mut(&key)?.merge(scope);
+        }
+
+        Ok(())
+    }
+
+    /// Apply settings to a [SigningSettings].
+    ///
+    /// Certificate and time-stamp settings aren't applied, as they require the
+    /// signing key to be resolved first.
+    ///
+    /// Errors identify the offending configuration key.
+    pub fn apply_to_settings(
+        &self,
+        settings: &mut SigningSettings,
+    ) -> Result<(), AppleCodesignError> {
+        if let Some(team_name) = &self.team_name {
+            settings.set_team_id(team_name);
+        }
+
+        if let Some(value) = &self.digest {
+            let digest_type = DigestType::try_from(value.as_str()).map_err(|e| {
+                AppleCodesignError::SigningConfigInvalid("digest".into(), e.to_string())
+            })?;
+            settings.set_digest_type(digest_type);
+        }
+
+        for pattern in &self.exclude {
+            settings.add_path_exclusion(pattern).map_err(|e| {
+                AppleCodesignError::SigningConfigInvalid("exclude".into(), e.to_string())
+            })?;
+        }
+
+        for (key, scope_config) in &self.scopes {
+            let scope = SettingsScope::try_from(key.as_str()).map_err(|e| {
+                AppleCodesignError::SigningConfigInvalid(format!("scopes.\"{key}\""), e.to_string())
+            })?;
+
+            scope_config.apply(key, scope, settings)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_formats() -> Result<(), AppleCodesignError> {
+        let yaml = SigningConfig::from_data(
+            "digest: sha256\nscopes:\n  \"@main\":\n    code-signature-flags: [runtime]\n",
+            SigningConfigFormat::Yaml,
+        )?;
+        let toml = SigningConfig::from_data(
+            "digest = \"sha256\"\n[scopes.\"@main\"]\ncode-signature-flags = [\"runtime\"]\n",
+            SigningConfigFormat::Toml,
+        )?;
+
+        assert_eq!(yaml, toml);
+        assert_eq!(
+            yaml.scopes["@main"].code_signature_flags,
+            Some(vec!["runtime".to_string()])
+        );
+
+        assert_eq!(
+            SigningConfig::from_data(
+                &yaml.to_string(SigningConfigFormat::Toml)?,
+                SigningConfigFormat::Toml
+            )?,
+            yaml
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_keys_rejected() {
+        let err = SigningConfig::from_data(
+            "scopes:\n  \"@main\":\n    binary-identifer: foo\n",
+            SigningConfigFormat::Yaml,
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("binary-identifer"));
+    }
+
+    #[test]
+    fn invalid_values_identify_key() {
+        let config = SigningConfig::from_data(
+            "scopes:\n  \"@[cpu_type=arm64]\":\n    runtime-version: bad\n",
+            SigningConfigFormat::Yaml,
+        )
+        .unwrap();
+
+        let err = config
+            .apply_to_settings(&mut SigningSettings::default())
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            AppleCodesignError::SigningConfigInvalid(key, _) if key == "scopes.\"@[cpu_type=arm64]\".runtime-version"
+        ));
+    }
+
+    #[test]
+    fn merge() -> Result<(), AppleCodesignError> {
+        let mut config = SigningConfig::from_data(
+            "team-name: A\nexclude: [a]\nscopes:\n  \"@[cpu_type=arm64]\":\n    binary-identifier: file\n    runtime-version: 12.0.0\n",
+            SigningConfigFormat::Yaml,
+        )?;
+
+        let mut cli = SigningConfig {
+            exclude: vec!["b".into()],
+            ..Default::default()
+        };
+        cli.scope_mut("@[cpu_type=16777228]")?.binary_identifier = Some("cli".into());
+
+        config.merge(cli)?;
+
+        assert_eq!(config.team_name.as_deref(), Some("A"));
+        assert_eq!(config.exclude, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(config.scopes.len(), 1);
+        let scope = &config.scopes["@[cpu_type=arm64]"];
+        assert_eq!(scope.binary_identifier.as_deref(), Some("cli"));
+        assert_eq!(scope.runtime_version.as_deref(), Some("12.0.0"));
+
+        Ok(())
+    }
+}
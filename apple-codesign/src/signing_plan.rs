@@ -0,0 +1,546 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Describing what signing would do without signing.
+//!
+//! [crate::UnifiedSigner::plan_path()] produces a [SigningPlan] describing how
+//! each entity would be signed: which files in bundles are signed as nested
+//! code, sealed as resources, or excluded, and which identifier, entitlements,
+//! flags, designated requirement, and digests apply to each signature after
+//! scoped settings are merged. Nothing is written.
+
+use {
+    crate::{
+        code_requirement::RequirementType,
+        error::AppleCodesignError,
+        macho::MachFile,
+        macho_signing::create_requirement_set_blob,
+        signing_settings::{SettingsScope, SigningSettings},
+    },
+    serde::Serialize,
+};
+
+/// Describes how a filesystem path would be signed.
+#[derive(Clone, Debug, Serialize)]
+pub struct SigningPlan {
+    /// The path being signed.
+    pub path: String,
+    /// The type of entity being signed, such as `bundle` or `macho`.
+    pub path_type: String,
+    /// Subject common name of the signing certificate. Absent for ad-hoc signing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_certificate: Option<String>,
+    /// URL of the time-stamp server signatures would be time-stamped with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_stamp_url: Option<String>,
+    /// Pinned signing time of deterministic signing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing_time: Option<String>,
+    /// Bundles that would be signed, in signing order.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bundles: Vec<BundlePlan>,
+    /// Signatures of a Mach-O binary, DMG, or other file being signed directly.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<CodeSignaturePlan>,
+}
+
+impl SigningPlan {
+    /// Construct an instance describing global settings.
+    pub(crate) fn new(path: String, path_type: &str, settings: &SigningSettings) -> Self {
+        Self {
+            path,
+            path_type: path_type.to_string(),
+            signing_certificate: settings
+                .signing_key()
+                .and_then(|(_, cert)| cert.subject_common_name()),
+            time_stamp_url: settings.effective_time_stamp_url().map(|x| x.to_string()),
+            signing_time: settings.signing_time().map(|x| x.to_rfc3339()),
+            bundles: vec![],
+            signatures: vec![],
+        }
+    }
+}
+
+/// Describes how a single bundle would be signed.
+#[derive(Clone, Debug, Serialize)]
+pub struct BundlePlan {
+    /// Path of the bundle relative to the path being signed.
+    ///
+    /// Empty for the main bundle.
+    pub path: String,
+    /// The bundle's `CFBundleIdentifier`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    /// Whether the bundle matches an exclusion pattern and is copied without signing.
+    pub excluded: bool,
+    /// Whether the bundle is already signed as it would be and is copied without
+    /// signing. See [crate::bundle_signing::SingleBundleSigner::is_signed_with()].
+    pub reused: bool,
+    /// Digests recorded for sealed resources.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub resources_digests: Vec<String>,
+    /// Files of the bundle and how each would be handled.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<BundleFilePlan>,
+}
+
+/// How a file in a bundle would be handled by signing.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BundleFileAction {
+    /// The main executable. Signed last, sealing the bundle's resources.
+    MainExecutable,
+    /// A Mach-O binary signed and sealed as nested code.
+    NestedCode,
+    /// A Mach-O binary already signed with the same settings. Its existing
+    /// signature is kept and it is sealed as nested code.
+    ReusedCode,
+    /// A file or symlink sealed as a resource.
+    Resource,
+    /// A file installed without being sealed.
+    Omitted,
+    /// A file not sealed by this bundle.
+    ///
+    /// This includes files of nested bundles and code signature files.
+    Excluded,
+    /// A file replaced by the provisioning profile being embedded.
+    ProvisioningProfile,
+    /// A file copied as-is, such as files outside the versions of a versioned framework.
+    Copied,
+}
+
+/// Describes how a file in a bundle would be handled.
+#[derive(Clone, Debug, Serialize)]
+pub struct BundleFilePlan {
+    /// Path of the file relative to the bundle root.
+    pub path: String,
+    pub action: BundleFileAction,
+    /// Signatures of a main executable or nested code, one per architecture.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<CodeSignaturePlan>,
+}
+
+/// Describes the signature that would be created for a single entity.
+///
+/// Values reflect settings after scoped settings have been merged and
+/// existing signature settings have been imported.
+#[derive(Clone, Debug, Serialize)]
+pub struct CodeSignaturePlan {
+    /// Architecture of the Mach-O binary within a fat binary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub team_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_signature_flags: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runtime_version: Option<String>,
+    /// Digests of code directories. The first is the primary code directory.
+    pub digests: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub designated_requirement: Option<String>,
+    /// Entitlements as an XML plist.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entitlements: Option<String>,
+}
+
+impl CodeSignaturePlan {
+    /// Describe the signature created from the main scope of settings.
+    pub(crate) fn from_settings(
+        settings: &SigningSettings,
+        architecture: Option<String>,
+    ) -> Result<Self, AppleCodesignError> {
+        let requirements = create_requirement_set_blob(settings)?;
+        let designated_requirement = requirements
+            .requirements
+            .get(&RequirementType::Designated)
+            .map(|blob| blob.parse_expressions())
+            .transpose()?
+            .map(|x| format!("{x}"));
+
+        Ok(Self {
+            architecture,
+            identifier: settings
+                .binary_identifier(SettingsScope::Main)
+                .map(|x| x.to_string()),
+            team_id: settings.team_id().map(|x| x.to_string()),
+            code_signature_flags: settings
+                .code_signature_flags(SettingsScope::Main)
+                .map(|x| format!("{x:?}")),
+            runtime_version: settings
+                .runtime_version(SettingsScope::Main)
+                .map(|x| x.to_string()),
+            digests: settings
+                .all_digests(SettingsScope::Main)
+                .into_iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>(),
+            designated_requirement,
+            entitlements: settings.entitlements_xml(SettingsScope::Main)?,
+        })
+    }
+
+    /// Describe the signatures of each architecture of a Mach-O binary.
+    ///
+    /// `settings` should be the settings the whole binary would be signed with.
+    pub(crate) fn from_macho(
+        settings: &SigningSettings,
+        macho_data: &[u8],
+    ) -> Result<Vec<Self>, AppleCodesignError> {
+        let mach = MachFile::parse(macho_data)?;
+        let fat = mach.is_fat();
+
+        mach.iter_macho()
+            .enumerate()
+            .map(|(index, macho)| {
+                let settings =
+                    settings.as_nested_macho_settings(index, macho.macho.header.cputype());
+
+                Self::from_settings(
+                    &settings,
+                    if fat {
+                        Some(macho.architecture_name())
+                    } else {
+                        None
+                    },
+                )
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            code_directory::CodeSignatureFlags,
+            testutil::{
+                dmg_data, rsa_signing_identity, universal_macho_data, write_app_bundle, xar_data,
+            },
+            UnifiedSigner,
+        },
+    };
+
+    const INFO_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>CFBundleIdentifier</key>
+    <string>IDENTIFIER</string>
+    <key>CFBundlePackageType</key>
+    <string>APPL</string>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn plan_bundle() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let root = td.path().join("My.app");
+
+        for (path, identifier) in [
+            ("My.app", "com.example.app"),
+            ("My.app/Contents/Helpers/A.app", "com.example.a"),
+            ("My.app/Contents/Helpers/B.app", "com.example.b"),
+        ] {
+            let contents = td.path().join(path).join("Contents");
+            std::fs::create_dir_all(contents.join("Resources"))?;
+            std::fs::write(
+                contents.join("Info.plist"),
+                INFO_PLIST.replace("IDENTIFIER", identifier),
+            )?;
+            std::fs::write(contents.join("Resources/file.txt"), b"content")?;
+        }
+
+        let mut settings = SigningSettings::default();
+        settings.add_path_exclusion("Contents/Helpers/B.app")?;
+
+        let plan = UnifiedSigner::new(settings).plan_path(&root)?;
+        assert_eq!(plan.path_type, "bundle");
+
+        let bundles = plan
+            .bundles
+            .iter()
+            .map(|bundle| (bundle.path.as_str(), bundle.excluded))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bundles,
+            vec![
+                ("Contents/Helpers/A.app", false),
+                ("Contents/Helpers/B.app", true),
+                ("", false),
+            ]
+        );
+
+        let main = &plan.bundles[2];
+        assert_eq!(main.identifier.as_deref(), Some("com.example.app"));
+        assert_eq!(main.resources_digests, vec!["sha256".to_string()]);
+
+        let files = main
+            .files
+            .iter()
+            .map(|file| (file.path.as_str(), file.action))
+            .collect::<Vec<_>>();
+        assert!(files.contains(&("Contents/Resources/file.txt", BundleFileAction::Resource)));
+        assert!(files.contains(&(
+            "Contents/Helpers/A.app/Contents/Resources/file.txt",
+            BundleFileAction::Excluded
+        )));
+
+        // Nothing is written.
+        assert!(!root.join("Contents/_CodeSignature").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn plan_bundle_code() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let root = td.path().join("test.app");
+        write_app_bundle(&root)?;
+        std::fs::write(
+            root.join("Contents/MacOS/universal"),
+            universal_macho_data(),
+        )?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(
+            SettingsScope::Path("Contents/MacOS/helper".into()),
+            "com.example.helper",
+        );
+        settings.set_code_signature_flags(
+            SettingsScope::Path("Contents/PlugIns/One.bundle".into()),
+            CodeSignatureFlags::RUNTIME,
+        );
+
+        let plan = UnifiedSigner::new(settings).plan_path(&root)?;
+
+        assert_eq!(
+            plan.bundles
+                .iter()
+                .map(|bundle| bundle.path.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Contents/PlugIns/One.bundle",
+                "Contents/PlugIns/Two.bundle",
+                ""
+            ]
+        );
+
+        let find = |bundle: &BundlePlan, path: &str| {
+            bundle
+                .files
+                .iter()
+                .find(|file| file.path == path)
+                .cloned()
+                .expect("file should be in plan")
+        };
+
+        // Bundle scoped settings apply to the nested bundle's main executable.
+        let one = find(&plan.bundles[0], "Contents/MacOS/One");
+        assert_eq!(one.action, BundleFileAction::MainExecutable);
+        assert_eq!(one.signatures.len(), 1);
+        assert_eq!(
+            one.signatures[0].identifier.as_deref(),
+            Some("com.example.One")
+        );
+        assert_eq!(
+            one.signatures[0].code_signature_flags.as_deref(),
+            Some("CodeSignatureFlags(RUNTIME)")
+        );
+
+        let two = find(&plan.bundles[1], "Contents/MacOS/Two");
+        assert_eq!(two.signatures[0].code_signature_flags, None);
+
+        let main = &plan.bundles[2];
+
+        let app = find(main, "Contents/MacOS/app");
+        assert_eq!(app.action, BundleFileAction::MainExecutable);
+        assert_eq!(
+            app.signatures[0].identifier.as_deref(),
+            Some("com.example.app")
+        );
+        assert_eq!(app.signatures[0].architecture, None);
+        assert_eq!(app.signatures[0].digests, vec!["sha256".to_string()]);
+
+        let helper = find(main, "Contents/MacOS/helper");
+        assert_eq!(helper.action, BundleFileAction::NestedCode);
+        assert_eq!(
+            helper.signatures[0].identifier.as_deref(),
+            Some("com.example.helper")
+        );
+
+        // Universal binaries have a signature per architecture. Identifiers are
+        // derived from file names.
+        let universal = find(main, "Contents/MacOS/universal");
+        assert_eq!(universal.action, BundleFileAction::NestedCode);
+        assert_eq!(
+            universal
+                .signatures
+                .iter()
+                .map(|x| (x.architecture.as_deref(), x.identifier.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some("x86_64"), Some("universal")),
+                (Some("arm64"), Some("universal"))
+            ]
+        );
+
+        assert_eq!(
+            find(main, "Contents/PlugIns/One.bundle/Contents/MacOS/One").action,
+            BundleFileAction::Excluded
+        );
+
+        // Nothing is written.
+        assert!(!root.join("Contents/_CodeSignature").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn plan_reuse() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let root = td.path().join("test.app");
+        write_app_bundle(&root)?;
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+
+        let signer = UnifiedSigner::new(settings.clone());
+        signer.sign_path_in_place(&root)?;
+
+        let reused = |plan: &SigningPlan| {
+            plan.bundles
+                .iter()
+                .map(|bundle| (bundle.path.clone(), bundle.reused))
+                .collect::<Vec<_>>()
+        };
+        let helper_action = |plan: &SigningPlan| {
+            plan.bundles[2]
+                .files
+                .iter()
+                .find(|file| file.path == "Contents/MacOS/helper")
+                .unwrap()
+                .action
+        };
+
+        let plan = signer.plan_path(&root)?;
+        assert_eq!(
+            reused(&plan),
+            vec![
+                ("Contents/PlugIns/One.bundle".to_string(), true),
+                ("Contents/PlugIns/Two.bundle".to_string(), true),
+                ("".to_string(), true)
+            ]
+        );
+        assert_eq!(helper_action(&plan), BundleFileAction::ReusedCode);
+
+        // A nested bundle that would be re-signed prevents reuse of its parent.
+        std::fs::write(
+            root.join("Contents/PlugIns/One.bundle/Contents/Info.plist"),
+            std::fs::read_to_string(root.join("Contents/PlugIns/One.bundle/Contents/Info.plist"))?
+                .replace("com.example.One", "com.example.changed"),
+        )?;
+
+        let plan = signer.plan_path(&root)?;
+        assert_eq!(
+            reused(&plan),
+            vec![
+                ("Contents/PlugIns/One.bundle".to_string(), false),
+                ("Contents/PlugIns/Two.bundle".to_string(), true),
+                ("".to_string(), false)
+            ]
+        );
+        assert_eq!(helper_action(&plan), BundleFileAction::ReusedCode);
+
+        // Nothing is reused when forced.
+        settings.set_force(true);
+        let plan = UnifiedSigner::new(settings).plan_path(&root)?;
+        assert!(plan.bundles.iter().all(|bundle| !bundle.reused));
+        assert_eq!(helper_action(&plan), BundleFileAction::NestedCode);
+
+        Ok(())
+    }
+
+    #[test]
+    fn plan_other_paths() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+
+        let macho_path = td.path().join("universal");
+        std::fs::write(&macho_path, universal_macho_data())?;
+        let dmg_path = td.path().join("test.dmg");
+        std::fs::write(&dmg_path, dmg_data())?;
+        let file_path = td.path().join("script.sh");
+        std::fs::write(&file_path, b"#!/bin/sh\n")?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "com.example.test");
+        settings.set_binary_identifier(SettingsScope::MultiArchIndex(1), "com.example.arm64");
+        let signer = UnifiedSigner::new(settings);
+
+        let plan = signer.plan_path(&macho_path)?;
+        assert_eq!(plan.path_type, "macho");
+        assert!(plan.bundles.is_empty());
+        assert_eq!(
+            plan.signatures
+                .iter()
+                .map(|x| (x.architecture.as_deref(), x.identifier.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some("x86_64"), Some("com.example.test")),
+                (Some("arm64"), Some("com.example.arm64"))
+            ]
+        );
+
+        let plan = signer.plan_path(&dmg_path)?;
+        assert_eq!(plan.path_type, "dmg");
+        assert_eq!(plan.signatures.len(), 1);
+        assert_eq!(
+            plan.signatures[0].identifier.as_deref(),
+            Some("com.example.test")
+        );
+        assert_eq!(plan.signatures[0].digests, vec!["sha256".to_string()]);
+
+        let plan = signer.plan_path(&file_path)?;
+        assert_eq!(plan.path_type, "file");
+        assert_eq!(plan.signatures.len(), 1);
+        assert_eq!(plan.signatures[0].runtime_version, None);
+
+        // Nothing is written.
+        assert_eq!(std::fs::read(&dmg_path)?, dmg_data());
+        assert_eq!(std::fs::read(&macho_path)?, universal_macho_data());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_xar() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let source = td.path().join("source.pkg");
+        let signed = td.path().join("signed.pkg");
+        std::fs::write(&source, xar_data(&[("Distribution", b"distribution")]))?;
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+        settings.set_signing_time(Some(chrono::Utc::now()));
+        let signer = UnifiedSigner::new(settings);
+
+        assert!(matches!(
+            signer.plan_path(&source),
+            Err(AppleCodesignError::XarDeterministic)
+        ));
+        assert!(matches!(
+            signer.sign_path(&source, &signed),
+            Err(AppleCodesignError::XarDeterministic)
+        ));
+        assert!(!signed.exists());
+
+        Ok(())
+    }
+}
@@ -12,7 +12,9 @@ use {
         embedded_signature::{Blob, DigestType, RequirementBlob},
         error::AppleCodesignError,
         macho::{parse_version_nibbles, MachFile},
+        provisioning_profile::ProvisioningProfile,
     },
+    chrono::{DateTime, Utc},
     glob::Pattern,
     goblin::mach::cputype::{
         CpuType, CPU_TYPE_ARM, CPU_TYPE_ARM64, CPU_TYPE_ARM64_32, CPU_TYPE_X86_64,
@@ -249,11 +251,13 @@ pub enum DesignatedRequirementMode {
 #[derive(Clone, Default)]
 pub struct SigningSettings<'key> {
     // Global settings.
-    signing_key: Option<(&'key dyn KeyInfoSigner, CapturedX509Certificate)>,
+    signing_key: Option<(&'key (dyn KeyInfoSigner + Sync), CapturedX509Certificate)>,
     certificates: Vec<CapturedX509Certificate>,
     time_stamp_url: Option<Url>,
+    signing_time: Option<DateTime<Utc>>,
     digest_type: DigestType,
     path_exclusion_patterns: Vec<Pattern>,
+    force: bool,
 
     // Scope-specific settings.
     // These are BTreeMap so when we filter the keys, keys with higher precedence come
@@ -267,6 +271,7 @@ pub struct SigningSettings<'key> {
     info_plist_data: BTreeMap<SettingsScope, Vec<u8>>,
     code_resources_data: BTreeMap<SettingsScope, Vec<u8>>,
     extra_digests: BTreeMap<SettingsScope, BTreeSet<DigestType>>,
+    provisioning_profiles: BTreeMap<SettingsScope, ProvisioningProfile>,
 }
 
 impl<'key> SigningSettings<'key> {
@@ -286,7 +291,9 @@ impl<'key> SigningSettings<'key> {
 
     /// Obtain the signing key to use.
     pub fn signing_key(&self) -> Option<(&'key dyn KeyInfoSigner, &CapturedX509Certificate)> {
-        self.signing_key.as_ref().map(|(key, cert)| (*key, cert))
+        self.signing_key
+            .as_ref()
+            .map(|(key, cert)| (*key as &dyn KeyInfoSigner, cert))
     }
 
     /// Set the signing key-pair for producing a cryptographic signature over code.
@@ -297,7 +304,7 @@ impl<'key> SigningSettings<'key> {
     /// not run in all environments.
     pub fn set_signing_key(
         &mut self,
-        private: &'key dyn KeyInfoSigner,
+        private: &'key (dyn KeyInfoSigner + Sync),
         public: CapturedX509Certificate,
     ) {
         self.signing_key = Some((private, public));
@@ -373,10 +380,22 @@ impl<'key> SigningSettings<'key> {
     }
 
     /// Obtain the Time-Stamp Protocol server URL.
+    ///
+    /// Signing doesn't use the server if a signing time is set, as Time-Stamp
+    /// Tokens can't be reproduced. See [Self::effective_time_stamp_url()].
     pub fn time_stamp_url(&self) -> Option<&Url> {
         self.time_stamp_url.as_ref()
     }
 
+    /// Obtain the Time-Stamp Protocol server URL signing should use.
+    ///
+    /// This is [Self::time_stamp_url()] unless a signing time is pinned.
+    pub fn effective_time_stamp_url(&self) -> Option<&Url> {
+        self.time_stamp_url
+            .as_ref()
+            .filter(|_| self.signing_time.is_none())
+    }
+
     /// Set the Time-Stamp Protocol server URL to use to generate a Time-Stamp Token.
     ///
     /// When set and a signing key-pair is defined, the server will be contacted during
@@ -391,6 +410,25 @@ impl<'key> SigningSettings<'key> {
         Ok(())
     }
 
+    /// Obtain the pinned signing time.
+    pub fn signing_time(&self) -> Option<&DateTime<Utc>> {
+        self.signing_time.as_ref()
+    }
+
+    /// Pin the signing time to make signing deterministic.
+    ///
+    /// When set, CMS signatures record this signing time instead of the current
+    /// time and no Time-Stamp Token is requested. Files in signed bundles and ZIP
+    /// archives get this modification time and ZIP archive entries are written in
+    /// sorted order. Signing identical input with the same settings then yields
+    /// identical output, provided the signature algorithm is deterministic (e.g.
+    /// RSA, but not ECDSA).
+    ///
+    /// XAR archives (flat packages) can't be signed with a pinned signing time.
+    pub fn set_signing_time(&mut self, time: Option<DateTime<Utc>>) {
+        self.signing_time = time;
+    }
+
     /// Obtain the team identifier for signed binaries.
     pub fn team_id(&self) -> Option<&str> {
         self.team_id.get(&SettingsScope::Main).map(|x| x.as_str())
@@ -447,6 +485,19 @@ impl<'key> SigningSettings<'key> {
         Ok(())
     }
 
+    /// Whether to sign code that is already signed as it would be by these settings.
+    ///
+    /// By default, bundle signing reuses existing signatures of nested code whose
+    /// signature would not change.
+    pub fn force(&self) -> bool {
+        self.force
+    }
+
+    /// Set whether to sign code that is already signed as it would be by these settings.
+    pub fn set_force(&mut self, value: bool) {
+        self.force = value;
+    }
+
     /// Obtain the binary identifier string for a given scope.
     pub fn binary_identifier(&self, scope: impl AsRef<SettingsScope>) -> Option<&str> {
         self.identifiers.get(scope.as_ref()).map(|s| s.as_str())
@@ -704,6 +755,29 @@ impl<'key> SigningSettings<'key> {
         self.code_resources_data.insert(scope, data);
     }
 
+    /// Obtain the provisioning profile registered to a given scope.
+    pub fn provisioning_profile(
+        &self,
+        scope: impl AsRef<SettingsScope>,
+    ) -> Option<&ProvisioningProfile> {
+        self.provisioning_profiles.get(scope.as_ref())
+    }
+
+    /// Define the provisioning profile to embed in a bundle.
+    ///
+    /// When signing a bundle, the profile is embedded as `embedded.mobileprovision`
+    /// (shallow bundles) or `Contents/embedded.provisionprofile` (macOS bundles) and
+    /// sealed in the bundle's resources. Signing fails if the profile is expired,
+    /// doesn't allow the signing certificate, or doesn't grant the entitlements of
+    /// the bundle's main executable.
+    ///
+    /// Unlike other settings, a profile registered to the main scope only applies
+    /// to the main bundle and not to nested bundles. Nested bundles (e.g. app
+    /// extensions) need their own profile registered to their path.
+    pub fn set_provisioning_profile(&mut self, scope: SettingsScope, profile: ProvisioningProfile) {
+        self.provisioning_profiles.insert(scope, profile);
+    }
+
     /// Obtain extra digests to include in signatures.
     pub fn extra_digests(&self, scope: impl AsRef<SettingsScope>) -> Option<&BTreeSet<DigestType>> {
         self.extra_digests.get(scope.as_ref())
@@ -860,7 +934,18 @@ impl<'key> SigningSettings<'key> {
     /// Convert this instance to settings appropriate for a nested bundle.
     #[must_use]
     pub fn as_nested_bundle_settings(&self, bundle_path: &str) -> Self {
-        self.clone_strip_prefix(bundle_path, format!("{bundle_path}/"))
+        let mut settings = self.clone_strip_prefix(bundle_path, format!("{bundle_path}/"));
+
+        // Provisioning profiles are specific to a bundle. So only a profile scoped to
+        // the nested bundle applies to it.
+        if !self
+            .provisioning_profiles
+            .contains_key(&SettingsScope::Path(bundle_path.to_string()))
+        {
+            settings.provisioning_profiles.remove(&SettingsScope::Main);
+        }
+
+        settings
     }
 
     /// Convert this instance to settings appropriate for a Mach-O binary in a bundle.
@@ -932,9 +1017,11 @@ impl<'key> SigningSettings<'key> {
             signing_key: self.signing_key.clone(),
             certificates: self.certificates.clone(),
             time_stamp_url: self.time_stamp_url.clone(),
+            signing_time: self.signing_time,
             team_id: self.team_id.clone(),
             digest_type: self.digest_type,
             path_exclusion_patterns: self.path_exclusion_patterns.clone(),
+            force: self.force,
             identifiers: self
                 .identifiers
                 .clone()
@@ -983,6 +1070,12 @@ impl<'key> SigningSettings<'key> {
                 .into_iter()
                 .filter_map(|(key, value)| key_map(key).map(|key| (key, value)))
                 .collect::<BTreeMap<_, _>>(),
+            provisioning_profiles: self
+                .provisioning_profiles
+                .clone()
+                .into_iter()
+                .filter_map(|(key, value)| key_map(key).map(|key| (key, value)))
+                .collect::<BTreeMap<_, _>>(),
         }
     }
 }
@@ -1235,4 +1328,22 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn effective_time_stamp_url() -> Result<(), AppleCodesignError> {
+        let mut settings = SigningSettings::default();
+        assert!(settings.effective_time_stamp_url().is_none());
+
+        settings.set_time_stamp_url("http://timestamp.example.com/")?;
+        assert_eq!(
+            settings.effective_time_stamp_url(),
+            settings.time_stamp_url()
+        );
+
+        settings.set_signing_time(Some(chrono::Utc::now()));
+        assert!(settings.time_stamp_url().is_some());
+        assert!(settings.effective_time_stamp_url().is_none());
+
+        Ok(())
+    }
 }
@@ -322,9 +322,9 @@ impl Stapler {
                 let xar = XarReader::new(File::options().read(true).write(true).open(path)?)?;
                 self.staple_xar(xar)
             }
-            PathType::Zip | PathType::Other => Err(AppleCodesignError::StapleUnsupportedPath(
-                path.to_path_buf(),
-            )),
+            PathType::Zip | PathType::File | PathType::Other => Err(
+                AppleCodesignError::StapleUnsupportedPath(path.to_path_buf()),
+            ),
         }
     }
 }
@@ -0,0 +1,409 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Fixtures shared by tests.
+
+use {
+    crate::{
+        cryptography::InMemoryPrivateKey, dmg::KolyTrailer, macho_universal::create_universal_macho,
+    },
+    apple_xar::table_of_contents::ChecksumType,
+    bcder::encode::Values,
+    goblin::mach::load_command::{
+        LC_SEGMENT_64, LC_SYMTAB, SIZEOF_SECTION_64, SIZEOF_SEGMENT_COMMAND_64,
+        SIZEOF_SYMTAB_COMMAND,
+    },
+    scroll::{Pread, Pwrite},
+    std::io::Write,
+    x509_certificate::{
+        certificate::KeyUsage, rfc5280, CapturedX509Certificate, InMemorySigningKeyPair,
+        KeyAlgorithm, Sign, Signer, X509Certificate, X509CertificateBuilder,
+    },
+};
+
+/// Obtain the RSA key and self-signed certificate in `testdata`.
+///
+/// RSA signatures are deterministic, making this suitable for comparing
+/// signing output.
+pub fn rsa_signing_identity() -> (InMemoryPrivateKey, CapturedX509Certificate) {
+    (
+        InMemoryPrivateKey::from_pkcs8_der(include_bytes!("testdata/rsa-2048.pk8")).unwrap(),
+        CapturedX509Certificate::from_der(include_bytes!("testdata/rsa-2048.cer").to_vec())
+            .unwrap(),
+    )
+}
+
+/// Create a self-signed certificate authority.
+pub fn ca_certificate(name: &str) -> (CapturedX509Certificate, InMemorySigningKeyPair) {
+    let mut builder = X509CertificateBuilder::new(KeyAlgorithm::Ed25519);
+    builder
+        .subject()
+        .append_common_name_utf8_string(name)
+        .unwrap();
+    // BasicConstraints { cA: TRUE }.
+    builder.add_extension_der_data(
+        bcder::Oid([85, 29, 19].as_ref().into()),
+        true,
+        [0x30, 0x03, 0x01, 0x01, 0xff],
+    );
+    builder.key_usage(KeyUsage::KeyCertSign);
+    builder.validity_duration(chrono::Duration::hours(1));
+
+    let (cert, key, _) = builder.create_with_random_keypair().unwrap();
+
+    (cert, key)
+}
+
+/// Create a self-signed time-stamp authority certificate.
+///
+/// It has the critical time stamping extended key usage RFC 3161 requires.
+pub fn time_stamp_authority_certificate() -> (CapturedX509Certificate, InMemorySigningKeyPair) {
+    let mut builder = X509CertificateBuilder::new(KeyAlgorithm::Ed25519);
+    builder
+        .subject()
+        .append_common_name_utf8_string("Time Stamp Authority")
+        .unwrap();
+    // ExtKeyUsageSyntax { id-kp-timeStamping }.
+    builder.add_extension_der_data(
+        bcder::Oid([85, 29, 37].as_ref().into()),
+        true,
+        [
+            0x30, 0x0a, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x08,
+        ],
+    );
+    builder.key_usage(KeyUsage::DigitalSignature);
+    builder.validity_duration(chrono::Duration::hours(1));
+
+    let (cert, key, _) = builder.create_with_random_keypair().unwrap();
+
+    (cert, key)
+}
+
+/// Create a certificate from `builder` issued by another certificate.
+pub fn issue_certificate(
+    builder: &X509CertificateBuilder,
+    issuer: &CapturedX509Certificate,
+    issuer_key: &InMemorySigningKeyPair,
+) -> (CapturedX509Certificate, InMemorySigningKeyPair) {
+    let (cert, key, _) = builder.create_with_random_keypair().unwrap();
+
+    let cert: &rfc5280::Certificate = cert.as_ref();
+    let mut cert = cert.clone();
+    let signature_algorithm = issuer_key.signature_algorithm().unwrap();
+    cert.tbs_certificate.issuer = issuer.subject_name().clone();
+    cert.tbs_certificate.signature = signature_algorithm.into();
+    cert.tbs_certificate.raw_data = None;
+
+    let mut tbs_der = vec![];
+    cert.tbs_certificate
+        .encode_ref()
+        .write_encoded(bcder::Mode::Der, &mut tbs_der)
+        .unwrap();
+
+    cert.signature_algorithm = signature_algorithm.into();
+    cert.signature = bcder::BitString::new(
+        0,
+        issuer_key
+            .try_sign(&tbs_der)
+            .unwrap()
+            .as_ref()
+            .to_vec()
+            .into(),
+    );
+
+    let der = X509Certificate::from(cert).encode_der().unwrap();
+
+    (CapturedX509Certificate::from_der(der).unwrap(), key)
+}
+
+/// Construct an unsigned XAR archive holding files at the root directory.
+///
+/// File data is zlib compressed. All checksums are SHA-1.
+pub fn xar_data(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let sha1 = |data: &[u8]| hex::encode(ChecksumType::Sha1.digest_data(data).unwrap());
+    let zlib = |data: &[u8]| {
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    };
+
+    // The heap starts with the table of contents checksum.
+    let mut heap = vec![0u8; 20];
+    let mut file_elements = String::new();
+
+    for (index, (name, data)) in files.iter().enumerate() {
+        let archived = zlib(data);
+
+        file_elements.push_str(&format!(
+            r#"<file id="{id}"><data><length>{length}</length><offset>{offset}</offset><size>{size}</size><encoding style="application/x-gzip"/><extracted-checksum style="sha1">{extracted}</extracted-checksum><archived-checksum style="sha1">{archived_checksum}</archived-checksum></data><name>{name}</name><type>file</type></file>"#,
+            id = index + 1,
+            length = archived.len(),
+            offset = heap.len(),
+            size = data.len(),
+            extracted = sha1(data),
+            archived_checksum = sha1(&archived),
+        ));
+
+        heap.extend(archived);
+    }
+
+    let toc = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?><xar><toc><creation-time>2023-01-01T00:00:00</creation-time><checksum style="sha1"><offset>0</offset><size>20</size></checksum>{file_elements}</toc></xar>"#
+    );
+    let toc_compressed = zlib(toc.as_bytes());
+    heap[0..20].copy_from_slice(&ChecksumType::Sha1.digest_data(&toc_compressed).unwrap());
+
+    let mut data = b"xar!".to_vec();
+    data.extend(28u16.to_be_bytes());
+    data.extend(1u16.to_be_bytes());
+    data.extend((toc_compressed.len() as u64).to_be_bytes());
+    data.extend((toc.len() as u64).to_be_bytes());
+    data.extend(1u32.to_be_bytes());
+    data.extend(toc_compressed);
+    data.extend(heap);
+
+    data
+}
+
+pub const VM_BASE: u64 = 0x1_0000_0000;
+
+pub fn u32s(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub fn u64s(values: &[u64]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+pub fn segment(name: &str, vm: (u64, u64), file: (u64, u64), sections: &[(&str, u32)]) -> Vec<u8> {
+    let mut name_bytes = name.as_bytes().to_vec();
+    name_bytes.resize(16, 0);
+
+    let mut res = u32s(&[
+        LC_SEGMENT_64,
+        (SIZEOF_SEGMENT_COMMAND_64 + SIZEOF_SECTION_64 * sections.len()) as u32,
+    ]);
+    res.extend(name_bytes);
+    res.extend(u64s(&[vm.0, vm.1, file.0, file.1]));
+    res.extend(u32s(&[7, 5, sections.len() as u32, 0]));
+
+    for (name, offset) in sections {
+        let mut name_bytes = name.as_bytes().to_vec();
+        name_bytes.resize(16, 0);
+        let mut segname = b"__TEXT".to_vec();
+        segname.resize(16, 0);
+
+        res.extend(name_bytes);
+        res.extend(segname);
+        res.extend(u64s(&[VM_BASE + *offset as u64, 0x10]));
+        res.extend(u32s(&[*offset, 4, 0, 0, 0x80000400, 0, 0, 0]));
+    }
+
+    res
+}
+
+/// Construct a minimal 64-bit x86_64 executable.
+///
+/// `__LINKEDIT` holds a symbol table at `linkedit_offset`.
+pub fn macho_data(linkedit_offset: u64, extra_commands: &[Vec<u8>]) -> Vec<u8> {
+    let symbols = b"SYMBOLS!".repeat(2);
+    let strings = b"\0_main\0\0";
+    let linkedit_size = (symbols.len() + strings.len()) as u64;
+
+    let mut commands = vec![
+        segment("__PAGEZERO", (0, VM_BASE), (0, 0), &[]),
+        segment(
+            "__TEXT",
+            (VM_BASE, 0x4000),
+            (0, 0x4000),
+            &[("__text", 0x1000)],
+        ),
+        segment(
+            "__LINKEDIT",
+            (VM_BASE + linkedit_offset, 0x4000),
+            (linkedit_offset, linkedit_size),
+            &[],
+        ),
+        u32s(&[
+            LC_SYMTAB,
+            SIZEOF_SYMTAB_COMMAND as u32,
+            linkedit_offset as u32,
+            1,
+            (linkedit_offset as usize + symbols.len()) as u32,
+            strings.len() as u32,
+        ]),
+    ];
+    commands.extend(extra_commands.iter().cloned());
+
+    let mut data = u32s(&[
+        0xfeedfacf,
+        0x01000007,
+        3,
+        2,
+        commands.len() as u32,
+        commands.iter().map(|x| x.len()).sum::<usize>() as u32,
+        0,
+        0,
+    ]);
+    for command in commands {
+        data.extend(command);
+    }
+
+    data.resize(0x1000, 0);
+    data.extend(b"\xc3".repeat(0x10));
+    data.resize(linkedit_offset as usize, 0);
+    data.extend(symbols);
+    data.extend(strings);
+
+    data
+}
+
+/// Construct a minimal universal binary with x86_64 and arm64 executables.
+pub fn universal_macho_data() -> Vec<u8> {
+    let x86_64 = macho_data(0x4000, &[]);
+
+    let mut arm64 = x86_64.clone();
+    arm64[4..12].copy_from_slice(&u32s(&[0x0100000c, 0]));
+
+    let mut data = vec![];
+    create_universal_macho(&mut data, [x86_64.as_slice(), arm64.as_slice()].into_iter()).unwrap();
+
+    data
+}
+
+/// Construct a minimal unsigned DMG.
+///
+/// Content followed by a plist and a trailer is enough to sign.
+pub fn dmg_data() -> Vec<u8> {
+    let mut koly = [0u8; 512].pread_with::<KolyTrailer>(0, scroll::BE).unwrap();
+    koly.signature = *b"koly";
+    koly.plist_offset = 8192;
+    koly.plist_length = 64;
+
+    let mut trailer = [0u8; 512];
+    trailer.pwrite_with(koly, 0, scroll::BE).unwrap();
+
+    let mut data = b"\x42".repeat(8192 + 64);
+    data.extend_from_slice(&trailer);
+
+    data
+}
+
+fn bundle_info_plist(identifier: &str, executable: &str, package_type: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>CFBundleExecutable</key>
+  <string>{executable}</string>
+  <key>CFBundleIdentifier</key>
+  <string>{identifier}</string>
+  <key>CFBundlePackageType</key>
+  <string>{package_type}</string>
+</dict>
+</plist>
+"#
+    )
+}
+
+/// Write an unsigned macOS application bundle to the given directory.
+///
+/// The bundle has a main executable, an additional Mach-O, two nested
+/// bundles each with their own executable, and a resource file.
+pub fn write_app_bundle(path: &std::path::Path) -> std::io::Result<()> {
+    let contents = path.join("Contents");
+
+    std::fs::create_dir_all(contents.join("MacOS"))?;
+    std::fs::create_dir_all(contents.join("Resources"))?;
+    std::fs::write(
+        contents.join("Info.plist"),
+        bundle_info_plist("com.example.app", "app", "APPL"),
+    )?;
+    std::fs::write(contents.join("MacOS/app"), macho_data(0x4000, &[]))?;
+    std::fs::write(contents.join("MacOS/helper"), macho_data(0x8000, &[]))?;
+    std::fs::write(contents.join("Resources/a.txt"), b"a")?;
+
+    for name in ["One", "Two"] {
+        let nested = contents.join(format!("PlugIns/{name}.bundle/Contents"));
+
+        std::fs::create_dir_all(nested.join("MacOS"))?;
+        std::fs::write(
+            nested.join("Info.plist"),
+            bundle_info_plist(&format!("com.example.{name}"), name, "BNDL"),
+        )?;
+        std::fs::write(
+            nested.join(format!("MacOS/{name}")),
+            macho_data(0x4000, &[]),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write an unsigned iOS application bundle to the given directory.
+///
+/// iOS bundles are shallow: the `Info.plist` and main executable are at the
+/// bundle root. The app embeds a shallow framework and has a resource file.
+pub fn write_ios_app_bundle(path: &std::path::Path) -> std::io::Result<()> {
+    let framework = path.join("Frameworks/One.framework");
+
+    std::fs::create_dir_all(&framework)?;
+    std::fs::write(
+        path.join("Info.plist"),
+        bundle_info_plist("com.example.app", "app", "APPL"),
+    )?;
+    std::fs::write(path.join("app"), macho_data(0x4000, &[]))?;
+    std::fs::write(path.join("a.txt"), b"a")?;
+
+    std::fs::write(
+        framework.join("Info.plist"),
+        bundle_info_plist("com.example.One", "One", "FMWK"),
+    )?;
+    std::fs::write(framework.join("One"), macho_data(0x4000, &[]))?;
+
+    Ok(())
+}
+
+/// Read the content of all files and symlinks under a directory, keyed by relative path.
+pub fn read_tree(
+    root: &std::path::Path,
+) -> std::io::Result<std::collections::BTreeMap<String, Vec<u8>>> {
+    fn walk(
+        root: &std::path::Path,
+        dir: &std::path::Path,
+        res: &mut std::collections::BTreeMap<String, Vec<u8>>,
+    ) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let rel = path
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/");
+            let metadata = std::fs::symlink_metadata(&path)?;
+
+            if metadata.is_dir() {
+                walk(root, &path, res)?;
+            } else if metadata.file_type().is_symlink() {
+                res.insert(
+                    rel,
+                    std::fs::read_link(&path)?
+                        .to_string_lossy()
+                        .as_bytes()
+                        .to_vec(),
+                );
+            } else {
+                res.insert(rel, std::fs::read(&path)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    let mut res = std::collections::BTreeMap::new();
+    walk(root, root, &mut res)?;
+
+    Ok(res)
+}
@@ -0,0 +1,583 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+/*! A minimal RFC 3161 time-stamp authority.
+
+Signing normally records signatures with Apple's time-stamp server. This is
+not possible in environments without network access to Apple. This module
+implements a time-stamp authority (TSA) serving RFC 3161 responses signed
+with a key and certificate of your choosing so signing can obtain time-stamp
+tokens from a local server.
+
+Time-stamp tokens from a TSA not operated by Apple will not be trusted by
+Apple's verification. Verification in this crate trusts them when the TSA
+certificate (or an issuer of it) is registered as a trust anchor.
+*/
+
+use {
+    crate::AppleCodesignError,
+    bcder::{
+        decode::{Constructed, SliceSource},
+        encode::{self, Values},
+        Captured, Integer, Mode, OctetString, Oid,
+    },
+    bytes::Bytes,
+    chrono::Utc,
+    cryptographic_message_syntax::{
+        asn1::{
+            rfc3161::{
+                MessageImprint, PkiFailureInfo, PkiStatus, PkiStatusInfo, TimeStampReq,
+                TimeStampResp, TstInfo, OID_CONTENT_TYPE_TST_INFO,
+            },
+            rfc5652::{ContentInfo, IssuerAndSerialNumber, SignerIdentifier},
+        },
+        SignedDataBuilder, SignerBuilder,
+    },
+    log::{info, warn},
+    std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        str::FromStr,
+        time::Duration,
+    },
+    x509_certificate::{
+        asn1time::{GeneralizedTime, GeneralizedTimeAllowedTimezone},
+        rfc5652::AttributeValue,
+        CapturedX509Certificate, DigestAlgorithm, KeyInfoSigner,
+    },
+};
+
+/// Default policy OID of time-stamp tokens.
+///
+/// This is the policy used by OpenSSL's example TSA configuration.
+pub const DEFAULT_TIME_STAMP_POLICY: &str = "1.2.3.4.1";
+
+/// HTTP content type of time-stamp requests.
+const HTTP_CONTENT_TYPE_REQUEST: &str = "application/timestamp-query";
+
+/// HTTP content type of time-stamp responses.
+const HTTP_CONTENT_TYPE_RESPONSE: &str = "application/timestamp-reply";
+
+/// Maximum size of HTTP request bodies we accept.
+///
+/// Requests only contain a digest and some small fields, so this is generous.
+const MAX_REQUEST_SIZE: usize = 65536;
+
+/// Maximum size of the HTTP request line and headers we accept.
+const MAX_HEADER_SIZE: u64 = 8192;
+
+/// ESS signing certificate v2 attribute (1.2.840.113549.1.9.16.2.47).
+///
+/// RFC 3161 requires this attribute to be present in time-stamp tokens.
+const OID_SIGNING_CERTIFICATE_V2: [u8; 11] = [42, 134, 72, 134, 247, 13, 1, 9, 16, 2, 47];
+
+/// An RFC 3161 time-stamp authority.
+pub struct TimeStampAuthority<'key> {
+    signing_key: &'key (dyn KeyInfoSigner + Sync),
+    signing_certificate: CapturedX509Certificate,
+    certificates: Vec<CapturedX509Certificate>,
+    policy: Oid,
+}
+
+impl<'key> TimeStampAuthority<'key> {
+    /// Construct a new instance signing tokens with the given key and certificate.
+    ///
+    /// The certificate should have the critical time stamping extended key
+    /// usage. Verification rejects tokens signed by other certificates.
+    pub fn new(
+        signing_key: &'key (dyn KeyInfoSigner + Sync),
+        signing_certificate: CapturedX509Certificate,
+    ) -> Self {
+        Self {
+            signing_key,
+            signing_certificate,
+            certificates: vec![],
+            policy: Oid::from_str(DEFAULT_TIME_STAMP_POLICY)
+                .expect("default policy should be a valid OID"),
+        }
+    }
+
+    /// Register an additional certificate to include in time-stamp tokens.
+    ///
+    /// This is typically used to include the issuers of the TSA certificate.
+    /// Certificates are only included when the request asks for them.
+    pub fn chain_certificate(&mut self, cert: CapturedX509Certificate) {
+        self.certificates.push(cert);
+    }
+
+    /// Set the policy OID recorded in time-stamp tokens.
+    pub fn set_policy(&mut self, policy: Oid) {
+        self.policy = policy;
+    }
+
+    /// Produce a response to a time-stamp request.
+    ///
+    /// Requests using an unsupported digest algorithm are rejected via the
+    /// status of the response.
+    pub fn time_stamp_request(
+        &self,
+        request: &TimeStampReq,
+    ) -> Result<TimeStampResp, AppleCodesignError> {
+        let imprint = &request.message_imprint;
+
+        let fail_info = match DigestAlgorithm::try_from(&imprint.hash_algorithm) {
+            Ok(alg) if alg.digest_data(b"").len() == imprint.hashed_message.to_bytes().len() => {
+                None
+            }
+            Ok(_) => Some(PkiFailureInfo::BadDataFormat),
+            Err(_) => Some(PkiFailureInfo::BadAlg),
+        };
+
+        if let Some(fail_info) = fail_info {
+            warn!("rejecting time-stamp request: {:?}", fail_info);
+            return Ok(TimeStampResp {
+                status: PkiStatusInfo {
+                    status: PkiStatus::Rejection,
+                    status_string: None,
+                    fail_info: Some(fail_info),
+                },
+                time_stamp_token: None,
+            });
+        }
+
+        let gen_time = Utc::now().format("%Y%m%d%H%M%SZ").to_string();
+        let gen_time = GeneralizedTime::parse(
+            SliceSource::new(gen_time.as_bytes()),
+            false,
+            GeneralizedTimeAllowedTimezone::Z,
+        )
+        .map_err(|e| AppleCodesignError::TimeStampAuthority(e.to_string()))?;
+
+        let tst_info = TstInfo {
+            version: Integer::from(1u8),
+            policy: self.policy.clone(),
+            message_imprint: MessageImprint {
+                hash_algorithm: imprint.hash_algorithm.clone(),
+                hashed_message: imprint.hashed_message.clone(),
+            },
+            serial_number: Integer::from(uuid::Uuid::new_v4().as_u128()),
+            gen_time,
+            accuracy: None,
+            ordering: None,
+            nonce: request.nonce.clone(),
+            // The TSA name is optional. Our ASN.1 library encodes directory names
+            // with an implicit tag instead of the required explicit tag. So omit
+            // it. Consumers can identify the TSA from the signing certificate.
+            tsa: None,
+            extensions: None,
+        };
+
+        let mut content = vec![];
+        tst_info
+            .encode_ref()
+            .write_encoded(Mode::Der, &mut content)?;
+
+        let certificate_hash =
+            DigestAlgorithm::Sha256.digest_data(&self.signing_certificate.encode_der()?);
+
+        let content_type = Oid(Bytes::copy_from_slice(OID_CONTENT_TYPE_TST_INFO.as_ref()));
+
+        // RFC 3161 section 2.4.2: the TSA's certificate (and its chain) is only
+        // included in the token when the request asks for it.
+        let cert_req = request.cert_req.unwrap_or(false);
+
+        let signer = if cert_req {
+            SignerBuilder::new(self.signing_key, self.signing_certificate.clone())
+        } else {
+            SignerBuilder::new_with_signer_identifier(
+                self.signing_key,
+                SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+                    issuer: self.signing_certificate.issuer_name().clone(),
+                    serial_number: self.signing_certificate.serial_number_asn1().clone(),
+                }),
+            )
+        }
+        .content_type(content_type.clone())
+        .signed_attribute(
+            Oid(Bytes::copy_from_slice(&OID_SIGNING_CERTIFICATE_V2)),
+            vec![AttributeValue::new(Captured::from_values(
+                Mode::Der,
+                // SigningCertificateV2 ::= SEQUENCE { certs SEQUENCE OF ESSCertIDv2 }
+                // ESSCertIDv2 ::= SEQUENCE { certHash OCTET STRING } (SHA-256 default)
+                encode::sequence(encode::sequence(encode::sequence(
+                    OctetString::new(certificate_hash.into()).encode(),
+                ))),
+            ))],
+        );
+
+        let mut builder = SignedDataBuilder::default()
+            .content_inline(content)
+            .content_type(content_type)
+            .signer(signer);
+
+        if cert_req {
+            builder = builder.certificates(self.certificates.iter().cloned());
+        }
+
+        let der = builder.build_der()?;
+
+        // `ContentInfo` doesn't round-trip the explicit tag of its content. So
+        // capture the tagged value so it is preserved when encoding.
+        let token = Constructed::decode(der.as_slice(), Mode::Der, |cons| {
+            cons.take_sequence(|cons| {
+                Ok(ContentInfo {
+                    content_type: Oid::take_from(cons)?,
+                    content: cons.capture_one()?,
+                })
+            })
+        })
+        .map_err(|e| AppleCodesignError::TimeStampAuthority(e.to_string()))?;
+
+        Ok(TimeStampResp {
+            status: PkiStatusInfo {
+                status: PkiStatus::Granted,
+                status_string: None,
+                fail_info: None,
+            },
+            time_stamp_token: Some(token),
+        })
+    }
+
+    /// Produce a DER encoded response to a DER encoded time-stamp request.
+    pub fn time_stamp_request_der(&self, data: &[u8]) -> Result<Vec<u8>, AppleCodesignError> {
+        let request = Constructed::decode(data, Mode::Der, TimeStampReq::take_from)
+            .map_err(|e| AppleCodesignError::TimeStampRequestMalformed(e.to_string()))?;
+
+        let response = self.time_stamp_request(&request)?;
+
+        let mut der = vec![];
+        response.encode_ref().write_encoded(Mode::Der, &mut der)?;
+
+        Ok(der)
+    }
+
+    /// Serve time-stamp requests received over HTTP on a listening socket.
+    ///
+    /// This runs forever, handling each connection on its own thread. Errors
+    /// handling individual connections are logged and do not stop the server.
+    pub fn serve(&self, listener: &TcpListener) -> Result<(), AppleCodesignError> {
+        std::thread::scope(|scope| {
+            for stream in listener.incoming() {
+                let stream = stream?;
+
+                scope.spawn(move || {
+                    if let Err(e) = self.handle_connection(stream) {
+                        warn!("error handling time-stamp connection: {}", e);
+                    }
+                });
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Handle a single HTTP request on a connected socket.
+    ///
+    /// The connection is closed after the response is sent.
+    pub fn handle_connection(&self, stream: TcpStream) -> Result<(), AppleCodesignError> {
+        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+
+        let peer = stream.peer_addr()?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        // Bound how much of the request we buffer before seeing its body.
+        let mut head = (&mut reader).take(MAX_HEADER_SIZE);
+
+        let mut request_line = String::new();
+        head.read_line(&mut request_line)?;
+
+        let mut content_length = None;
+        let mut content_type = None;
+        let mut headers_complete = false;
+
+        loop {
+            let mut line = String::new();
+            if head.read_line(&mut line)? == 0 {
+                break;
+            }
+
+            let line = line.trim_end();
+            if line.is_empty() {
+                headers_complete = true;
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                let value = value.trim();
+
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.parse::<usize>().ok();
+                } else if name.eq_ignore_ascii_case("content-type") {
+                    content_type = Some(value.to_string());
+                }
+            }
+        }
+
+        if !headers_complete && head.limit() == 0 {
+            return write_http_response(&mut writer, "431 Request Header Fields Too Large", None);
+        }
+
+        if !request_line.starts_with("POST ") {
+            return write_http_response(&mut writer, "405 Method Not Allowed", None);
+        }
+
+        if content_type.as_deref() != Some(HTTP_CONTENT_TYPE_REQUEST) {
+            return write_http_response(&mut writer, "415 Unsupported Media Type", None);
+        }
+
+        let content_length = match content_length {
+            Some(size) if size <= MAX_REQUEST_SIZE => size,
+            _ => return write_http_response(&mut writer, "400 Bad Request", None),
+        };
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+
+        match self.time_stamp_request_der(&body) {
+            Ok(response) => {
+                info!("issued time-stamp response to {}", peer);
+                write_http_response(&mut writer, "200 OK", Some(&response))
+            }
+            Err(AppleCodesignError::TimeStampRequestMalformed(e)) => {
+                warn!("malformed time-stamp request from {}: {}", peer, e);
+                write_http_response(&mut writer, "400 Bad Request", None)
+            }
+            Err(e) => {
+                write_http_response(&mut writer, "500 Internal Server Error", None)?;
+                Err(e)
+            }
+        }
+    }
+}
+
+fn write_http_response(
+    writer: &mut impl Write,
+    status: &str,
+    body: Option<&[u8]>,
+) -> Result<(), AppleCodesignError> {
+    write!(writer, "HTTP/1.1 {status}\r\nConnection: close\r\n")?;
+
+    if let Some(body) = body {
+        write!(
+            writer,
+            "Content-Type: {HTTP_CONTENT_TYPE_RESPONSE}\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )?;
+        writer.write_all(body)?;
+    } else {
+        write!(writer, "Content-Length: 0\r\n\r\n")?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            certificate::CertificateProfile, create_self_signed_code_signing_certificate,
+            embedded_signature::EmbeddedSignature, reader::CmsSigner,
+            signing_settings::SigningSettings, testutil::time_stamp_authority_certificate,
+            xattr_signing::XattrSigner, SettingsScope,
+        },
+        bcder::encode::PrimitiveContent,
+        cryptographic_message_syntax::SignedData,
+        x509_certificate::KeyAlgorithm,
+    };
+
+    #[test]
+    fn reject_unknown_digest() -> Result<(), AppleCodesignError> {
+        let (cert, signing_key) = time_stamp_authority_certificate();
+        let tsa = TimeStampAuthority::new(&signing_key, cert);
+
+        let mut request = TimeStampReq {
+            version: Integer::from(1u8),
+            message_imprint: MessageImprint {
+                hash_algorithm: DigestAlgorithm::Sha256.into(),
+                hashed_message: OctetString::new(Bytes::from_static(b"too short")),
+            },
+            req_policy: None,
+            nonce: Some(Integer::from(42u8)),
+            cert_req: Some(true),
+            extensions: None,
+        };
+
+        let response = tsa.time_stamp_request(&request)?;
+        assert_eq!(response.status.status, PkiStatus::Rejection);
+        assert_eq!(
+            response.status.fail_info,
+            Some(PkiFailureInfo::BadDataFormat)
+        );
+
+        request.message_imprint.hashed_message =
+            OctetString::new(DigestAlgorithm::Sha256.digest_data(b"hello").into());
+        let response = tsa.time_stamp_request(&request)?;
+        assert_eq!(response.status.status, PkiStatus::Granted);
+        assert!(response.time_stamp_token.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn certificates_only_when_requested() -> Result<(), AppleCodesignError> {
+        let (cert, signing_key) = time_stamp_authority_certificate();
+        let tsa = TimeStampAuthority::new(&signing_key, cert.clone());
+
+        let mut request = TimeStampReq {
+            version: Integer::from(1u8),
+            message_imprint: MessageImprint {
+                hash_algorithm: DigestAlgorithm::Sha256.into(),
+                hashed_message: OctetString::new(
+                    DigestAlgorithm::Sha256.digest_data(b"hello").into(),
+                ),
+            },
+            req_policy: None,
+            nonce: None,
+            cert_req: Some(false),
+            extensions: None,
+        };
+
+        let token_certificates = |request: &TimeStampReq| -> Result<_, AppleCodesignError> {
+            let response = tsa.time_stamp_request(request)?;
+            assert_eq!(response.status.status, PkiStatus::Granted);
+
+            let token = response
+                .time_stamp_token
+                .expect("time-stamp token should be present");
+            let mut der = vec![];
+            encode::sequence((token.content_type.encode_ref(), &token.content))
+                .write_encoded(Mode::Der, &mut der)?;
+            let signed_data = SignedData::parse_ber(&der)?;
+
+            Ok(signed_data.certificates().cloned().collect::<Vec<_>>())
+        };
+
+        assert!(token_certificates(&request)?.is_empty());
+
+        request.cert_req = None;
+        assert!(token_certificates(&request)?.is_empty());
+
+        request.cert_req = Some(true);
+        assert_eq!(token_certificates(&request)?, vec![cert]);
+
+        Ok(())
+    }
+
+    fn http_status(stream: &mut TcpStream) -> Result<String, AppleCodesignError> {
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+
+        Ok(status.trim_end().to_string())
+    }
+
+    #[test]
+    fn reject_oversized_headers() -> Result<(), AppleCodesignError> {
+        let (cert, signing_key) = time_stamp_authority_certificate();
+        let tsa = TimeStampAuthority::new(&signing_key, cert);
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let mut client = TcpStream::connect(listener.local_addr()?)?;
+
+        // The request is larger than socket buffers. So send it in a separate
+        // thread while this thread services the connection.
+        let sender = std::thread::spawn(move || -> std::io::Result<TcpStream> {
+            write!(
+                client,
+                "POST / HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+                "a".repeat(MAX_HEADER_SIZE as usize)
+            )?;
+
+            Ok(client)
+        });
+
+        tsa.handle_connection(listener.accept()?.0)?;
+
+        let mut client = sender.join().expect("sending thread should not panic")?;
+        assert_eq!(
+            http_status(&mut client)?,
+            "HTTP/1.1 431 Request Header Fields Too Large"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn serve_concurrent_connections() -> Result<(), AppleCodesignError> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+
+        // The server runs forever. So it owns everything it needs.
+        std::thread::spawn(move || {
+            let (cert, signing_key) = time_stamp_authority_certificate();
+            TimeStampAuthority::new(&signing_key, cert).serve(&listener)
+        });
+
+        // A client that never finishes its request must not block others.
+        let mut idle = TcpStream::connect(addr)?;
+        write!(idle, "POST / HTTP/1.1\r\n")?;
+
+        let mut client = TcpStream::connect(addr)?;
+        client.set_read_timeout(Some(Duration::from_secs(10)))?;
+        write!(client, "GET / HTTP/1.1\r\n\r\n")?;
+        assert_eq!(http_status(&mut client)?, "HTTP/1.1 405 Method Not Allowed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_with_time_stamp() -> Result<(), AppleCodesignError> {
+        let (tsa_cert, tsa_key) = time_stamp_authority_certificate();
+        let tsa = TimeStampAuthority::new(&tsa_key, tsa_cert.clone());
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/", listener.local_addr()?);
+
+        // Signing blocks on the HTTP request. So sign in a separate thread while
+        // this thread services the request.
+        let signer = std::thread::spawn(move || -> Result<Vec<u8>, AppleCodesignError> {
+            let (cert, signing_key, _) = create_self_signed_code_signing_certificate(
+                KeyAlgorithm::Ed25519,
+                CertificateProfile::DeveloperIdApplication,
+                "team",
+                "Joe Developer",
+                "US",
+                chrono::Duration::hours(1),
+            )?;
+
+            let mut settings = SigningSettings::default();
+            settings.set_binary_identifier(SettingsScope::Main, "file");
+            settings.set_signing_key(&signing_key, cert);
+            settings.set_time_stamp_url(url)?;
+
+            XattrSigner::default().create_superblob(&settings, b"hello, world")
+        });
+
+        tsa.handle_connection(listener.accept()?.0)?;
+
+        let superblob = signer.join().expect("signing thread should not panic")?;
+        let signature = EmbeddedSignature::from_bytes(&superblob)?;
+        let signed_data = SignedData::parse_ber(
+            signature
+                .signature_data()?
+                .expect("CMS signature should be present"),
+        )?;
+
+        for signer in signed_data.signers() {
+            assert_eq!(signer.verify_time_stamp_token()?, Some(()));
+
+            let cms_signer = CmsSigner::from_signer_info_and_signed_data(signer, &signed_data)?;
+            assert!(cms_signer.time_stamp_time.is_some());
+            assert_eq!(
+                cms_signer.time_stamp_authority,
+                tsa_cert.subject_name().user_friendly_str().ok()
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,479 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Removal of code signatures.
+//!
+//! Signatures can be removed from all entities we know how to sign:
+//!
+//! * Mach-O binaries have their `LC_CODE_SIGNATURE` load command removed and
+//!   `__LINKEDIT` truncated to drop the signature data. Each architecture in
+//!   a universal binary is processed independently.
+//! * Bundles have `_CodeSignature` directories and stapled `CodeResources`
+//!   notarization tickets deleted. Mach-O binaries within the bundle, including
+//!   within nested bundles, have their signatures removed.
+//! * DMGs have their signature data removed and the code signature fields in
+//!   the koly trailer cleared.
+//! * XAR archives (`.pkg` installers) have their RSA and CMS signatures removed
+//!   from the table of contents.
+//! * ZIP archives have signatures removed from the bundles within them.
+//! * Other files have their signature extended attributes removed.
+
+use {
+    crate::{
+        bundle_signing::copy_bundle,
+        dmg::DmgSigner,
+        error::AppleCodesignError,
+        macho::MachFile,
+        macho_rewrite::remove_code_signature,
+        macho_signing::write_macho_file,
+        macho_universal::create_universal_macho,
+        reader::{MachOType, PathType},
+        xattr_signing::remove_signature_xattrs,
+        zip_signing::ExtractedZip,
+    },
+    apple_bundles::DirectoryBundle,
+    apple_xar::{reader::XarReader, signing::XarSigner},
+    log::{info, warn},
+    std::{fs::File, path::Path},
+};
+
+/// Remove the code signature from Mach-O data.
+///
+/// Returns `None` if none of the binaries within the data are signed.
+/// Otherwise returns the unsigned Mach-O data. Universal binaries have the
+/// signature of every architecture removed.
+pub fn unsign_macho_data(data: &[u8]) -> Result<Option<Vec<u8>>, AppleCodesignError> {
+    let mach = MachFile::parse(data)?;
+
+    if mach
+        .iter_macho()
+        .all(|macho| macho.code_signature_load_command().is_none())
+    {
+        return Ok(None);
+    }
+
+    let binaries = mach
+        .iter_macho()
+        .map(|macho| {
+            if macho.code_signature_load_command().is_some() {
+                if let Some(index) = macho.index {
+                    info!("removing signature from Mach-O binary at index {}", index);
+                }
+                remove_code_signature(macho)
+            } else {
+                Ok(macho.data.to_vec())
+            }
+        })
+        .collect::<Result<Vec<_>, AppleCodesignError>>()?;
+
+    if binaries.len() > 1 {
+        let mut data = vec![];
+        create_universal_macho(&mut data, binaries.iter().map(|x| x.as_slice()))?;
+
+        Ok(Some(data))
+    } else {
+        Ok(binaries.into_iter().next())
+    }
+}
+
+/// Remove code signatures from `input_path`, writing the result to `output_path`.
+///
+/// The input and output paths can be the same, in which case the signature is
+/// removed in place. Unsigned content is copied unmodified.
+pub fn unsign_path(
+    input_path: impl AsRef<Path>,
+    output_path: impl AsRef<Path>,
+) -> Result<(), AppleCodesignError> {
+    let input_path = input_path.as_ref();
+    let output_path = output_path.as_ref();
+
+    match PathType::from_path(input_path)? {
+        PathType::Bundle => unsign_bundle(input_path, output_path),
+        PathType::Dmg => unsign_dmg(input_path, output_path),
+        PathType::MachO => unsign_macho(input_path, output_path),
+        PathType::Xar => unsign_xar(input_path, output_path),
+        PathType::Zip => unsign_zip(input_path, output_path),
+        PathType::File => unsign_file(input_path, output_path),
+        PathType::Other => Err(AppleCodesignError::UnrecognizedPathType),
+    }
+}
+
+/// Copy a file to a new path if the paths are different.
+fn copy_to_output(input_path: &Path, output_path: &Path) -> Result<(), AppleCodesignError> {
+    // This is not robust same file detection.
+    if input_path != output_path {
+        info!(
+            "copying {} to {}",
+            input_path.display(),
+            output_path.display()
+        );
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        std::fs::copy(input_path, output_path)?;
+    }
+
+    Ok(())
+}
+
+fn unsign_macho(input_path: &Path, output_path: &Path) -> Result<(), AppleCodesignError> {
+    warn!("removing signature from Mach-O {}", input_path.display());
+    let macho_data = std::fs::read(input_path)?;
+
+    if let Some(macho_data) = unsign_macho_data(&macho_data)? {
+        warn!("writing Mach-O to {}", output_path.display());
+        write_macho_file(input_path, output_path, &macho_data)
+    } else {
+        warn!("Mach-O is not signed");
+        copy_to_output(input_path, output_path)
+    }
+}
+
+fn unsign_bundle(input_path: &Path, output_path: &Path) -> Result<(), AppleCodesignError> {
+    warn!("removing signatures from bundle {}", input_path.display());
+
+    if input_path != output_path {
+        let bundle = DirectoryBundle::new_from_path(input_path)
+            .map_err(AppleCodesignError::DirectoryBundle)?;
+
+        copy_bundle(&bundle, output_path)?;
+
+        for (rel, nested) in bundle
+            .nested_bundles(true)
+            .map_err(AppleCodesignError::DirectoryBundle)?
+        {
+            copy_bundle(&nested, &output_path.join(rel))?;
+        }
+    }
+
+    unsign_directory(output_path)
+}
+
+/// Remove signatures from all content of a directory, recursively.
+fn unsign_directory(dir: &Path) -> Result<(), AppleCodesignError> {
+    let signature_dir = dir.join("_CodeSignature");
+
+    if signature_dir
+        .symlink_metadata()
+        .map(|m| m.is_dir())
+        .unwrap_or(false)
+    {
+        warn!("removing {}", signature_dir.display());
+        std::fs::remove_dir_all(&signature_dir)?;
+
+        // A stapled notarization ticket is stored next to the signature directory.
+        let ticket_path = dir.join("CodeResources");
+        if ticket_path
+            .symlink_metadata()
+            .map(|m| m.is_file())
+            .unwrap_or(false)
+        {
+            warn!("removing notarization ticket {}", ticket_path.display());
+            std::fs::remove_file(&ticket_path)?;
+        }
+    }
+
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+
+        if file_type.is_dir() {
+            unsign_directory(&path)?;
+        } else if file_type.is_file() && MachOType::from_path(&path)?.is_some() {
+            let data = std::fs::read(&path)?;
+
+            // Other files, such as Java class files, share the universal binary magic.
+            // So ignore files failing to parse.
+            let unsigned = match unsign_macho_data(&data) {
+                Ok(unsigned) => unsigned,
+                Err(AppleCodesignError::Goblin(e)) => {
+                    info!("ignoring {}: {}", path.display(), e);
+                    None
+                }
+                Err(e) => return Err(e),
+            };
+
+            if let Some(data) = unsigned {
+                warn!("removed signature from Mach-O {}", path.display());
+                write_macho_file(&path, &path, &data)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn unsign_dmg(input_path: &Path, output_path: &Path) -> Result<(), AppleCodesignError> {
+    warn!("removing signature from DMG {}", input_path.display());
+    copy_to_output(input_path, output_path)?;
+
+    let mut fh = File::options().read(true).write(true).open(output_path)?;
+
+    if !DmgSigner::default().remove_signature(&mut fh)? {
+        warn!("DMG is not signed");
+    }
+
+    Ok(())
+}
+
+fn unsign_xar(input_path: &Path, output_path: &Path) -> Result<(), AppleCodesignError> {
+    warn!(
+        "removing signature from XAR pkg installer {}",
+        input_path.display()
+    );
+
+    // As with signing, go through a temporary file so we can operate in place.
+    let output_path_temp =
+        output_path.with_file_name(if let Some(file_name) = output_path.file_name() {
+            file_name.to_string_lossy().to_string() + ".tmp"
+        } else {
+            "xar.tmp".to_string()
+        });
+
+    {
+        let reader = XarReader::new(File::open(input_path)?)?;
+        if reader.table_of_contents().signatures().is_empty() {
+            warn!("XAR is not signed");
+        }
+
+        let mut signer = XarSigner::new(reader);
+        let mut fh = File::create(&output_path_temp)?;
+        signer.remove_signature(&mut fh)?;
+    }
+
+    if output_path.exists() {
+        std::fs::remove_file(output_path)?;
+    }
+    std::fs::rename(&output_path_temp, output_path)?;
+
+    Ok(())
+}
+
+fn unsign_zip(input_path: &Path, output_path: &Path) -> Result<(), AppleCodesignError> {
+    warn!(
+        "removing signatures from ZIP archive {}",
+        input_path.display()
+    );
+
+    let zip = ExtractedZip::from_path(input_path)?;
+
+    for path in zip.bundle_paths()? {
+        unsign_directory(&path)?;
+    }
+
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    warn!("writing ZIP archive to {}", output_path.display());
+    zip.write_zip(File::create(output_path)?)?;
+
+    Ok(())
+}
+
+fn unsign_file(input_path: &Path, output_path: &Path) -> Result<(), AppleCodesignError> {
+    warn!(
+        "removing signature extended attributes from {}",
+        input_path.display()
+    );
+    copy_to_output(input_path, output_path)?;
+
+    remove_signature_xattrs(output_path)
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            bundle_signing::BundleSigner,
+            dmg::KolyTrailer,
+            macho_signing::MachOSigner,
+            signing::UnifiedSigner,
+            signing_settings::{SettingsScope, SigningSettings},
+            testutil::{
+                dmg_data, macho_data, rsa_signing_identity, u32s, u64s, universal_macho_data,
+                xar_data,
+            },
+            verify::{verify_xar, VerificationProblemType},
+        },
+        goblin::mach::load_command::LC_NOTE,
+    };
+
+    const INFO_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>CFBundleIdentifier</key>
+  <string>com.example.resources</string>
+  <key>CFBundlePackageType</key>
+  <string>BNDL</string>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn unsign_bundle() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("source.bundle");
+        let signed = temp_dir.path().join("signed.bundle");
+        let unsigned = temp_dir.path().join("unsigned.bundle");
+
+        std::fs::create_dir_all(source.join("Contents/Resources"))?;
+        std::fs::write(source.join("Contents/Info.plist"), INFO_PLIST)?;
+        std::fs::write(source.join("Contents/Resources/a.txt"), b"a")?;
+
+        BundleSigner::new_from_path(&source)?
+            .write_signed_bundle(&signed, &SigningSettings::default())?;
+        // Simulate a stapled notarization ticket.
+        std::fs::write(signed.join("Contents/CodeResources"), b"ticket")?;
+
+        unsign_path(&signed, &unsigned)?;
+
+        assert!(!unsigned.join("Contents/_CodeSignature").exists());
+        assert!(!unsigned.join("Contents/CodeResources").exists());
+        assert_eq!(
+            std::fs::read(unsigned.join("Contents/Resources/a.txt"))?,
+            b"a"
+        );
+        assert!(signed.join("Contents/_CodeSignature").exists());
+
+        // Unsigning in place works too.
+        unsign_path(&signed, &signed)?;
+        assert!(!signed.join("Contents/_CodeSignature").exists());
+
+        Ok(())
+    }
+
+    fn unsign_macho_data_test(data: &[u8]) -> Result<(), AppleCodesignError> {
+        assert!(unsign_macho_data(data)?.is_none());
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "test");
+
+        let mut signed = vec![];
+        MachOSigner::new(data)?.write_signed_binary(&settings, &mut signed)?;
+
+        let unsigned = unsign_macho_data(&signed)?.expect("signature should be removed");
+        let mach = MachFile::parse(&unsigned)?;
+        assert_eq!(
+            mach.iter_macho().count(),
+            MachFile::parse(data)?.iter_macho().count()
+        );
+
+        for macho in mach.iter_macho() {
+            assert!(macho.code_signature_load_command().is_none());
+            macho.check_signing_capability()?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsign_macho() -> Result<(), AppleCodesignError> {
+        unsign_macho_data_test(&macho_data(0x4000, &[]))
+    }
+
+    #[test]
+    fn unsign_macho_unknown_load_command() -> Result<(), AppleCodesignError> {
+        // goblin doesn't know the layout of LC_NOTE. So data it references can't move.
+        let mut note = u32s(&[LC_NOTE, 40]);
+        note.extend(b"owner".iter().chain([0u8; 11].iter()));
+        note.extend(u64s(&[0x4000, 8]));
+
+        let data = macho_data(0x4000, &[note.clone()]);
+        unsign_macho_data_test(&data)?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "test");
+
+        let mut signed = vec![];
+        MachOSigner::new(&data)?.write_signed_binary(&settings, &mut signed)?;
+
+        // The signature is truncated. Everything before it is retained.
+        let unsigned = unsign_macho_data(&signed)?.unwrap();
+        assert!(unsigned.len() < signed.len());
+        assert!(unsigned.starts_with(&data[..0x20]));
+        assert_eq!(&unsigned[0x1000..], &signed[0x1000..unsigned.len()]);
+        assert!(unsigned
+            .windows(note.len())
+            .any(|window| window == note.as_slice()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsign_universal_macho() -> Result<(), AppleCodesignError> {
+        unsign_macho_data_test(&universal_macho_data())
+    }
+
+    #[test]
+    fn unsign_dmg() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("test.dmg");
+
+        let data = dmg_data();
+        std::fs::write(&path, &data)?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "com.example.dmg");
+
+        let mut fh = File::options().read(true).write(true).open(&path)?;
+        DmgSigner::default().sign_file(&settings, &mut fh)?;
+        assert!(fh.metadata()?.len() > data.len() as u64);
+
+        assert!(DmgSigner::default().remove_signature(&mut fh)?);
+        assert_eq!(fh.metadata()?.len(), data.len() as u64);
+
+        let koly = KolyTrailer::read_from(&mut fh)?;
+        assert_eq!(koly.code_signature_offset, 0);
+        assert_eq!(koly.code_signature_size, 0);
+        assert_eq!(std::fs::read(&path)?, data);
+
+        assert!(!DmgSigner::default().remove_signature(&mut fh)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn unsign_xar() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("source.pkg");
+        let signed = temp_dir.path().join("signed.pkg");
+        let unsigned = temp_dir.path().join("unsigned.pkg");
+
+        std::fs::write(
+            &source,
+            xar_data(&[
+                ("Distribution", b"distribution"),
+                ("Payload", &b"payload".repeat(1000)),
+            ]),
+        )?;
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+        UnifiedSigner::new(settings).sign_xar(&source, &signed)?;
+
+        unsign_path(&signed, &unsigned)?;
+
+        let mut reader = XarReader::new(File::open(&unsigned)?)?;
+        assert!(reader.table_of_contents().signatures().is_empty());
+        assert!(reader.verify_table_of_contents_checksum()?);
+
+        // The table of contents and file checksums verify. Only the signature is missing.
+        let problems = verify_xar(&unsigned);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0].problem,
+            VerificationProblemType::XarNoSignature
+        ));
+
+        Ok(())
+    }
+}
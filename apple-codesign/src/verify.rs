@@ -5,7 +5,8 @@
 //! Code signing verification.
 //!
 //! This module implements functionality for verifying code signatures on
-//! Mach-O binaries.
+//! Mach-O binaries, bundles, DMG images, flat packages (XAR archives), and
+//! bundles within ZIP archives.
 //!
 //! # Verification Caveats
 //!
@@ -20,19 +21,120 @@
 //! we have verification for a subset of them. Read the code or the set of
 //! verification problem types enumerated by [VerificationProblemType] to get
 //! a sense of what we do.
+//!
+//! # Code Requirements
+//!
+//! [VerificationSettings] can define code requirements that signatures must
+//! satisfy. See [crate::CodeRequirementEvaluator] for how these are evaluated.
+//!
+//! # Certificate Trust
+//!
+//! The certificate chain of each CMS signer is resolved using certificates in
+//! the signature plus built-in Apple certificates. The chain must end at a known
+//! Apple root certificate authority or at a trust anchor registered via
+//! [VerificationSettings::add_trust_anchor()]. Certificates must be valid at
+//! the time recorded in the time-stamp token, if present, else at the signing
+//! time or the current time. Revocation is not checked.
+//!
+//! # Bundles
+//!
+//! Bundle verification verifies the main executable and then verifies the
+//! resources sealed in `_CodeSignature/CodeResources`. The rules in that file
+//! are applied to the files in the bundle to determine which files should be
+//! sealed. Regular files and symlinks are compared against their seals. Nested
+//! code has its code directory hash and designated requirement checked and is
+//! verified like any other binary. Nested bundles are verified recursively. The
+//! digests of the `Info.plist` and `CodeResources` files must match the digests
+//! recorded in the main executable's code directory.
+//!
+//! # DMGs and Flat Packages
+//!
+//! DMG verification recomputes the digests of the image content and the
+//! trailer and compares them against the code directory. The CMS signature,
+//! if present, is verified like the signature of a Mach-O binary. A stapled
+//! notarization ticket must be well-formed. Its content is not validated.
+//!
+//! Flat package verification verifies the table of contents checksum, the RSA
+//! and CMS signatures over that checksum, the certificate chain of the signer,
+//! and the archived and extracted checksums of every file in the archive.
+//!
+//! # ZIP Archives
+//!
+//! ZIP archives, including `.ipa` files, are extracted to a temporary directory
+//! and the bundles within are verified as described above. Bundles are those in
+//! the `Payload` directory, if present, else at the root of the archive.
+//!
+//! # Other Files
+//!
+//! Files that aren't any of the above, such as scripts, are verified against
+//! the signature stored in their extended attributes. See [verify_file()].
+//!
+//! # Detached Signatures
+//!
+//! [verify_macho_detached()] verifies a Mach-O binary against a detached
+//! signature file instead of its embedded signature. The signature for each
+//! Mach-O is found by CPU type and verified like an embedded signature.
 
 use {
     crate::{
+        apple_certificates::KnownCertificate,
+        certificate::{
+            asn1_time_to_datetime, cms_signer_certificate, AppleCertificate,
+            ExtendedKeyUsagePurpose,
+        },
         code_directory::CodeDirectoryBlob,
-        embedded_signature::{CodeSigningSlot, DigestType, EmbeddedSignature},
+        code_requirement::{CodeRequirementExpression, CodeRequirements},
+        code_requirement_evaluator::{CodeRequirementEvaluator, CodeRequirementFailure},
+        code_resources::{
+            CodeResources, CodeResourcesBuilder, CodeResourcesRule, RulesEvaluation, SealedResource,
+        },
+        dmg::DmgReader,
+        embedded_signature::{
+            Blob, CodeSigningMagic, CodeSigningSlot, DetachedSignatureBlob, DigestType,
+            EmbeddedSignature,
+        },
         error::AppleCodesignError,
         macho::{MachFile, MachOBinary},
+        reader::PathType,
+        xattr_signing::read_signature_xattrs,
+        zip_signing::ExtractedZip,
     },
-    cryptographic_message_syntax::{CmsError, SignedData},
-    std::path::{Path, PathBuf},
-    x509_certificate::{DigestAlgorithm, SignatureAlgorithm},
+    apple_bundles::{BundlePackageType, DirectoryBundle},
+    apple_xar::reader::XarReader,
+    bcder::{decode::Constructed, BitString, ConstOid, Mode, Oid},
+    chrono::{DateTime, Utc},
+    cryptographic_message_syntax::{asn1::rfc3161::TstInfo, CmsError, SignedData, SignerInfo},
+    goblin::mach::constants::SEG_LINKEDIT,
+    std::{
+        collections::BTreeSet,
+        fmt::Debug,
+        io::{Read, Seek},
+        path::{Path, PathBuf},
+    },
+    x509_certificate::{CapturedX509Certificate, DigestAlgorithm, SignatureAlgorithm},
 };
 
+/// X.509 key usage extension (2.5.29.15).
+const OID_KEY_USAGE: ConstOid = Oid(&[85, 29, 15]);
+
+/// X.509 basic constraints extension (2.5.29.19).
+const OID_BASIC_CONSTRAINTS: ConstOid = Oid(&[85, 29, 19]);
+
+/// Bit of the `digitalSignature` key usage.
+const KEY_USAGE_DIGITAL_SIGNATURE: usize = 0;
+
+/// Bit of the `keyCertSign` key usage.
+const KEY_USAGE_KEY_CERT_SIGN: usize = 5;
+
+/// X.509 extended key usage extension (2.5.29.37).
+const OID_EXTENDED_KEY_USAGE: ConstOid = Oid(&[85, 29, 37]);
+
+/// Extended key usage purpose allowing any usage (2.5.29.37.0).
+const OID_EKU_ANY: ConstOid = Oid(&[85, 29, 37, 0]);
+
+/// Extended key usage purpose of time-stamp authorities (1.3.6.1.5.5.7.3.8).
+const OID_EKU_TIME_STAMPING: ConstOid = Oid(&[43, 6, 1, 5, 5, 7, 3, 8]);
+
 /// Context for a verification issue.
 #[derive(Clone, Debug)]
 pub struct VerificationContext {
@@ -66,6 +168,42 @@ pub enum VerificationProblemType {
     ExtraSlotDigest(CodeSigningSlot, Vec<u8>),
     SlotDigestMismatch(CodeSigningSlot, Vec<u8>, Vec<u8>),
     SlotDigestError(AppleCodesignError),
+    RequirementNotSatisfied(String, CodeRequirementFailure),
+    RequirementEvaluationError(AppleCodesignError),
+    NoSigningCertificate,
+    CertificateUntrusted(String),
+    CertificateExpired(String, DateTime<Utc>, DateTime<Utc>),
+    CertificateNotYetValid(String, DateTime<Utc>, DateTime<Utc>),
+    CertificateMisprofiled(String, String),
+    TimeStampTokenError(CmsError),
+    TimeStampTokenMalformed,
+    TimeStampImprintMismatch,
+    TimeStampUntrusted(String),
+    BundleError(AppleCodesignError),
+    NoCodeResources,
+    CodeResourcesParseError(AppleCodesignError),
+    ResourceAdded,
+    ResourceMissing,
+    ResourceKindMismatch,
+    ResourceDigestMismatch(DigestType, Vec<u8>, Vec<u8>),
+    ResourceSymlinkMismatch(String, String),
+    NestedCodeError(AppleCodesignError),
+    NestedCodeHashMismatch(Vec<u8>, Vec<u8>),
+    NestedCodeRequirementNotSatisfied(String, CodeRequirementFailure),
+    DmgError(AppleCodesignError),
+    DmgNoSignature,
+    NotarizationTicketInvalid(String),
+    XarError(AppleCodesignError),
+    XarNoSignature,
+    XarTableOfContentsChecksumMismatch,
+    XarRsaSignatureError(AppleCodesignError),
+    XarFileArchivedChecksumMismatch(String, String),
+    XarFileExtractedChecksumMismatch(String, String),
+    ZipError(AppleCodesignError),
+    DetachedSignatureError(AppleCodesignError),
+    DetachedSignatureMissingCpuType(u32),
+    XattrError(AppleCodesignError),
+    XattrNoSignature,
 }
 
 #[derive(Debug)]
@@ -158,6 +296,115 @@ impl std::fmt::Display for VerificationProblem {
             VerificationProblemType::SlotDigestError(e) => {
                 format!("error computing slot digest: {e:?}")
             }
+            VerificationProblemType::RequirementNotSatisfied(requirement, failure) => {
+                format!("code requirement not satisfied: {requirement}; failed at {failure}")
+            }
+            VerificationProblemType::RequirementEvaluationError(e) => {
+                format!("error evaluating code requirements: {e}")
+            }
+            VerificationProblemType::NoSigningCertificate => {
+                "signing certificate not found in CMS signature".to_string()
+            }
+            VerificationProblemType::CertificateUntrusted(subject) => {
+                format!(
+                    "certificate chain does not lead to a trusted root; chain ends at {subject}"
+                )
+            }
+            VerificationProblemType::CertificateExpired(subject, not_after, time) => {
+                format!("certificate {subject} expired at {not_after}; verified at {time}")
+            }
+            VerificationProblemType::CertificateNotYetValid(subject, not_before, time) => {
+                format!("certificate {subject} not valid until {not_before}; verified at {time}")
+            }
+            VerificationProblemType::CertificateMisprofiled(subject, reason) => {
+                format!("certificate {subject} is not suitable for code signing: {reason}")
+            }
+            VerificationProblemType::TimeStampTokenError(e) => {
+                format!("error verifying time-stamp token: {e}")
+            }
+            VerificationProblemType::TimeStampTokenMalformed => {
+                "time-stamp token does not contain time-stamp info".to_string()
+            }
+            VerificationProblemType::TimeStampImprintMismatch => {
+                "time-stamp token message imprint does not match signature".to_string()
+            }
+            VerificationProblemType::TimeStampUntrusted(anchor) => {
+                format!("time-stamp authority is not trusted (certificate chain ends at {anchor})")
+            }
+            VerificationProblemType::BundleError(e) => format!("error reading bundle: {e}"),
+            VerificationProblemType::NoCodeResources => {
+                "_CodeSignature/CodeResources not found".to_string()
+            }
+            VerificationProblemType::CodeResourcesParseError(e) => {
+                format!("error parsing CodeResources: {e}")
+            }
+            VerificationProblemType::ResourceAdded => {
+                "file not sealed in CodeResources".to_string()
+            }
+            VerificationProblemType::ResourceMissing => {
+                "sealed resource does not exist".to_string()
+            }
+            VerificationProblemType::ResourceKindMismatch => {
+                "file type does not match sealed resource".to_string()
+            }
+            VerificationProblemType::ResourceDigestMismatch(digest_type, sealed, actual) => {
+                format!(
+                    "resource content modified; sealed {:?} digest {}, actual {}",
+                    digest_type,
+                    hex::encode(sealed),
+                    hex::encode(actual)
+                )
+            }
+            VerificationProblemType::ResourceSymlinkMismatch(sealed, actual) => {
+                format!("symlink target modified; sealed {sealed}, actual {actual}")
+            }
+            VerificationProblemType::NestedCodeError(e) => {
+                format!("error examining nested code: {e}")
+            }
+            VerificationProblemType::NestedCodeHashMismatch(sealed, actual) => {
+                format!(
+                    "nested code modified; sealed cdhash {}, actual {}",
+                    hex::encode(sealed),
+                    hex::encode(actual)
+                )
+            }
+            VerificationProblemType::NestedCodeRequirementNotSatisfied(requirement, failure) => {
+                format!(
+                    "nested code does not satisfy sealed requirement: {requirement}; failed at {failure}"
+                )
+            }
+            VerificationProblemType::DmgError(e) => format!("error reading DMG: {e}"),
+            VerificationProblemType::DmgNoSignature => "DMG has no code signature".to_string(),
+            VerificationProblemType::NotarizationTicketInvalid(reason) => {
+                format!("stapled notarization ticket is invalid: {reason}")
+            }
+            VerificationProblemType::XarError(e) => format!("error reading XAR archive: {e}"),
+            VerificationProblemType::XarNoSignature => "XAR archive has no signature".to_string(),
+            VerificationProblemType::XarTableOfContentsChecksumMismatch => {
+                "XAR table of contents checksum mismatch".to_string()
+            }
+            VerificationProblemType::XarRsaSignatureError(e) => {
+                format!("XAR RSA signature verification failed: {e}")
+            }
+            VerificationProblemType::XarFileArchivedChecksumMismatch(recorded, actual) => {
+                format!("archived checksum mismatch; recorded {recorded}, actual {actual}")
+            }
+            VerificationProblemType::XarFileExtractedChecksumMismatch(recorded, actual) => {
+                format!("extracted checksum mismatch; recorded {recorded}, actual {actual}")
+            }
+            VerificationProblemType::ZipError(e) => format!("error reading ZIP archive: {e}"),
+            VerificationProblemType::DetachedSignatureError(e) => {
+                format!("error parsing detached signature: {e}")
+            }
+            VerificationProblemType::DetachedSignatureMissingCpuType(cpu_type) => {
+                format!("detached signature has no signature for CPU type {cpu_type}")
+            }
+            VerificationProblemType::XattrError(e) => {
+                format!("error reading signature from extended attributes: {e}")
+            }
+            VerificationProblemType::XattrNoSignature => {
+                "no code signature in extended attributes".to_string()
+            }
         };
 
         match context {
@@ -167,13 +414,99 @@ impl std::fmt::Display for VerificationProblem {
     }
 }
 
+/// Settings that influence verification.
+#[derive(Clone, Debug, Default)]
+pub struct VerificationSettings {
+    requirements: Vec<CodeRequirementExpression<'static>>,
+    trust_anchors: Vec<CapturedX509Certificate>,
+}
+
+impl VerificationSettings {
+    /// Obtain additional certificates that are trusted as roots.
+    pub fn trust_anchors(&self) -> &[CapturedX509Certificate] {
+        &self.trust_anchors
+    }
+
+    /// Register a certificate to trust as a root when validating certificate chains.
+    ///
+    /// Known Apple root certificate authorities are always trusted. This allows
+    /// trusting additional certificates, such as a self-signed code signing
+    /// certificate or a private certificate authority.
+    ///
+    /// Trust anchors also apply to time-stamp tokens. Otherwise time-stamp tokens
+    /// must be issued by Apple's time-stamp authority.
+    pub fn add_trust_anchor(&mut self, cert: CapturedX509Certificate) {
+        self.trust_anchors.push(cert);
+    }
+
+    /// Obtain the code requirements that signatures must satisfy.
+    pub fn requirements(&self) -> &[CodeRequirementExpression<'static>] {
+        &self.requirements
+    }
+
+    /// Add a code requirement that signatures must satisfy.
+    ///
+    /// Requirements are evaluated with [CodeRequirementEvaluator]. A problem is
+    /// reported for every requirement that isn't satisfied.
+    pub fn add_requirement(&mut self, requirement: CodeRequirementExpression<'static>) {
+        self.requirements.push(requirement);
+    }
+
+    /// Obtain settings to use for code nested within a bundle.
+    ///
+    /// Code requirements only apply to the main code being verified.
+    fn as_nested_settings(&self) -> Self {
+        Self {
+            requirements: vec![],
+            trust_anchors: self.trust_anchors.clone(),
+        }
+    }
+}
+
 /// Verifies a binary in a given path.
 ///
 /// Returns a vector of problems detected. An empty vector means no
 /// problems were found.
 pub fn verify_path(path: impl AsRef<Path>) -> Vec<VerificationProblem> {
+    verify_path_with_settings(path, &VerificationSettings::default())
+}
+
+/// Verifies a binary in a given path using custom settings.
+///
+/// The type of entity is sniffed from the path. Directories are verified as
+/// bundles. DMGs, flat packages (XAR archives), and ZIP archives are also
+/// supported.
+/// Everything else is verified as a Mach-O binary.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_path_with_settings(
+    path: impl AsRef<Path>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
     let path = path.as_ref();
 
+    // Errors are most likely I/O errors, which are reported by the Mach-O
+    // handling below.
+    match PathType::from_path(path).unwrap_or(PathType::Other) {
+        PathType::Bundle => {
+            return verify_bundle_with_settings(path, settings);
+        }
+        PathType::Dmg => {
+            return verify_dmg_with_settings(path, settings);
+        }
+        PathType::Xar => {
+            return verify_xar_with_settings(path, settings);
+        }
+        PathType::Zip => {
+            return verify_zip_with_settings(path, settings);
+        }
+        PathType::File => {
+            return verify_file_with_settings(path, settings);
+        }
+        PathType::MachO | PathType::Other => {}
+    }
+
     let context = VerificationContext {
         path: Some(path.to_path_buf()),
         fat_index: None,
@@ -189,7 +522,169 @@ pub fn verify_path(path: impl AsRef<Path>) -> Vec<VerificationProblem> {
         }
     };
 
-    verify_macho_data_internal(data, context)
+    verify_macho_data_internal(data, context, settings)
+}
+
+/// Verifies a bundle in a given directory.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_bundle(path: impl AsRef<Path>) -> Vec<VerificationProblem> {
+    verify_bundle_with_settings(path, &VerificationSettings::default())
+}
+
+/// Verifies a bundle in a given directory using custom settings.
+///
+/// Code requirements in the settings apply to the bundle's main executable.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_bundle_with_settings(
+    path: impl AsRef<Path>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let path = path.as_ref();
+
+    match DirectoryBundle::new_from_path(path) {
+        Ok(bundle) => verify_bundle_internal(&bundle, settings),
+        Err(e) => vec![VerificationProblem {
+            context: VerificationContext {
+                path: Some(path.to_path_buf()),
+                fat_index: None,
+            },
+            problem: VerificationProblemType::BundleError(AppleCodesignError::DirectoryBundle(e)),
+        }],
+    }
+}
+
+/// Verifies a DMG in a given path.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_dmg(path: impl AsRef<Path>) -> Vec<VerificationProblem> {
+    verify_dmg_with_settings(path, &VerificationSettings::default())
+}
+
+/// Verifies a DMG in a given path using custom settings.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_dmg_with_settings(
+    path: impl AsRef<Path>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let path = path.as_ref();
+    let context = path_context(path);
+
+    let mut fh = match std::fs::File::open(path) {
+        Ok(fh) => fh,
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::IoError(e),
+            }];
+        }
+    };
+
+    verify_dmg_internal(&mut fh, context, settings)
+}
+
+/// Verifies a flat package (XAR archive) in a given path.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_xar(path: impl AsRef<Path>) -> Vec<VerificationProblem> {
+    verify_xar_with_settings(path, &VerificationSettings::default())
+}
+
+/// Verifies a flat package (XAR archive) in a given path using custom settings.
+///
+/// Code requirements in the settings are evaluated against the certificate
+/// chain of the signer.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_xar_with_settings(
+    path: impl AsRef<Path>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let path = path.as_ref();
+    let context = path_context(path);
+
+    let xar = match std::fs::File::open(path)
+        .map_err(AppleCodesignError::from)
+        .and_then(|fh| XarReader::new(fh).map_err(AppleCodesignError::from))
+    {
+        Ok(xar) => xar,
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::XarError(e),
+            }];
+        }
+    };
+
+    verify_xar_internal(xar, context, settings)
+}
+
+/// Verifies the bundles within a ZIP archive in a given path.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_zip(path: impl AsRef<Path>) -> Vec<VerificationProblem> {
+    verify_zip_with_settings(path, &VerificationSettings::default())
+}
+
+/// Verifies the bundles within a ZIP archive in a given path using custom settings.
+///
+/// Paths in reported problems are the path of the archive joined with the path
+/// within the archive.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_zip_with_settings(
+    path: impl AsRef<Path>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let path = path.as_ref();
+    let context = path_context(path);
+
+    let res = ExtractedZip::from_path(path).and_then(|zip| {
+        let bundles = zip.bundle_paths()?;
+
+        Ok((zip, bundles))
+    });
+
+    let (zip, bundles) = match res {
+        Ok((_, bundles)) if bundles.is_empty() => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::ZipError(AppleCodesignError::ZipNoBundles),
+            }];
+        }
+        Ok(v) => v,
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::ZipError(e),
+            }];
+        }
+    };
+
+    bundles
+        .into_iter()
+        .flat_map(|bundle| verify_bundle_with_settings(bundle, settings))
+        .map(|mut problem| {
+            // Rewrite paths in the temporary directory to be relative to the archive.
+            problem.context.path = problem.context.path.map(|p| {
+                p.strip_prefix(zip.root_dir())
+                    .map(|rel| path.join(rel))
+                    .unwrap_or(p)
+            });
+
+            problem
+        })
+        .collect::<Vec<_>>()
 }
 
 /// Verifies unparsed Mach-O data.
@@ -197,17 +692,29 @@ pub fn verify_path(path: impl AsRef<Path>) -> Vec<VerificationProblem> {
 /// Returns a vector of problems detected. An empty vector means no
 /// problems were found.
 pub fn verify_macho_data(data: impl AsRef<[u8]>) -> Vec<VerificationProblem> {
+    verify_macho_data_with_settings(data, &VerificationSettings::default())
+}
+
+/// Verifies unparsed Mach-O data using custom settings.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_macho_data_with_settings(
+    data: impl AsRef<[u8]>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
     let context = VerificationContext {
         path: None,
         fat_index: None,
     };
 
-    verify_macho_data_internal(data, context)
+    verify_macho_data_internal(data, context, settings)
 }
 
 fn verify_macho_data_internal(
     data: impl AsRef<[u8]>,
     context: VerificationContext,
+    settings: &VerificationSettings,
 ) -> Vec<VerificationProblem> {
     match MachFile::parse(data.as_ref()) {
         Ok(mach) => {
@@ -217,7 +724,7 @@ fn verify_macho_data_internal(
                 let mut context = context.clone();
                 context.fat_index = macho.index;
 
-                problems.extend(verify_macho_internal(&macho, context));
+                problems.extend(verify_macho_internal(&macho, context, settings));
             }
 
             problems
@@ -236,18 +743,31 @@ fn verify_macho_data_internal(
 /// Returns a vector of problems detected. An empty vector means no
 /// problems were found.
 pub fn verify_macho(macho: &MachOBinary) -> Vec<VerificationProblem> {
+    verify_macho_with_settings(macho, &VerificationSettings::default())
+}
+
+/// Verifies a parsed Mach-O binary using custom settings.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_macho_with_settings(
+    macho: &MachOBinary,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
     verify_macho_internal(
         macho,
         VerificationContext {
             path: None,
             fat_index: None,
         },
+        settings,
     )
 }
 
 fn verify_macho_internal(
     macho: &MachOBinary,
     context: VerificationContext,
+    settings: &VerificationSettings,
 ) -> Vec<VerificationProblem> {
     let signature_data = match macho.find_signature_data() {
         Ok(Some(data)) => data,
@@ -267,8 +787,16 @@ fn verify_macho_internal(
 
     let mut problems = vec![];
 
-    // __LINKEDIT segment should be the last segment.
-    if signature_data.linkedit_segment_index != macho.macho.segments.len() - 1 {
+    // __LINKEDIT segment should be the last segment in the file. Segment load commands
+    // can't be reordered without invalidating segment indices used by dyld. So the
+    // order of load commands isn't significant.
+    if !matches!(
+        macho
+            .segments_by_file_offset()
+            .last()
+            .map(|segment| segment.name()),
+        Some(Ok(SEG_LINKEDIT))
+    ) {
         problems.push(VerificationProblem {
             context: context.clone(),
             problem: VerificationProblemType::LinkeditNotLastSegment,
@@ -299,9 +827,82 @@ fn verify_macho_internal(
         }
     };
 
+    problems.extend(verify_code_signature(
+        SignedCode::MachO(macho),
+        &signature,
+        context,
+        settings,
+    ));
+
+    problems
+}
+
+/// Code covered by a signature.
+#[derive(Clone, Copy)]
+enum SignedCode<'a, 'data> {
+    /// A Mach-O binary.
+    MachO(&'a MachOBinary<'data>),
+    /// The content of a file signed via extended attributes.
+    File(&'a [u8]),
+}
+
+impl<'a, 'data> SignedCode<'a, 'data> {
+    /// Compute digests over the code.
+    fn code_digests(
+        &self,
+        digest_type: DigestType,
+        page_size: usize,
+    ) -> Result<Vec<Vec<u8>>, AppleCodesignError> {
+        match self {
+            Self::MachO(macho) => macho.code_digests(digest_type, page_size),
+            // A page size of 0 means the content is digested as a single page.
+            Self::File(data) if page_size == 0 => Ok(vec![digest_type.digest_data(data)?]),
+            Self::File(data) => data
+                .chunks(page_size)
+                .map(|chunk| digest_type.digest_data(chunk))
+                .collect::<Result<Vec<_>, AppleCodesignError>>(),
+        }
+    }
+
+    fn requirements_evaluator(
+        &self,
+        signature: &EmbeddedSignature,
+    ) -> Result<CodeRequirementEvaluator, AppleCodesignError> {
+        let mut evaluator = CodeRequirementEvaluator::from_signature(signature)?;
+
+        // Standalone binaries can have their Info.plist embedded in a section.
+        if let Self::MachO(macho) = self {
+            if let Some(data) = macho.embedded_info_plist()? {
+                evaluator.set_info_plist_data(&data)?;
+            }
+        }
+
+        Ok(evaluator)
+    }
+}
+
+/// Verify code against a signature, which may be embedded, detached, or in extended attributes.
+fn verify_code_signature(
+    code: SignedCode,
+    signature: &EmbeddedSignature,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let mut problems = vec![];
+
     match signature.signature_data() {
         Ok(Some(cms_blob)) => {
-            problems.extend(verify_cms_signature(cms_blob, context.clone()));
+            // The CMS signature is over the primary code directory.
+            let cd_data = signature
+                .find_slot(CodeSigningSlot::CodeDirectory)
+                .map(|entry| entry.data);
+
+            problems.extend(verify_cms_signature(
+                cms_blob,
+                cd_data,
+                context.clone(),
+                settings,
+            ));
         }
         Ok(None) => problems.push(VerificationProblem {
             context: context.clone(),
@@ -315,9 +916,18 @@ fn verify_macho_internal(
         }
     }
 
+    if !settings.requirements.is_empty() {
+        problems.extend(verify_requirements(
+            code,
+            signature,
+            settings.requirements(),
+            context.clone(),
+        ));
+    }
+
     match signature.code_directory() {
         Ok(Some(cd)) => {
-            problems.extend(verify_code_directory(macho, &signature, &cd, context));
+            problems.extend(verify_code_directory(code, signature, &cd, context));
         }
         Ok(None) => {
             problems.push(VerificationProblem {
@@ -336,32 +946,1208 @@ fn verify_macho_internal(
     problems
 }
 
-fn verify_cms_signature(data: &[u8], context: VerificationContext) -> Vec<VerificationProblem> {
-    let signed_data = match SignedData::parse_ber(data) {
-        Ok(signed_data) => signed_data,
+/// Verifies a Mach-O binary against a detached signature.
+///
+/// The detached signature can be a [DetachedSignatureBlob] holding a signature
+/// for each architecture, as written by [crate::MachOSigner::write_detached_signature()]
+/// and `codesign --detached`, or a single embedded signature SuperBlob.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_macho_detached(
+    path: impl AsRef<Path>,
+    signature_path: impl AsRef<Path>,
+) -> Vec<VerificationProblem> {
+    verify_macho_detached_with_settings(path, signature_path, &VerificationSettings::default())
+}
+
+/// Verifies a Mach-O binary against a detached signature using custom settings.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_macho_detached_with_settings(
+    path: impl AsRef<Path>,
+    signature_path: impl AsRef<Path>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let path = path.as_ref();
+    let context = path_context(path);
+
+    let (data, signature_data) =
+        match std::fs::read(path).and_then(|data| Ok((data, std::fs::read(signature_path)?))) {
+            Ok(res) => res,
+            Err(e) => {
+                return vec![VerificationProblem {
+                    context,
+                    problem: VerificationProblemType::IoError(e),
+                }];
+            }
+        };
+
+    verify_macho_detached_data_internal(&data, &signature_data, context, settings)
+}
+
+fn verify_macho_detached_data_internal(
+    data: &[u8],
+    signature_data: &[u8],
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let mach = match MachFile::parse(data) {
+        Ok(mach) => mach,
         Err(e) => {
             return vec![VerificationProblem {
                 context,
-                problem: VerificationProblemType::CmsError(e),
+                problem: VerificationProblemType::MachOParseError(e),
             }];
         }
     };
 
-    let mut problems = vec![];
-
-    for signer in signed_data.signers() {
-        match signer.digest_algorithm() {
-            DigestAlgorithm::Sha1 => {
-                problems.push(VerificationProblem {
-                    context: context.clone(),
-                    problem: VerificationProblemType::CmsOldDigestAlgorithm(
-                        signer.digest_algorithm(),
-                    ),
-                });
+    // A bare embedded signature applies to every Mach-O.
+    let detached = if signature_data.get(0..4)
+        == Some(&u32::from(CodeSigningMagic::EmbeddedSignature).to_be_bytes())
+    {
+        None
+    } else {
+        match DetachedSignatureBlob::from_blob_bytes(signature_data) {
+            Ok(blob) => Some(blob),
+            Err(e) => {
+                return vec![VerificationProblem {
+                    context,
+                    problem: VerificationProblemType::DetachedSignatureError(e),
+                }];
             }
-            DigestAlgorithm::Sha384 => {}
-            DigestAlgorithm::Sha256 => {}
-            DigestAlgorithm::Sha512 => {}
+        }
+    };
+
+    let mut problems = vec![];
+
+    for macho in mach.iter_macho() {
+        let mut context = context.clone();
+        context.fat_index = macho.index;
+
+        let cpu_type = macho.macho.header.cputype();
+
+        let signature = match &detached {
+            Some(blob) => blob.signature_for_cpu_type(cpu_type),
+            None => EmbeddedSignature::from_bytes(signature_data).map(Some),
+        };
+
+        match signature {
+            Ok(Some(signature)) => {
+                problems.extend(verify_code_signature(
+                    SignedCode::MachO(macho),
+                    &signature,
+                    context,
+                    settings,
+                ));
+            }
+            Ok(None) => problems.push(VerificationProblem {
+                context,
+                problem: VerificationProblemType::DetachedSignatureMissingCpuType(cpu_type),
+            }),
+            Err(e) => problems.push(VerificationProblem {
+                context,
+                problem: VerificationProblemType::DetachedSignatureError(e),
+            }),
+        }
+    }
+
+    problems
+}
+
+/// Verifies a file signed via extended attributes.
+///
+/// This is used for files that aren't Mach-O binaries or any other signable
+/// type, such as scripts. See [crate::XattrSigner].
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_file(path: impl AsRef<Path>) -> Vec<VerificationProblem> {
+    verify_file_with_settings(path, &VerificationSettings::default())
+}
+
+/// Verifies a file signed via extended attributes using custom settings.
+///
+/// Returns a vector of problems detected. An empty vector means no
+/// problems were found.
+pub fn verify_file_with_settings(
+    path: impl AsRef<Path>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let path = path.as_ref();
+    let context = path_context(path);
+
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::IoError(e),
+            }];
+        }
+    };
+
+    let signature_data = match read_signature_xattrs(path) {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::XattrNoSignature,
+            }];
+        }
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::XattrError(e),
+            }];
+        }
+    };
+
+    match EmbeddedSignature::from_bytes(&signature_data) {
+        Ok(signature) => {
+            verify_code_signature(SignedCode::File(&data), &signature, context, settings)
+        }
+        Err(e) => vec![VerificationProblem {
+            context,
+            problem: VerificationProblemType::XattrError(e),
+        }],
+    }
+}
+
+fn path_context(path: &Path) -> VerificationContext {
+    VerificationContext {
+        path: Some(path.to_path_buf()),
+        fat_index: None,
+    }
+}
+
+fn verify_bundle_internal(
+    bundle: &DirectoryBundle,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let mut problems = vec![];
+    let nested_settings = settings.as_nested_settings();
+
+    let bundle_problem = |e| VerificationProblem {
+        context: path_context(bundle.root_dir()),
+        problem: VerificationProblemType::BundleError(AppleCodesignError::DirectoryBundle(e)),
+    };
+
+    let nested_bundles = match bundle.nested_bundles(false) {
+        Ok(bundles) => bundles,
+        Err(e) => {
+            return vec![bundle_problem(e)];
+        }
+    };
+
+    // Versioned frameworks aren't signed themselves. Each version is signed
+    // as its own bundle. See the corresponding logic in bundle signing.
+    if bundle.package_type() == BundlePackageType::Framework
+        && bundle.root_dir().join("Versions").is_dir()
+    {
+        for (_, nested) in nested_bundles {
+            problems.extend(verify_bundle_internal(&nested, &nested_settings));
+        }
+
+        return problems;
+    }
+
+    let files = match bundle.files(true) {
+        Ok(files) => files,
+        Err(e) => {
+            return vec![bundle_problem(e)];
+        }
+    };
+
+    let mut main_exe = None;
+    let mut other_files = vec![];
+
+    for file in files {
+        match file.is_main_executable() {
+            Ok(true) => {
+                main_exe = Some(file);
+            }
+            Ok(false) => {
+                other_files.push(file);
+            }
+            Err(e) => {
+                return vec![bundle_problem(e)];
+            }
+        }
+    }
+
+    let code_resources_path = bundle.resolve_path("_CodeSignature/CodeResources");
+
+    let code_resources_data = match std::fs::read(&code_resources_path) {
+        Ok(data) => Some(data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            problems.push(VerificationProblem {
+                context: path_context(&code_resources_path),
+                problem: VerificationProblemType::NoCodeResources,
+            });
+            None
+        }
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: path_context(&code_resources_path),
+                problem: VerificationProblemType::IoError(e),
+            });
+            None
+        }
+    };
+
+    if let Some(exe) = &main_exe {
+        problems.extend(verify_bundle_main_executable(
+            exe.absolute_path(),
+            &bundle.info_plist_path(),
+            &code_resources_path,
+            code_resources_data.as_deref(),
+            settings,
+        ));
+    }
+
+    let resources = match code_resources_data
+        .as_deref()
+        .map(CodeResources::from_xml)
+        .transpose()
+    {
+        Ok(resources) => resources,
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: path_context(&code_resources_path),
+                problem: VerificationProblemType::CodeResourcesParseError(e),
+            });
+            None
+        }
+    };
+
+    if let Some(resources) = resources {
+        match verify_bundle_resources(
+            bundle,
+            &resources,
+            &other_files,
+            &nested_bundles,
+            &nested_settings,
+        ) {
+            Ok(resource_problems) => problems.extend(resource_problems),
+            Err(e) => problems.push(VerificationProblem {
+                context: path_context(&code_resources_path),
+                problem: VerificationProblemType::CodeResourcesParseError(e),
+            }),
+        }
+    }
+
+    for (_, nested) in &nested_bundles {
+        problems.extend(verify_bundle_internal(nested, &nested_settings));
+    }
+
+    problems
+}
+
+/// Verifies the main executable of a bundle.
+///
+/// In addition to normal binary verification, this verifies that the digests
+/// of the `Info.plist` and `CodeResources` files match the special slots in
+/// the code directories.
+fn verify_bundle_main_executable(
+    path: &Path,
+    info_plist_path: &Path,
+    code_resources_path: &Path,
+    code_resources_data: Option<&[u8]>,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let context = path_context(path);
+
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::IoError(e),
+            }];
+        }
+    };
+
+    let mut problems = verify_macho_data_internal(&data, context.clone(), settings);
+
+    // Problems with parsing were reported above.
+    let mach = match MachFile::parse(&data) {
+        Ok(mach) => mach,
+        Err(_) => {
+            return problems;
+        }
+    };
+
+    let info_plist_data = std::fs::read(info_plist_path).ok();
+
+    for macho in mach.iter_macho() {
+        let mut context = context.clone();
+        context.fat_index = macho.index;
+
+        let code_directories = match macho.code_signature() {
+            Ok(Some(signature)) => match signature.all_code_directories() {
+                Ok(cds) => cds,
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+
+        for (_, cd) in code_directories {
+            for (slot, external_data, external_path) in [
+                (
+                    CodeSigningSlot::Info,
+                    info_plist_data.as_deref(),
+                    info_plist_path,
+                ),
+                (
+                    CodeSigningSlot::ResourceDir,
+                    code_resources_data,
+                    code_resources_path,
+                ),
+            ] {
+                match (cd.slot_digests().get(&slot), external_data) {
+                    (Some(recorded), Some(external_data)) => {
+                        match cd.digest_type.digest_data(external_data) {
+                            Ok(actual) if actual == recorded.to_vec() => {}
+                            Ok(actual) => problems.push(VerificationProblem {
+                                context: context.clone(),
+                                problem: VerificationProblemType::SlotDigestMismatch(
+                                    slot,
+                                    recorded.to_vec(),
+                                    actual,
+                                ),
+                            }),
+                            Err(e) => problems.push(VerificationProblem {
+                                context: context.clone(),
+                                problem: VerificationProblemType::SlotDigestError(e),
+                            }),
+                        }
+                    }
+                    (Some(_), None) => problems.push(VerificationProblem {
+                        context: path_context(external_path),
+                        problem: VerificationProblemType::ResourceMissing,
+                    }),
+                    // CodeResources must be bound to the main executable.
+                    (None, Some(_)) if slot == CodeSigningSlot::ResourceDir => {
+                        problems.push(VerificationProblem {
+                            context: context.clone(),
+                            problem: VerificationProblemType::SlotDigestMissing(slot),
+                        })
+                    }
+                    (None, _) => {}
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+/// Verifies files in a bundle against the seals in its `CodeResources`.
+///
+/// `files` should not contain the main executable.
+fn verify_bundle_resources(
+    bundle: &DirectoryBundle,
+    resources: &CodeResources,
+    files: &[apple_bundles::DirectoryBundleFile],
+    nested_bundles: &[(String, DirectoryBundle)],
+    settings: &VerificationSettings,
+) -> Result<Vec<VerificationProblem>, AppleCodesignError> {
+    let mut problems = vec![];
+
+    // Like Apple's tooling, the legacy `<rules>` and `<files>` are only
+    // consulted when the modern `<rules2>` and `<files2>` aren't present.
+    let files2 = resources.sealed_files2();
+    let (mut rules, sealed, legacy) = if files2.is_empty() && resources.rules2()?.is_empty() {
+        (resources.rules()?, resources.sealed_files(), true)
+    } else {
+        (resources.rules2()?, files2, false)
+    };
+
+    // Files written by signing aren't sealed.
+    rules.push(CodeResourcesRule::new("^_CodeSignature/")?.exclude());
+    rules.push(CodeResourcesRule::new("^CodeResources$")?.exclude());
+    rules.sort();
+
+    let mut seen = BTreeSet::new();
+
+    for file in files {
+        let symlink_target = file
+            .symlink_target()
+            .map_err(AppleCodesignError::DirectoryBundle)?;
+
+        let relative_path = match CodeResourcesBuilder::evaluate_rules(
+            &rules,
+            file.relative_path(),
+            symlink_target.clone(),
+        )? {
+            RulesEvaluation::Exclude | RulesEvaluation::Omit | RulesEvaluation::NoRule => {
+                continue;
+            }
+            // The legacy rules can only seal regular files.
+            RulesEvaluation::SealSymlink(..) | RulesEvaluation::SealNested(..) if legacy => {
+                continue;
+            }
+            RulesEvaluation::SealSymlink(path, _)
+            | RulesEvaluation::SealNested(path, _)
+            | RulesEvaluation::SealRegularFile(path, _) => path,
+        };
+
+        let context = path_context(file.absolute_path());
+
+        match sealed.get(&relative_path) {
+            Some(seal) => {
+                problems.extend(verify_sealed_file(
+                    file.absolute_path(),
+                    symlink_target,
+                    seal,
+                    context,
+                    settings,
+                ));
+            }
+            None => problems.push(VerificationProblem {
+                context,
+                problem: VerificationProblemType::ResourceAdded,
+            }),
+        }
+
+        seen.insert(relative_path);
+    }
+
+    // Nested bundles are sealed by the code directory of their main executable.
+    for (relative_path, nested) in nested_bundles {
+        let relative_path = relative_path
+            .strip_prefix("Contents/")
+            .unwrap_or(relative_path);
+
+        if let Some(seal @ SealedResource::Code { .. }) = sealed.get(relative_path) {
+            let context = path_context(nested.root_dir());
+
+            let main_exe = nested
+                .files(false)
+                .map_err(AppleCodesignError::DirectoryBundle)?
+                .into_iter()
+                .find(|file| matches!(file.is_main_executable(), Ok(true)));
+
+            match main_exe {
+                Some(exe) => {
+                    // The nested bundle's main executable is verified when the
+                    // nested bundle is verified. So only check the seal here.
+                    problems.extend(
+                        verify_sealed_file(
+                            exe.absolute_path(),
+                            None,
+                            seal,
+                            context,
+                            &settings.as_nested_settings(),
+                        )
+                        .into_iter()
+                        .filter(|problem| {
+                            matches!(
+                                problem.problem,
+                                VerificationProblemType::NestedCodeError(_)
+                                    | VerificationProblemType::NestedCodeHashMismatch(..)
+                                    | VerificationProblemType::NestedCodeRequirementNotSatisfied(
+                                        ..
+                                    )
+                            )
+                        }),
+                    );
+                }
+                None => problems.push(VerificationProblem {
+                    context,
+                    problem: VerificationProblemType::ResourceKindMismatch,
+                }),
+            }
+
+            seen.insert(relative_path.to_string());
+        }
+    }
+
+    for (path, seal) in sealed {
+        if !seen.contains(&path) && !seal.is_optional() {
+            problems.push(VerificationProblem {
+                context: path_context(&bundle.resolve_path(&path)),
+                problem: VerificationProblemType::ResourceMissing,
+            });
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Verifies a file in a bundle against its seal.
+fn verify_sealed_file(
+    path: &Path,
+    symlink_target: Option<PathBuf>,
+    seal: &SealedResource,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let kind_mismatch = || {
+        vec![VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::ResourceKindMismatch,
+        }]
+    };
+
+    let data = match (seal, symlink_target) {
+        (SealedResource::Symlink(sealed), Some(actual)) => {
+            let actual = actual.to_string_lossy().replace('\\', "/");
+
+            return if &actual == sealed {
+                vec![]
+            } else {
+                vec![VerificationProblem {
+                    context,
+                    problem: VerificationProblemType::ResourceSymlinkMismatch(
+                        sealed.clone(),
+                        actual,
+                    ),
+                }]
+            };
+        }
+        (SealedResource::Symlink(_), None) | (_, Some(_)) => {
+            return kind_mismatch();
+        }
+        (_, None) => match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                return vec![VerificationProblem {
+                    context,
+                    problem: VerificationProblemType::IoError(e),
+                }];
+            }
+        },
+    };
+
+    match seal {
+        SealedResource::RegularFile { digests, .. } => digests
+            .iter()
+            .filter_map(|(digest_type, sealed)| {
+                let problem = match digest_type.digest_data(&data) {
+                    Ok(actual) if &actual == sealed => {
+                        return None;
+                    }
+                    Ok(actual) => VerificationProblemType::ResourceDigestMismatch(
+                        *digest_type,
+                        sealed.clone(),
+                        actual,
+                    ),
+                    Err(e) => VerificationProblemType::SlotDigestError(e),
+                };
+
+                Some(VerificationProblem {
+                    context: context.clone(),
+                    problem,
+                })
+            })
+            .collect::<Vec<_>>(),
+        SealedResource::Code {
+            cdhash,
+            requirement,
+            ..
+        } => verify_nested_code(&data, cdhash, requirement.as_deref(), context, settings),
+        SealedResource::Symlink(_) => unreachable!("symlinks handled above"),
+    }
+}
+
+/// Verifies nested code against its seal.
+fn verify_nested_code(
+    data: &[u8],
+    cdhash: &[u8],
+    requirement: Option<&str>,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let mut problems = verify_macho_data_internal(data, context.clone(), settings);
+
+    let nested_error = |e| VerificationProblem {
+        context: context.clone(),
+        problem: VerificationProblemType::NestedCodeError(e),
+    };
+
+    let mach = match MachFile::parse(data) {
+        Ok(mach) => mach,
+        Err(e) => {
+            problems.push(nested_error(e));
+            return problems;
+        }
+    };
+
+    // Like signing, the seal refers to the first Mach-O in a universal binary.
+    let signature = match mach.nth_macho(0).and_then(|macho| {
+        macho
+            .code_signature()?
+            .ok_or(AppleCodesignError::BinaryNoCodeSignature)
+    }) {
+        Ok(signature) => signature,
+        Err(e) => {
+            problems.push(nested_error(e));
+            return problems;
+        }
+    };
+
+    match signature
+        .preferred_code_directory()
+        .and_then(|cd| cd.digest_with(cd.digest_type))
+    {
+        Ok(mut actual) => {
+            // Seals record the first 20 bytes of the digest. Sealed values of
+            // any other length, including empty ones, never match.
+            actual.truncate(20);
+
+            if cdhash.len() != 20 || actual != cdhash {
+                problems.push(VerificationProblem {
+                    context: context.clone(),
+                    problem: VerificationProblemType::NestedCodeHashMismatch(
+                        cdhash.to_vec(),
+                        actual,
+                    ),
+                });
+            }
+        }
+        Err(e) => {
+            problems.push(nested_error(e));
+        }
+    }
+
+    if let Some(requirement) = requirement {
+        let result = CodeRequirements::parse_str(requirement).and_then(|requirements| {
+            CodeRequirementEvaluator::from_signature(&signature)
+                .map(|evaluator| evaluator.evaluate_requirements(&requirements))
+        });
+
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(failure)) => problems.push(VerificationProblem {
+                context,
+                problem: VerificationProblemType::NestedCodeRequirementNotSatisfied(
+                    requirement.to_string(),
+                    failure,
+                ),
+            }),
+            Err(e) => problems.push(nested_error(e)),
+        }
+    }
+
+    problems
+}
+
+/// Verifies the embedded signature of a DMG.
+///
+/// The code directory's single code digest must cover all content before the
+/// signature and the rep specific slot must match the trailer.
+fn verify_dmg_internal(
+    fh: &mut std::fs::File,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let reader = match DmgReader::new(fh) {
+        Ok(reader) => reader,
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::DmgError(e),
+            }];
+        }
+    };
+
+    let signature = match reader.embedded_signature() {
+        Ok(Some(signature)) => signature,
+        Ok(None) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::DmgNoSignature,
+            }];
+        }
+        Err(e) => {
+            return vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::DmgError(e),
+            }];
+        }
+    };
+
+    let mut problems = vec![];
+
+    let cd = match signature.code_directory() {
+        Ok(Some(cd)) => cd,
+        Ok(None) => {
+            problems.push(VerificationProblem {
+                context,
+                problem: VerificationProblemType::NoCodeDirectory,
+            });
+
+            return problems;
+        }
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context,
+                problem: VerificationProblemType::DmgError(e),
+            });
+
+            return problems;
+        }
+    };
+
+    match cd.digest_type {
+        DigestType::Sha256 | DigestType::Sha384 => {}
+        hash_type => problems.push(VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::CodeDirectoryOldDigestAlgorithm(hash_type),
+        }),
+    }
+
+    // DMGs have a single code digest covering all content before the signature.
+    match reader.digest_content_with(cd.digest_type, fh) {
+        Ok(actual_digest) => {
+            for (index, cd_digest) in cd.code_digests.iter().enumerate() {
+                if index == 0 {
+                    if cd_digest.data != actual_digest.data {
+                        problems.push(VerificationProblem {
+                            context: context.clone(),
+                            problem: VerificationProblemType::CodeDigestMismatch(
+                                index,
+                                cd_digest.to_vec(),
+                                actual_digest.to_vec(),
+                            ),
+                        });
+                    }
+                } else {
+                    problems.push(VerificationProblem {
+                        context: context.clone(),
+                        problem: VerificationProblemType::CodeDigestExtraEntry(
+                            index,
+                            cd_digest.to_vec(),
+                        ),
+                    });
+                }
+            }
+
+            if cd.code_digests.is_empty() {
+                problems.push(VerificationProblem {
+                    context: context.clone(),
+                    problem: VerificationProblemType::CodeDigestMissingEntry(
+                        0,
+                        actual_digest.to_vec(),
+                    ),
+                });
+            }
+        }
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::CodeDigestError(e),
+            });
+        }
+    }
+
+    // The trailer is digested into the rep specific slot.
+    if let Some(cd_digest) = cd.slot_digests().get(&CodeSigningSlot::RepSpecific) {
+        match reader.koly().digest_for_code_directory(cd.digest_type) {
+            Ok(actual_digest) => {
+                if actual_digest != cd_digest.to_vec() {
+                    problems.push(VerificationProblem {
+                        context: context.clone(),
+                        problem: VerificationProblemType::SlotDigestMismatch(
+                            CodeSigningSlot::RepSpecific,
+                            cd_digest.to_vec(),
+                            actual_digest,
+                        ),
+                    });
+                }
+            }
+            Err(e) => {
+                problems.push(VerificationProblem {
+                    context: context.clone(),
+                    problem: VerificationProblemType::SlotDigestError(e),
+                });
+            }
+        }
+    } else {
+        problems.push(VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::SlotDigestMissing(CodeSigningSlot::RepSpecific),
+        });
+    }
+
+    // Other slots with digests must match their blobs.
+    for (slot, cd_digest) in cd.slot_digests().iter() {
+        if *slot == CodeSigningSlot::RepSpecific {
+            continue;
+        }
+
+        if let Some(entry) = signature.find_slot(*slot) {
+            match entry.digest_with(cd.digest_type) {
+                Ok(actual_digest) => {
+                    if actual_digest != cd_digest.to_vec() {
+                        problems.push(VerificationProblem {
+                            context: context.clone(),
+                            problem: VerificationProblemType::SlotDigestMismatch(
+                                *slot,
+                                cd_digest.to_vec(),
+                                actual_digest,
+                            ),
+                        });
+                    }
+                }
+                Err(e) => {
+                    problems.push(VerificationProblem {
+                        context: context.clone(),
+                        problem: VerificationProblemType::SlotDigestError(e),
+                    });
+                }
+            }
+        }
+    }
+
+    match signature.signature_data() {
+        Ok(Some(cms_blob)) => {
+            let cd_data = signature
+                .find_slot(CodeSigningSlot::CodeDirectory)
+                .map(|entry| entry.data);
+
+            problems.extend(verify_cms_signature(
+                cms_blob,
+                cd_data,
+                context.clone(),
+                settings,
+            ));
+        }
+        Ok(None) => problems.push(VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::NoCryptographicSignature,
+        }),
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::DmgError(e),
+            });
+        }
+    }
+
+    if let Some(entry) = signature.find_slot(CodeSigningSlot::Ticket) {
+        let reason = if entry.magic != CodeSigningMagic::BlobWrapper {
+            Some(format!("unexpected blob magic {:?}", entry.magic))
+        } else {
+            match entry.payload() {
+                Ok([]) => Some("ticket is empty".to_string()),
+                Ok(_) => None,
+                Err(e) => Some(e.to_string()),
+            }
+        };
+
+        if let Some(reason) = reason {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::NotarizationTicketInvalid(reason),
+            });
+        }
+    }
+
+    if !settings.requirements.is_empty() {
+        match CodeRequirementEvaluator::from_signature(&signature) {
+            Ok(evaluator) => {
+                problems.extend(evaluate_requirements(
+                    &evaluator,
+                    settings.requirements(),
+                    context,
+                ));
+            }
+            Err(e) => {
+                problems.push(VerificationProblem {
+                    context,
+                    problem: VerificationProblemType::RequirementEvaluationError(e),
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+fn verify_xar_internal<R: Read + Seek + Sized + Debug>(
+    mut xar: XarReader<R>,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let mut problems = vec![];
+
+    match xar.verify_table_of_contents_checksum() {
+        Ok(true) => {}
+        Ok(false) => {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::XarTableOfContentsChecksumMismatch,
+            });
+        }
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::XarError(e.into()),
+            });
+        }
+    }
+
+    let rsa_signature = match xar.rsa_signature() {
+        Ok(signature) => signature,
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::XarError(e.into()),
+            });
+
+            None
+        }
+    };
+
+    let cms_signature = match xar.cms_signature() {
+        Ok(signature) => signature,
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::XarError(e.into()),
+            });
+
+            None
+        }
+    };
+
+    if rsa_signature.is_none() && cms_signature.is_none() {
+        problems.push(VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::XarNoSignature,
+        });
+    }
+
+    let mut rsa_chain = None;
+
+    if let Some((_, certs)) = &rsa_signature {
+        if let Err(e) = xar.verify_rsa_checksum_signature() {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::XarRsaSignatureError(e.into()),
+            });
+        }
+
+        if let Some(cert) = certs.first() {
+            rsa_chain = Some((cert.clone(), certs.clone()));
+        } else {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::NoSigningCertificate,
+            });
+        }
+    }
+
+    let mut cms_chain = None;
+
+    if let Some(signed_data) = &cms_signature {
+        match xar.checksum() {
+            Ok((_, checksum)) => {
+                problems.extend(verify_signed_data(
+                    signed_data,
+                    Some(&checksum),
+                    context.clone(),
+                    settings,
+                ));
+            }
+            Err(e) => {
+                problems.push(VerificationProblem {
+                    context: context.clone(),
+                    problem: VerificationProblemType::XarError(e.into()),
+                });
+            }
+        }
+
+        let certs = signed_data.certificates().cloned().collect::<Vec<_>>();
+
+        cms_chain = signed_data
+            .signers()
+            .next()
+            .and_then(|signer| cms_signer_certificate(signed_data, signer))
+            .map(|cert| (cert.clone(), certs));
+    }
+
+    // Verifying the CMS signature verifies its chain. The RSA signature can
+    // carry different certificates. So verify its chain unless they're the same.
+    if let Some((cert, certs)) = &rsa_chain {
+        let verified = cms_chain.as_ref().map_or(false, |(cms_cert, cms_certs)| {
+            cms_cert == cert
+                && certs.iter().all(|x| cms_certs.contains(x))
+                && cms_certs.iter().all(|x| certs.contains(x))
+        });
+
+        if !verified {
+            problems.extend(verify_certificate_chain(
+                cert,
+                certs,
+                Utc::now(),
+                context.clone(),
+                settings,
+            ));
+        }
+    }
+
+    let signing_chain = rsa_chain.or(cms_chain);
+
+    if !settings.requirements.is_empty() {
+        let mut evaluator = CodeRequirementEvaluator::default();
+
+        if let Some((cert, certs)) = &signing_chain {
+            evaluator.set_signing_certificate(cert, certs.iter());
+        }
+
+        problems.extend(evaluate_requirements(
+            &evaluator,
+            settings.requirements(),
+            context.clone(),
+        ));
+    }
+
+    let files = match xar.files() {
+        Ok(files) => files,
+        Err(e) => {
+            problems.push(VerificationProblem {
+                context,
+                problem: VerificationProblemType::XarError(e.into()),
+            });
+
+            return problems;
+        }
+    };
+
+    for (member_path, file) in files {
+        let data = if let Some(data) = &file.data {
+            data
+        } else {
+            continue;
+        };
+
+        let member_context = VerificationContext {
+            path: context.path.as_ref().map(|path| path.join(&member_path)),
+            ..context.clone()
+        };
+
+        // Digest data as it is read so large members aren't buffered in memory.
+        match data
+            .archived_checksum
+            .style
+            .digest_writer()
+            .and_then(|mut writer| {
+                xar.write_file_data_heap_from_file(&file, &mut writer)?;
+                Ok(writer.finish())
+            }) {
+            Ok(digest) => {
+                let digest = hex::encode(digest);
+
+                if !data
+                    .archived_checksum
+                    .checksum
+                    .eq_ignore_ascii_case(&digest)
+                {
+                    problems.push(VerificationProblem {
+                        context: member_context.clone(),
+                        problem: VerificationProblemType::XarFileArchivedChecksumMismatch(
+                            data.archived_checksum.checksum.clone(),
+                            digest,
+                        ),
+                    });
+
+                    // Decoding corrupted data will likely fail. So don't bother.
+                    continue;
+                }
+            }
+            Err(e) => {
+                problems.push(VerificationProblem {
+                    context: member_context,
+                    problem: VerificationProblemType::XarError(e.into()),
+                });
+
+                continue;
+            }
+        }
+
+        match data
+            .extracted_checksum
+            .style
+            .digest_writer()
+            .and_then(|mut writer| {
+                xar.write_file_data_decoded_from_file(&file, &mut writer)?;
+                Ok(writer.finish())
+            }) {
+            Ok(digest) => {
+                let digest = hex::encode(digest);
+
+                if !data
+                    .extracted_checksum
+                    .checksum
+                    .eq_ignore_ascii_case(&digest)
+                {
+                    problems.push(VerificationProblem {
+                        context: member_context,
+                        problem: VerificationProblemType::XarFileExtractedChecksumMismatch(
+                            data.extracted_checksum.checksum.clone(),
+                            digest,
+                        ),
+                    });
+                }
+            }
+            Err(e) => {
+                problems.push(VerificationProblem {
+                    context: member_context,
+                    problem: VerificationProblemType::XarError(e.into()),
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+/// Verifies a CMS signature.
+///
+/// `signed_content` is the content the signature is over, if known.
+fn verify_cms_signature(
+    data: &[u8],
+    signed_content: Option<&[u8]>,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    match SignedData::parse_ber(data) {
+        Ok(signed_data) => verify_signed_data(&signed_data, signed_content, context, settings),
+        Err(e) => {
+            vec![VerificationProblem {
+                context,
+                problem: VerificationProblemType::CmsError(e),
+            }]
+        }
+    }
+}
+
+fn verify_signed_data(
+    signed_data: &SignedData,
+    signed_content: Option<&[u8]>,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let mut problems = vec![];
+
+    for signer in signed_data.signers() {
+        match signer.digest_algorithm() {
+            DigestAlgorithm::Sha1 => {
+                problems.push(VerificationProblem {
+                    context: context.clone(),
+                    problem: VerificationProblemType::CmsOldDigestAlgorithm(
+                        signer.digest_algorithm(),
+                    ),
+                });
+            }
+            DigestAlgorithm::Sha384 => {}
+            DigestAlgorithm::Sha256 => {}
+            DigestAlgorithm::Sha512 => {}
         }
 
         match signer.signature_algorithm() {
@@ -382,26 +2168,477 @@ fn verify_cms_signature(data: &[u8], context: VerificationContext) -> Vec<Verifi
             }
         }
 
-        match signer.verify_signature_with_signed_data(&signed_data) {
-            Ok(()) => {}
-            Err(e) => {
-                problems.push(VerificationProblem {
-                    context: context.clone(),
-                    problem: VerificationProblemType::CmsError(e),
-                });
+        match signer.verify_signature_with_signed_data(signed_data) {
+            Ok(()) => {}
+            Err(e) => {
+                problems.push(VerificationProblem {
+                    context: context.clone(),
+                    problem: VerificationProblemType::CmsError(e),
+                });
+            }
+        }
+
+        if let Some(content) = signed_content {
+            if let Err(e) = signer.verify_message_digest_with_content(content) {
+                problems.push(VerificationProblem {
+                    context: context.clone(),
+                    problem: VerificationProblemType::CmsError(e),
+                });
+            }
+        }
+
+        problems.extend(verify_signer_certificate_chain(
+            signed_data,
+            signer,
+            context.clone(),
+            settings,
+        ));
+
+        // TODO verify key length meets standards.
+    }
+
+    problems
+}
+
+/// Decode the `TSTInfo` content of a time-stamp token.
+pub(crate) fn time_stamp_token_info(token: &SignedData) -> Option<TstInfo> {
+    token
+        .signed_content()
+        .and_then(|content| Constructed::decode(content, Mode::Der, TstInfo::take_from).ok())
+}
+
+/// Verify a time-stamp token attesting to a CMS signature.
+///
+/// The token's message imprint must be a digest of `signature`, the token's
+/// signature must verify, and its signing certificate must carry the critical
+/// time stamping extended key usage and chain to Apple's time-stamp authority
+/// or to a trust anchor in `settings`.
+///
+/// Returns the time attested to by the token if no problems were found.
+fn verify_time_stamp_token(
+    signature: &[u8],
+    token: &SignedData,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> (Option<DateTime<Utc>>, Vec<VerificationProblem>) {
+    let mut problems = vec![];
+
+    let tst_info = match time_stamp_token_info(token) {
+        Some(tst_info) if token.signers().next().is_some() => tst_info,
+        _ => {
+            problems.push(VerificationProblem {
+                context,
+                problem: VerificationProblemType::TimeStampTokenMalformed,
+            });
+            return (None, problems);
+        }
+    };
+
+    let time: DateTime<Utc> = tst_info.gen_time.into();
+
+    let imprint = &tst_info.message_imprint;
+    let imprint_matches = DigestAlgorithm::try_from(&imprint.hash_algorithm)
+        .map(|alg| alg.digest_data(signature) == imprint.hashed_message.to_bytes().as_ref())
+        .unwrap_or_default();
+
+    if !imprint_matches {
+        problems.push(VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::TimeStampImprintMismatch,
+        });
+    }
+
+    let certificates = token.certificates().cloned().collect::<Vec<_>>();
+
+    for signer in token.signers() {
+        if let Err(e) = signer
+            .verify_signature_with_signed_data(token)
+            .and_then(|_| signer.verify_message_digest_with_signed_data(token))
+        {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::TimeStampTokenError(e),
+            });
+        }
+
+        match cms_signer_certificate(token, signer) {
+            Some(cert) => {
+                let chain = resolve_certificate_chain(cert, &certificates, settings);
+                let anchor = chain
+                    .last()
+                    .expect("chain should have at least 1 certificate");
+
+                // Apple's time-stamp authority is issued by the Apple Timestamp
+                // CA, which chains to an Apple root.
+                let apple_tsa = anchor.is_apple_root_ca()
+                    && chain
+                        .iter()
+                        .any(|c| c == KnownCertificate::Timestamp.as_ref());
+
+                if !apple_tsa && !chain.iter().any(|c| settings.trust_anchors.contains(c)) {
+                    problems.push(VerificationProblem {
+                        context: context.clone(),
+                        problem: VerificationProblemType::TimeStampUntrusted(certificate_subject(
+                            anchor,
+                        )),
+                    });
+                }
+
+                problems.extend(verify_certificate_validity(&chain, time, context.clone()));
+                problems.extend(verify_certificate_issuers(&chain, context.clone()));
+
+                // RFC 3161 requires this. Otherwise any certificate under a trust
+                // anchor could attest to arbitrary times.
+                if !is_time_stamping_certificate(cert) {
+                    problems.push(VerificationProblem {
+                        context: context.clone(),
+                        problem: VerificationProblemType::CertificateMisprofiled(
+                            certificate_subject(cert),
+                            "extended key usage does not allow time stamping".to_string(),
+                        ),
+                    });
+                }
+            }
+            None => problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::NoSigningCertificate,
+            }),
+        }
+    }
+
+    if problems.is_empty() {
+        (Some(time), problems)
+    } else {
+        (None, problems)
+    }
+}
+
+/// Resolve the certificate chain for a signing certificate.
+///
+/// The chain starts with the signing certificate. It ends at the first trust
+/// anchor or self-signed certificate or when no issuer can be found.
+fn resolve_certificate_chain(
+    cert: &CapturedX509Certificate,
+    certificates: &[CapturedX509Certificate],
+    settings: &VerificationSettings,
+) -> Vec<CapturedX509Certificate> {
+    let is_anchor = |cert: &CapturedX509Certificate| {
+        cert.is_apple_root_ca() || settings.trust_anchors.contains(cert)
+    };
+
+    let mut chain = vec![cert.clone()];
+
+    if is_anchor(cert) || cert.subject_is_issuer() {
+        return chain;
+    }
+
+    let candidates = certificates
+        .iter()
+        .chain(KnownCertificate::all().iter().copied())
+        .chain(settings.trust_anchors.iter());
+
+    for issuer in cert.resolve_signing_chain(candidates) {
+        chain.push(issuer.clone());
+
+        if is_anchor(issuer) || issuer.subject_is_issuer() {
+            break;
+        }
+    }
+
+    chain
+}
+
+/// Obtain the OIDs in an extended key usage extension, if present.
+fn extended_key_usages(cert: &CapturedX509Certificate) -> Option<Vec<Oid>> {
+    let ext = cert
+        .iter_extensions()
+        .find(|ext| ext.id.as_ref() == OID_EXTENDED_KEY_USAGE.as_ref())?;
+
+    Constructed::decode(ext.value.to_bytes(), Mode::Der, |cons| {
+        cons.take_sequence(|cons| {
+            let mut oids = vec![];
+            while let Some(oid) = Oid::take_opt_from(cons)? {
+                oids.push(oid);
             }
+
+            Ok(oids)
+        })
+    })
+    .ok()
+}
+
+/// Whether a certificate has the critical time stamping extended key usage.
+fn is_time_stamping_certificate(cert: &CapturedX509Certificate) -> bool {
+    let critical = cert.iter_extensions().any(|ext| {
+        ext.id.as_ref() == OID_EXTENDED_KEY_USAGE.as_ref() && ext.critical == Some(true)
+    });
+
+    critical
+        && extended_key_usages(cert)
+            .unwrap_or_default()
+            .iter()
+            .any(|oid| oid.as_ref() == OID_EKU_TIME_STAMPING.as_ref())
+}
+
+/// Whether the key usage extension, if present, has the given usage bit set.
+fn key_usage_allows(cert: &CapturedX509Certificate, bit: usize) -> bool {
+    match cert
+        .iter_extensions()
+        .find(|ext| ext.id.as_ref() == OID_KEY_USAGE.as_ref())
+    {
+        Some(ext) => Constructed::decode(ext.value.to_bytes(), Mode::Der, BitString::take_from)
+            .map(|bits| bits.bit(bit))
+            .unwrap_or_default(),
+        None => true,
+    }
+}
+
+/// Whether the basic constraints extension marks a certificate as a CA.
+fn is_certificate_authority(cert: &CapturedX509Certificate) -> bool {
+    cert.iter_extensions()
+        .find(|ext| ext.id.as_ref() == OID_BASIC_CONSTRAINTS.as_ref())
+        .and_then(|ext| {
+            Constructed::decode(ext.value.to_bytes(), Mode::Der, |cons| {
+                cons.take_sequence(|cons| {
+                    let ca = cons.take_opt_bool()?.unwrap_or_default();
+                    cons.skip_all()?;
+
+                    Ok(ca)
+                })
+            })
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+/// Verifies that every issuer in a chain may issue certificates.
+///
+/// Otherwise any certificate, such as a code signing leaf, could mint
+/// certificates chaining to its anchor.
+fn verify_certificate_issuers(
+    chain: &[CapturedX509Certificate],
+    context: VerificationContext,
+) -> Vec<VerificationProblem> {
+    chain
+        .iter()
+        .skip(1)
+        .filter_map(|issuer| {
+            let reason = if !is_certificate_authority(issuer) {
+                "issuer is not a certificate authority"
+            } else if !key_usage_allows(issuer, KEY_USAGE_KEY_CERT_SIGN) {
+                "issuer key usage does not allow signing certificates"
+            } else {
+                return None;
+            };
+
+            Some(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::CertificateMisprofiled(
+                    certificate_subject(issuer),
+                    reason.to_string(),
+                ),
+            })
+        })
+        .collect()
+}
+
+fn verify_signer_certificate_chain(
+    signed_data: &SignedData,
+    signer: &SignerInfo,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let (time_stamp_time, mut problems) = match signer.time_stamp_token_signed_data() {
+        Ok(Some(token)) => {
+            verify_time_stamp_token(signer.signature(), &token, context.clone(), settings)
         }
+        Ok(None) => (None, vec![]),
+        Err(e) => (
+            None,
+            vec![VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::TimeStampTokenError(e),
+            }],
+        ),
+    };
 
-        // TODO verify key length meets standards.
-        // TODO verify CA chain is fully present.
-        // TODO verify signing cert chains to Apple?
+    // Certificates must be valid at the time attested to by a valid time-stamp
+    // token, falling back to the signing time attribute and finally the current
+    // time.
+    let time = time_stamp_time
+        .or_else(|| {
+            signer
+                .signed_attributes()
+                .and_then(|attrs| attrs.signing_time().copied())
+        })
+        .unwrap_or_else(Utc::now);
+
+    match cms_signer_certificate(signed_data, signer) {
+        Some(cert) => problems.extend(verify_certificate_chain(
+            cert,
+            &signed_data.certificates().cloned().collect::<Vec<_>>(),
+            time,
+            context,
+            settings,
+        )),
+        None => problems.push(VerificationProblem {
+            context,
+            problem: VerificationProblemType::NoSigningCertificate,
+        }),
+    }
+
+    problems
+}
+
+/// Obtain a human readable subject name of a certificate.
+fn certificate_subject(cert: &CapturedX509Certificate) -> String {
+    cert.subject_name()
+        .user_friendly_str()
+        .unwrap_or_else(|_| "<unknown>".into())
+}
+
+/// Verifies that all certificates in a chain are valid at `time`.
+fn verify_certificate_validity(
+    chain: &[CapturedX509Certificate],
+    time: DateTime<Utc>,
+    context: VerificationContext,
+) -> Vec<VerificationProblem> {
+    let mut problems = vec![];
+
+    for cert in chain {
+        let certificate: &x509_certificate::rfc5280::Certificate = cert.as_ref();
+        let validity = &certificate.tbs_certificate.validity;
+        let not_before = asn1_time_to_datetime(&validity.not_before);
+        let not_after = asn1_time_to_datetime(&validity.not_after);
+
+        if time < not_before {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::CertificateNotYetValid(
+                    certificate_subject(cert),
+                    not_before,
+                    time,
+                ),
+            });
+        } else if time > not_after {
+            problems.push(VerificationProblem {
+                context: context.clone(),
+                problem: VerificationProblemType::CertificateExpired(
+                    certificate_subject(cert),
+                    not_after,
+                    time,
+                ),
+            });
+        }
+    }
+
+    problems
+}
+
+/// Verifies the certificate chain of a signing certificate.
+///
+/// `certificates` are additional certificates that may be part of the chain.
+/// Certificates must be valid at `time`.
+fn verify_certificate_chain(
+    cert: &CapturedX509Certificate,
+    certificates: &[CapturedX509Certificate],
+    time: DateTime<Utc>,
+    context: VerificationContext,
+    settings: &VerificationSettings,
+) -> Vec<VerificationProblem> {
+    let mut problems = vec![];
+
+    let chain = resolve_certificate_chain(cert, certificates, settings);
+    let anchor = chain
+        .last()
+        .expect("chain should have at least 1 certificate");
+
+    let apple_anchored = anchor.is_apple_root_ca();
+
+    if !apple_anchored && !chain.iter().any(|c| settings.trust_anchors.contains(c)) {
+        problems.push(VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::CertificateUntrusted(certificate_subject(anchor)),
+        });
+    }
+
+    problems.extend(verify_certificate_validity(&chain, time, context.clone()));
+    problems.extend(verify_certificate_issuers(&chain, context.clone()));
+
+    let mut misprofiled = |reason: &str| {
+        problems.push(VerificationProblem {
+            context: context.clone(),
+            problem: VerificationProblemType::CertificateMisprofiled(
+                certificate_subject(cert),
+                reason.to_string(),
+            ),
+        });
+    };
+
+    if !key_usage_allows(cert, KEY_USAGE_DIGITAL_SIGNATURE) {
+        misprofiled("key usage does not allow digital signatures");
+    }
+
+    if let Some(ekus) = extended_key_usages(cert) {
+        if !ekus.iter().any(|oid| {
+            oid.as_ref() == OID_EKU_ANY.as_ref() || ExtendedKeyUsagePurpose::try_from(oid).is_ok()
+        }) {
+            misprofiled("extended key usage does not allow code signing");
+        }
+    }
+
+    // Certificates issued by Apple advertise what they can sign via Apple's
+    // certificate extensions.
+    if apple_anchored
+        && chain.len() > 1
+        && cert.apple_code_signing_extensions().is_empty()
+        && cert.apple_extended_key_usage_purposes().is_empty()
+    {
+        misprofiled("Apple issued certificate lacks Apple code signing extensions");
     }
 
     problems
 }
 
+fn verify_requirements(
+    code: SignedCode,
+    signature: &EmbeddedSignature,
+    requirements: &[CodeRequirementExpression],
+    context: VerificationContext,
+) -> Vec<VerificationProblem> {
+    match code.requirements_evaluator(signature) {
+        Ok(evaluator) => evaluate_requirements(&evaluator, requirements, context),
+        Err(e) => vec![VerificationProblem {
+            context,
+            problem: VerificationProblemType::RequirementEvaluationError(e),
+        }],
+    }
+}
+
+fn evaluate_requirements(
+    evaluator: &CodeRequirementEvaluator,
+    requirements: &[CodeRequirementExpression],
+    context: VerificationContext,
+) -> Vec<VerificationProblem> {
+    requirements
+        .iter()
+        .filter_map(|requirement| {
+            evaluator
+                .evaluate(requirement)
+                .err()
+                .map(|failure| VerificationProblem {
+                    context: context.clone(),
+                    problem: VerificationProblemType::RequirementNotSatisfied(
+                        requirement.to_string(),
+                        failure,
+                    ),
+                })
+        })
+        .collect::<Vec<_>>()
+}
+
 fn verify_code_directory(
-    macho: &MachOBinary,
+    code: SignedCode,
     signature: &EmbeddedSignature,
     cd: &CodeDirectoryBlob,
     context: VerificationContext,
@@ -416,7 +2653,7 @@ fn verify_code_directory(
         }),
     }
 
-    match macho.code_digests(cd.digest_type, cd.page_size as _) {
+    match code.code_digests(cd.digest_type, cd.page_size as _) {
         Ok(digests) => {
             let mut cd_iter = cd.code_digests.iter().enumerate();
             let mut actual_iter = digests.iter().enumerate();
@@ -545,3 +2782,760 @@ fn verify_code_directory(
 
     problems
 }
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            bundle_signing::BundleSigner,
+            certificate::{AppleCertificateBuilder, CertificateProfile},
+            create_self_signed_code_signing_certificate,
+            dmg::DmgSigner,
+            signing::UnifiedSigner,
+            signing_settings::{SettingsScope, SigningSettings},
+            testutil::{
+                ca_certificate, dmg_data, issue_certificate, macho_data, rsa_signing_identity,
+                time_stamp_authority_certificate, xar_data,
+            },
+            zip_signing::ZipSigner,
+        },
+        bcder::{decode::SliceSource, encode::Values, OctetString},
+        cryptographic_message_syntax::{
+            asn1::{
+                rfc3161::{MessageImprint, OID_CONTENT_TYPE_TST_INFO},
+                rfc5652::OID_ID_DATA,
+            },
+            SignedDataBuilder, SignerBuilder,
+        },
+        scroll::Pwrite,
+        signature::Signer,
+        std::io::{Cursor, Read, SeekFrom, Write},
+        x509_certificate::{
+            asn1time::{GeneralizedTime, GeneralizedTimeAllowedTimezone},
+            InMemorySigningKeyPair, KeyAlgorithm, X509CertificateBuilder, X509CertificateError,
+        },
+    };
+
+    fn signed_data(validity: chrono::Duration) -> (CapturedX509Certificate, SignedData) {
+        let (cert, signing_key, _) = create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::DeveloperIdApplication,
+            "team",
+            "Joe Developer",
+            "US",
+            validity,
+        )
+        .unwrap();
+
+        let cms = SignedDataBuilder::default()
+            .certificate(cert.clone())
+            .content_inline(b"hello, world".to_vec())
+            .signer(SignerBuilder::new(&signing_key, cert.clone()))
+            .build_der()
+            .unwrap();
+
+        (cert, SignedData::parse_ber(&cms).unwrap())
+    }
+
+    fn chain_problems(
+        signed_data: &SignedData,
+        settings: &VerificationSettings,
+    ) -> Vec<VerificationProblemType> {
+        signed_data
+            .signers()
+            .flat_map(|signer| {
+                verify_signer_certificate_chain(
+                    signed_data,
+                    signer,
+                    VerificationContext {
+                        path: None,
+                        fat_index: None,
+                    },
+                    settings,
+                )
+            })
+            .map(|problem| problem.problem)
+            .collect()
+    }
+
+    #[test]
+    fn self_signed_untrusted() {
+        let (_, signed_data) = signed_data(chrono::Duration::hours(1));
+
+        let problems = chain_problems(&signed_data, &VerificationSettings::default());
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0],
+            VerificationProblemType::CertificateUntrusted(_)
+        ));
+    }
+
+    #[test]
+    fn self_signed_trust_anchor() {
+        let (cert, signed_data) = signed_data(chrono::Duration::hours(1));
+
+        let mut settings = VerificationSettings::default();
+        settings.add_trust_anchor(cert);
+
+        assert!(chain_problems(&signed_data, &settings).is_empty());
+    }
+
+    #[test]
+    fn expired_certificate() {
+        let (cert, signed_data) = signed_data(chrono::Duration::seconds(-60));
+
+        let mut settings = VerificationSettings::default();
+        settings.add_trust_anchor(cert);
+
+        let problems = chain_problems(&signed_data, &settings);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0],
+            VerificationProblemType::CertificateExpired(..)
+        ));
+    }
+
+    #[test]
+    fn issuer_must_be_certificate_authority() {
+        let (ca, ca_key) = ca_certificate("Test CA");
+
+        let builder = |name: &str| {
+            let mut builder = X509CertificateBuilder::new(KeyAlgorithm::Ed25519);
+            builder
+                .apple_certificate_profile(CertificateProfile::DeveloperIdApplication)
+                .unwrap();
+            builder.apple_subject("team", name, "US").unwrap();
+            builder.validity_duration(chrono::Duration::hours(1));
+            builder
+        };
+
+        let (leaf, leaf_key) = issue_certificate(&builder("Joe Developer"), &ca, &ca_key);
+        // A code signing certificate must not be usable as an issuer.
+        let (forged, forged_key) = issue_certificate(&builder("Mallory"), &leaf, &leaf_key);
+
+        let mut settings = VerificationSettings::default();
+        settings.add_trust_anchor(ca);
+
+        let signed_data = |certs: &[&CapturedX509Certificate],
+                           key,
+                           cert: &CapturedX509Certificate| {
+            let mut builder = SignedDataBuilder::default().content_inline(b"hello, world".to_vec());
+            for cert in certs {
+                builder = builder.certificate((*cert).clone());
+            }
+
+            SignedData::parse_ber(
+                &builder
+                    .signer(SignerBuilder::new(key, cert.clone()))
+                    .build_der()
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+
+        assert!(chain_problems(&signed_data(&[&leaf], &leaf_key, &leaf), &settings).is_empty());
+
+        let problems = chain_problems(
+            &signed_data(&[&forged, &leaf], &forged_key, &forged),
+            &settings,
+        );
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            &problems[0],
+            VerificationProblemType::CertificateMisprofiled(subject, _)
+                if subject == &certificate_subject(&leaf)
+        ));
+    }
+
+    fn time_stamp_token(signature: &[u8]) -> (CapturedX509Certificate, SignedData) {
+        let (cert, signing_key) = time_stamp_authority_certificate();
+
+        (
+            cert.clone(),
+            time_stamp_token_signed_by(signature, &cert, &signing_key),
+        )
+    }
+
+    fn time_stamp_token_signed_by(
+        signature: &[u8],
+        cert: &CapturedX509Certificate,
+        signing_key: &InMemorySigningKeyPair,
+    ) -> SignedData {
+        let gen_time = Utc::now().format("%Y%m%d%H%M%SZ").to_string();
+
+        let tst_info = TstInfo {
+            version: 1u8.into(),
+            policy: Oid(bytes::Bytes::from_static(&[43, 6, 1, 4, 1, 0])),
+            message_imprint: MessageImprint {
+                hash_algorithm: DigestAlgorithm::Sha256.into(),
+                hashed_message: OctetString::new(
+                    DigestAlgorithm::Sha256.digest_data(signature).into(),
+                ),
+            },
+            serial_number: 1u8.into(),
+            gen_time: GeneralizedTime::parse(
+                SliceSource::new(gen_time.as_bytes()),
+                false,
+                GeneralizedTimeAllowedTimezone::Z,
+            )
+            .unwrap(),
+            accuracy: None,
+            ordering: None,
+            nonce: None,
+            tsa: None,
+            extensions: None,
+        };
+
+        let mut content = vec![];
+        tst_info
+            .encode_ref()
+            .write_encoded(Mode::Der, &mut content)
+            .unwrap();
+
+        let cms = SignedDataBuilder::default()
+            .certificate(cert.clone())
+            .content_inline(content)
+            .content_type(Oid(OID_CONTENT_TYPE_TST_INFO.as_ref().into()))
+            .signer(SignerBuilder::new(signing_key, cert.clone()))
+            .build_der()
+            .unwrap();
+
+        SignedData::parse_ber(&cms).unwrap()
+    }
+
+    fn time_stamp_problems(
+        signature: &[u8],
+        token: &SignedData,
+        settings: &VerificationSettings,
+    ) -> (Option<DateTime<Utc>>, Vec<VerificationProblemType>) {
+        let (time, problems) = verify_time_stamp_token(
+            signature,
+            token,
+            VerificationContext {
+                path: None,
+                fat_index: None,
+            },
+            settings,
+        );
+
+        (
+            time,
+            problems
+                .into_iter()
+                .map(|problem| problem.problem)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn time_stamp_token_trusted() {
+        let (cert, token) = time_stamp_token(b"signature");
+
+        let mut settings = VerificationSettings::default();
+        settings.add_trust_anchor(cert);
+
+        let (time, problems) = time_stamp_problems(b"signature", &token, &settings);
+        assert!(problems.is_empty());
+        assert_eq!(
+            time,
+            time_stamp_token_info(&token).map(|info| info.gen_time.into())
+        );
+    }
+
+    #[test]
+    fn time_stamp_token_untrusted() {
+        let (_, token) = time_stamp_token(b"signature");
+
+        let (time, problems) =
+            time_stamp_problems(b"signature", &token, &VerificationSettings::default());
+        assert!(time.is_none());
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0],
+            VerificationProblemType::TimeStampUntrusted(_)
+        ));
+    }
+
+    #[test]
+    fn time_stamp_token_imprint_mismatch() {
+        let (cert, token) = time_stamp_token(b"signature");
+
+        let mut settings = VerificationSettings::default();
+        settings.add_trust_anchor(cert);
+
+        let (time, problems) = time_stamp_problems(b"other signature", &token, &settings);
+        assert!(time.is_none());
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0],
+            VerificationProblemType::TimeStampImprintMismatch
+        ));
+    }
+
+    #[test]
+    fn time_stamp_token_not_time_stamping_certificate() {
+        // Code signing certificates can't attest to times, even when trusted.
+        let (cert, signing_key, _) = create_self_signed_code_signing_certificate(
+            KeyAlgorithm::Ed25519,
+            CertificateProfile::DeveloperIdApplication,
+            "team",
+            "Time Stamp Authority",
+            "US",
+            chrono::Duration::hours(1),
+        )
+        .unwrap();
+        let token = time_stamp_token_signed_by(b"signature", &cert, &signing_key);
+
+        let mut settings = VerificationSettings::default();
+        settings.add_trust_anchor(cert);
+
+        let (time, problems) = time_stamp_problems(b"signature", &token, &settings);
+        assert!(time.is_none());
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0],
+            VerificationProblemType::CertificateMisprofiled(..)
+        ));
+    }
+
+    const RESOURCE_BUNDLE_INFO_PLIST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>CFBundleIdentifier</key>
+  <string>com.example.resources</string>
+  <key>CFBundlePackageType</key>
+  <string>BNDL</string>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn bundle_resources() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("source.bundle");
+        let signed = temp_dir.path().join("signed.bundle");
+
+        std::fs::create_dir_all(source.join("Contents/Resources"))?;
+        std::fs::write(
+            source.join("Contents/Info.plist"),
+            RESOURCE_BUNDLE_INFO_PLIST,
+        )?;
+        std::fs::write(source.join("Contents/Resources/a.txt"), b"a")?;
+        std::fs::write(source.join("Contents/Resources/b.txt"), b"b")?;
+        simple_file_manifest::create_symlink(source.join("Contents/Resources/link"), "a.txt")?;
+
+        BundleSigner::new_from_path(&source)?
+            .write_signed_bundle(&signed, &SigningSettings::default())?;
+
+        assert!(verify_path(&signed).is_empty());
+
+        std::fs::write(signed.join("Contents/Resources/a.txt"), b"modified")?;
+        std::fs::remove_file(signed.join("Contents/Resources/b.txt"))?;
+        std::fs::write(signed.join("Contents/Resources/c.txt"), b"c")?;
+        std::fs::remove_file(signed.join("Contents/Resources/link"))?;
+        simple_file_manifest::create_symlink(signed.join("Contents/Resources/link"), "c.txt")?;
+
+        let problems = verify_path(&signed)
+            .into_iter()
+            .map(|problem| {
+                let path = problem.context.path.expect("path should be set");
+                let path = path.strip_prefix(&signed).unwrap().to_string_lossy();
+
+                (path.replace('\\', "/"), problem.problem)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(problems.len(), 4);
+        assert!(problems
+            .iter()
+            .any(|(path, problem)| path == "Contents/Resources/a.txt"
+                && matches!(problem, VerificationProblemType::ResourceDigestMismatch(..))));
+        assert!(problems
+            .iter()
+            .any(|(path, problem)| path == "Contents/Resources/b.txt"
+                && matches!(problem, VerificationProblemType::ResourceMissing)));
+        assert!(problems
+            .iter()
+            .any(|(path, problem)| path == "Contents/Resources/c.txt"
+                && matches!(problem, VerificationProblemType::ResourceAdded)));
+        assert!(problems
+            .iter()
+            .any(|(path, problem)| path == "Contents/Resources/link"
+                && matches!(
+                    problem,
+                    VerificationProblemType::ResourceSymlinkMismatch(..)
+                )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_code_cdhash() -> Result<(), AppleCodesignError> {
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "com.example.test");
+
+        let mut signed = vec![];
+        crate::macho_signing::MachOSigner::new(&macho_data(0x4000, &[]))?
+            .write_signed_binary(&settings, &mut signed)?;
+
+        let cdhash = MachFile::parse(&signed)?
+            .nth_macho(0)?
+            .code_signature()?
+            .expect("should be signed")
+            .code_directory()?
+            .expect("should have code directory")
+            .digest_with(DigestType::Sha256Truncated)?;
+        assert_eq!(cdhash.len(), 20);
+
+        let problems = |cdhash: &[u8]| {
+            verify_nested_code(
+                &signed,
+                cdhash,
+                None,
+                VerificationContext {
+                    path: None,
+                    fat_index: None,
+                },
+                &VerificationSettings::default(),
+            )
+            .into_iter()
+            .filter(|problem| {
+                matches!(
+                    problem.problem,
+                    VerificationProblemType::NestedCodeHashMismatch(..)
+                )
+            })
+            .count()
+        };
+
+        assert_eq!(problems(&cdhash), 0);
+        // Empty and truncated seals must not match a prefix of the digest.
+        assert_eq!(problems(&[]), 1);
+        assert_eq!(problems(&cdhash[0..8]), 1);
+
+        let mut other = cdhash.clone();
+        other[19] ^= 0xff;
+        assert_eq!(problems(&other), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn detached_signature_blob() -> Result<(), AppleCodesignError> {
+        let requirements = crate::embedded_signature::RequirementSetBlob::default();
+        let superblob = crate::embedded_signature::create_superblob(
+            CodeSigningMagic::EmbeddedSignature,
+            [(
+                CodeSigningSlot::RequirementSet,
+                requirements.to_blob_bytes()?,
+            )]
+            .iter(),
+        )?;
+
+        let blob = DetachedSignatureBlob::from_signatures([
+            (0x0100_0007, superblob.clone()),
+            (0x0100_000c, superblob.clone()),
+        ])?;
+        let data = blob.to_blob_bytes()?;
+
+        let blob = DetachedSignatureBlob::from_blob_bytes(&data)?;
+        let signatures = blob.signatures()?;
+        assert_eq!(
+            signatures
+                .iter()
+                .map(|(cpu_type, signature)| (*cpu_type, signature.data))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x0100_0007, superblob.as_slice()),
+                (0x0100_000c, superblob.as_slice())
+            ]
+        );
+        assert!(blob.signature_for_cpu_type(0x0100_000c)?.is_some());
+        assert!(blob.signature_for_cpu_type(7)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_bundle() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("test.zip");
+
+        let mut zf = zip::ZipWriter::new(std::fs::File::create(&path)?);
+        zf.start_file(
+            "Payload/test.bundle/Contents/Info.plist",
+            Default::default(),
+        )?;
+        zf.write_all(RESOURCE_BUNDLE_INFO_PLIST.as_bytes())?;
+        zf.start_file(
+            "Payload/test.bundle/Contents/Resources/a.txt",
+            Default::default(),
+        )?;
+        zf.write_all(b"a")?;
+        zf.finish()?;
+
+        let problems = verify_path(&path);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(
+            problems[0].context.path,
+            Some(path.join("Payload/test.bundle/Contents/_CodeSignature/CodeResources"))
+        );
+        assert!(matches!(
+            problems[0].problem,
+            VerificationProblemType::NoCodeResources
+        ));
+
+        ZipSigner::new_from_path(&path)?.write_signed_zip(&path, &SigningSettings::default())?;
+        assert!(verify_path(&path).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn dmg_digests() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = temp_dir.path().join("test.dmg");
+
+        std::fs::write(&path, dmg_data())?;
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(crate::SettingsScope::Main, "com.example.dmg");
+
+        let mut fh = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .open(&path)?;
+        DmgSigner::default().sign_file(&settings, &mut fh)?;
+
+        // Ad-hoc signatures have no CMS signature but are otherwise sound.
+        let problems = verify_path(&path);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0].problem,
+            VerificationProblemType::NoCryptographicSignature
+        ));
+
+        fh.seek(SeekFrom::Start(100))?;
+        fh.write_all(b"modified")?;
+
+        let problems = verify_dmg(&path);
+        assert_eq!(problems.len(), 2);
+        assert!(problems.iter().any(|p| matches!(
+            p.problem,
+            VerificationProblemType::CodeDigestMismatch(0, ..)
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn xar_problems() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let unsigned_path = td.path().join("unsigned.pkg");
+        let signed_path = td.path().join("signed.pkg");
+        let path = td.path().join("test.pkg");
+
+        std::fs::write(
+            &unsigned_path,
+            xar_data(&[
+                ("Distribution", b"distribution"),
+                ("Payload", &b"payload".repeat(1000)),
+            ]),
+        )?;
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert.clone());
+        UnifiedSigner::new(settings).sign_xar(&unsigned_path, &signed_path)?;
+        let signed = std::fs::read(&signed_path)?;
+
+        let mut verify_settings = VerificationSettings::default();
+        verify_settings.add_trust_anchor(cert);
+
+        let problems = |data: &[u8]| -> Vec<VerificationProblemType> {
+            std::fs::write(&path, data).unwrap();
+
+            verify_xar_with_settings(&path, &verify_settings)
+                .into_iter()
+                .map(|problem| problem.problem)
+                .collect()
+        };
+
+        assert!(problems(&signed).is_empty());
+
+        let mut xar = XarReader::new(Cursor::new(signed.clone()))?;
+        let toc_start = xar.header().size as usize;
+        let heap_start = toc_start + xar.header().toc_length_compressed as usize;
+        let rsa_signature = xar.table_of_contents().signature.clone().unwrap();
+        let cms_signature = xar.cms_signature()?.unwrap();
+
+        // Changing the table of contents invalidates its checksum.
+        let mut toc_xml = String::new();
+        flate2::read::ZlibDecoder::new(&signed[toc_start..heap_start])
+            .read_to_string(&mut toc_xml)?;
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(toc_xml.replace("Distribution", "Distributiom").as_bytes())?;
+        let toc_compressed = encoder.finish()?;
+
+        let mut data = signed[..toc_start].to_vec();
+        data.pwrite_with(toc_compressed.len() as u64, 8, scroll::BE)?;
+        data.extend(toc_compressed);
+        data.extend(&signed[heap_start..]);
+        assert!(matches!(
+            problems(&data).as_slice(),
+            [VerificationProblemType::XarTableOfContentsChecksumMismatch]
+        ));
+
+        // Changing file data in the heap invalidates its checksum.
+        let mut data = signed.clone();
+        *data.last_mut().unwrap() ^= 0xff;
+        assert!(matches!(
+            problems(&data).as_slice(),
+            [VerificationProblemType::XarFileArchivedChecksumMismatch(..)]
+        ));
+
+        // Corrupted signatures don't verify.
+        let mut data = signed.clone();
+        data[heap_start + rsa_signature.offset as usize] ^= 0xff;
+        assert!(matches!(
+            problems(&data).as_slice(),
+            [VerificationProblemType::XarRsaSignatureError(_)]
+        ));
+
+        let cms_signature_value = cms_signature.signers().next().unwrap().signature();
+        let cms_offset = signed
+            .windows(cms_signature_value.len())
+            .position(|window| window == cms_signature_value)
+            .unwrap();
+        let mut data = signed.clone();
+        data[cms_offset] ^= 0xff;
+        assert!(matches!(
+            problems(&data).as_slice(),
+            [VerificationProblemType::CmsError(_)]
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn xar_rsa_chain() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let unsigned_path = td.path().join("unsigned.pkg");
+        let signed_path = td.path().join("signed.pkg");
+        let path = td.path().join("test.pkg");
+
+        std::fs::write(
+            &unsigned_path,
+            xar_data(&[("Distribution", b"distribution")]),
+        )?;
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert.clone());
+        UnifiedSigner::new(settings).sign_xar(&unsigned_path, &signed_path)?;
+        let signed = std::fs::read(&signed_path)?;
+
+        // A self-signed certificate for the same key that isn't trusted.
+        let untrusted = {
+            let mut name = x509_certificate::rfc3280::Name::default();
+            name.append_common_name_utf8_string("Untrusted").unwrap();
+
+            let untrusted: &x509_certificate::rfc5280::Certificate = cert.as_ref();
+            let mut untrusted = untrusted.clone();
+            untrusted.tbs_certificate.subject = name.clone();
+            untrusted.tbs_certificate.issuer = name;
+            untrusted.tbs_certificate.raw_data = None;
+
+            let mut tbs_der = vec![];
+            untrusted
+                .tbs_certificate
+                .encode_ref()
+                .write_encoded(bcder::Mode::Der, &mut tbs_der)?;
+            let signature = signing_key
+                .try_sign(&tbs_der)
+                .map_err(X509CertificateError::from)?;
+            untrusted.signature = bcder::BitString::new(0, signature.as_ref().to_vec().into());
+
+            CapturedX509Certificate::from_der(
+                x509_certificate::X509Certificate::from(untrusted).encode_der()?,
+            )?
+        };
+
+        // Rebuild the archive with the untrusted certificate in the RSA signature and
+        // valid signatures.
+        let xar = XarReader::new(Cursor::new(signed.clone()))?;
+        let toc_start = xar.header().size as usize;
+        let heap_start = toc_start + xar.header().toc_length_compressed as usize;
+        let mut toc = xar.table_of_contents().clone();
+        let cms_signature = toc.x_signature.clone().unwrap();
+        toc.signature.as_mut().unwrap().key_info =
+            apple_xar::table_of_contents::KeyInfo::from_certificates(std::iter::once(&untrusted))?;
+
+        let toc_xml = toc.to_xml()?;
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&toc_xml)?;
+        let toc_compressed = encoder.finish()?;
+        let digest = toc.checksum.style.digest_data(&toc_compressed)?;
+
+        let mut cms = SignedDataBuilder::default()
+            .content_type(Oid(OID_ID_DATA.as_ref().into()))
+            .signer(
+                SignerBuilder::new(&signing_key, cert.clone()).message_id_content(digest.clone()),
+            )
+            .build_der()?;
+        cms.resize(cms_signature.size as usize, 0);
+
+        let mut data = signed[..toc_start].to_vec();
+        data.pwrite_with(toc_compressed.len() as u64, 8, scroll::BE)?;
+        data.pwrite_with(toc_xml.len() as u64, 16, scroll::BE)?;
+        data.extend(toc_compressed);
+        data.extend(&digest);
+        data.extend(
+            signing_key
+                .try_sign(&digest)
+                .map_err(X509CertificateError::from)?
+                .as_ref(),
+        );
+        data.extend(cms);
+        data.extend(
+            &signed[heap_start + cms_signature.offset as usize + cms_signature.size as usize..],
+        );
+        std::fs::write(&path, data)?;
+
+        // Identical chains are only verified once.
+        let problems = verify_xar_with_settings(&signed_path, &VerificationSettings::default());
+        assert!(
+            matches!(
+                problems.as_slice(),
+                [VerificationProblem {
+                    problem: VerificationProblemType::CertificateUntrusted(_),
+                    ..
+                }]
+            ),
+            "{:?}",
+            problems
+        );
+
+        let mut verify_settings = VerificationSettings::default();
+        verify_settings.add_trust_anchor(cert);
+
+        assert!(verify_xar_with_settings(&signed_path, &verify_settings).is_empty());
+
+        let problems = verify_xar_with_settings(&path, &verify_settings)
+            .into_iter()
+            .map(|problem| problem.problem)
+            .collect::<Vec<_>>();
+        assert!(
+            matches!(
+                problems.as_slice(),
+                [VerificationProblemType::CertificateUntrusted(_)]
+            ),
+            "{:?}",
+            problems
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,390 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Signing of non-Mach-O files via extended attributes.
+//!
+//! Files that aren't Mach-O binaries, such as scripts, have nowhere to embed a
+//! code signature. Instead, Apple's tooling stores each blob that would be in
+//! the signature SuperBlob in its own extended attribute on the file. e.g. the
+//! Code Directory is stored in `com.apple.cs.CodeDirectory`, the Requirement
+//! Set in `com.apple.cs.CodeRequirements`, and the CMS signature in
+//! `com.apple.cs.CodeSignature`. Attribute values are the raw blob data,
+//! including the blob header.
+//!
+//! Alternate Code Directories are stored in `com.apple.cs.CodeRequirements-1`,
+//! `com.apple.cs.CodeRequirements-2`, etc. The naming is odd but it is what
+//! Apple's tooling does.
+//!
+//! The Code Directory digests the content of the file in 4096 byte pages, just
+//! like a Mach-O binary. The code limit is the size of the file.
+//!
+//! Linux only allows unprivileged processes to write extended attributes in the
+//! `user.` namespace. So on platforms other than macOS, attribute names are
+//! prefixed with `user.`. This allows signing and verification to work on Linux.
+//! But Apple's tooling won't see these signatures unless the attributes are
+//! renamed.
+
+use {
+    crate::{
+        code_directory::{CodeDirectoryBlob, CodeSignatureFlags},
+        embedded_signature::{
+            create_superblob, BlobData, CodeSigningMagic, CodeSigningSlot, Digest,
+            EmbeddedSignature, EntitlementsBlob,
+        },
+        embedded_signature_builder::EmbeddedSignatureBuilder,
+        error::AppleCodesignError,
+        macho_signing::create_requirement_set_blob,
+        signing_settings::{SettingsScope, SigningSettings},
+    },
+    log::{info, warn},
+    std::{borrow::Cow, path::Path},
+};
+
+/// Prefix of extended attributes holding code signature data.
+#[cfg(target_os = "macos")]
+pub const XATTR_PREFIX: &str = "com.apple.cs.";
+
+/// Prefix of extended attributes holding code signature data.
+#[cfg(not(target_os = "macos"))]
+pub const XATTR_PREFIX: &str = "user.com.apple.cs.";
+
+/// Page size used for code digests.
+const PAGE_SIZE: usize = 4096;
+
+/// Obtain the name of the extended attribute holding a signature slot.
+///
+/// Returns `None` if the slot can't be stored in an extended attribute.
+pub fn slot_xattr_name(slot: CodeSigningSlot) -> Option<String> {
+    let name = match slot {
+        CodeSigningSlot::CodeDirectory => "CodeDirectory",
+        CodeSigningSlot::RequirementSet => "CodeRequirements",
+        CodeSigningSlot::ResourceDir => "CodeResources",
+        CodeSigningSlot::Entitlements => "CodeEntitlements",
+        CodeSigningSlot::EntitlementsDer => "CodeEntitlementsDER",
+        CodeSigningSlot::RepSpecific => "CodeRepSpecific",
+        CodeSigningSlot::AlternateCodeDirectory0 => "CodeRequirements-1",
+        CodeSigningSlot::AlternateCodeDirectory1 => "CodeRequirements-2",
+        CodeSigningSlot::AlternateCodeDirectory2 => "CodeRequirements-3",
+        CodeSigningSlot::AlternateCodeDirectory3 => "CodeRequirements-4",
+        CodeSigningSlot::AlternateCodeDirectory4 => "CodeRequirements-5",
+        CodeSigningSlot::Signature => "CodeSignature",
+        _ => return None,
+    };
+
+    Some(format!("{XATTR_PREFIX}{name}"))
+}
+
+/// All signature slots that can be stored in extended attributes.
+fn xattr_slots() -> impl Iterator<Item = (CodeSigningSlot, String)> {
+    [
+        CodeSigningSlot::CodeDirectory,
+        CodeSigningSlot::RequirementSet,
+        CodeSigningSlot::ResourceDir,
+        CodeSigningSlot::Entitlements,
+        CodeSigningSlot::EntitlementsDer,
+        CodeSigningSlot::RepSpecific,
+        CodeSigningSlot::AlternateCodeDirectory0,
+        CodeSigningSlot::AlternateCodeDirectory1,
+        CodeSigningSlot::AlternateCodeDirectory2,
+        CodeSigningSlot::AlternateCodeDirectory3,
+        CodeSigningSlot::AlternateCodeDirectory4,
+        CodeSigningSlot::Signature,
+    ]
+    .into_iter()
+    .filter_map(|slot| slot_xattr_name(slot).map(|name| (slot, name)))
+}
+
+#[cfg(unix)]
+fn get_xattr(path: &Path, name: &str) -> Result<Option<Vec<u8>>, AppleCodesignError> {
+    Ok(xattr::get(path, name)?)
+}
+
+#[cfg(not(unix))]
+fn get_xattr(_: &Path, _: &str) -> Result<Option<Vec<u8>>, AppleCodesignError> {
+    Err(AppleCodesignError::XattrUnsupported)
+}
+
+#[cfg(unix)]
+fn set_xattr(path: &Path, name: &str, value: &[u8]) -> Result<(), AppleCodesignError> {
+    Ok(xattr::set(path, name, value)?)
+}
+
+#[cfg(not(unix))]
+fn set_xattr(_: &Path, _: &str, _: &[u8]) -> Result<(), AppleCodesignError> {
+    Err(AppleCodesignError::XattrUnsupported)
+}
+
+#[cfg(unix)]
+fn remove_xattr(path: &Path, name: &str) -> Result<(), AppleCodesignError> {
+    if xattr::get(path, name)?.is_some() {
+        xattr::remove(path, name)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn remove_xattr(_: &Path, _: &str) -> Result<(), AppleCodesignError> {
+    Err(AppleCodesignError::XattrUnsupported)
+}
+
+/// Read the code signature stored in the extended attributes of a file.
+///
+/// Returns the data of an embedded signature SuperBlob assembled from the
+/// attributes, which can be parsed with [EmbeddedSignature::from_bytes()].
+/// Returns `None` if the file has no Code Directory attribute.
+pub fn read_signature_xattrs(
+    path: impl AsRef<Path>,
+) -> Result<Option<Vec<u8>>, AppleCodesignError> {
+    let path = path.as_ref();
+
+    let mut blobs = vec![];
+
+    for (slot, name) in xattr_slots() {
+        if let Some(data) = get_xattr(path, &name)? {
+            blobs.push((slot, data));
+        }
+    }
+
+    if !blobs
+        .iter()
+        .any(|(slot, _)| *slot == CodeSigningSlot::CodeDirectory)
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(create_superblob(
+        CodeSigningMagic::EmbeddedSignature,
+        blobs.iter(),
+    )?))
+}
+
+/// Remove code signature extended attributes from a file.
+pub fn remove_signature_xattrs(path: impl AsRef<Path>) -> Result<(), AppleCodesignError> {
+    let path = path.as_ref();
+
+    for (_, name) in xattr_slots() {
+        remove_xattr(path, &name)?;
+    }
+
+    Ok(())
+}
+
+/// Entity for signing files via extended attributes.
+///
+/// This can sign any file. But it is intended for files that can't hold an
+/// embedded signature, such as scripts.
+#[derive(Clone, Debug, Default)]
+pub struct XattrSigner {}
+
+impl XattrSigner {
+    /// Sign a file in place.
+    ///
+    /// The content of the file isn't modified. Any existing signature attributes
+    /// are removed and new ones are written.
+    pub fn sign_file(
+        &self,
+        settings: &SigningSettings,
+        path: impl AsRef<Path>,
+    ) -> Result<(), AppleCodesignError> {
+        let path = path.as_ref();
+
+        warn!("signing {} via extended attributes", path.display());
+
+        let data = std::fs::read(path)?;
+        let superblob = self.create_superblob(settings, &data)?;
+        let signature = EmbeddedSignature::from_bytes(&superblob)?;
+
+        remove_signature_xattrs(path)?;
+
+        for blob in &signature.blobs {
+            let name = slot_xattr_name(blob.slot)
+                .ok_or(AppleCodesignError::XattrUnsupportedSlot(blob.slot))?;
+
+            info!("writing {} ({} bytes)", name, blob.data.len());
+            set_xattr(path, &name, blob.data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Create the embedded signature SuperBlob for file content.
+    ///
+    /// The blobs within are what is written to extended attributes.
+    pub fn create_superblob(
+        &self,
+        settings: &SigningSettings,
+        data: &[u8],
+    ) -> Result<Vec<u8>, AppleCodesignError> {
+        let mut builder = EmbeddedSignatureBuilder::default();
+
+        for (slot, blob) in self.create_special_blobs(settings)? {
+            builder.add_blob(slot, blob)?;
+        }
+
+        builder.add_code_directory(
+            CodeSigningSlot::CodeDirectory,
+            self.create_code_directory(settings, data)?,
+        )?;
+
+        if let Some(digests) = settings.extra_digests(SettingsScope::Main) {
+            for digest_type in digests {
+                let mut alt_settings = settings.clone();
+                alt_settings.set_digest_type(*digest_type);
+
+                builder.add_alternative_code_directory(
+                    self.create_code_directory(&alt_settings, data)?,
+                )?;
+            }
+        }
+
+        builder.set_signing_time(settings.signing_time().copied());
+
+        if let Some((signing_key, signing_cert)) = settings.signing_key() {
+            builder.create_cms_signature(
+                signing_key,
+                signing_cert,
+                settings.effective_time_stamp_url(),
+                settings.certificate_chain().iter().cloned(),
+            )?;
+        }
+
+        builder.create_superblob()
+    }
+
+    /// Create the Code Directory for file content.
+    pub fn create_code_directory(
+        &self,
+        settings: &SigningSettings,
+        data: &[u8],
+    ) -> Result<CodeDirectoryBlob<'static>, AppleCodesignError> {
+        let mut flags = settings
+            .code_signature_flags(SettingsScope::Main)
+            .unwrap_or_else(CodeSignatureFlags::empty);
+
+        if settings.signing_key().is_some() {
+            flags -= CodeSignatureFlags::ADHOC;
+        } else {
+            flags |= CodeSignatureFlags::ADHOC;
+        }
+
+        let ident = Cow::Owned(
+            settings
+                .binary_identifier(SettingsScope::Main)
+                .ok_or(AppleCodesignError::NoIdentifier)?
+                .to_string(),
+        );
+
+        let (code_limit, code_limit_64) = match data.len() as u64 {
+            x if x > u32::MAX as u64 => (0, Some(x)),
+            x => (x as u32, None),
+        };
+
+        let code_digests = data
+            .chunks(PAGE_SIZE)
+            .map(|chunk| {
+                Ok(Digest {
+                    data: settings.digest_type().digest_data(chunk)?.into(),
+                })
+            })
+            .collect::<Result<Vec<_>, AppleCodesignError>>()?;
+
+        let mut cd = CodeDirectoryBlob {
+            flags,
+            code_limit,
+            digest_size: settings.digest_type().hash_len()? as u8,
+            digest_type: *settings.digest_type(),
+            page_size: PAGE_SIZE as u32,
+            code_limit_64,
+            ident,
+            team_name: settings.team_id().map(|x| Cow::Owned(x.to_string())),
+            code_digests,
+            ..Default::default()
+        };
+
+        if let Some(data) = settings.info_plist_data(SettingsScope::Main) {
+            cd.set_slot_digest(
+                CodeSigningSlot::Info,
+                settings.digest_type().digest_data(data)?,
+            )?;
+        }
+
+        cd.adjust_version(None);
+        cd.clear_newer_fields();
+
+        Ok(cd)
+    }
+
+    /// Create blobs other than the Code Directory and CMS signature.
+    pub fn create_special_blobs(
+        &self,
+        settings: &SigningSettings,
+    ) -> Result<Vec<(CodeSigningSlot, BlobData<'static>)>, AppleCodesignError> {
+        let mut res = vec![(
+            CodeSigningSlot::RequirementSet,
+            create_requirement_set_blob(settings)?.into(),
+        )];
+
+        if let Some(entitlements) = settings.entitlements_xml(SettingsScope::Main)? {
+            res.push((
+                CodeSigningSlot::Entitlements,
+                EntitlementsBlob::from_string(&entitlements).into(),
+            ));
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::verify::{verify_file, VerificationProblemType},
+    };
+
+    #[test]
+    fn sign_and_verify_file() -> Result<(), AppleCodesignError> {
+        let td = tempfile::tempdir()?;
+        let path = td.path().join("script.sh");
+        std::fs::write(&path, b"#!/bin/sh\necho hello\n")?;
+
+        // Not all filesystems support extended attributes.
+        if set_xattr(&path, &format!("{XATTR_PREFIX}test"), b"").is_err() {
+            return Ok(());
+        }
+        remove_xattr(&path, &format!("{XATTR_PREFIX}test"))?;
+
+        assert!(read_signature_xattrs(&path)?.is_none());
+
+        let mut settings = SigningSettings::default();
+        settings.set_binary_identifier(SettingsScope::Main, "script.sh");
+
+        XattrSigner::default().sign_file(&settings, &path)?;
+
+        let data = read_signature_xattrs(&path)?.expect("signature should be present");
+        let signature = EmbeddedSignature::from_bytes(&data)?;
+        let cd = signature
+            .code_directory()?
+            .expect("code directory should be present");
+        assert_eq!(cd.ident, "script.sh");
+        assert!(cd.flags.contains(CodeSignatureFlags::ADHOC));
+
+        // Ad-hoc signatures have no CMS signature but are otherwise valid.
+        let problems = verify_file(&path);
+        assert_eq!(problems.len(), 1);
+        assert!(matches!(
+            problems[0].problem,
+            VerificationProblemType::NoCryptographicSignature
+        ));
+
+        std::fs::write(&path, b"#!/bin/sh\necho goodbye\n")?;
+        assert!(!verify_file(&path).is_empty());
+
+        remove_signature_xattrs(&path)?;
+        assert!(read_signature_xattrs(&path)?.is_none());
+
+        Ok(())
+    }
+}
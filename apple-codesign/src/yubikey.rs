@@ -685,7 +685,7 @@ impl PublicKeyPeerDecrypt for CertificateSigner {
 }
 
 impl PrivateKey for CertificateSigner {
-    fn as_key_info_signer(&self) -> &dyn KeyInfoSigner {
+    fn as_key_info_signer(&self) -> &(dyn KeyInfoSigner + Sync) {
         self
     }
 
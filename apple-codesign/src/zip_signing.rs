@@ -0,0 +1,645 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! ZIP archive signing.
+//!
+//! ZIP archives (including iOS `.ipa` files) don't have a code signature of their
+//! own. Instead, the bundles within them are signed. We do this by extracting the
+//! archive to a temporary directory, signing the bundles in place, and writing out
+//! a new archive.
+//!
+//! Entries in the new archive are written in the same order as the original
+//! archive and retain their original permissions, modification times, and
+//! compression methods. Entries created by signing, such as
+//! `_CodeSignature/CodeResources`, are appended at the end in sorted order.
+
+use {
+    crate::{
+        bundle_signing::BundleSigner, error::AppleCodesignError, signing_plan::BundlePlan,
+        signing_settings::SigningSettings,
+    },
+    apple_bundles::DirectoryBundle,
+    chrono::{Datelike, Timelike},
+    log::{info, warn},
+    std::{
+        collections::BTreeSet,
+        fs::File,
+        io::{Read, Seek, Write},
+        path::{Path, PathBuf},
+    },
+    zip::{write::FileOptions, CompressionMethod, DateTime, ZipArchive, ZipWriter},
+};
+
+/// Unix file type bits for a symlink.
+const S_IFLNK: u32 = 0o120000;
+
+/// Unix file type mask.
+const S_IFMT: u32 = 0o170000;
+
+/// Describes an entry in a ZIP archive.
+#[derive(Clone, Debug)]
+struct ZipEntry {
+    /// Name of the entry, without a trailing slash for directories.
+    name: String,
+    /// Unix mode of the entry, if recorded.
+    unix_mode: Option<u32>,
+    /// Last modified time of the entry.
+    last_modified: DateTime,
+    /// Compression method of the entry.
+    compression: CompressionMethod,
+    /// Whether the entry is a directory.
+    is_dir: bool,
+}
+
+/// A ZIP archive extracted to a temporary directory.
+///
+/// The temporary directory is deleted when this instance is dropped.
+pub struct ExtractedZip {
+    temp_dir: tempfile::TempDir,
+    entries: Vec<ZipEntry>,
+}
+
+impl ExtractedZip {
+    /// Extract the ZIP archive at the given path.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, AppleCodesignError> {
+        Self::from_reader(File::open(path.as_ref())?)
+    }
+
+    /// Extract a ZIP archive from a reader.
+    pub fn from_reader(reader: impl Read + Seek) -> Result<Self, AppleCodesignError> {
+        let temp_dir = tempfile::Builder::new()
+            .prefix("apple-codesign-zip-")
+            .tempdir()?;
+
+        let mut archive = ZipArchive::new(reader)?;
+        let mut entries = Vec::with_capacity(archive.len());
+
+        for index in 0..archive.len() {
+            let mut zf = archive.by_index(index)?;
+
+            // Refuse to extract anything that would escape the destination directory.
+            let rel_path = zf
+                .enclosed_name()
+                .ok_or_else(|| AppleCodesignError::ZipUnsafePath(zf.name().to_string()))?
+                .to_path_buf();
+            let dest_path = temp_dir.path().join(&rel_path);
+
+            // Symlinks extracted by earlier entries could redirect writes outside the
+            // destination directory. So refuse to write through any of them.
+            if traverses_symlink(temp_dir.path(), &rel_path)? {
+                return Err(AppleCodesignError::ZipUnsafePath(zf.name().to_string()));
+            }
+
+            let entry = ZipEntry {
+                name: zf.name().trim_end_matches('/').to_string(),
+                unix_mode: zf.unix_mode(),
+                last_modified: zf.last_modified(),
+                compression: zf.compression(),
+                is_dir: zf.is_dir(),
+            };
+
+            if let Some(parent) = dest_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            if entry.is_dir {
+                std::fs::create_dir_all(&dest_path)?;
+            } else if entry.is_symlink() {
+                let mut target = String::new();
+                zf.read_to_string(&mut target)?;
+
+                simple_file_manifest::create_symlink(&dest_path, target)?;
+            } else {
+                let mut fh = File::create(&dest_path)?;
+                std::io::copy(&mut zf, &mut fh)?;
+
+                if entry.unix_mode.unwrap_or(0) & 0o111 != 0 {
+                    simple_file_manifest::set_executable(&mut fh)?;
+                }
+            }
+
+            entries.push(entry);
+        }
+
+        Ok(Self { temp_dir, entries })
+    }
+
+    /// The directory the archive was extracted to.
+    pub fn root_dir(&self) -> &Path {
+        self.temp_dir.path()
+    }
+
+    /// Resolve the bundles within this archive that should be signed.
+    ///
+    /// If the archive has a `Payload` directory, as `.ipa` files do, bundles
+    /// in that directory are returned. Otherwise bundles at the root of the
+    /// archive are returned.
+    pub fn bundle_paths(&self) -> Result<Vec<PathBuf>, AppleCodesignError> {
+        let payload = self.root_dir().join("Payload");
+
+        let search_dir = if payload.is_dir() {
+            payload
+        } else {
+            self.root_dir().to_path_buf()
+        };
+
+        let mut paths = vec![];
+
+        for entry in std::fs::read_dir(&search_dir)? {
+            let path = entry?.path();
+
+            // Ignore resource fork metadata added by macOS archivers.
+            if path.file_name() == Some("__MACOSX".as_ref()) || !path.is_dir() {
+                continue;
+            }
+
+            if DirectoryBundle::new_from_path(&path).is_ok() {
+                paths.push(path);
+            }
+        }
+
+        paths.sort();
+
+        Ok(paths)
+    }
+
+    /// Write the current content of the extracted directory as a ZIP archive.
+    pub fn write_zip<W: Write + Seek>(&self, writer: W) -> Result<W, AppleCodesignError> {
+        self.write_entries(writer, self.current_entries()?)
+    }
+
+    /// Write the current content of the extracted directory as a normalized ZIP archive.
+    ///
+    /// Entries are written in sorted order and all have the given modification time.
+    /// So identical content produces an identical archive.
+    pub fn write_zip_normalized<W: Write + Seek>(
+        &self,
+        writer: W,
+        last_modified: DateTime,
+    ) -> Result<W, AppleCodesignError> {
+        let mut entries = self.current_entries()?;
+
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        for entry in entries.iter_mut() {
+            entry.last_modified = last_modified;
+        }
+
+        self.write_entries(writer, entries)
+    }
+
+    fn write_entries<W: Write + Seek>(
+        &self,
+        writer: W,
+        entries: Vec<ZipEntry>,
+    ) -> Result<W, AppleCodesignError> {
+        let mut zf = ZipWriter::new(writer);
+
+        for entry in &entries {
+            self.write_entry(&mut zf, entry)?;
+        }
+
+        Ok(zf.finish()?)
+    }
+
+    /// Resolve entries for the current content of the extracted directory.
+    fn current_entries(&self) -> Result<Vec<ZipEntry>, AppleCodesignError> {
+        let mut remaining = BTreeSet::new();
+        walk_directory(self.root_dir(), Path::new(""), &mut remaining)?;
+
+        // Only emit entries for new directories if the original archive had them.
+        let have_dirs = self.entries.iter().any(|entry| entry.is_dir);
+
+        let mut entries = vec![];
+
+        for entry in &self.entries {
+            if remaining.remove(&entry.name) {
+                entries.push(entry.clone());
+            } else {
+                info!("{} no longer exists; removing from archive", entry.name);
+            }
+        }
+
+        for name in remaining {
+            let path = self.root_dir().join(&name);
+            let metadata = std::fs::symlink_metadata(&path)?;
+
+            if metadata.is_dir() && !have_dirs {
+                continue;
+            }
+
+            let entry = ZipEntry {
+                unix_mode: if metadata.is_dir() {
+                    Some(0o755)
+                } else if metadata.file_type().is_symlink() {
+                    Some(S_IFLNK | 0o777)
+                } else if simple_file_manifest::is_executable(&metadata) {
+                    Some(0o755)
+                } else {
+                    Some(0o644)
+                },
+                name,
+                last_modified: DateTime::default(),
+                compression: CompressionMethod::Deflated,
+                is_dir: metadata.is_dir(),
+            };
+
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    fn write_entry<W: Write + Seek>(
+        &self,
+        zf: &mut ZipWriter<W>,
+        entry: &ZipEntry,
+    ) -> Result<(), AppleCodesignError> {
+        let path = self.root_dir().join(&entry.name);
+        let metadata = std::fs::symlink_metadata(&path)?;
+
+        let mut options = FileOptions::default()
+            .last_modified_time(entry.last_modified)
+            .compression_method(match entry.compression {
+                CompressionMethod::Stored => CompressionMethod::Stored,
+                _ => CompressionMethod::Deflated,
+            });
+
+        if let Some(mode) = entry.unix_mode {
+            options = options.unix_permissions(mode);
+        }
+
+        // Signing can change the type of a path. So key off the filesystem state
+        // rather than the original entry.
+        if metadata.is_dir() {
+            zf.add_directory(&entry.name, options)?;
+        } else if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(&path)?;
+
+            zf.add_symlink(
+                &entry.name,
+                target.to_string_lossy().replace('\\', "/"),
+                options,
+            )?;
+        } else {
+            zf.start_file(&entry.name, options)?;
+            std::io::copy(&mut File::open(&path)?, zf)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ZipEntry {
+    fn is_symlink(&self) -> bool {
+        self.unix_mode
+            .map(|mode| mode & S_IFMT == S_IFLNK)
+            .unwrap_or(false)
+    }
+}
+
+/// Whether a relative path under a directory resolves through a symlink.
+///
+/// The path itself and all its ancestors under `root` are checked.
+fn traverses_symlink(root: &Path, rel: &Path) -> Result<bool, AppleCodesignError> {
+    let mut path = root.to_path_buf();
+
+    for component in rel.components() {
+        path.push(component);
+
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => return Ok(true),
+            Ok(_) => {}
+            // Nothing exists at or below this path yet.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(false)
+}
+
+/// Record the relative paths of all entities under a directory.
+fn walk_directory(
+    root: &Path,
+    rel: &Path,
+    paths: &mut BTreeSet<String>,
+) -> Result<(), AppleCodesignError> {
+    for entry in std::fs::read_dir(root.join(rel))? {
+        let entry = entry?;
+        let rel_path = rel.join(entry.file_name());
+
+        paths.insert(rel_path.to_string_lossy().replace('\\', "/"));
+
+        if entry.file_type()?.is_dir() {
+            walk_directory(root, &rel_path, paths)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Entity for signing ZIP archives.
+///
+/// Bundles within the archive are signed with [BundleSigner]. See
+/// [ExtractedZip::bundle_paths] for how bundles are discovered.
+pub struct ZipSigner {
+    zip: ExtractedZip,
+}
+
+impl ZipSigner {
+    /// Construct an instance from a ZIP archive at the given path.
+    pub fn new_from_path(path: impl AsRef<Path>) -> Result<Self, AppleCodesignError> {
+        Ok(Self {
+            zip: ExtractedZip::from_path(path)?,
+        })
+    }
+
+    /// Describe how [Self::write_signed_zip()] would sign bundles in the archive.
+    ///
+    /// Bundle paths are relative to the root of the archive.
+    pub fn plan(&self, settings: &SigningSettings) -> Result<Vec<BundlePlan>, AppleCodesignError> {
+        let bundles = self.zip.bundle_paths()?;
+
+        if bundles.is_empty() {
+            return Err(AppleCodesignError::ZipNoBundles);
+        }
+
+        let mut plans = vec![];
+
+        for path in bundles {
+            let prefix = path
+                .strip_prefix(self.zip.root_dir())
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+
+            for mut plan in BundleSigner::new_from_path(&path)?.plan(settings)? {
+                plan.path = if plan.path.is_empty() {
+                    prefix.clone()
+                } else {
+                    format!("{}/{}", prefix, plan.path)
+                };
+
+                plans.push(plan);
+            }
+        }
+
+        Ok(plans)
+    }
+
+    /// Sign bundles in the archive and write a new archive to the given path.
+    ///
+    /// The output path can be the same as the input path.
+    pub fn write_signed_zip(
+        &self,
+        output_path: impl AsRef<Path>,
+        settings: &SigningSettings,
+    ) -> Result<(), AppleCodesignError> {
+        let output_path = output_path.as_ref();
+
+        let bundles = self.zip.bundle_paths()?;
+
+        if bundles.is_empty() {
+            return Err(AppleCodesignError::ZipNoBundles);
+        }
+
+        for path in bundles {
+            warn!(
+                "signing bundle {} in ZIP archive",
+                path.strip_prefix(self.zip.root_dir())
+                    .unwrap_or(&path)
+                    .display()
+            );
+
+            BundleSigner::new_from_path(&path)?.write_signed_bundle(&path, settings)?;
+        }
+
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        warn!("writing ZIP archive to {}", output_path.display());
+        let fh = File::create(output_path)?;
+
+        if let Some(signing_time) = settings.signing_time() {
+            // ZIP times are limited to years 1980 through 2107. Fall back to the
+            // earliest time outside that range.
+            let last_modified = DateTime::from_date_and_time(
+                signing_time.year().try_into().unwrap_or(0),
+                signing_time.month() as _,
+                signing_time.day() as _,
+                signing_time.hour() as _,
+                signing_time.minute() as _,
+                signing_time.second() as _,
+            )
+            .unwrap_or_default();
+
+            self.zip.write_zip_normalized(fh, last_modified)?;
+        } else {
+            self.zip.write_zip(fh)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::{
+            testutil::{rsa_signing_identity, write_ios_app_bundle},
+            verify::{verify_zip, verify_zip_with_settings, VerificationSettings},
+        },
+    };
+
+    #[test]
+    fn roundtrip_preserves_entries() -> Result<(), AppleCodesignError> {
+        let mut zf = ZipWriter::new(std::io::Cursor::new(vec![]));
+
+        zf.add_directory("b", FileOptions::default())?;
+        zf.start_file(
+            "b/exe",
+            FileOptions::default()
+                .unix_permissions(0o755)
+                .compression_method(CompressionMethod::Stored),
+        )?;
+        zf.write_all(b"exe")?;
+        zf.start_file("a.txt", FileOptions::default().unix_permissions(0o600))?;
+        zf.write_all(b"a")?;
+        zf.add_symlink("b/link", "../a.txt", FileOptions::default())?;
+
+        let zip = ExtractedZip::from_reader(zf.finish()?)?;
+        std::fs::write(zip.root_dir().join("b/new.txt"), b"new")?;
+        std::fs::remove_file(zip.root_dir().join("a.txt"))?;
+
+        let mut archive = ZipArchive::new(zip.write_zip(std::io::Cursor::new(vec![]))?)?;
+
+        let entries = (0..archive.len())
+            .map(|i| {
+                let zf = archive.by_index(i).unwrap();
+                (zf.name().to_string(), zf.unix_mode(), zf.compression())
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            entries,
+            vec![
+                ("b/".to_string(), Some(0o40755), CompressionMethod::Stored),
+                (
+                    "b/exe".to_string(),
+                    Some(0o100755),
+                    CompressionMethod::Stored
+                ),
+                (
+                    "b/link".to_string(),
+                    Some(0o120777),
+                    CompressionMethod::Stored
+                ),
+                (
+                    "b/new.txt".to_string(),
+                    Some(0o100644),
+                    CompressionMethod::Deflated
+                ),
+            ]
+        );
+
+        let mut target = String::new();
+        archive.by_name("b/link")?.read_to_string(&mut target)?;
+        assert_eq!(target, "../a.txt");
+
+        Ok(())
+    }
+
+    #[test]
+    fn extract_rejects_symlink_traversal() -> Result<(), AppleCodesignError> {
+        let outside = tempfile::tempdir()?;
+
+        let mut zf = ZipWriter::new(std::io::Cursor::new(vec![]));
+        zf.add_symlink(
+            "a",
+            outside.path().to_string_lossy(),
+            FileOptions::default(),
+        )?;
+        zf.start_file("a/passwd", FileOptions::default())?;
+        zf.write_all(b"owned")?;
+
+        let res = ExtractedZip::from_reader(zf.finish()?);
+        assert!(matches!(res, Err(AppleCodesignError::ZipUnsafePath(name)) if name == "a/passwd"));
+        assert!(!outside.path().join("passwd").exists());
+
+        // Overwriting the symlink itself would also write through it.
+        let mut zf = ZipWriter::new(std::io::Cursor::new(vec![]));
+        zf.add_symlink(
+            "b",
+            outside.path().join("b").to_string_lossy(),
+            FileOptions::default(),
+        )?;
+        zf.start_file("b", FileOptions::default())?;
+        zf.write_all(b"owned")?;
+
+        assert!(matches!(
+            ExtractedZip::from_reader(zf.finish()?),
+            Err(AppleCodesignError::ZipUnsafePath(_))
+        ));
+        assert!(!outside.path().join("b").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn sign_ipa() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let bundle = temp_dir.path().join("Payload/test.app");
+        let path = temp_dir.path().join("test.ipa");
+
+        write_ios_app_bundle(&bundle)?;
+
+        let mut zf = ZipWriter::new(File::create(&path)?);
+        let mut paths = BTreeSet::new();
+        walk_directory(temp_dir.path(), Path::new("Payload"), &mut paths)?;
+        for name in paths {
+            let file_path = temp_dir.path().join(&name);
+            if file_path.is_dir() {
+                continue;
+            }
+
+            zf.start_file(&name, FileOptions::default())?;
+            zf.write_all(&std::fs::read(file_path)?)?;
+        }
+        zf.finish()?;
+
+        assert!(!verify_zip(&path).is_empty());
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert.clone());
+
+        ZipSigner::new_from_path(&path)?.write_signed_zip(&path, &settings)?;
+
+        let mut verify_settings = VerificationSettings::default();
+        verify_settings.add_trust_anchor(cert);
+        let problems = verify_zip_with_settings(&path, &verify_settings);
+        assert!(problems.is_empty(), "{:?}", problems);
+
+        let zip = ExtractedZip::from_path(&path)?;
+        let root = zip.root_dir().join("Payload/test.app");
+        assert!(root.join("_CodeSignature/CodeResources").is_file());
+        assert!(root
+            .join("Frameworks/One.framework/_CodeSignature/CodeResources")
+            .is_file());
+        assert!(!root.join("Contents").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn deterministic_signing() -> Result<(), AppleCodesignError> {
+        let temp_dir = tempfile::tempdir()?;
+        let bundle = temp_dir.path().join("Payload/test.app");
+        write_ios_app_bundle(&bundle)?;
+
+        let mut paths = BTreeSet::new();
+        walk_directory(temp_dir.path(), Path::new("Payload"), &mut paths)?;
+        let files = paths
+            .into_iter()
+            .filter(|name| !temp_dir.path().join(name).is_dir())
+            .collect::<Vec<_>>();
+
+        let (signing_key, cert) = rsa_signing_identity();
+        let mut settings = SigningSettings::default();
+        settings.set_signing_key(&signing_key, cert);
+        settings.set_signing_time(Some(
+            chrono::TimeZone::timestamp_opt(&chrono::Utc, 1_600_000_000, 0).unwrap(),
+        ));
+
+        let mut outputs = vec![];
+
+        // Inputs differ in entry order and modification times.
+        for (i, reverse) in [false, true].into_iter().enumerate() {
+            let path = temp_dir.path().join(format!("test-{i}.ipa"));
+            let options = FileOptions::default().last_modified_time(
+                DateTime::from_date_and_time(2000 + i as u16, 1, 1, 0, 0, 0).unwrap(),
+            );
+
+            let mut zf = ZipWriter::new(File::create(&path)?);
+            let mut names = files.clone();
+            if reverse {
+                names.reverse();
+            }
+            for name in names {
+                zf.start_file(&name, options)?;
+                zf.write_all(&std::fs::read(temp_dir.path().join(&name))?)?;
+            }
+            zf.finish()?;
+
+            ZipSigner::new_from_path(&path)?.write_signed_zip(&path, &settings)?;
+            outputs.push(std::fs::read(&path)?);
+        }
+
+        assert_eq!(outputs[0], outputs[1]);
+
+        Ok(())
+    }
+}
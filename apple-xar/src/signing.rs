@@ -19,7 +19,9 @@ use {
     crate::{
         format::XarChecksum,
         reader::XarReader,
-        table_of_contents::{Checksum, ChecksumType, File, KeyInfo, Signature, SignatureStyle},
+        table_of_contents::{
+            Checksum, ChecksumType, File, KeyInfo, Signature, SignatureStyle, TableOfContents,
+        },
         Error, XarResult,
     },
     bcder::Oid,
@@ -131,30 +133,12 @@ impl<R: Read + Seek + Sized + Debug> XarSigner<R> {
             key_info: KeyInfo::from_certificates(chain.iter().copied())?,
         };
 
-        let mut current_offset = cms_signature.offset + cms_signature.size;
+        let heap_offset = cms_signature.offset + cms_signature.size;
 
         toc.signature = Some(rsa_signature);
         toc.x_signature = Some(cms_signature);
 
-        // Now go through and update file offsets. Files are nested. So we do a pass up
-        // front to calculate all the offsets then we recursively descend and update all
-        // references.
-        let mut ids_to_offsets = HashMap::new();
-
-        for (_, file) in self.reader.files()? {
-            if let Some(data) = &file.data {
-                ids_to_offsets.insert(file.id, current_offset);
-                current_offset += data.length;
-            }
-        }
-
-        toc.visit_files_mut(&|file: &mut File| {
-            if let Some(data) = &mut file.data {
-                data.offset = *ids_to_offsets
-                    .get(&file.id)
-                    .expect("file should have offset recorded");
-            }
-        });
+        self.update_file_offsets(&mut toc, heap_offset)?;
 
         // The TOC should be all set up now. Let's serialize it so we can produce
         // a valid signature.
@@ -190,18 +174,93 @@ impl<R: Read + Seek + Sized + Debug> XarSigner<R> {
         }
 
         // Now let's write everything out.
+        self.write_header(writer, &toc_data, &toc_compressed)?;
+        writer.write_all(&toc_digest)?;
+        writer.write_all(rsa_signature.as_ref())?;
+        writer.write_all(&cms_signature)?;
+
+        self.write_file_data(writer)
+    }
+
+    /// Write a copy of the XAR with its signatures removed.
+    ///
+    /// The RSA and CMS signature elements are removed from the table of contents
+    /// and their data is removed from the heap. The table of contents checksum
+    /// is retained.
+    pub fn remove_signature<W: Write>(&mut self, writer: &mut W) -> XarResult<()> {
+        let mut toc = self.reader.table_of_contents().clone();
+
+        let digest_size = self.checksum_type.digest_data(&[])?.len() as u64;
+        toc.checksum = Checksum {
+            style: self.checksum_type,
+            offset: 0,
+            size: digest_size,
+        };
+        toc.signature = None;
+        toc.x_signature = None;
+
+        self.update_file_offsets(&mut toc, digest_size)?;
+
+        warn!("generating new XAR table of contents XML");
+        let toc_data = toc.to_xml()?;
+        info!("table of contents size: {}", toc_data.len());
+
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&toc_data)?;
+        let toc_compressed = zlib.finish()?;
+
+        let toc_digest = self.checksum_type.digest_data(&toc_compressed)?;
+
+        self.write_header(writer, &toc_data, &toc_compressed)?;
+        writer.write_all(&toc_digest)?;
+
+        self.write_file_data(writer)
+    }
+
+    /// Update file offsets in a table of contents so file data follows `heap_offset`.
+    fn update_file_offsets(&self, toc: &mut TableOfContents, heap_offset: u64) -> XarResult<()> {
+        // Files are nested. So we do a pass up front to calculate all the offsets
+        // then we recursively descend and update all references.
+        let mut current_offset = heap_offset;
+        let mut ids_to_offsets = HashMap::new();
+
+        for (_, file) in self.reader.files()? {
+            if let Some(data) = &file.data {
+                ids_to_offsets.insert(file.id, current_offset);
+                current_offset += data.length;
+            }
+        }
+
+        toc.visit_files_mut(&|file: &mut File| {
+            if let Some(data) = &mut file.data {
+                data.offset = *ids_to_offsets
+                    .get(&file.id)
+                    .expect("file should have offset recorded");
+            }
+        });
+
+        Ok(())
+    }
+
+    fn write_header<W: Write>(
+        &self,
+        writer: &mut W,
+        toc_data: &[u8],
+        toc_compressed: &[u8],
+    ) -> XarResult<()> {
         let mut header = *self.reader.header();
         header.checksum_algorithm_id = XarChecksum::from(self.checksum_type).into();
         header.toc_length_compressed = toc_compressed.len() as _;
         header.toc_length_uncompressed = toc_data.len() as _;
 
         writer.iowrite_with(header, scroll::BE)?;
-        writer.write_all(&toc_compressed)?;
-        writer.write_all(&toc_digest)?;
-        writer.write_all(rsa_signature.as_ref())?;
-        writer.write_all(&cms_signature)?;
+        writer.write_all(toc_compressed)?;
+
+        Ok(())
+    }
 
-        // And write all the files to the heap.
+    /// Write all the files to the heap.
+    fn write_file_data<W: Write>(&mut self, writer: &mut W) -> XarResult<()> {
         for (path, file) in self.reader.files()? {
             if file.data.is_some() {
                 info!("copying {} to output XAR", path);
@@ -235,19 +235,48 @@ impl From<ChecksumType> for XarChecksum {
 }
 
 impl ChecksumType {
-    /// Digest a slice of data.
-    pub fn digest_data(&self, data: &[u8]) -> XarResult<Vec<u8>> {
-        let mut h: Box<dyn DynDigest> = match self {
+    /// Obtain a writer digesting data written to it.
+    pub fn digest_writer(&self) -> XarResult<DigestWriter> {
+        Ok(DigestWriter(match self {
             Self::None => return Err(Error::Unsupported("cannot digest None checksum")),
             Self::Md5 => Box::<md5::Md5>::default(),
             Self::Sha1 => Box::<sha1::Sha1>::default(),
             Self::Sha256 => Box::<sha2::Sha256>::default(),
             Self::Sha512 => Box::<sha2::Sha512>::default(),
-        };
+        }))
+    }
 
-        h.update(data);
+    /// Digest a slice of data.
+    pub fn digest_data(&self, data: &[u8]) -> XarResult<Vec<u8>> {
+        let mut h = self.digest_writer()?;
+        h.0.update(data);
+
+        Ok(h.finish())
+    }
+}
 
-        Ok(h.finalize().to_vec())
+/// A writer computing the digest of data written to it.
+///
+/// Instances are obtained from [ChecksumType::digest_writer()]. This allows
+/// digesting data without buffering all of it.
+pub struct DigestWriter(Box<dyn DynDigest>);
+
+impl DigestWriter {
+    /// Obtain the digest of all data written.
+    pub fn finish(self) -> Vec<u8> {
+        self.0.finalize().to_vec()
+    }
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 